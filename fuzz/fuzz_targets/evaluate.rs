@@ -0,0 +1,47 @@
+//! Generated programs against inputs made of the characters their literals use: evaluating
+//! mustn't panic (debug builds also check the invariants of the solver's memo tables), and
+//! `matches` has to agree with `evaluate_partition`.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use std::mem::discriminant;
+use strql::error::StrqlError;
+use strql::{evaluate_partition, matches, Program};
+
+/// Inputs longer than this mostly take longer to run, not different paths.
+const MAX_INPUT_LEN: usize = 64;
+
+const ALPHABET: &[char] = &['a', 'b', 'B', '1', ' ', '.', '-', '"', '\n', 'é', '{', '}'];
+
+#[derive(Debug, Arbitrary)]
+struct Case {
+    program: Program,
+    input: Vec<u8>,
+}
+
+fuzz_target!(|case: Case| {
+    let input: String = case
+        .input
+        .iter()
+        .take(MAX_INPUT_LEN)
+        .map(|&b| ALPHABET[usize::from(b) % ALPHABET.len()])
+        .collect();
+    let source = &case.program.source;
+
+    let matched = matches(source, &input);
+    let evaluated = evaluate_partition(source, &input);
+    match (&matched, &evaluated) {
+        (Ok(true), Ok(_)) => {}
+        (
+            Ok(false),
+            Err(StrqlError::PatternNoMatch { .. } | StrqlError::PartialMatch { .. }),
+        ) => {}
+        (Err(a), Err(b)) if discriminant(a) == discriminant(b) => {}
+        _ => panic!(
+            "matches and evaluate_partition disagree on {:?}:\n{}\n{:?}\n{:?}",
+            input, source, matched, evaluated
+        ),
+    }
+});
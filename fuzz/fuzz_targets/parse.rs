@@ -0,0 +1,12 @@
+//! Arbitrary text, as a query: parsing and compiling it may fail, but mustn't panic.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use strql::{parser, CompiledQuery};
+
+fuzz_target!(|query: &str| {
+    if let Ok(program) = parser::parse(query) {
+        let _ = CompiledQuery::new(&program);
+    }
+});
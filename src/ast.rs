@@ -1,8 +1,54 @@
+use crate::visit::{walk_pattern, Visitor};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::ops::Range;
 
+#[derive(Debug, Clone)]
 pub struct Program {
     pub statements: Vec<Statement>,
+    pub options: QueryOptions,
+    /// the query text the program was parsed from, shown in errors about the query; empty
+    /// for programs built by hand
+    pub source: String,
+}
+
+/// Query-wide settings declared with `OPTION <NAME>` directives.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct QueryOptions {
+    /// `OPTION NORMALIZE_CRLF`: turn every `\r\n` of the input into `\n`
+    pub normalize_crlf: bool,
+    /// `OPTION TRIM_TRAILING_WHITESPACE`: strip whitespace at the end of every input line
+    pub trim_trailing_whitespace: bool,
+    /// `OPTION NFC`: apply unicode canonical composition to the input
+    pub nfc: bool,
+    /// `OPTION ANYCASE`: match the whole query as if it were wrapped in `ANYCASE`
+    pub anycase: bool,
+    /// `OPTION REORDER_ALTERNATIVES`: try the alternatives of an `OR` whose matches start
+    /// differently narrowest first, and only the one that can start where it is matched
+    pub reorder_alternatives: bool,
+    /// `OPTION CAPTURE_RAW [AS <field>]`: store the whole text `TEXT` matched under this field
+    /// of the output (`_raw` by default)
+    pub capture_raw: Option<String>,
+    /// `OPTION STRATEGY <PEG|CFG>`: how the parses of an input are chosen between, `None` (the
+    /// default, `CFG`) when the query doesn't set it
+    pub strategy: Option<Strategy>,
+    /// `OPTION SKIP_UNMATCHED`: the items of `SPLITBY`s that don't match are output under
+    /// `ROOT._unmatched[]` instead of failing the evaluation
+    pub skip_unmatched: bool,
+}
+
+/// How the solver chooses between the parses of an input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Strategy {
+    /// `CFG`: every parse counts, and inputs with several equally preferred ones are ambiguous
+    #[default]
+    Cfg,
+    /// `PEG`: repetitions without a bias are `GREEDY`, `OR`s without one are `FIRST OR`, and
+    /// of equally preferred parses the first found is taken, so no input is ambiguous
+    Peg,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -14,14 +60,22 @@ pub struct Spanned<T> {
 /// `<name> = <pattern> -> <capture>`
 ///
 /// example: `TEXT = ln SPLITBY NEWLINE -> ADD item{} TO ROOT.items[]`
+#[derive(Debug, Clone)]
 pub struct Statement {
     pub name: String,
     pub pattern: Pattern,
     pub capture: Option<CaptureClause>,
     pub span: Range<usize>,
+    /// text of the `///` comments attached to the statement, one line per comment
+    pub doc: Option<String>,
+    /// `<name> |= <pattern>`: adds `pattern` as an alternative of the statement `name` of a
+    /// program this one gets merged into (extensions of earlier statements of the same query
+    /// are applied by the parser)
+    pub extends: bool,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum QuantifierBias {
     #[default]
     Neutral,
@@ -29,6 +83,21 @@ pub enum QuantifierBias {
     Lazy,
 }
 
+/// `FIRST OR`, `LONGEST OR`: which alternative an `OR` prefers where several lead to a match.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AlternationBias {
+    #[default]
+    Neutral,
+    /// the one written first, like the ordered choice of a PEG
+    First,
+    /// the one matching the most text
+    Longest,
+    /// `"ERROR" WEIGHT 3 OR "ERR" WEIGHT 1`: the weight of each alternative, of which parses
+    /// prefer the most in total
+    Weighted(Vec<u32>),
+}
+
 pub type Pattern = Spanned<PatternKind>;
 
 #[derive(PartialEq, Clone, Debug)]
@@ -36,8 +105,19 @@ pub enum PatternKind {
     Literal(String),
     Variable(String),
     Builtin(Builtin),
+    /// `DICT(name)`: any word of the word list registered as `name`
+    Dict(String),
     Sequence(Vec<Pattern>),
-    OrChain(Vec<Pattern>),
+    /// `PERMUTE(a, b, c)`: each of the patterns once, in any order
+    Permute(Vec<Pattern>),
+    /// `FIELDS { a = ...; b = ... }`: each of the fields at most once, in any order, where
+    /// the fields that can match empty text may be left out. The fields are the statements
+    /// they are inlined into
+    Fields(Vec<Pattern>),
+    OrChain {
+        alternatives: Vec<Pattern>,
+        bias: AlternationBias,
+    },
     Repetition {
         min: Bound,
         max: Bound,
@@ -48,11 +128,19 @@ pub enum PatternKind {
     Upper(Box<Pattern>),
     Lower(Box<Pattern>),
     Group(Box<Pattern>),
+    /// `PRECEDEDBY <pattern>`: matches no text, where the text right before ends a match of
+    /// the pattern, which must match a fixed number of characters
+    PrecededBy(Box<Pattern>),
+    /// stands in for a `SPLITBY` item that doesn't match, with `OPTION SKIP_UNMATCHED`: the
+    /// text up to the next match of the separator, the pattern, which is output under
+    /// `ROOT._unmatched[]`
+    Unmatched(Box<Pattern>),
 }
 
 pub type Bound = Option<usize>;
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Builtin {
     Digit,
     Letter,
@@ -60,37 +148,126 @@ pub enum Builtin {
     Newline,
     Space,
     Line, // other multichar builtins like words are missing because they're easy to desugar
+    /// lines up to (not including) the next blank line
+    Paragraph,
+    /// a newline and the line after it, which starts with a space or a tab
+    ContLine,
+    /// `BYTE(0x0A)`: one byte of the given value
+    Byte(u8),
+    /// `BYTES(n)`: any `n` bytes
+    Bytes(usize),
+    /// a whole run of base64 (or base64url) characters, with a valid length and padding
+    Base64,
+    /// a whole run of an even number of hex digits
+    HexBlob,
+    /// a complete JSON value: an object, array, string, number, `true`, `false` or `null`
+    JsonValue,
+    /// no text, at the start of the input
+    Bof,
+    /// no text, at the end of the input
+    Eof,
+    HexDigit,
+    BinDigit,
+    OctDigit,
 }
 
 /// `ADD <name><{} if is_object> TO <path>`
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct CaptureClause {
     pub name: String,
     /// distinguishes between `ADD item{} TO ROOT.items[]` and `ADD item TO ROOT.items[]`
     pub is_object: bool,
     pub path: CapturePath,
+    /// `ADD UPPER(code) TO ROOT`: changes the case of the captured text, whatever the input case
+    pub case: Option<CaptureCase>,
+    /// `ADD code AS INT TO ROOT`: converts the captured text before storing it
+    pub cast: Option<CaptureCast>,
+    /// `GROUP item{} BY category TO ROOT.groups`: the field of `item` its objects are bucketed by
+    pub group_by: Option<String>,
+    /// `ADD tag TO ROOT.tags[] UNIQUE`: skip values already in the array
+    pub unique: bool,
+    /// `ADD score TO ROOT.scores[] SORTED [DESC]`: order the array once all captures are in
+    pub sort: Option<SortOrder>,
+    /// `ADD TO item.note NULLABLE`: store `null` when the value is empty or never captured
+    pub nullable: bool,
+    /// `ADD TO ROOT.name ONCE`: fail instead of overwriting the field when it is written again
+    pub once: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SortOrder {
+    Ascending,
+    Descending,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CaptureCase {
+    Upper,
+    Lower,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CaptureCast {
+    /// `AS INT` or `AS INT(<radix>)`. Without a radix, `0x`/`0b`/`0o` prefixes pick one and
+    /// unprefixed values are decimal, unless the capture is a `HEXNUM`, `BINNUM` or `OCTNUM`.
+    Int { radix: Option<u32> },
+    /// `AS DECODED`: the text encoded by the captured `BASE64` or `HEXBLOB` run
+    Decoded { encoding: Encoding },
+    /// `AS JSON`: the JSON value the captured text spells
+    Json,
+    /// `AS DATETIME("<format>")`, or `AS EPOCH("<format>")` for Unix timestamps: the date and
+    /// time read with a strftime-like format (needs the `datetime` feature)
+    DateTime { format: String, epoch: bool },
+    /// `AS NUMBER` or `AS NUMBER(locale = "de")`: a JSON number, read with the digit grouping
+    /// and decimal mark of the locale if given
+    Number { locale: Option<String> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Encoding {
+    Base64,
+    Hex,
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct CapturePath {
     pub segments: Vec<PathSegment>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum PathSegment {
     Root,
     /// `ROOT.field`
     Field(String),
-    /// `ROOT.field[key]`
-    DynamicField(String),
+    /// `ROOT.field[key]`, or a compound key like `ROOT.field[host "_" port]`
+    DynamicField(Vec<KeyPart>),
     /// `ROOT.arr[]`
     ArrayAppend,
 }
 
+/// One piece of a dynamic field key; the pieces are concatenated in order.
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum KeyPart {
+    /// the captured value of a variable
+    Variable(String),
+    /// `"_"`
+    Literal(String),
+}
+
 impl Program {
     pub fn new() -> Self {
         Self {
             statements: Vec::new(),
+            options: QueryOptions::default(),
+            source: String::new(),
         }
     }
 
@@ -116,29 +293,91 @@ impl Pattern {
         matches!(self.node, PatternKind::Variable(_))
     }
     pub fn variables(&self) -> Vec<&str> {
-        let mut vars = Vec::new();
-        self.node.collect_variables(&mut vars);
-        vars
+        let mut vars = Variables(Vec::new());
+        vars.visit_pattern(self);
+        vars.0
+    }
+
+    /// The radix of the digits when the pattern is the desugared `HEXNUM`, `BINNUM` or `OCTNUM`:
+    /// an optional prefix followed by a run of digits.
+    pub fn number_radix(&self) -> Option<u32> {
+        let PatternKind::Sequence(parts) = &self.node else {
+            return match &self.node {
+                PatternKind::Group(inner) => inner.number_radix(),
+                _ => None,
+            };
+        };
+        let [prefix, digits] = parts.as_slice() else {
+            return None;
+        };
+        let PatternKind::Repetition {
+            min: Some(0),
+            max: Some(1),
+            pattern: prefix,
+            ..
+        } = &prefix.node
+        else {
+            return None;
+        };
+        let PatternKind::Repetition {
+            min: Some(1),
+            max: None,
+            pattern: digits,
+            ..
+        } = &digits.node
+        else {
+            return None;
+        };
+        let (radix, expected) = match digits.node {
+            PatternKind::Builtin(Builtin::HexDigit) => (16, "0x"),
+            PatternKind::Builtin(Builtin::BinDigit) => (2, "0b"),
+            PatternKind::Builtin(Builtin::OctDigit) => (8, "0o"),
+            _ => return None,
+        };
+        let PatternKind::OrChain { alternatives, .. } = &prefix.node else {
+            return None;
+        };
+        alternatives
+            .iter()
+            .all(|alt| matches!(&alt.node, PatternKind::Literal(p) if p.eq_ignore_ascii_case(expected)))
+            .then_some(radix)
+    }
+}
+
+/// Collects the variable names referenced by a pattern, in order.
+struct Variables<'ast>(Vec<&'ast str>);
+
+impl<'ast> Visitor<'ast> for Variables<'ast> {
+    fn visit_pattern(&mut self, pattern: &'ast Pattern) {
+        if let PatternKind::Variable(name) = &pattern.node {
+            self.0.push(name);
+        }
+        walk_pattern(self, pattern);
+    }
+}
+
+impl CaptureCase {
+    pub fn apply(self, value: &str) -> String {
+        match self {
+            CaptureCase::Upper => value.to_uppercase(),
+            CaptureCase::Lower => value.to_lowercase(),
+        }
     }
 }
 
-impl PatternKind {
-    fn collect_variables<'a>(&'a self, vars: &mut Vec<&'a str>) {
+impl CaptureCast {
+    pub fn describe(&self) -> &'static str {
         match self {
-            PatternKind::Variable(name) => vars.push(name),
-            PatternKind::Sequence(patterns) | PatternKind::OrChain(patterns) => {
-                for p in patterns {
-                    p.node.collect_variables(vars);
-                }
-            }
-            PatternKind::Repetition { pattern, .. } => pattern.node.collect_variables(vars),
-            PatternKind::AnyCase(p)
-            | PatternKind::Upper(p)
-            | PatternKind::Lower(p)
-            | PatternKind::Group(p) => {
-                p.node.collect_variables(vars);
-            }
-            PatternKind::Literal(_) | PatternKind::Builtin(_) => {}
+            CaptureCast::Int { .. } => "an integer",
+            CaptureCast::Decoded {
+                encoding: Encoding::Base64,
+            } => "base64-encoded text",
+            CaptureCast::Decoded {
+                encoding: Encoding::Hex,
+            } => "hex-encoded text",
+            CaptureCast::Json => "JSON",
+            CaptureCast::DateTime { .. } => "a date in the given format",
+            CaptureCast::Number { .. } => "a number",
         }
     }
 }
@@ -154,7 +393,10 @@ impl CapturePath {
         self
     }
     pub fn add_dynamic_field(mut self, var: impl Into<String>) -> Self {
-        self.segments.push(PathSegment::DynamicField(var.into()));
+        self.segments
+            .push(PathSegment::DynamicField(vec![KeyPart::Variable(
+                var.into(),
+            )]));
         self
     }
     pub fn add_array_append(mut self) -> Self {
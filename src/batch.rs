@@ -0,0 +1,64 @@
+use crate::error::{StrqlError, StrqlResult};
+use crate::solver::CompiledQuery;
+use rayon::prelude::*;
+use serde_json::Value;
+
+// Workers share one compiled query and each build their own solver.
+const _: () = {
+    const fn assert_sync<T: Sync>() {}
+    assert_sync::<CompiledQuery>();
+};
+
+impl CompiledQuery {
+    /// Evaluates every input in parallel, returning one result per input in the same order.
+    ///
+    /// `parallelism` is the number of worker threads; `0` uses one per available core.
+    pub fn evaluate_batch(
+        &self,
+        inputs: &[&str],
+        parallelism: usize,
+    ) -> StrqlResult<Vec<StrqlResult<Value>>> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(parallelism)
+            .build()
+            .map_err(|_| StrqlError::Internal {
+                _message: "could not start the batch evaluation thread pool",
+            })?;
+        Ok(pool.install(|| {
+            inputs
+                .par_iter()
+                .map(|input| self.evaluate(input))
+                .collect()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn batch_preserves_input_order() {
+        let query = CompiledQuery::compile("TEXT = 1..N DIGIT -> ADD TO ROOT.num").unwrap();
+        let inputs: Vec<String> = (0..200).map(|i| i.to_string()).collect();
+        let inputs: Vec<&str> = inputs.iter().map(String::as_str).collect();
+
+        let results = query.evaluate_batch(&inputs, 4).unwrap();
+
+        assert_eq!(results.len(), inputs.len());
+        for (input, result) in inputs.iter().zip(&results) {
+            assert_eq!(result.as_ref().unwrap(), &json!({ "num": input }));
+        }
+    }
+
+    #[test]
+    fn batch_reports_failures_per_input() {
+        let query = CompiledQuery::compile("TEXT = 1..N DIGIT").unwrap();
+        let results = query.evaluate_batch(&["12", "ab", "3"], 0).unwrap();
+
+        assert!(results[0].is_ok());
+        assert!(matches!(results[1], Err(StrqlError::PatternNoMatch { .. })));
+        assert!(results[2].is_ok());
+    }
+}
@@ -0,0 +1,67 @@
+//! Byte inputs are matched as text with one character per byte (that is, decoded as Latin-1),
+//! so the solver can keep indexing `&str`s; captures are turned back into bytes afterwards.
+
+use serde_json::Value;
+
+/// The text holding the character `U+00XX` for every byte `0xXX` of `input`.
+pub(crate) fn decode(input: &[u8]) -> String {
+    input.iter().map(|&byte| char::from(byte)).collect()
+}
+
+/// Turns the captured strings of an output built from [`decode`]d text back into the bytes
+/// they were matched from: strings when those are valid UTF-8, arrays of bytes otherwise.
+pub(crate) fn encode_captures(value: &mut Value) {
+    match value {
+        Value::String(text) => {
+            // strings that aren't text from the input, like rule names, are left alone
+            if let Some(bytes) = encode(text) {
+                *value = match String::from_utf8(bytes) {
+                    Ok(text) => Value::String(text),
+                    Err(e) => e.into_bytes().into_iter().map(Value::from).collect(),
+                };
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(encode_captures),
+        Value::Object(fields) => {
+            // keys can be captured too, but have to stay strings
+            *fields = std::mem::take(fields)
+                .into_iter()
+                .map(|(key, mut field)| {
+                    encode_captures(&mut field);
+                    let key = encode(&key)
+                        .and_then(|bytes| String::from_utf8(bytes).ok())
+                        .unwrap_or(key);
+                    (key, field)
+                })
+                .collect();
+        }
+        _ => {}
+    }
+}
+
+fn encode(text: &str) -> Option<Vec<u8>> {
+    text.chars().map(|c| u8::try_from(c).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn round_trips_captures() {
+        let input = "é\n"
+            .as_bytes()
+            .iter()
+            .chain(&[0xff, 0x00])
+            .copied()
+            .collect::<Vec<_>>();
+        let text = decode(&input);
+        assert_eq!(text.chars().count(), input.len());
+
+        let mut output =
+            json!({"text": &text[..text.len() - 4], "raw": &text[text.len() - 4..], "n": 1});
+        encode_captures(&mut output);
+        assert_eq!(output, json!({"text": "é", "raw": [10, 255, 0], "n": 1}));
+    }
+}
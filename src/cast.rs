@@ -0,0 +1,193 @@
+use crate::ast::{CaptureCast, Encoding};
+use crate::number::{parse_number, separators};
+use serde_json::Value;
+
+/// Converts captured text according to an `AS <type>` clause, or `None` if it doesn't fit.
+pub(crate) fn apply_cast(cast: &CaptureCast, value: &str) -> Option<Value> {
+    match cast {
+        CaptureCast::Int { radix } => parse_int(value, *radix).map(Value::from),
+        CaptureCast::Decoded { encoding } => {
+            let bytes = match encoding {
+                Encoding::Base64 => decode_base64(value)?,
+                Encoding::Hex => decode_hex(value)?,
+            };
+            String::from_utf8(bytes).ok().map(Value::from)
+        }
+        CaptureCast::Json => serde_json::from_str(value).ok(),
+        CaptureCast::Number {
+            locale: Some(locale),
+        } => parse_number(value, separators(locale)?),
+        CaptureCast::Number { locale: None } => match value.parse::<i64>() {
+            Ok(int) => Some(int.into()),
+            Err(_) => value
+                .parse::<f64>()
+                .ok()
+                .filter(|n| n.is_finite())
+                .map(Value::from),
+        },
+        #[cfg(feature = "datetime")]
+        CaptureCast::DateTime { format, epoch } => parse_datetime(value, format, *epoch),
+        // rejected by the parser
+        #[cfg(not(feature = "datetime"))]
+        CaptureCast::DateTime { .. } => None,
+    }
+}
+
+/// ISO-8601 text (or seconds since the Unix epoch) for the date `value`. Dates without an
+/// offset stay without one in text, and are taken as UTC for timestamps.
+#[cfg(feature = "datetime")]
+fn parse_datetime(value: &str, format: &str, epoch: bool) -> Option<Value> {
+    use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, SecondsFormat};
+
+    if let Ok(datetime) = DateTime::parse_from_str(value, format) {
+        return Some(if epoch {
+            datetime.timestamp().into()
+        } else {
+            datetime.to_rfc3339_opts(SecondsFormat::AutoSi, true).into()
+        });
+    }
+    if let Ok(datetime) = NaiveDateTime::parse_from_str(value, format) {
+        return Some(if epoch {
+            datetime.and_utc().timestamp().into()
+        } else {
+            datetime.format("%Y-%m-%dT%H:%M:%S%.f").to_string().into()
+        });
+    }
+    let date = NaiveDate::parse_from_str(value, format).ok()?;
+    Some(if epoch {
+        date.and_time(NaiveTime::MIN).and_utc().timestamp().into()
+    } else {
+        date.to_string().into()
+    })
+}
+
+/// Decodes standard or URL-safe base64, with or without padding.
+fn decode_base64(value: &str) -> Option<Vec<u8>> {
+    let digits = value.trim_end_matches('=');
+    if digits.len() % 4 == 1 || value.len() - digits.len() > 2 {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(digits.len() * 3 / 4);
+    let (mut bits, mut count) = (0u32, 0);
+    for b in digits.bytes() {
+        let sextet = match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return None,
+        };
+        bits = bits << 6 | u32::from(sextet);
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+            bits &= (1 << count) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) || !value.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&value[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn parse_int(value: &str, radix: Option<u32>) -> Option<i64> {
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let unprefixed = digits;
+    let (prefix_radix, digits) = match digits.get(..2).map(str::to_ascii_lowercase).as_deref() {
+        Some("0x") => (Some(16), &digits[2..]),
+        Some("0b") => (Some(2), &digits[2..]),
+        Some("0o") => (Some(8), &digits[2..]),
+        _ => (None, digits),
+    };
+    // a prefix of another radix is read as digits, like the `0b` of hex `0b1`
+    let (radix, digits) = match (radix, prefix_radix) {
+        (Some(r), Some(p)) if r != p => (r, unprefixed),
+        (r, p) => (r.or(p).unwrap_or(10), digits),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    let magnitude = i64::from_str_radix(digits, radix).ok()?;
+    Some(if negative { -magnitude } else { magnitude })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn int_radix_from_prefix() {
+        assert_eq!(parse_int("42", None), Some(42));
+        assert_eq!(parse_int("0x1F", None), Some(31));
+        assert_eq!(parse_int("0B101", None), Some(5));
+        assert_eq!(parse_int("0o17", None), Some(15));
+        assert_eq!(parse_int("-0x10", None), Some(-16));
+        assert_eq!(parse_int("ff", None), None);
+        assert_eq!(parse_int("0b1", Some(16)), Some(0xb1));
+        assert_eq!(parse_int("0x1", Some(2)), None);
+    }
+
+    #[test]
+    fn decodes_base64_and_hex() {
+        assert_eq!(decode_base64("aGVsbG8=").unwrap(), b"hello");
+        assert_eq!(decode_base64("aGVsbG8").unwrap(), b"hello");
+        assert_eq!(
+            decode_base64("eyJhbGciOiJIUzI1NiJ9").unwrap(),
+            br#"{"alg":"HS256"}"#
+        );
+        assert_eq!(decode_base64("-_8").unwrap(), [0xfb, 0xff]);
+        assert!(decode_base64("aGVsb").is_none());
+        assert!(decode_base64("a===").is_none());
+        assert_eq!(decode_hex("68690A").unwrap(), b"hi\n");
+        assert!(decode_hex("686").is_none());
+        let cast = CaptureCast::Decoded {
+            encoding: Encoding::Base64,
+        };
+        assert_eq!(apply_cast(&cast, "/w=="), None);
+    }
+
+    #[test]
+    #[cfg(feature = "datetime")]
+    fn datetimes() {
+        let apache = "%d/%b/%Y:%H:%M:%S %z";
+        assert_eq!(
+            parse_datetime("10/Oct/2000:13:55:36 -0700", apache, false).unwrap(),
+            "2000-10-10T13:55:36-07:00"
+        );
+        assert_eq!(
+            parse_datetime("10/Oct/2000:13:55:36 -0700", apache, true).unwrap(),
+            971211336
+        );
+        assert_eq!(
+            parse_datetime("2024-02-29 08:00", "%Y-%m-%d %H:%M", false).unwrap(),
+            "2024-02-29T08:00:00"
+        );
+        assert_eq!(
+            parse_datetime("1970-01-02", "%Y-%m-%d", true).unwrap(),
+            86400
+        );
+        assert_eq!(parse_datetime("2023-02-29", "%Y-%m-%d", false), None);
+    }
+
+    #[test]
+    fn int_explicit_radix() {
+        assert_eq!(parse_int("ff", Some(16)), Some(255));
+        assert_eq!(parse_int("0xff", Some(16)), Some(255));
+        assert_eq!(parse_int("0xff", Some(8)), None);
+        assert_eq!(parse_int("102", Some(2)), None);
+        assert_eq!(parse_int("0x", None), None);
+        assert_eq!(parse_int("99999999999999999999", None), None);
+    }
+}
@@ -0,0 +1,244 @@
+use crate::error::StrqlResult;
+use crate::parser::parse;
+use crate::shape::Shape;
+use std::collections::HashSet;
+use std::fmt::Write;
+
+/// Generates Rust structs (with serde derives) matching the output of the query `source`.
+///
+/// The top-level struct is named `root_name`; nested objects are named after the field
+/// holding them. Meant to be called from a build script, with the result written to
+/// `OUT_DIR` and `include!`d, then used with [`crate::evaluate_into`].
+pub fn rust_types(source: &str, root_name: &str) -> StrqlResult<String> {
+    let shape = Shape::infer(&parse(source)?);
+    let mut generator = Generator::default();
+    generator.struct_for(&shape, root_name);
+    let structs = generator.output.trim_end();
+    Ok(format!("{}\n", structs))
+}
+
+#[derive(Default)]
+struct Generator {
+    output: String,
+    used_names: HashSet<String>,
+}
+
+impl Generator {
+    /// Emits a struct for an object shape and returns its (deduplicated) name.
+    fn struct_for(&mut self, shape: &Shape, name: &str) -> String {
+        let Shape::Object { fields, additional } = shape else {
+            return self.type_for(shape, name);
+        };
+        let name = self.unique_name(name);
+
+        let mut body = String::new();
+        let mut used_fields = HashSet::new();
+        for (key, field) in fields {
+            let ident = unique_in(&mut used_fields, &field_ident(key));
+            let ty = self.field_type(field, key);
+            if ident.trim_start_matches("r#") != key {
+                writeln!(body, "    #[serde(rename = {:?})]", key).unwrap();
+            }
+            writeln!(body, "    #[serde(default)]\n    pub {}: {},", ident, ty).unwrap();
+        }
+        if let Some(additional) = additional {
+            let ty = self.type_for(additional, &format!("{}Value", name));
+            writeln!(
+                body,
+                "    #[serde(flatten)]\n    pub {}: std::collections::BTreeMap<String, {}>,",
+                unique_in(&mut used_fields, "extra"),
+                ty
+            )
+            .unwrap();
+        }
+
+        write!(
+            self.output,
+            "#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]\n\
+             pub struct {} {{\n{}}}\n\n",
+            name, body
+        )
+        .unwrap();
+        name
+    }
+
+    /// No field is guaranteed to be captured, so every field has a default for when it's missing.
+    fn field_type(&mut self, field: &Shape, key: &str) -> String {
+        match field {
+            Shape::String { .. } => "Option<String>".to_string(),
+            Shape::Integer { .. } => "Option<i64>".to_string(),
+            Shape::Number { .. } => "Option<f64>".to_string(),
+            Shape::Object { .. } => format!("Option<{}>", self.type_for(field, key)),
+            Shape::Array(_) | Shape::Any => self.type_for(field, key),
+        }
+    }
+
+    fn type_for(&mut self, shape: &Shape, name_hint: &str) -> String {
+        match shape {
+            Shape::Object { fields, additional } if fields.is_empty() => match additional {
+                Some(values) => format!(
+                    "std::collections::BTreeMap<String, {}>",
+                    self.type_for(values, &format!("{}Value", pascal_case(name_hint)))
+                ),
+                None => self.struct_for(shape, &pascal_case(name_hint)),
+            },
+            Shape::Object { .. } => self.struct_for(shape, &pascal_case(name_hint)),
+            Shape::Array(items) => format!(
+                "Vec<{}>",
+                self.type_for(items, &format!("{}Item", pascal_case(name_hint)))
+            ),
+            Shape::String { nullable: false } => "String".to_string(),
+            Shape::String { nullable: true } => "Option<String>".to_string(),
+            Shape::Integer { nullable: false } => "i64".to_string(),
+            Shape::Integer { nullable: true } => "Option<i64>".to_string(),
+            Shape::Number { nullable: false } => "f64".to_string(),
+            Shape::Number { nullable: true } => "Option<f64>".to_string(),
+            Shape::Any => "serde_json::Value".to_string(),
+        }
+    }
+
+    fn unique_name(&mut self, name: &str) -> String {
+        unique_in(&mut self.used_names, name)
+    }
+}
+
+/// `name`, or `name` with the first number from 2 that makes it not in `used`; added to `used`.
+fn unique_in(used: &mut HashSet<String>, name: &str) -> String {
+    let mut candidate = name.to_string();
+    let mut suffix = 2;
+    while !used.insert(candidate.clone()) {
+        candidate = format!("{}{}", name.trim_start_matches("r#"), suffix);
+        suffix += 1;
+    }
+    candidate
+}
+
+fn pascal_case(name: &str) -> String {
+    let pascal: String = name
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            let first = chars.next().unwrap().to_ascii_uppercase();
+            std::iter::once(first).chain(chars).collect::<String>()
+        })
+        .collect();
+    match pascal.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() => pascal,
+        _ => format!("T{}", pascal),
+    }
+}
+
+/// A snake_case field name for a JSON key; keywords are raw identifiers.
+fn field_ident(key: &str) -> String {
+    let mut ident = String::new();
+    for (i, c) in key.chars().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 && !ident.ends_with('_') {
+            ident.push('_');
+        }
+        if c.is_ascii_alphanumeric() {
+            ident.push(c.to_ascii_lowercase());
+        } else if !ident.ends_with('_') {
+            ident.push('_');
+        }
+    }
+    if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) {
+        ident.insert(0, '_');
+    }
+    if ["self", "super", "crate"].contains(&ident.as_str()) {
+        ident.push('_');
+    } else if KEYWORDS.contains(&ident.as_str()) {
+        ident.insert_str(0, "r#");
+    }
+    ident
+}
+
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn",
+    "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl", "in", "let", "loop",
+    "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return", "static",
+    "struct", "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use", "virtual",
+    "where", "while", "yield",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_nested_structs() {
+        let source = r#"
+TEXT = l SPLITBY NEWLINE
+l = members " are " kind -> ADD item{} TO ROOT.results[]
+members = member SPLITBY ", "
+member = WORD -> ADD TO item.members[]
+kind = WORD -> ADD TO item.type
+count = DIGIT -> ADD count AS INT TO ROOT.totalCount NULLABLE
+"#;
+        let expected = r#"#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ResultsItem {
+    #[serde(default)]
+    pub members: Vec<String>,
+    #[serde(default)]
+    pub r#type: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Output {
+    #[serde(default)]
+    pub results: Vec<ResultsItem>,
+    #[serde(rename = "totalCount")]
+    #[serde(default)]
+    pub total_count: Option<i64>,
+}
+"#;
+        assert_eq!(rust_types(source, "Output").unwrap(), expected);
+    }
+
+    #[test]
+    fn dynamic_keys_become_maps() {
+        let source = r#"
+TEXT = f SPLITBY ", "
+f = key ": " value
+key = WORD
+value = WORD -> ADD TO ROOT.fields[key]
+"#;
+        let generated = rust_types(source, "Fields").unwrap();
+        assert!(
+            generated.contains("pub fields: Option<std::collections::BTreeMap<String, String>>,")
+        );
+    }
+
+    #[test]
+    fn clashing_field_names_are_numbered() {
+        let source = r#"
+TEXT = a b c d
+a = "1" -> ADD TO ROOT.userId
+b = "2" -> ADD TO ROOT.user_id
+c = "3" -> ADD TO ROOT.extra
+d = key ":" value
+key = WORD
+value = WORD -> ADD TO ROOT[key]
+"#;
+        let generated = rust_types(source, "Output").unwrap();
+        for line in [
+            "    pub user_id: Option<String>,",
+            "    #[serde(rename = \"user_id\")]\n    #[serde(default)]\n    pub user_id2: Option<String>,",
+            "    pub extra: Option<String>,",
+            "    pub extra2: std::collections::BTreeMap<String, String>,",
+        ] {
+            assert!(generated.contains(line), "{}", generated);
+        }
+    }
+
+    #[test]
+    fn identifiers() {
+        assert_eq!(field_ident("fooBar"), "foo_bar");
+        assert_eq!(field_ident("a-b c"), "a_b_c");
+        assert_eq!(field_ident("1st"), "_1st");
+        assert_eq!(field_ident("match"), "r#match");
+        assert_eq!(field_ident("self"), "self_");
+        assert_eq!(pascal_case("log_entries"), "LogEntries");
+        assert_eq!(pascal_case("2x"), "T2x");
+    }
+}
@@ -0,0 +1,262 @@
+use crate::ast::{
+    AlternationBias, KeyPart, PathSegment, Pattern, PatternKind, Program, QueryOptions, Statement,
+    Strategy,
+};
+use crate::error::{query_to_named, StrqlError, StrqlResult};
+use crate::visit::{walk_pattern_mut, VisitorMut};
+use std::collections::HashSet;
+
+impl Program {
+    /// Adds the statements of `other` to this program, e.g. a base grammar plus the statements
+    /// specific to one customer, and enables the options either program sets. The statements
+    /// `other` extends with `|=` get their alternative added to the ones of this program.
+    ///
+    /// A statement both programs define is a [`StrqlError::StatementConflict`], which leaves
+    /// this program unchanged; [`Program::rename_prefix`] keeps the names of one apart. So are
+    /// programs setting different `OPTION STRATEGY`s, a [`StrqlError::StrategyConflict`].
+    pub fn merge(&mut self, other: Program) -> StrqlResult<()> {
+        let QueryOptions {
+            normalize_crlf,
+            trim_trailing_whitespace,
+            nfc,
+            anycase,
+            reorder_alternatives,
+            capture_raw,
+            strategy,
+            skip_unmatched,
+        } = other.options;
+        if let (Some(ours), Some(theirs)) = (self.options.strategy, strategy) {
+            if ours != theirs {
+                return Err(StrqlError::StrategyConflict {
+                    _ours: strategy_keyword(ours).to_string(),
+                    _theirs: strategy_keyword(theirs).to_string(),
+                });
+            }
+        }
+
+        // spans of `other` move into the joined source, so errors still show its text
+        let offset = if self.source.is_empty() || other.source.is_empty() {
+            self.source.len()
+        } else {
+            self.source.len() + 1
+        };
+        let mut source = self.source.clone();
+        if offset > source.len() {
+            source.push('\n');
+        }
+        source.push_str(&other.source);
+
+        let names: HashSet<&str> = self.statements.iter().map(|s| s.name.as_str()).collect();
+        if let Some(stmt) = other
+            .statements
+            .iter()
+            .find(|s| !s.extends && names.contains(s.name.as_str()))
+        {
+            let span = stmt.span.start + offset..stmt.span.end + offset;
+            return Err(StrqlError::StatementConflict {
+                _name: stmt.name.clone(),
+                _src: query_to_named(&source),
+                _span: span.into(),
+            });
+        }
+
+        for mut stmt in other.statements {
+            stmt.span = stmt.span.start + offset..stmt.span.end + offset;
+            ShiftSpans(offset).visit_pattern_mut(&mut stmt.pattern);
+            let base = stmt
+                .extends
+                .then(|| self.statements.iter_mut().find(|s| s.name == stmt.name))
+                .flatten();
+            match base {
+                Some(base) => base.extend_with(stmt),
+                None => self.statements.push(stmt),
+            }
+        }
+        self.source = source;
+
+        let options = &mut self.options;
+        options.normalize_crlf |= normalize_crlf;
+        options.trim_trailing_whitespace |= trim_trailing_whitespace;
+        options.nfc |= nfc;
+        options.anycase |= anycase;
+        options.reorder_alternatives |= reorder_alternatives;
+        options.skip_unmatched |= skip_unmatched;
+        options.capture_raw = options.capture_raw.take().or(capture_raw);
+        options.strategy = options.strategy.or(strategy);
+        Ok(())
+    }
+
+    /// Prepends `prefix` to the name of every statement, `TEXT` included, and to the
+    /// references to them. Names no statement defines, like custom builtins or the targets of
+    /// `|=` extensions, are kept, and so is the output: captures keep storing values under
+    /// their original names.
+    pub fn rename_prefix(&mut self, prefix: &str) {
+        let names: HashSet<String> = self
+            .statements
+            .iter()
+            .filter(|s| !s.extends)
+            .map(|s| s.name.clone())
+            .collect();
+        let mut renamer = Rename {
+            prefix,
+            names: &names,
+        };
+        for stmt in &mut self.statements {
+            // extensions keep the name of the statement they extend in another program
+            renamer.rename(&mut stmt.name);
+            renamer.visit_pattern_mut(&mut stmt.pattern);
+            let Some(capture) = &mut stmt.capture else {
+                continue;
+            };
+            for segment in &mut capture.path.segments {
+                if let PathSegment::DynamicField(parts) = segment {
+                    for part in parts {
+                        if let KeyPart::Variable(name) = part {
+                            renamer.rename(name);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// How `OPTION STRATEGY` writes a strategy.
+fn strategy_keyword(strategy: Strategy) -> &'static str {
+    match strategy {
+        Strategy::Cfg => "CFG",
+        Strategy::Peg => "PEG",
+    }
+}
+
+impl Statement {
+    /// Adds the pattern of `extension`, a `<name> |= <pattern>` statement, as the last
+    /// alternative of this one.
+    pub(crate) fn extend_with(&mut self, extension: Statement) {
+        let span = self.pattern.span.clone();
+        let (mut alternatives, bias) =
+            match std::mem::replace(&mut self.pattern.node, PatternKind::Literal(String::new())) {
+                PatternKind::OrChain { alternatives, bias } => (alternatives, bias),
+                node => (vec![Pattern { node, span }], AlternationBias::Neutral),
+            };
+        alternatives.push(extension.pattern);
+        self.pattern.node = PatternKind::OrChain { alternatives, bias };
+    }
+}
+
+struct ShiftSpans(usize);
+
+impl VisitorMut for ShiftSpans {
+    fn visit_pattern_mut(&mut self, pattern: &mut Pattern) {
+        pattern.span = pattern.span.start + self.0..pattern.span.end + self.0;
+        walk_pattern_mut(self, pattern);
+    }
+}
+
+struct Rename<'a> {
+    prefix: &'a str,
+    names: &'a HashSet<String>,
+}
+
+impl Rename<'_> {
+    fn rename(&self, name: &mut String) {
+        if self.names.contains(name.as_str()) {
+            name.insert_str(0, self.prefix);
+        }
+    }
+}
+
+impl VisitorMut for Rename<'_> {
+    fn visit_pattern_mut(&mut self, pattern: &mut Pattern) {
+        if let PatternKind::Variable(name) = &mut pattern.node {
+            self.rename(name);
+        }
+        walk_pattern_mut(self, pattern);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::Strategy;
+    use crate::error::StrqlError;
+    use crate::parser::parse;
+    use crate::CompiledQuery;
+
+    #[test]
+    fn merges_renamed_programs() {
+        let mut base =
+            parse("TEXT = date \" \" lib_TEXT\ndate = 1..N DIGIT -> ADD TO ROOT").unwrap();
+        let mut library = parse("OPTION NFC\nTEXT = item SPLITBY \",\"\nitem = WORD -> ADD TO ROOT.items[]\nkey = WORD -> ADD TO ROOT.by_key[item]").unwrap();
+        library.rename_prefix("lib_");
+        let names: Vec<_> = library.statements.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["lib_TEXT", "lib_item", "lib_key"]);
+
+        base.merge(library).unwrap();
+        assert!(base.options.nfc);
+        let query = CompiledQuery::new(&base).unwrap();
+        assert_eq!(
+            query.evaluate("20 a,b").unwrap(),
+            serde_json::json!({"date": "20", "items": ["a", "b"]})
+        );
+    }
+
+    #[test]
+    fn extends_statements_of_the_base() {
+        let mut base = parse("TEXT = sep\nsep = \", \" -> ADD TO ROOT").unwrap();
+        let layer = parse("sep |= \" / \"\nsep |= \";\"").unwrap();
+        assert!(layer.statements.iter().all(|s| s.extends));
+        assert!(matches!(
+            CompiledQuery::new(&layer),
+            Err(StrqlError::UndefinedExtension { .. })
+        ));
+
+        base.merge(layer).unwrap();
+        assert_eq!(base.statements.len(), 2);
+        let query = CompiledQuery::new(&base).unwrap();
+        assert_eq!(
+            query.evaluate(" / ").unwrap(),
+            serde_json::json!({"sep": " / "})
+        );
+        assert!(query.evaluate(";").is_ok());
+    }
+
+    #[test]
+    fn conflicts_point_into_the_merged_source() {
+        let mut base = parse("TEXT = x\nx = WORD").unwrap();
+        let other = parse("y = DIGIT\nx = LETTER").unwrap();
+        let Err(error @ StrqlError::StatementConflict { .. }) = base.merge(other) else {
+            panic!("expected a conflict");
+        };
+        let location = error.location().unwrap();
+        assert_eq!((location.line, location.column), (4, 1));
+        assert_eq!(base.statements.len(), 2);
+    }
+
+    #[test]
+    fn merges_strategies() {
+        let mut base = parse("TEXT = x x\nx = 1..N DIGIT -> ADD TO ROOT.x[]").unwrap();
+        assert!(CompiledQuery::new(&base).unwrap().evaluate("123").is_err());
+        let peg = parse("OPTION STRATEGY PEG\ny = 1..N DIGIT").unwrap();
+        base.merge(peg).unwrap();
+        assert_eq!(base.options.strategy, Some(Strategy::Peg));
+        let query = CompiledQuery::new(&base).unwrap();
+        assert!(query.evaluate("123").is_ok());
+
+        // a program that doesn't set one keeps the strategy of the other
+        base.merge(parse("z = LETTER").unwrap()).unwrap();
+        assert_eq!(base.options.strategy, Some(Strategy::Peg));
+        base.merge(parse("OPTION STRATEGY peg\nw = LETTER").unwrap())
+            .unwrap();
+
+        let cfg = parse("OPTION STRATEGY CFG\nv = LETTER").unwrap();
+        let Err(error @ StrqlError::StrategyConflict { .. }) = base.merge(cfg) else {
+            panic!("expected a conflict");
+        };
+        assert_eq!(
+            error.to_string(),
+            "The programs set different strategies, PEG and CFG"
+        );
+        assert_eq!(base.statements.len(), 5);
+        assert_eq!(base.options.strategy, Some(Strategy::Peg));
+    }
+}
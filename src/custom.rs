@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Given the input and a byte position in it, returns where a match starting there ends.
+pub type Matcher = dyn Fn(&str, usize) -> Option<usize> + Send + Sync;
+
+/// Matchers written in Rust that queries refer to by name, like builtins, and the word lists
+/// matched by `DICT(name)`.
+///
+/// Queries compiled with [`crate::CompiledQuery::compile_with`] can use any registered name
+/// where a statement could be used; statements of the same name take precedence.
+///
+/// ```
+/// use strql::{CompiledQuery, CustomBuiltins};
+///
+/// let mut builtins = CustomBuiltins::new();
+/// builtins.register("HEXBYTE", |input: &str, pos: usize| {
+///     let digits = input.as_bytes().get(pos..pos + 2)?;
+///     digits.iter().all(u8::is_ascii_hexdigit).then_some(pos + 2)
+/// });
+/// let query = CompiledQuery::compile_with("TEXT = HEXBYTE SPLITBY \":\"", &builtins).unwrap();
+/// assert!(query.matches("de:ad:be:ef").unwrap());
+/// ```
+#[derive(Clone, Default)]
+pub struct CustomBuiltins {
+    matchers: HashMap<String, Arc<Matcher>>,
+    dicts: HashMap<String, Arc<Dict>>,
+}
+
+impl CustomBuiltins {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `matcher` under `name`, replacing any matcher already registered there.
+    ///
+    /// Matchers see the whole input and return the (byte) position their match ends at,
+    /// which must be a character boundary after `pos`. Empty matches count as no match, and
+    /// case modifiers like `ANYCASE` don't apply to custom matchers.
+    pub fn register(
+        &mut self,
+        name: impl Into<String>,
+        matcher: impl Fn(&str, usize) -> Option<usize> + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.matchers.insert(name.into(), Arc::new(matcher));
+        self
+    }
+
+    /// Registers the word list `DICT(name)` matches any word of, skipping empty words.
+    pub fn add_dict<S: AsRef<str>>(
+        &mut self,
+        name: impl Into<String>,
+        words: impl IntoIterator<Item = S>,
+    ) -> &mut Self {
+        let mut dict = Dict::default();
+        for word in words {
+            dict.insert(word.as_ref());
+        }
+        self.dicts.insert(name.into(), Arc::new(dict));
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Matcher> {
+        self.matchers.get(name).map(Arc::as_ref)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.matchers.keys().map(String::as_str)
+    }
+
+    pub(crate) fn dict(&self, name: &str) -> Option<&Dict> {
+        self.dicts.get(name).map(Arc::as_ref)
+    }
+}
+
+impl fmt::Debug for CustomBuiltins {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CustomBuiltins")
+            .field("matchers", &self.matchers.keys().collect::<Vec<_>>())
+            .field("dicts", &self.dicts.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// A word list, as a trie over the bytes of its words.
+#[derive(Debug)]
+pub(crate) struct Dict {
+    nodes: Vec<TrieNode>,
+}
+
+#[derive(Debug, Default)]
+struct TrieNode {
+    /// sorted by byte
+    children: Vec<(u8, usize)>,
+    /// whether a word ends here
+    word: bool,
+}
+
+impl Default for Dict {
+    fn default() -> Self {
+        Dict {
+            nodes: vec![TrieNode::default()],
+        }
+    }
+}
+
+impl Dict {
+    fn insert(&mut self, word: &str) {
+        if word.is_empty() {
+            return;
+        }
+        let mut node = 0;
+        for byte in word.bytes() {
+            node = match self.child(node, byte) {
+                Some(child) => child,
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes.push(TrieNode::default());
+                    let children = &mut self.nodes[node].children;
+                    let at = children.partition_point(|&(b, _)| b < byte);
+                    children.insert(at, (byte, child));
+                    child
+                }
+            };
+        }
+        self.nodes[node].word = true;
+    }
+
+    fn child(&self, node: usize, byte: u8) -> Option<usize> {
+        let children = &self.nodes[node].children;
+        children
+            .binary_search_by_key(&byte, |&(b, _)| b)
+            .ok()
+            .map(|i| children[i].1)
+    }
+
+    /// The bytes words start with.
+    pub(crate) fn first_bytes(&self) -> impl Iterator<Item = u8> + '_ {
+        self.nodes[0].children.iter().map(|&(byte, _)| byte)
+    }
+
+    /// The positions at which the words found at `pos` end, shortest first. With `fold_case`,
+    /// ASCII letters match in either case.
+    pub(crate) fn ends(&self, input: &str, pos: usize, fold_case: bool) -> Vec<usize> {
+        let mut ends = Vec::new();
+        let mut nodes = vec![0];
+        for (i, &byte) in input.as_bytes()[pos..].iter().enumerate() {
+            let mut next = Vec::new();
+            for &node in &nodes {
+                let cases = if fold_case {
+                    [byte.to_ascii_lowercase(), byte.to_ascii_uppercase()]
+                } else {
+                    [byte, byte]
+                };
+                for child in cases.iter().filter_map(|&b| self.child(node, b)) {
+                    if !next.contains(&child) {
+                        next.push(child);
+                    }
+                }
+            }
+            if next.is_empty() {
+                break;
+            }
+            if next.iter().any(|&node| self.nodes[node].word) {
+                ends.push(pos + i + 1);
+            }
+            nodes = next;
+        }
+        ends
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dict_finds_every_word_at_a_position() {
+        let mut dict = Dict::default();
+        for word in ["New", "New York", "Newark", "", "York"] {
+            dict.insert(word);
+        }
+        let input = "New York, Newark";
+        assert_eq!(dict.ends(input, 0, false), vec![3, 8]);
+        assert_eq!(dict.ends(input, 10, false), vec![13, 16]);
+        assert_eq!(dict.ends(input, 4, false), vec![8]);
+        assert!(dict.ends(input, 1, false).is_empty());
+        assert_eq!(dict.ends("NEW YORK", 0, true), vec![3, 8]);
+        assert!(dict.ends("NEW YORK", 0, false).is_empty());
+    }
+}
@@ -0,0 +1,62 @@
+//! Differential testing against the `regex` crate, with the `differential` feature: queries
+//! that lower to a regular expression ([`Program::to_regex`]) have to accept the same inputs.
+
+use crate::ast::Program;
+use crate::error::StrqlError;
+use crate::CompiledQuery;
+use regex::Regex;
+use std::fmt;
+
+/// An input a query and its regular expression disagree on.
+#[derive(Debug)]
+pub struct Disagreement {
+    pub input: String,
+    pub regex: String,
+    /// whether the query matched the input, ambiguously or not, or why it failed to tell
+    pub strql: Result<bool, StrqlError>,
+}
+
+impl fmt::Display for Disagreement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.strql {
+            Ok(true) => write!(f, "the query matches {:?}, ", self.input)?,
+            Ok(false) => write!(f, "the query doesn't match {:?}, ", self.input)?,
+            Err(e) => write!(f, "the query fails on {:?} ({}), ", self.input, e)?,
+        }
+        match self.strql {
+            Ok(false) => write!(f, "but `{}` does", self.regex),
+            _ => write!(f, "but `{}` doesn't", self.regex),
+        }
+    }
+}
+
+impl std::error::Error for Disagreement {}
+
+/// Checks that `program` and the regular expression it lowers to agree on which of `inputs`
+/// they match. Returns whether there was anything to compare: programs that don't lower, or
+/// whose lowering or compilation fails, are skipped.
+pub fn check_against_regex<'a>(
+    program: &Program,
+    inputs: impl IntoIterator<Item = &'a str>,
+) -> Result<bool, Disagreement> {
+    let Some(pattern) = program.to_regex() else {
+        return Ok(false);
+    };
+    let (Ok(regex), Ok(query)) = (Regex::new(&pattern), CompiledQuery::new(program)) else {
+        return Ok(false);
+    };
+    for input in inputs {
+        let strql = match query.matches(input) {
+            Err(StrqlError::AmbiguousParse { .. }) => Ok(true),
+            outcome => outcome,
+        };
+        if strql.as_ref().ok() != Some(&regex.is_match(input)) {
+            return Err(Disagreement {
+                input: input.to_string(),
+                regex: pattern,
+                strql,
+            });
+        }
+    }
+    Ok(true)
+}
@@ -0,0 +1,139 @@
+//! Telling the parses of an ambiguous input apart, by trying the `LAZY` and `GREEDY` biases
+//! suggested by [`StrqlError::AmbiguousParse`](crate::error::StrqlError::AmbiguousParse).
+
+use crate::ast::{PatternKind, Program, QuantifierBias};
+use crate::error::AmbiguitySuggestion;
+use crate::visit::{walk_pattern_mut, VisitorMut};
+use crate::{CompiledQuery, CustomBuiltins, Pattern};
+use serde_json::Value;
+use std::ops::Range;
+
+/// One of the parses of an ambiguous input, and the bias that makes the query pick it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Disambiguation {
+    /// the repetition to give `bias` to
+    pub suggestion: AmbiguitySuggestion,
+    pub bias: QuantifierBias,
+    /// the output of the parse
+    pub output: Value,
+    /// the statements that captured something in the parse and what they captured, ordered by
+    /// position, outermost first
+    pub captures: Vec<(String, Range<usize>)>,
+}
+
+impl Program {
+    /// Up to `limit` distinct parses of `input`, which this program matches ambiguously with
+    /// the given `suggestions`, each found by making one of the suggested repetitions `GREEDY`
+    /// or `LAZY`. Parses that no single bias picks aren't found.
+    ///
+    /// Spans point into `input` as the `OPTION`s of the program normalize it.
+    pub fn disambiguations(
+        &self,
+        builtins: &CustomBuiltins,
+        input: &str,
+        suggestions: &[AmbiguitySuggestion],
+        limit: usize,
+    ) -> Vec<Disambiguation> {
+        let mut parses: Vec<Disambiguation> = Vec::new();
+        for suggestion in suggestions {
+            for bias in [QuantifierBias::Greedy, QuantifierBias::Lazy] {
+                if parses.len() >= limit {
+                    return parses;
+                }
+                let mut program = self.clone();
+                let mut biased = Bias {
+                    span: suggestion.span.clone(),
+                    bias,
+                };
+                biased.visit_program_mut(&mut program);
+                let Ok(query) = CompiledQuery::with_builtins(&program, builtins) else {
+                    continue;
+                };
+                // the other repetitions may still leave the input ambiguous
+                let Ok(output) = query.evaluate(input) else {
+                    continue;
+                };
+                let mut captures = Vec::new();
+                let replayed = query.evaluate_with_sink(input, |event| {
+                    captures.push((event.statement.to_string(), event.span.clone()));
+                });
+                if replayed.is_err() {
+                    continue;
+                }
+                captures.sort_by_key(|(_, span)| (span.start, std::cmp::Reverse(span.end)));
+                if parses
+                    .iter()
+                    .any(|p| p.output == output && p.captures == captures)
+                {
+                    continue;
+                }
+                parses.push(Disambiguation {
+                    suggestion: suggestion.clone(),
+                    bias,
+                    output,
+                    captures,
+                });
+            }
+        }
+        parses
+    }
+}
+
+/// Gives `bias` to the repetition at `span`.
+struct Bias {
+    span: Range<usize>,
+    bias: QuantifierBias,
+}
+
+impl VisitorMut for Bias {
+    fn visit_pattern_mut(&mut self, pattern: &mut Pattern) {
+        if pattern.span == self.span {
+            if let PatternKind::Repetition { bias, .. } = &mut pattern.node {
+                *bias = self.bias;
+            }
+        }
+        walk_pattern_mut(self, pattern);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::StrqlError;
+    use crate::parser::parse;
+    use serde_json::json;
+
+    #[test]
+    fn finds_the_parses_each_bias_picks() {
+        let program =
+            parse("TEXT = x y\nx = 1..N LETTER -> ADD TO ROOT\ny = 1..N LETTER -> ADD TO ROOT")
+                .unwrap();
+        let query = CompiledQuery::new(&program).unwrap();
+        let Err(StrqlError::AmbiguousParse { _suggestions, .. }) = query.evaluate("abcd") else {
+            panic!("expected an ambiguous parse");
+        };
+
+        let parses = program.disambiguations(&CustomBuiltins::new(), "abcd", &_suggestions, 5);
+        let outputs: Vec<_> = parses.iter().map(|p| &p.output).collect();
+        assert_eq!(
+            outputs,
+            [
+                &json!({"x": "a", "y": "bcd"}),
+                &json!({"x": "abc", "y": "d"})
+            ]
+        );
+        assert_eq!(parses[0].captures, [("x".into(), 0..1), ("y".into(), 1..4)]);
+        assert_eq!(parses[1].bias, QuantifierBias::Lazy);
+        assert_eq!(
+            &program.source[parses[1].suggestion.span.clone()],
+            "1..N LETTER"
+        );
+
+        assert_eq!(
+            program
+                .disambiguations(&CustomBuiltins::new(), "abcd", &_suggestions, 1)
+                .len(),
+            1
+        );
+    }
+}
@@ -1,198 +1,1211 @@
 #![allow(non_snake_case)]
 
-use miette::{Diagnostic, NamedSource, SourceSpan};
+use crate::line_index::{LineIndex, Location};
+#[cfg(feature = "diagnostics")]
+use miette::{Diagnostic, MietteError, MietteSpanContents, SourceCode, SpanContents};
+#[cfg(feature = "diagnostics")]
+pub use miette::{NamedSource, SourceSpan};
+use serde_json::{json, Value};
+use std::fmt;
+use std::ops::Range;
 use thiserror::Error;
 
-#[derive(Error, Diagnostic, Debug)]
+#[derive(Error, Debug)]
+#[cfg_attr(feature = "diagnostics", derive(Diagnostic))]
 pub enum StrqlError {
     #[error("Unexpected character")]
-    #[diagnostic(code(lexer::unexpected_char), help("Remove or escape this character"))]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(code(lexer::unexpected_char), help("Remove or escape this character"))
+    )]
     LexerError {
-        #[source_code]
-        _src: NamedSource<String>,
-        #[label("unexpected character here")]
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("unexpected character here"))]
         _span: SourceSpan,
     },
     #[error("Unexpected token `{_found}`)")]
-    #[diagnostic(code(parser::unexpected_token), help("Was expecting: `{_expected}`"))]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(code(parser::unexpected_token), help("Was expecting: `{_expected}`"))
+    )]
     UnexpectedToken {
         _expected: String,
         _found: String,
-        #[source_code]
-        _src: NamedSource<String>,
-        #[label("here")]
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("here"))]
         _span: SourceSpan,
     },
 
-    #[error("Unbound variable '{_name}'")]
-    #[diagnostic(
-        code(solver::unbound_variable),
-        help("Create the variable first with: [...] -> ADD {_name}{{}} TO [...]")
+    #[error("The query has {} syntax errors", _errors.len())]
+    #[cfg_attr(feature = "diagnostics", diagnostic(code(parser::syntax_errors)))]
+    SyntaxErrors {
+        /// ordered by position, each on a different line
+        #[cfg_attr(feature = "diagnostics", related)]
+        _errors: Vec<StrqlError>,
+    },
+
+    #[error("No value given for parameter '{_name}'")]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(lexer::missing_param),
+            help("Pass it with `--param {_name}=<value>`, or escape the `$` as `\\$` to match it literally")
+        )
+    )]
+    MissingParam {
+        _name: String,
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("used in this literal"))]
+        _span: SourceSpan,
+    },
+
+    #[error("Invalid escape sequence: {_reason}")]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(lexer::invalid_escape),
+            help("Escape a backslash as `\\\\` to match it literally")
+        )
+    )]
+    InvalidEscape {
+        _reason: String,
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("this escape"))]
+        _span: SourceSpan,
+    },
+
+    #[error("A documentation comment is written before {_keyword}, which it can't document")]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(parser::misplaced_doc_comment),
+            help("`///` documents the statement below it; write `//` for a plain comment")
+        )
+    )]
+    MisplacedDocComment {
+        _keyword: String,
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("documentation comment"))]
+        _span: SourceSpan,
+    },
+
+    #[error("Unknown option '{_name}'")]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(parser::unknown_option),
+            help("Supported options are NORMALIZE_CRLF, TRIM_TRAILING_WHITESPACE, NFC, ANYCASE, REORDER_ALTERNATIVES, CAPTURE_RAW, STRATEGY and SKIP_UNMATCHED")
+        )
+    )]
+    UnknownOption {
+        _name: String,
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("unknown option"))]
+        _span: SourceSpan,
+    },
+
+    #[error("'{_name}' is both a DEFINE and a statement")]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(parser::define_conflict),
+            help("Rename either the DEFINE or the statement")
+        )
+    )]
+    DefineConflict {
+        _name: String,
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("statement shadowing a DEFINE"))]
+        _span: SourceSpan,
+    },
+
+    #[error("'{_name}' is defined twice")]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(parser::duplicate_define),
+            help("Remove one of the DEFINEs, or rename it along with its uses")
+        )
+    )]
+    DuplicateDefine {
+        _name: String,
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("defined here first"))]
+        _first: SourceSpan,
+        #[cfg_attr(feature = "diagnostics", label("and again here"))]
+        _span: SourceSpan,
+    },
+
+    #[error("Statement '{_name}' is named after a builtin, which references to it would match")]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(parser::redefined_builtin),
+            help("Escape the name in backticks, like `{_name}`, to define a statement of that name, or rename it")
+        )
+    )]
+    RedefinedBuiltin {
+        _name: String,
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("shadows the builtin"))]
+        _span: SourceSpan,
+    },
+
+    #[error("Statement '{_name}' is defined by both programs")]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(compose::statement_conflict),
+            help("Rename the statements of one program, e.g. with `Program::rename_prefix`")
+        )
+    )]
+    StatementConflict {
+        _name: String,
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("defined again here"))]
+        _span: SourceSpan,
+    },
+
+    #[error("The programs set different strategies, {_ours} and {_theirs}")]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(compose::strategy_conflict),
+            help("Set the same `OPTION STRATEGY` in both programs, or leave it out of one")
+        )
+    )]
+    StrategyConflict { _ours: String, _theirs: String },
+
+    #[error("'{_name}' is extended with `|=` but never defined")]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(solver::undefined_extension),
+            help("Define `{_name} = ...` before extending it, or merge this query into one that defines it")
+        )
+    )]
+    UndefinedExtension {
+        _name: String,
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("extended here"))]
+        _span: SourceSpan,
+    },
+
+    #[error("DEFINE '{_name}' refers to itself")]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(parser::recursive_define),
+            help("Defines are substituted textually; use a statement for recursive patterns")
+        )
+    )]
+    RecursiveDefine {
+        _name: String,
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("recursive reference"))]
+        _span: SourceSpan,
+    },
+
+    #[error("Quantifier bounds {_min}..{_max} are reversed")]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(parser::invalid_quantifier_bounds),
+            help("The minimum can't be greater than the maximum: did you mean `{_max}..{_min}`?")
+        )
+    )]
+    InvalidQuantifierBounds {
+        _min: usize,
+        _max: usize,
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("minimum greater than maximum"))]
+        _span: SourceSpan,
+    },
+
+    #[error("{_count} patterns to match in any order")]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(parser::too_many_permuted),
+            help("PERMUTE and FIELDS take at most 16 patterns: group the ones that always come together")
+        )
     )]
+    TooManyPermuted {
+        _count: usize,
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("too many patterns"))]
+        _span: SourceSpan,
+    },
+
+    #[error("Statement '{_name}' can reach itself without consuming input")]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(parser::left_recursion),
+            help("Matching `{_name}` would loop forever: make it consume text before it refers back to itself")
+        )
+    )]
+    LeftRecursion {
+        _name: String,
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("can loop back to itself"))]
+        _span: SourceSpan,
+    },
+
+    #[error("PRECEDEDBY needs a pattern matching a fixed number of characters")]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(solver::variable_length_lookbehind),
+            help("Lookbehinds can only match literals, single characters and fixed repetitions of them, like `PRECEDEDBY (3..3 DIGIT \"-\")`")
+        )
+    )]
+    VariableLengthLookbehind {
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("can match text of different lengths"))]
+        _span: SourceSpan,
+    },
+
+    #[error("Unbound variable '{_name}'")]
+    #[cfg_attr(feature = "diagnostics", diagnostic(code(solver::unbound_variable)))]
     UnboundVariable {
         _name: String,
-        #[source_code]
-        _src: NamedSource<String>,
-        #[label("node '{_name}' not yet created")]
+        /// defined names close to `_name`, best first
+        _suggestions: Vec<String>,
+        #[cfg_attr(feature = "diagnostics", help)]
+        _help: String,
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("node '{_name}' not yet created"))]
         _span: SourceSpan,
     },
 
     #[error("VariableIsNotObject variable '{_name}'")]
-    #[diagnostic(
-        code(solver::variable_is_not_object),
-        help("VariableIsNotObject the variable first with: [...] -> ADD {_name}{{}} TO [...]")
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(solver::variable_is_not_object),
+            help("VariableIsNotObject the variable first with: [...] -> ADD {_name}{{}} TO [...]")
+        )
     )]
     VariableTypeMismatch {
         _name: String,
         _expected: String,
-        #[source_code]
-        _src: NamedSource<String>,
-        #[label("node '{_name}' not yet created")]
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("node '{_name}' not yet created"))]
         _span: SourceSpan,
     },
+    #[error("Unknown word list '{_name}'")]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(solver::unknown_dict),
+            help("Register it with `CustomBuiltins::add_dict`, or `--dict {_name}=<file>` on the command line")
+        )
+    )]
+    UnknownDict {
+        _name: String,
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("no such word list"))]
+        _span: SourceSpan,
+    },
+
+    #[error("Unknown grammar '{_name}'")]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(code(parser::unknown_import), help("{_help}"))
+    )]
+    UnknownImport {
+        _name: String,
+        _help: String,
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("no such grammar"))]
+        _span: SourceSpan,
+    },
+
+    #[error("Grammar '{_name}' needs the `{_feature}` feature")]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(parser::import_needs_feature),
+            help("Build strql with `--features {_feature}` to import it")
+        )
+    )]
+    ImportNeedsFeature {
+        _name: String,
+        _feature: String,
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("not built in"))]
+        _span: SourceSpan,
+    },
+
+    #[error("Custom builtin '{_name}' is not registered")]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(solver::unregistered_builtin),
+            help("Compile the query with `CompiledQuery::compile_with` and the builtins it uses")
+        )
+    )]
+    UnregisteredBuiltin { _name: String },
+
+    #[error("Custom builtin '{_name}' matched from {_pos} to {_end}")]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(solver::invalid_builtin_match),
+            help("Matchers must return a character boundary of the input after the position they are given")
+        )
+    )]
+    InvalidBuiltinMatch {
+        _name: String,
+        _pos: usize,
+        _end: usize,
+    },
+
+    #[error("Invalid compiled query: {_message}")]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(compiled::invalid_bytes),
+            help("Recompile the query with the current version of strql")
+        )
+    )]
+    InvalidCompiledQuery { _message: String },
+
+    #[error("Query output does not fit the requested type: {_message}")]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(output::mismatch),
+            help("Regenerate the output types from the current version of the query")
+        )
+    )]
+    OutputMismatch { _message: String },
+
     #[error("Internal error: {_message}")]
-    #[diagnostic(code(internal), help("Please open a github issue about this!"))]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(code(internal), help("Please open a github issue about this!"))
+    )]
     Internal { _message: &'static str },
 
     // ========== Solver Errors (input-level) ==========
     #[error("Input does not match the pattern")]
-    #[diagnostic(code(solver::no_match), help("The statements do not match the input"))]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(code(solver::no_match), help("The statements do not match the input"))
+    )]
     PatternNoMatch {
-        #[source_code]
-        _src: NamedSource<String>,
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
     },
 
-    #[error("Input text ambiguously matches the pattern")]
-    #[diagnostic(
-        code(solver::ambiguous),
-        help("Add LAZY or GREEDY disambiguators to refine your statement set")
-    )]
+    #[error("Input text ambiguously matches the pattern ({_parses} equally preferred parses)")]
+    #[cfg_attr(feature = "diagnostics", diagnostic(code(solver::ambiguous)))]
     AmbiguousParse {
-        #[source_code]
-        _src: NamedSource<String>,
+        _parses: ParseCount,
+        /// the parts of the input the parses disagree on, ordered by position
+        _branches: Vec<AmbiguityBranch>,
+        /// the repetitions that could be given a bias to tell the parses apart
+        _suggestions: Vec<AmbiguitySuggestion>,
+        #[cfg_attr(feature = "diagnostics", help)]
+        _help: String,
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("parses branch here"))]
+        _span: Option<SourceSpan>,
+        /// where the suggested repetitions are in the query
+        #[cfg_attr(feature = "diagnostics", related)]
+        _notes: Vec<QueryNote>,
     },
 
     #[error("Expected literal \"{_expected}\"")]
-    #[diagnostic(code(solver::literal_mismatch), help("Found \"{_found}\" instead"))]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(code(solver::literal_mismatch), help("Found \"{_found}\" instead"))
+    )]
     LiteralMismatch {
         _expected: String,
         _found: String,
-        #[source_code]
-        _src: NamedSource<String>,
-        #[label("mismatch here")]
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("mismatch here"))]
         _span: SourceSpan,
     },
 
     #[error("Expected {_expected}")]
-    #[diagnostic(
-        code(solver::builtin_mismatch),
-        help("Found '{_found}' which is not a valid {_expected}")
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(solver::builtin_mismatch),
+            help("Found '{_found}' which is not a valid {_expected}")
+        )
     )]
     BuiltinMismatch {
         _expected: &'static str,
         _found: String,
-        #[source_code]
-        _src: NamedSource<String>,
-        #[label("here")]
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("here"))]
         _span: SourceSpan,
     },
 
     #[error("Unexpected end of input")]
-    #[diagnostic(
-        code(solver::unexpected_eof),
-        help("Expected {_expected} but input ended")
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(solver::unexpected_eof),
+            help("Expected {_expected} but input ended")
+        )
     )]
     UnexpectedEndOfInput {
         _expected: &'static str,
-        #[source_code]
-        _src: NamedSource<String>,
-        #[label("input ends here")]
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("input ends here"))]
         _span: SourceSpan,
     },
 
     #[error("No alternative matched")]
-    #[diagnostic(
-        code(solver::no_alternative),
-        help("None of the OR alternatives matched the input at this position")
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(solver::no_alternative),
+            help("None of the OR alternatives matched the input at this position")
+        )
     )]
     NoAlternativeMatched {
-        #[source_code]
-        _src: NamedSource<String>,
-        #[label("no alternative matches here")]
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("no alternative matches here"))]
         _span: SourceSpan,
     },
 
     #[error("Pattern matched only {_matched} of {_total} bytes")]
-    #[diagnostic(
-        code(solver::partial_match),
-        help("Extend your statement set to match the missing portion of the text")
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(solver::partial_match),
+            help("Extend your statement set to match the missing portion of the text")
+        )
     )]
     PartialMatch {
         _matched: usize,
         _total: usize,
-        #[source_code]
-        _src: NamedSource<String>,
-        #[label("unmatched portion starts here")]
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("unmatched portion starts here"))]
         _span: SourceSpan,
     },
 
     #[error("Quantifier requires at least {_min} repetitions, found {_found}")]
-    #[diagnostic(
-        code(solver::quantifier_min),
-        help("The pattern needs to repeat at least {_min} times")
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(solver::quantifier_min),
+            help("The pattern needs to repeat at least {_min} times")
+        )
     )]
     QuantifierMinNotMet {
         _min: usize,
         _found: usize,
-        #[source_code]
-        _src: NamedSource<String>,
-        #[label("quantifier failed here")]
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("quantifier failed here"))]
         _span: SourceSpan,
     },
 
     #[error("Constraint not satisfied")]
-    #[diagnostic(code(solver::constraint_failed))]
+    #[cfg_attr(feature = "diagnostics", diagnostic(code(solver::constraint_failed)))]
     ConstraintFailed {
-        #[source_code]
-        _src: NamedSource<String>,
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
     },
 
     #[error("Variable '{_name}' is not numeric")]
-    #[diagnostic(
-        code(solver::not_numeric),
-        help("Value \"{_value}\" cannot be used in numeric comparison")
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(solver::not_numeric),
+            help("Value \"{_value}\" cannot be used in numeric comparison")
+        )
     )]
     VariableNotNumeric {
         _name: String,
         _value: String,
-        #[source_code]
-        _src: NamedSource<String>,
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+    },
+    #[error("Captured value of '{_name}' is not {_target}")]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(solver::cast_failed),
+            help("Value \"{_value}\" cannot be converted; check the pattern or the `AS` clause")
+        )
+    )]
+    CaptureCastFailed {
+        _name: String,
+        _value: String,
+        _target: &'static str,
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("captured here"))]
+        _span: SourceSpan,
+    },
+    #[error("Field {_path} is captured twice, but is captured ONCE")]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(solver::captured_twice),
+            help("Only one match should be added to the field: check which statements add to it")
+        )
+    )]
+    CapturedTwice {
+        _path: String,
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
+        #[cfg_attr(feature = "diagnostics", label("captured here first"))]
+        _first: SourceSpan,
+        #[cfg_attr(feature = "diagnostics", label("and again here"))]
+        _span: SourceSpan,
+    },
+    #[error("Evaluation was cancelled")]
+    #[cfg_attr(feature = "diagnostics", diagnostic(code(solver::cancelled)))]
+    Cancelled,
+    #[error(
+        "Only {_matched} of {_total} records matched, fewer than the minimum ratio of {_min_ratio}"
+    )]
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(solver::too_few_records_matched),
+            help("Check the records that don't match, or lower the minimum ratio")
+        )
+    )]
+    TooFewRecordsMatched {
+        _matched: usize,
+        _total: usize,
+        _min_ratio: f64,
     },
+
     #[error("No TEXT statement given")]
-    #[diagnostic(
-        code(solver::no_text_statement),
-        help("Add a `TEXT = <expression>` statement to give the query an entry point")
+    #[cfg_attr(
+        feature = "diagnostics",
+        diagnostic(
+            code(solver::no_text_statement),
+            help("Add a `TEXT = <expression>` statement to give the query an entry point")
+        )
     )]
     NoTextStatement {
-        #[source_code]
-        _src: NamedSource<String>,
+        #[cfg_attr(feature = "diagnostics", source_code)]
+        _src: NamedSource<SourceWindow>,
     },
 }
 
+impl StrqlError {
+    /// The category of the error, which stays the same when variants and their fields change.
+    pub fn kind(&self) -> ErrorKind {
+        use StrqlError::*;
+        match self {
+            LexerError { .. }
+            | UnexpectedToken { .. }
+            | SyntaxErrors { .. }
+            | MissingParam { .. }
+            | InvalidEscape { .. }
+            | MisplacedDocComment { .. }
+            | UnknownOption { .. } => ErrorKind::Syntax,
+            DefineConflict { .. }
+            | DuplicateDefine { .. }
+            | RedefinedBuiltin { .. }
+            | StatementConflict { .. }
+            | StrategyConflict { .. }
+            | UndefinedExtension { .. }
+            | RecursiveDefine { .. }
+            | InvalidQuantifierBounds { .. }
+            | TooManyPermuted { .. }
+            | LeftRecursion { .. }
+            | VariableLengthLookbehind { .. }
+            | UnboundVariable { .. }
+            | VariableTypeMismatch { .. }
+            | UnknownDict { .. }
+            | UnknownImport { .. }
+            | ImportNeedsFeature { .. }
+            | NoTextStatement { .. } => ErrorKind::Query,
+            UnregisteredBuiltin { .. }
+            | InvalidBuiltinMatch { .. }
+            | InvalidCompiledQuery { .. }
+            | OutputMismatch { .. } => ErrorKind::Usage,
+            PatternNoMatch { .. }
+            | LiteralMismatch { .. }
+            | BuiltinMismatch { .. }
+            | UnexpectedEndOfInput { .. }
+            | NoAlternativeMatched { .. }
+            | PartialMatch { .. }
+            | QuantifierMinNotMet { .. }
+            | ConstraintFailed { .. }
+            | TooFewRecordsMatched { .. } => ErrorKind::NoMatch,
+            AmbiguousParse { .. } => ErrorKind::Ambiguous,
+            VariableNotNumeric { .. } | CaptureCastFailed { .. } | CapturedTwice { .. } => {
+                ErrorKind::Capture
+            }
+            Cancelled => ErrorKind::Cancelled,
+            Internal { .. } => ErrorKind::Internal,
+        }
+    }
+
+    /// The byte ranges the error points to: in the query for errors about the query, in the
+    /// input for errors about the input (where an ambiguous parse branches, for those, and both
+    /// captures of a field captured twice).
+    pub fn spans(&self) -> Vec<Range<usize>> {
+        match self {
+            StrqlError::SyntaxErrors { _errors } => {
+                _errors.iter().flat_map(|e| e.spans()).collect()
+            }
+            StrqlError::AmbiguousParse { _branches, .. } if !_branches.is_empty() => {
+                _branches.iter().map(|branch| branch.span.clone()).collect()
+            }
+            StrqlError::CapturedTwice { _first, _span, .. }
+            | StrqlError::DuplicateDefine { _first, _span, .. } => [_first, _span]
+                .iter()
+                .map(|span| span.offset()..span.offset() + span.len())
+                .collect(),
+            _ => self
+                .labeled()
+                .map(|(_, span)| span.offset()..span.offset() + span.len())
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    /// How far into the input matching got, for the errors of inputs that don't match or
+    /// match ambiguously.
+    pub fn input_offset(&self) -> Option<usize> {
+        match self {
+            StrqlError::PatternNoMatch { .. } => Some(0),
+            _ if matches!(self.kind(), ErrorKind::NoMatch | ErrorKind::Ambiguous) => {
+                self.spans().first().map(|span| span.start)
+            }
+            _ => None,
+        }
+    }
+
+    /// Where the error points to in its source (the query or the input), if anywhere.
+    pub fn location(&self) -> Option<Location> {
+        match self {
+            StrqlError::SyntaxErrors { _errors } => _errors.first()?.location(),
+            _ => {
+                let (src, span) = self.labeled()?;
+                src.inner().locate(span.offset())
+            }
+        }
+    }
+
+    /// The error as a JSON object, for tools reading errors like outputs: its `kind` (see
+    /// [`ErrorKind::name`]) and `message`, and the `span`, `line` and `column` it points to,
+    /// if it points anywhere.
+    pub fn to_json(&self) -> Value {
+        let mut error = json!({
+            "kind": self.kind().name(),
+            "message": self.to_string(),
+        });
+        if let Some(span) = self.spans().first() {
+            error["span"] = json!([span.start, span.end]);
+        }
+        if let Some(location) = self.location() {
+            error["line"] = json!(location.line);
+            error["column"] = json!(location.column);
+        }
+        error
+    }
+
+    /// The source and the labeled span of the errors that have one.
+    fn labeled(&self) -> Option<(&NamedSource<SourceWindow>, &SourceSpan)> {
+        use StrqlError::*;
+        match self {
+            LexerError { _src, _span }
+            | UnexpectedToken { _src, _span, .. }
+            | UnknownOption { _src, _span, .. }
+            | MissingParam { _src, _span, .. }
+            | InvalidEscape { _src, _span, .. }
+            | MisplacedDocComment { _src, _span, .. }
+            | DefineConflict { _src, _span, .. }
+            | DuplicateDefine { _src, _span, .. }
+            | RedefinedBuiltin { _src, _span, .. }
+            | StatementConflict { _src, _span, .. }
+            | UndefinedExtension { _src, _span, .. }
+            | RecursiveDefine { _src, _span, .. }
+            | InvalidQuantifierBounds { _src, _span, .. }
+            | TooManyPermuted { _src, _span, .. }
+            | LeftRecursion { _src, _span, .. }
+            | VariableLengthLookbehind { _src, _span }
+            | UnboundVariable { _src, _span, .. }
+            | UnknownDict { _src, _span, .. }
+            | UnknownImport { _src, _span, .. }
+            | ImportNeedsFeature { _src, _span, .. }
+            | VariableTypeMismatch { _src, _span, .. }
+            | LiteralMismatch { _src, _span, .. }
+            | BuiltinMismatch { _src, _span, .. }
+            | UnexpectedEndOfInput { _src, _span, .. }
+            | NoAlternativeMatched { _src, _span }
+            | PartialMatch { _src, _span, .. }
+            | QuantifierMinNotMet { _src, _span, .. }
+            | CaptureCastFailed { _src, _span, .. }
+            | CapturedTwice { _src, _span, .. }
+            | AmbiguousParse {
+                _src,
+                _span: Some(_span),
+                ..
+            } => Some((_src, _span)),
+            _ => None,
+        }
+    }
+}
+
+/// The category of a [`StrqlError`], from [`StrqlError::kind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// the query doesn't lex or parse
+    Syntax,
+    /// the query parses but can't be compiled, like one referring to undefined statements
+    Query,
+    /// the library was used wrong, like with a query compiled by another version
+    Usage,
+    /// the input doesn't match the query
+    NoMatch,
+    /// the input matches the query in several equally preferred ways
+    Ambiguous,
+    /// a captured value can't be cast or compared as the query asks
+    Capture,
+    /// the evaluation was cancelled
+    Cancelled,
+    /// a bug of strql
+    Internal,
+}
+
+impl ErrorKind {
+    /// The name of the kind in snake case, like `no_match`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorKind::Syntax => "syntax",
+            ErrorKind::Query => "query",
+            ErrorKind::Usage => "usage",
+            ErrorKind::NoMatch => "no_match",
+            ErrorKind::Ambiguous => "ambiguous",
+            ErrorKind::Capture => "capture",
+            ErrorKind::Cancelled => "cancelled",
+            ErrorKind::Internal => "internal",
+        }
+    }
+}
+
 pub type StrqlResult<T> = Result<T, StrqlError>;
 
+/// How many equally-preferred parses an ambiguous input has; counting stops at some point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseCount {
+    Exactly(usize),
+    AtLeast(usize),
+}
+
+impl fmt::Display for ParseCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseCount::Exactly(count) => write!(f, "{}", count),
+            ParseCount::AtLeast(count) => write!(f, "at least {}", count),
+        }
+    }
+}
+
+/// A repetition without `LAZY` or `GREEDY` that the parses of an ambiguous input disagree on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AmbiguitySuggestion {
+    pub statement: String,
+    /// byte range of the repetition in the query
+    pub span: Range<usize>,
+}
+
+/// A part of the input that a statement matches in several equally-preferred ways.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AmbiguityBranch {
+    pub statement: String,
+    /// byte range of the input
+    pub span: Range<usize>,
+}
+
 pub trait NamedSourceExt<'a> {
     fn src(&self) -> &'a str;
     fn source_name(&self) -> &str {
         "strql"
     }
 
-    fn src_to_named(&self) -> NamedSource<String> {
-        NamedSource::new(self.source_name(), self.src().to_string())
+    fn src_to_named(&self) -> NamedSource<SourceWindow> {
+        NamedSource::new(self.source_name(), SourceWindow::full(self.src()))
+    }
+
+    /// Like `src_to_named`, but only keeps the lines around `span`.
+    fn src_window(&self, span: Range<usize>) -> NamedSource<SourceWindow> {
+        NamedSource::new(self.source_name(), SourceWindow::around(self.src(), span))
     }
 }
 
 /// Create a NamedSource for input text (used by solver for error reporting)
-pub fn input_to_named(input: &str) -> NamedSource<String> {
-    NamedSource::new("input", input.to_string())
+pub fn input_to_named(input: &str) -> NamedSource<SourceWindow> {
+    NamedSource::new("input", SourceWindow::full(input))
+}
+
+/// Create a NamedSource for query text (used by solver for errors about the query itself)
+pub fn query_to_named(query: &str) -> NamedSource<SourceWindow> {
+    NamedSource::new("strql", SourceWindow::full(query))
+}
+
+/// A part of the query that an error about the input points back to.
+#[derive(Error, Debug)]
+#[cfg_attr(feature = "diagnostics", derive(Diagnostic))]
+#[error("{_message}")]
+#[cfg_attr(feature = "diagnostics", diagnostic(severity(Advice)))]
+pub struct QueryNote {
+    pub _message: String,
+    #[cfg_attr(feature = "diagnostics", source_code)]
+    pub _src: NamedSource<SourceWindow>,
+    #[cfg_attr(feature = "diagnostics", label)]
+    pub _span: SourceSpan,
+}
+
+/// Lines of context kept on each side of a windowed span.
+const WINDOW_CONTEXT_LINES: usize = 2;
+/// Bytes of context kept on each side of a windowed span, for texts with very long lines.
+const WINDOW_CONTEXT_BYTES: usize = 256;
+
+/// The text attached to a diagnostic: either a whole source or a window of it.
+///
+/// Inputs can be arbitrarily large, so errors pointing into them only keep the lines around
+/// the span. Spans are still offsets into the full text and line numbers are preserved.
+#[derive(Debug, Clone)]
+pub struct SourceWindow {
+    text: String,
+    /// byte offset of `text` in the full source
+    offset: usize,
+    /// number of lines before `text` in the full source
+    line: usize,
+    /// number of characters between the start of its line and `text`
+    column: usize,
+}
+
+impl SourceWindow {
+    pub fn full(text: &str) -> Self {
+        SourceWindow {
+            text: text.to_string(),
+            offset: 0,
+            line: 0,
+            column: 0,
+        }
+    }
+
+    /// The lines around `span`, capped to a few hundred bytes on each side.
+    pub fn around(text: &str, span: Range<usize>) -> Self {
+        let span_start = floor_char_boundary(text, span.start.min(text.len()));
+        let span_end = ceil_char_boundary(text, span.end.clamp(span_start, text.len()));
+
+        let mut start = text[..span_start].rfind('\n').map_or(0, |i| i + 1);
+        for _ in 0..WINDOW_CONTEXT_LINES {
+            if start == 0 {
+                break;
+            }
+            start = text[..start - 1].rfind('\n').map_or(0, |i| i + 1);
+        }
+        start = start.max(floor_char_boundary(
+            text,
+            span_start.saturating_sub(WINDOW_CONTEXT_BYTES),
+        ));
+
+        let mut end = text[span_end..]
+            .find('\n')
+            .map_or(text.len(), |i| span_end + i);
+        for _ in 0..WINDOW_CONTEXT_LINES {
+            if end == text.len() {
+                break;
+            }
+            end = text[end + 1..]
+                .find('\n')
+                .map_or(text.len(), |i| end + 1 + i);
+        }
+        end = end.min(ceil_char_boundary(
+            text,
+            (span_end + WINDOW_CONTEXT_BYTES).min(text.len()),
+        ));
+
+        let line_start = text[..start].rfind('\n').map_or(0, |i| i + 1);
+        SourceWindow {
+            text: text[start..end].to_string(),
+            offset: start,
+            line: text[..start].matches('\n').count(),
+            column: text[line_start..start].chars().count(),
+        }
+    }
+
+    /// The text kept in this window.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The byte offset of [`SourceWindow::text`] in the full source.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The location of `offset` (in the full source), if it falls inside this window.
+    pub fn locate(&self, offset: usize) -> Option<Location> {
+        let local = offset.checked_sub(self.offset)?;
+        if local > self.text.len() {
+            return None;
+        }
+        let location = LineIndex::new(&self.text).locate(local);
+        Some(Location {
+            line: location.line + self.line,
+            column: location.column + if location.line == 1 { self.column } else { 0 },
+        })
+    }
+}
+
+#[cfg(feature = "diagnostics")]
+impl SourceCode for SourceWindow {
+    fn read_span<'a>(
+        &'a self,
+        span: &SourceSpan,
+        context_lines_before: usize,
+        context_lines_after: usize,
+    ) -> Result<Box<dyn SpanContents<'a> + 'a>, MietteError> {
+        let local = SourceSpan::new(span.offset().saturating_sub(self.offset).into(), span.len());
+        let contents = self
+            .text
+            .read_span(&local, context_lines_before, context_lines_after)?;
+        // miette counts bytes, `location()` characters
+        let start = contents.span().offset();
+        let line_start = self.text[..start].rfind('\n').map_or(0, |i| i + 1);
+        let column = self.text[line_start..start].chars().count();
+        Ok(Box::new(MietteSpanContents::new(
+            contents.data(),
+            SourceSpan::new((start + self.offset).into(), contents.span().len()),
+            contents.line() + self.line,
+            column + if contents.line() == 0 { self.column } else { 0 },
+            contents.line_count(),
+        )))
+    }
+}
+
+/// Without the `diagnostics` feature, errors keep their source and span in these minimal
+/// stand-ins for miette's types, so [`StrqlError::location`] still works.
+#[cfg(not(feature = "diagnostics"))]
+mod plain {
+    use std::ops::Range;
+
+    #[derive(Debug, Clone)]
+    pub struct NamedSource<T> {
+        name: String,
+        source: T,
+    }
+
+    impl<T> NamedSource<T> {
+        pub fn new(name: impl AsRef<str>, source: T) -> Self {
+            NamedSource {
+                name: name.as_ref().to_string(),
+                source,
+            }
+        }
+
+        pub fn name(&self) -> &str {
+            &self.name
+        }
+
+        pub fn inner(&self) -> &T {
+            &self.source
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct SourceSpan {
+        offset: usize,
+        length: usize,
+    }
+
+    impl SourceSpan {
+        pub fn offset(&self) -> usize {
+            self.offset
+        }
+
+        pub fn len(&self) -> usize {
+            self.length
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.length == 0
+        }
+    }
+
+    impl From<usize> for SourceSpan {
+        fn from(offset: usize) -> Self {
+            SourceSpan { offset, length: 0 }
+        }
+    }
+
+    impl From<Range<usize>> for SourceSpan {
+        fn from(range: Range<usize>) -> Self {
+            SourceSpan {
+                offset: range.start,
+                length: range.len(),
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "diagnostics"))]
+pub use plain::{NamedSource, SourceSpan};
+
+fn floor_char_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+fn ceil_char_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index += 1;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "diagnostics")]
+    use miette::{GraphicalReportHandler, GraphicalTheme};
+
+    #[test]
+    #[cfg(feature = "diagnostics")]
+    fn window_keeps_lines_around_span() {
+        let text: String = (1..=100).map(|i| format!("line {}\n", i)).collect();
+        let start = text.find("line 50").unwrap();
+        let window = SourceWindow::around(&text, start..start + 4);
+        assert_eq!(window.text(), "line 48\nline 49\nline 50\nline 51\nline 52");
+        assert_eq!(window.offset(), text.find("line 48").unwrap());
+
+        let contents = window.read_span(&(start..start + 4).into(), 0, 0).unwrap();
+        assert_eq!(contents.data(), b"line");
+        assert_eq!(contents.line(), 49);
+        assert_eq!(contents.span().offset(), start);
+    }
+
+    #[test]
+    fn window_caps_long_lines() {
+        let text = format!("{}é{}", "a".repeat(1000), "b".repeat(1000));
+        let start = 1000;
+        let window = SourceWindow::around(&text, start..start + 2);
+        assert!(window.text().len() <= 2 * WINDOW_CONTEXT_BYTES + 2);
+        assert!(window.text().contains('é'));
+        assert_eq!(
+            &text[window.offset()..][..window.text().len()],
+            window.text()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "diagnostics")]
+    fn windowed_errors_render_original_line_numbers() {
+        let input: String = (1..=10_000).map(|i| format!("row {}\n", i)).collect();
+        let start = input.find("row 5000").unwrap();
+        let error = StrqlError::PartialMatch {
+            _matched: start,
+            _total: input.len(),
+            _src: NamedSource::new("input", SourceWindow::around(&input, start..start)),
+            _span: (start..start).into(),
+        };
+        let mut rendered = String::new();
+        GraphicalReportHandler::new_themed(GraphicalTheme::unicode_nocolor())
+            .render_report(&mut rendered, &error)
+            .unwrap();
+        assert!(rendered.contains("[input:5000:1]"), "{}", rendered);
+        assert!(rendered.contains("5000 │ row 5000"), "{}", rendered);
+        assert!(!rendered.contains("row 4000"));
+        assert_eq!(
+            error.location(),
+            Some(Location {
+                line: 5000,
+                column: 1
+            })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "diagnostics")]
+    fn rendered_columns_count_characters() {
+        let error = crate::evaluate_partition("TEXT = \"éé\" \"x\"", "ééy").unwrap_err();
+        let mut rendered = String::new();
+        GraphicalReportHandler::new_themed(GraphicalTheme::unicode_nocolor())
+            .render_report(&mut rendered, &error)
+            .unwrap();
+        assert_eq!(error.location(), Some(Location { line: 1, column: 3 }));
+        assert!(rendered.contains("[input:1:3]"), "{}", rendered);
+    }
+
+    #[test]
+    fn error_kinds_and_spans() {
+        let error = crate::parser::parse("TEXT = WORD ~").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Syntax);
+        assert_eq!(error.spans(), vec![12..13]);
+        assert_eq!(error.input_offset(), None);
+
+        let error = crate::evaluate_partition("TEXT = \"ab\" \"c\"", "abd").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NoMatch);
+        assert_eq!(error.input_offset(), Some(2));
+
+        assert_eq!(
+            error.to_json(),
+            serde_json::json!({
+                "kind": "no_match",
+                "message": error.to_string(),
+                "span": [2, 2],
+                "line": 1,
+                "column": 3
+            })
+        );
+
+        let error = crate::evaluate_partition("TEXT = ANY ANY", "ab").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Ambiguous);
+        assert_eq!(
+            error.input_offset(),
+            error.spans().first().map(|span| span.start)
+        );
+    }
+
+    #[test]
+    fn query_error_location() {
+        let Err(error) = crate::parser::parse("TEXT = WORD\nx = \"é\" ~") else {
+            panic!("expected a parse error");
+        };
+        assert_eq!(error.location(), Some(Location { line: 2, column: 9 }));
+    }
 }
@@ -0,0 +1,293 @@
+//! Random valid programs built from fuzzer data: [`Arbitrary`] for [`PatternKind`] and
+//! [`Program`], with the `arbitrary` feature. See `fuzz/`.
+
+use crate::ast::{AlternationBias, Builtin, Pattern, PatternKind, Program, QuantifierBias};
+use arbitrary::{Arbitrary, Result, Unstructured};
+use std::fmt::Write;
+
+/// The statements of generated programs, each of which only refers to the ones after it, so
+/// that they can't be left-recursive.
+const NAMES: [&str; 4] = ["TEXT", "s1", "s2", "s3"];
+
+/// How deep generated patterns nest.
+const MAX_DEPTH: usize = 4;
+
+/// Characters of generated literals, including the ones literals have to escape.
+const CHARS: [char; 10] = ['a', 'b', '1', ' ', '.', '-', '"', '\\', '\n', 'é'];
+
+impl<'a> Arbitrary<'a> for PatternKind {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        pattern(u, &[], MAX_DEPTH)
+    }
+}
+
+/// Programs of up to four statements, some capturing what they match to an array of
+/// `ROOT`. They are printed and parsed, so their spans point into [`Program::source`].
+impl<'a> Arbitrary<'a> for Program {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let count = u.int_in_range(1..=NAMES.len())?;
+        let mut source = String::new();
+        for (i, name) in NAMES[..count].iter().enumerate() {
+            let pattern = pattern(u, &NAMES[i + 1..count], MAX_DEPTH)?;
+            source.push_str(name);
+            source.push_str(" = ");
+            write_pattern(&mut source, &pattern);
+            if i > 0 && u.arbitrary()? {
+                let _ = write!(source, " -> ADD TO ROOT.{}[]", name);
+            }
+            source.push('\n');
+        }
+        // not parsing is a bug of either the parser or `write_pattern`
+        Ok(crate::parser::parse(&source)
+            .unwrap_or_else(|e| panic!("generated query doesn't parse: {}\n{}", e, source)))
+    }
+}
+
+/// A pattern at most `depth` deep, which may refer to the statements `names`.
+fn pattern(u: &mut Unstructured, names: &[&str], depth: usize) -> Result<PatternKind> {
+    let leaves = if names.is_empty() { 2 } else { 3 };
+    let choice = if depth == 0 || u.is_empty() {
+        u.choose_index(leaves)?
+    } else {
+        u.choose_index(10)?
+    };
+    let boxed = |u: &mut Unstructured| -> Result<Box<Pattern>> {
+        Ok(Box::new(spanned(pattern(u, names, depth - 1)?)))
+    };
+    let items = |u: &mut Unstructured| -> Result<Vec<Pattern>> {
+        (0..u.int_in_range(2..=3)?)
+            .map(|_| Ok(spanned(pattern(u, names, depth - 1)?)))
+            .collect()
+    };
+    Ok(match choice {
+        0 => {
+            let len = u.int_in_range(1..=3)?;
+            PatternKind::Literal(
+                (0..len)
+                    .map(|_| u.choose(&CHARS).copied())
+                    .collect::<Result<_>>()?,
+            )
+        }
+        1 => PatternKind::Builtin(builtin(u)?),
+        2 if !names.is_empty() => PatternKind::Variable(u.choose(names)?.to_string()),
+        2 | 3 => PatternKind::Sequence(items(u)?),
+        4 => {
+            let alternatives = items(u)?;
+            let bias = match u.choose_index(4)? {
+                0 => AlternationBias::Neutral,
+                1 => AlternationBias::First,
+                2 => AlternationBias::Longest,
+                _ => AlternationBias::Weighted(
+                    (0..alternatives.len())
+                        .map(|_| u.int_in_range(0..=3))
+                        .collect::<Result<_>>()?,
+                ),
+            };
+            PatternKind::OrChain { alternatives, bias }
+        }
+        5 => {
+            let min = u.int_in_range(0..=2)?;
+            let max = match u.arbitrary::<bool>()? {
+                true => Some(u.int_in_range(min..=min + 2)?),
+                false => None,
+            };
+            let bias = *u.choose(&[
+                QuantifierBias::Neutral,
+                QuantifierBias::Greedy,
+                QuantifierBias::Lazy,
+            ])?;
+            PatternKind::Repetition {
+                min: Some(min),
+                max,
+                pattern: boxed(u)?,
+                bias,
+            }
+        }
+        6 => PatternKind::AnyCase(boxed(u)?),
+        7 => PatternKind::Upper(boxed(u)?),
+        8 => PatternKind::Lower(boxed(u)?),
+        _ => PatternKind::Group(boxed(u)?),
+    })
+}
+
+fn builtin(u: &mut Unstructured) -> Result<Builtin> {
+    Ok(match u.choose_index(13)? {
+        0 => Builtin::Digit,
+        1 => Builtin::Letter,
+        2 => Builtin::AnyChar,
+        3 => Builtin::Newline,
+        4 => Builtin::Space,
+        5 => Builtin::Line,
+        6 => Builtin::Paragraph,
+        7 => Builtin::Byte(*u.choose(b"a1 \n")?),
+        8 => Builtin::Bytes(u.int_in_range(0..=2)?),
+        9 => Builtin::Base64,
+        10 => Builtin::HexBlob,
+        11 => Builtin::JsonValue,
+        _ => Builtin::ContLine,
+    })
+}
+
+fn spanned(node: PatternKind) -> Pattern {
+    Pattern { node, span: 0..0 }
+}
+
+/// Writes `pattern` as query text, in parentheses where the parser would otherwise read it
+/// differently. Only covers the patterns [`pattern`] generates.
+fn write_pattern(out: &mut String, pattern: &PatternKind) {
+    match pattern {
+        PatternKind::Literal(text) => {
+            out.push('"');
+            for c in text.chars() {
+                match c {
+                    '"' => out.push_str("\\\""),
+                    '\\' => out.push_str("\\\\"),
+                    '\n' => out.push_str("\\n"),
+                    c => out.push(c),
+                }
+            }
+            out.push('"');
+        }
+        PatternKind::Variable(name) => out.push_str(name),
+        PatternKind::Builtin(builtin) => {
+            let _ = match builtin {
+                Builtin::Digit => write!(out, "DIGIT"),
+                Builtin::Letter => write!(out, "LETTER"),
+                Builtin::AnyChar => write!(out, "ANYCHAR"),
+                Builtin::Newline => write!(out, "NEWLINE"),
+                Builtin::Space => write!(out, "SPACE"),
+                Builtin::Line => write!(out, "LINE"),
+                Builtin::Paragraph => write!(out, "PARAGRAPH"),
+                Builtin::ContLine => write!(out, "CONTLINE"),
+                Builtin::Byte(value) => write!(out, "BYTE(0x{:02X})", value),
+                Builtin::Bytes(count) => write!(out, "BYTES({})", count),
+                Builtin::Base64 => write!(out, "BASE64"),
+                Builtin::HexBlob => write!(out, "HEXBLOB"),
+                Builtin::JsonValue => write!(out, "JSONVALUE"),
+                other => unreachable!("write_pattern: {:?} isn't generated", other),
+            };
+        }
+        PatternKind::Sequence(items)
+        | PatternKind::OrChain {
+            alternatives: items,
+            ..
+        } => {
+            let separator = match pattern {
+                PatternKind::Sequence(_) => " ",
+                PatternKind::OrChain {
+                    bias: AlternationBias::First,
+                    ..
+                } => " FIRST OR ",
+                PatternKind::OrChain {
+                    bias: AlternationBias::Longest,
+                    ..
+                } => " LONGEST OR ",
+                _ => " OR ",
+            };
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(separator);
+                }
+                // keeps nested sequences apart from their parent, so the parse has them too
+                let nested = matches!(
+                    item.node,
+                    PatternKind::Sequence(_) | PatternKind::OrChain { .. }
+                );
+                write_grouped(out, &item.node, nested);
+                if let PatternKind::OrChain {
+                    bias: AlternationBias::Weighted(weights),
+                    ..
+                } = pattern
+                {
+                    let _ = write!(out, " WEIGHT {}", weights[i]);
+                }
+            }
+        }
+        PatternKind::Repetition {
+            min,
+            max,
+            pattern,
+            bias,
+        } => {
+            match bias {
+                QuantifierBias::Neutral => {}
+                QuantifierBias::Greedy => out.push_str("GREEDY "),
+                QuantifierBias::Lazy => out.push_str("LAZY "),
+            }
+            let _ = write!(out, "{}..", min.unwrap_or(0));
+            match max {
+                Some(max) => {
+                    let _ = write!(out, "{} ", max);
+                }
+                None => out.push_str("N "),
+            }
+            write_primary(out, &pattern.node);
+        }
+        PatternKind::AnyCase(inner)
+        | PatternKind::Upper(inner)
+        | PatternKind::Lower(inner)
+        | PatternKind::PrecededBy(inner) => {
+            out.push_str(match pattern {
+                PatternKind::AnyCase(_) => "ANYCASE ",
+                PatternKind::Upper(_) => "UPPER ",
+                PatternKind::Lower(_) => "LOWER ",
+                _ => "PRECEDEDBY ",
+            });
+            write_primary(out, &inner.node);
+        }
+        PatternKind::Group(inner) => write_grouped(out, &inner.node, true),
+        PatternKind::Fields(_) => unreachable!("write_pattern: FIELDS isn't generated"),
+        PatternKind::Unmatched(_) => unreachable!("write_pattern: SKIP_UNMATCHED isn't generated"),
+        PatternKind::Permute(items) => {
+            out.push_str("PERMUTE(");
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_pattern(out, &item.node);
+            }
+            out.push(')');
+        }
+        PatternKind::Dict(_) => unreachable!("write_pattern: DICT isn't generated"),
+    }
+}
+
+/// Writes `pattern` where the parser expects a single term, like after `1..N`.
+fn write_primary(out: &mut String, pattern: &PatternKind) {
+    let primary = matches!(
+        pattern,
+        PatternKind::Literal(_)
+            | PatternKind::Variable(_)
+            | PatternKind::Builtin(_)
+            | PatternKind::Group(_)
+    );
+    write_grouped(out, pattern, !primary);
+}
+
+fn write_grouped(out: &mut String, pattern: &PatternKind, parenthesize: bool) {
+    if parenthesize {
+        out.push('(');
+    }
+    write_pattern(out, pattern);
+    if parenthesize {
+        out.push(')');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_programs_that_compile() {
+        let data: Vec<u8> = (0..4096u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        let mut u = Unstructured::new(&data);
+        for _ in 0..16 {
+            let program = Program::arbitrary(&mut u).unwrap();
+            assert_eq!(program.statements[0].name, "TEXT");
+            crate::CompiledQuery::new(&program).unwrap();
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//! Drafting queries from sample inputs: the parts the samples share become literals, and
+//! the parts they differ in become captured fields.
+//!
+//! Experimental: the draft is a starting point to edit, it may need biases or tighter
+//! fields to parse the samples unambiguously.
+
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Class {
+    Digits,
+    Letters,
+    Space,
+    /// a single character of anything else
+    Symbol,
+    /// whatever the samples have there, which may be nothing
+    Mixed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Slot {
+    /// text all the samples have here
+    Literal(String, Class),
+    /// text the samples differ in, all of the class
+    Field(Class),
+}
+
+/// A query matching every line of `samples` (blank lines are skipped), capturing the
+/// digits, letters and other text the lines differ in to fields of `ROOT`.
+pub fn infer<'a>(samples: impl IntoIterator<Item = &'a str>) -> String {
+    let mut template: Option<Vec<Slot>> = None;
+    for sample in samples.into_iter().filter(|line| !line.trim().is_empty()) {
+        let tokens = tokenize(sample);
+        template = Some(match template {
+            None => tokens,
+            Some(template) => merge(&template, &tokens),
+        });
+    }
+    write_query(&template.unwrap_or_default())
+}
+
+/// The runs of digits, letters and spaces of `sample`, and its other characters one by one.
+fn tokenize(sample: &str) -> Vec<Slot> {
+    let mut tokens: Vec<Slot> = Vec::new();
+    for c in sample.chars() {
+        let class = if c.is_ascii_digit() {
+            Class::Digits
+        } else if c.is_ascii_alphabetic() {
+            Class::Letters
+        } else if c.is_whitespace() && c != '\n' {
+            Class::Space
+        } else {
+            Class::Symbol
+        };
+        match tokens.last_mut() {
+            Some(Slot::Literal(text, last)) if *last == class && class != Class::Symbol => {
+                text.push(c)
+            }
+            _ => tokens.push(Slot::Literal(c.to_string(), class)),
+        }
+    }
+    tokens
+}
+
+/// How well `slot` of the template lines up with a token of the next sample: equal text
+/// counts the most, so that the constant parts of the samples are kept.
+fn alignment_score(slot: &Slot, token: &Slot) -> usize {
+    let Slot::Literal(text, class) = token else {
+        unreachable!("alignment_score: tokens are literals");
+    };
+    match slot {
+        Slot::Literal(slot_text, _) if slot_text == text => 2,
+        Slot::Literal(_, slot_class) | Slot::Field(slot_class)
+            if slot_class == class && *class != Class::Symbol =>
+        {
+            1
+        }
+        _ => 0,
+    }
+}
+
+/// Generalizes `template` to match `tokens` too, along the alignment of the two scoring the
+/// most. What doesn't line up becomes a [`Class::Mixed`] field.
+fn merge(template: &[Slot], tokens: &[Slot]) -> Vec<Slot> {
+    let (n, m) = (template.len(), tokens.len());
+    // best[i][j]: the score of aligning template[i..] with tokens[j..]
+    let mut best = vec![vec![0; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            let score = alignment_score(&template[i], &tokens[j]);
+            let paired = match score {
+                0 => 0,
+                score => score + best[i + 1][j + 1],
+            };
+            best[i][j] = paired.max(best[i + 1][j]).max(best[i][j + 1]);
+        }
+    }
+
+    let mut merged = Vec::new();
+    let mut unaligned = false;
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        let score = match (template.get(i), tokens.get(j)) {
+            (Some(slot), Some(token)) => alignment_score(slot, token),
+            _ => 0,
+        };
+        if score > 0 && best[i][j] == score + best[i + 1][j + 1] {
+            if unaligned {
+                merged.push(Slot::Field(Class::Mixed));
+                unaligned = false;
+            }
+            merged.push(match score {
+                2 => template[i].clone(),
+                _ => match &tokens[j] {
+                    Slot::Literal(_, class) => Slot::Field(*class),
+                    field => field.clone(),
+                },
+            });
+            i += 1;
+            j += 1;
+            continue;
+        }
+        unaligned = true;
+        if j == m || (i < n && best[i][j] == best[i + 1][j]) {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    if unaligned {
+        merged.push(Slot::Field(Class::Mixed));
+    }
+    merged
+}
+
+fn write_query(template: &[Slot]) -> String {
+    let mut text = String::from("TEXT =");
+    let mut statements = String::new();
+    let mut counts = [0; 3];
+    let mut literal = String::new();
+    for slot in template {
+        let field = match slot {
+            Slot::Literal(part, _) => {
+                literal.push_str(part);
+                continue;
+            }
+            Slot::Field(class) => class,
+        };
+        if !literal.is_empty() {
+            text.push(' ');
+            write_literal(&mut text, &literal);
+            literal.clear();
+        }
+        let (kind, name, pattern) = match field {
+            Class::Digits => (0, "number", "GREEDY 1..N DIGIT"),
+            Class::Letters => (1, "word", "GREEDY 1..N LETTER"),
+            Class::Mixed => (2, "text", "LAZY ANY"),
+            // spaces are kept out of the output
+            Class::Space => {
+                text.push_str(" GREEDY 1..N SPACE");
+                continue;
+            }
+            Class::Symbol => unreachable!("write_query: symbols only line up with themselves"),
+        };
+        let count = &mut counts[kind];
+        *count += 1;
+        let _ = write!(text, " {}{}", name, count);
+        let _ = writeln!(
+            statements,
+            "{name}{count} = {pattern} -> ADD TO ROOT.{name}{count}"
+        );
+    }
+    if !literal.is_empty() || text == "TEXT =" {
+        text.push(' ');
+        write_literal(&mut text, &literal);
+    }
+    format!("{}\n{}", text, statements)
+}
+
+fn write_literal(out: &mut String, text: &str) {
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '$' => out.push_str("\\$"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CompiledQuery;
+    use serde_json::json;
+
+    #[test]
+    fn infers_fields_between_constant_parts() {
+        let samples = [
+            "[2024] ERROR disk full",
+            "[1999] WARN cpu hot",
+            "",
+            "[7] INFO ok",
+        ];
+        let query = infer(samples);
+        assert_eq!(
+            query,
+            "TEXT = \"[\" number1 \"] \" word1 \" \" word2 text1\n\
+             number1 = GREEDY 1..N DIGIT -> ADD TO ROOT.number1\n\
+             word1 = GREEDY 1..N LETTER -> ADD TO ROOT.word1\n\
+             word2 = GREEDY 1..N LETTER -> ADD TO ROOT.word2\n\
+             text1 = LAZY ANY -> ADD TO ROOT.text1\n"
+        );
+        let compiled = CompiledQuery::compile(&query).unwrap();
+        assert_eq!(
+            compiled.evaluate("[1999] WARN cpu hot").unwrap(),
+            json!({"number1": "1999", "word1": "WARN", "word2": "cpu", "text1": " hot"})
+        );
+        for sample in samples.iter().filter(|sample| !sample.is_empty()) {
+            compiled.evaluate(sample).unwrap();
+        }
+    }
+
+    #[test]
+    fn escapes_literals() {
+        assert_eq!(infer(["a\"$\\"]), "TEXT = \"a\\\"\\$\\\\\"\n");
+        assert_eq!(infer([]), "TEXT = \"\"\n");
+    }
+}
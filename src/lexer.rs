@@ -1,14 +1,29 @@
-use crate::error::{StrqlError, StrqlResult};
+use crate::error::{query_to_named, StrqlError, StrqlResult};
 use logos::Logos;
-use miette::NamedSource;
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 
+/// Values of the `${NAME}` placeholders in string literals.
+pub type Params = HashMap<String, String>;
+
+/// State the lexer carries along: the parameters literals are filled in with, the first one
+/// that had no value, and the first invalid escape sequence, with its range in the literal.
+#[derive(Default)]
+pub struct LexerExtras {
+    params: Params,
+    missing: Option<String>,
+    invalid_escape: Option<(String, std::ops::Range<usize>)>,
+}
+
 #[derive(Logos, Debug, PartialEq, Clone)]
+#[logos(extras = LexerExtras)]
 #[logos(skip r"[ \t]+")] // whitespace
-#[logos(skip r"//[^\n]*")] // line comments
+#[logos(skip(r"//[^\n]*", allow_greedy = true))] // line comments
 #[logos(skip r"/\*([^*]|\*[^/])*\*/")] // block comments
 pub enum Token {
-    // Keywords
+    // Keywords. The words of directives, casts and capture options, like `OPTION`, `INT` or
+    // `UNIQUE`, are identifiers the parser recognizes where they can appear, so that they
+    // still name statements and captures
     #[token("TEXT", ignore(case))]
     Text,
     #[token("ROOT", ignore(case))]
@@ -21,6 +36,8 @@ pub enum Token {
     To,
     #[token("SPLITBY", ignore(case))]
     SplitBy,
+    #[token("PRECEDEDBY", ignore(case))]
+    PrecededBy,
     #[token("ANYCASE", ignore(case))]
     AnyCase,
     #[token("UPPER", ignore(case))]
@@ -39,6 +56,10 @@ pub enum Token {
     Word,
     #[token("LINE", ignore(case))]
     Line,
+    #[token("CONTLINE", ignore(case))]
+    ContLine,
+    #[token("PARAGRAPH", ignore(case))]
+    Paragraph,
     #[token("NEWLINE", ignore(case))]
     Newline,
     #[token("SPACE", ignore(case))]
@@ -53,10 +74,34 @@ pub enum Token {
     Letter,
     #[token("ALPHANUM", ignore(case))]
     Alphanum,
+    #[token("HEXNUM", ignore(case))]
+    HexNum,
+    #[token("BINNUM", ignore(case))]
+    BinNum,
+    #[token("OCTNUM", ignore(case))]
+    OctNum,
+    #[token("BYTE", ignore(case))]
+    Byte,
+    #[token("BYTES", ignore(case))]
+    Bytes,
+    #[token("BASE64", ignore(case))]
+    Base64,
+    #[token("HEXBLOB", ignore(case))]
+    HexBlob,
+    #[token("JSONVALUE", ignore(case))]
+    JsonValue,
+    #[token("BOF", ignore(case))]
+    Bof,
+    #[token("EOF", ignore(case))]
+    Eof,
+    #[token("PERMUTE", ignore(case))]
+    Permute,
 
     // Operators and punctuation
     #[token("=")]
     Equals,
+    #[token("|=")]
+    OrEquals,
     #[token("->")]
     Arrow,
     #[token("..")]
@@ -77,14 +122,28 @@ pub enum Token {
     RBrace,
     #[token(":")]
     Colon,
+    #[token(",")]
+    Comma,
+    #[token(";")]
+    Semicolon,
     #[token("\n")]
     NewlineChar,
     #[token("\r\n")]
     CrLf,
 
+    /// `/// text`, documenting the statement it precedes (or trails)
+    #[regex(r"///[^\n]*", priority = 10, allow_greedy = true, callback = |lex| lex.slice()[3..].trim().to_string())]
+    DocComment(String),
+
+    /// a name, or `` `line` ``: a name escaped in backticks, which can be a keyword
     #[regex(r"[a-zA-Z_][a-zA-Z0-9_]*", priority = 1, callback = |lex| lex.slice().to_string())]
+    #[regex(r"`[a-zA-Z_][a-zA-Z0-9_]*`", |lex| lex.slice().trim_matches('`').to_string())]
     Identifier(String),
+    /// `$1`, the position of an element of a statement's sequence
+    #[regex(r"\$[0-9]+", |lex| lex.slice()[1..].parse::<usize>().ok())]
+    Positional(usize),
     #[regex(r"[0-9]+", |lex| lex.slice().parse::<usize>().ok())]
+    #[regex(r"0[xX][0-9a-fA-F]+", |lex| usize::from_str_radix(&lex.slice()[2..], 16).ok())]
     Number(usize),
     #[regex(r#""([^"\\]|\\.)*""#, parse_string_literal)]
     StringLiteral(String),
@@ -96,25 +155,50 @@ impl Display for Token {
     }
 }
 
-/// nodes escape sequences.
+/// nodes escape sequences and `${NAME}` parameters.
 fn parse_string_literal(lex: &mut logos::Lexer<Token>) -> Option<String> {
     let unquoted_slice = &lex.slice()[1..lex.slice().len() - 1];
 
     let mut result = String::new();
-    let mut chars = unquoted_slice.chars().peekable();
+    let mut chars = unquoted_slice.char_indices().peekable();
 
-    while let Some(c) = chars.next() {
-        if c != '\\' {
+    while let Some((start, c)) = chars.next() {
+        if c == '$' && chars.peek().map(|&(_, c)| c) == Some('{') {
+            chars.next();
+            let mut name = String::new();
+            loop {
+                match chars.next()?.1 {
+                    '}' if !name.is_empty() => break,
+                    '}' => return None,
+                    c => name.push(c),
+                }
+            }
+            match lex.extras.params.get(&name) {
+                Some(value) => result.push_str(value),
+                None => {
+                    lex.extras.missing = Some(name);
+                    return None;
+                }
+            }
+        } else if c != '\\' {
             result.push(c)
         } else {
-            match chars.next() {
-                Some('n') => result.push('\n'),
-                Some('r') => result.push('\r'),
-                Some('t') => result.push('\t'),
-                Some('\\') => result.push('\\'),
-                Some('"') => result.push('"'),
-                Some(other) => result.push(other),
-                None => return None,
+            let escaped = match chars.next()?.1 {
+                'n' => Ok('\n'),
+                'r' => Ok('\r'),
+                't' => Ok('\t'),
+                'x' => parse_hex_escape(&mut chars),
+                'u' => parse_unicode_escape(&mut chars),
+                other => Ok(other),
+            };
+            match escaped {
+                Ok(c) => result.push(c),
+                Err(reason) => {
+                    // the literal's opening quote comes before the slice
+                    let end = chars.peek().map_or(unquoted_slice.len(), |&(i, _)| i);
+                    lex.extras.invalid_escape = Some((reason, start + 1..end + 1));
+                    return None;
+                }
             }
         }
     }
@@ -122,6 +206,55 @@ fn parse_string_literal(lex: &mut logos::Lexer<Token>) -> Option<String> {
     Some(result)
 }
 
+type LiteralChars<'a> = std::iter::Peekable<std::str::CharIndices<'a>>;
+
+/// `\x41`: exactly two hex digits, limited to ASCII.
+fn parse_hex_escape(chars: &mut LiteralChars) -> Result<char, String> {
+    let mut digits = String::new();
+    while digits.len() < 2 {
+        match chars.next_if(|(_, c)| c.is_ascii_hexdigit()) {
+            Some((_, c)) => digits.push(c),
+            None => return Err("`\\x` needs two hex digits".to_string()),
+        }
+    }
+    let byte = u8::from_str_radix(&digits, 16).expect("two hex digits fit a byte");
+    if !byte.is_ascii() {
+        return Err(format!(
+            "`\\x{}` is not ASCII; write `\\u{{{}}}` for the character U+00{}",
+            digits, digits, digits
+        ));
+    }
+    Ok(char::from(byte))
+}
+
+/// `\u{1F600}`: one to six hex digits naming a unicode scalar value.
+fn parse_unicode_escape(chars: &mut LiteralChars) -> Result<char, String> {
+    if chars.next_if(|&(_, c)| c == '{').is_none() {
+        return Err("`\\u` needs its hex digits in braces, like `\\u{1F600}`".to_string());
+    }
+    let mut digits = String::new();
+    while let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_hexdigit()) {
+        digits.push(c);
+    }
+    if chars.next_if(|&(_, c)| c == '}').is_none() {
+        return Err("`\\u{` needs hex digits closed by `}`".to_string());
+    }
+    if digits.is_empty() || digits.len() > 6 {
+        return Err(format!("`\\u{{{}}}` needs one to six hex digits", digits));
+    }
+    let code_point = u32::from_str_radix(&digits, 16).expect("six hex digits fit a u32");
+    char::from_u32(code_point).ok_or_else(|| {
+        if (0xD800..=0xDFFF).contains(&code_point) {
+            format!("`\\u{{{}}}` is a surrogate, not a character", digits)
+        } else {
+            format!(
+                "`\\u{{{}}}` is past the last unicode code point, U+10FFFF",
+                digits
+            )
+        }
+    })
+}
+
 pub struct SpannedToken {
     pub token: Token,
     pub span: std::ops::Range<usize>,
@@ -129,14 +262,43 @@ pub struct SpannedToken {
 
 impl Token {
     pub fn vec_from(source: &str) -> StrqlResult<Vec<SpannedToken>> {
-        let lexer = Token::lexer(source);
+        Self::vec_with_params(source, &Params::new())
+    }
+
+    /// Like [`Token::vec_from`], filling in the `${NAME}` placeholders of literals from `params`.
+    pub fn vec_with_params(source: &str, params: &Params) -> StrqlResult<Vec<SpannedToken>> {
+        let extras = LexerExtras {
+            params: params.clone(),
+            missing: None,
+            invalid_escape: None,
+        };
+        let mut lexer = Token::lexer_with_extras(source, extras);
         let mut result = vec![];
-        for (tok, span) in lexer.spanned() {
-            tok.map_err(|_| StrqlError::LexerError {
-                _src: NamedSource::new("strql", source.to_string()),
-                _span: span.clone().into(),
-            })
-            .map(|token| result.push(SpannedToken { token, span }))?;
+        while let Some(tok) = lexer.next() {
+            let span = lexer.span();
+            match tok {
+                Ok(token) => result.push(SpannedToken { token, span }),
+                Err(_) => {
+                    if let Some((reason, range)) = lexer.extras.invalid_escape.take() {
+                        return Err(StrqlError::InvalidEscape {
+                            _reason: reason,
+                            _src: query_to_named(source),
+                            _span: (span.start + range.start..span.start + range.end).into(),
+                        });
+                    }
+                    return Err(match lexer.extras.missing.take() {
+                        Some(name) => StrqlError::MissingParam {
+                            _name: name,
+                            _src: query_to_named(source),
+                            _span: span.into(),
+                        },
+                        None => StrqlError::LexerError {
+                            _src: query_to_named(source),
+                            _span: span.into(),
+                        },
+                    });
+                }
+            }
         }
         Ok(result)
     }
@@ -183,6 +345,82 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_params() {
+        let params = Params::from([("SEP".to_string(), " | ".to_string())]);
+        let tokens = Token::vec_with_params(r#""a${SEP}b\${SEP}""#, &params).unwrap();
+        assert_eq!(
+            tokens[0].token,
+            Token::StringLiteral("a | b${SEP}".to_string())
+        );
+
+        let Err(StrqlError::MissingParam { _name, _span, .. }) =
+            Token::vec_from(r#"x = "${PREFIX}:""#)
+        else {
+            panic!("expected a missing parameter");
+        };
+        assert_eq!(_name, "PREFIX");
+        assert_eq!(_span.offset(), 4);
+
+        for source in [r#""a${SEP""#, r#""a${}b""#] {
+            assert!(
+                matches!(
+                    Token::vec_with_params(source, &params),
+                    Err(StrqlError::LexerError { .. })
+                ),
+                "{} should not lex",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_codepoint_escapes() {
+        let source = r#""\u{FEFF}a\x41\u{a0}\u{1F600}""#;
+        let tokens = Token::vec_from(source).unwrap();
+
+        assert_eq!(
+            tokens[0].token,
+            Token::StringLiteral("\u{feff}aA\u{a0}\u{1f600}".to_string())
+        );
+    }
+
+    #[test]
+    fn test_invalid_codepoint_escapes() {
+        for (source, escape, reason) in [
+            (r#"x = "\x4""#, r"\x4", "needs two hex digits"),
+            (r#"x = "\x""#, r"\x", "needs two hex digits"),
+            (r#"x = "a\xZZ""#, r"\x", "needs two hex digits"),
+            (r#"x = "\xFF""#, r"\xFF", "is not ASCII"),
+            (r#"x = "\u41""#, r"\u", "in braces"),
+            (r#"x = "\u{41""#, r"\u{41", "closed by `}`"),
+            (r#"x = "\u{}""#, r"\u{}", "one to six hex digits"),
+            (
+                r#"x = "\u{1234567}""#,
+                r"\u{1234567}",
+                "one to six hex digits",
+            ),
+            (r#"x = "\u{D800}""#, r"\u{D800}", "is a surrogate"),
+            (
+                r#"x = "\u{110000}b""#,
+                r"\u{110000}",
+                "past the last unicode code point",
+            ),
+        ] {
+            let Err(StrqlError::InvalidEscape { _reason, _span, .. }) = Token::vec_from(source)
+            else {
+                panic!("{} should not lex", source);
+            };
+            assert!(_reason.contains(reason), "{}: {}", source, _reason);
+            assert_eq!(
+                &source[_span.offset().._span.offset() + _span.len()],
+                escape,
+                "{}",
+                source
+            );
+        }
+    }
+
     #[test]
     fn test_quantifier() {
         let source = "0..N WORD";
@@ -207,6 +445,14 @@ mod tests {
         assert_eq!(tokens[3].token, Token::Word);
     }
 
+    #[test]
+    fn test_hex_number() {
+        let tokens = Token::vec_from("BYTE(0x0A) 0XfF").unwrap();
+        assert_eq!(tokens[0].token, Token::Byte);
+        assert_eq!(tokens[2].token, Token::Number(10));
+        assert_eq!(tokens[4].token, Token::Number(255));
+    }
+
     #[test]
     fn test_add_statement() {
         let source = "-> ADD item{} TO ROOT.items[]";
@@ -228,6 +474,20 @@ mod tests {
         assert_eq!(tokens[10].token, Token::RBracket);
     }
 
+    #[test]
+    fn test_doc_comments() {
+        let source = "/// the entry point\nTEXT = WORD // not a doc";
+        let tokens = Token::vec_from(source).unwrap();
+
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(
+            tokens[0].token,
+            Token::DocComment("the entry point".to_string())
+        );
+        assert_eq!(tokens[1].token, Token::NewlineChar);
+        assert_eq!(tokens[4].token, Token::Word);
+    }
+
     #[test]
     fn test_case_insensitive_keywords() {
         let source = "text = WORD splitby newline";
@@ -238,4 +498,14 @@ mod tests {
         assert_eq!(tokens[3].token, Token::SplitBy);
         assert_eq!(tokens[4].token, Token::Newline);
     }
+
+    #[test]
+    fn test_escaped_identifiers() {
+        let tokens = Token::vec_from("`word` `Line` `x1`").unwrap();
+        let names: Vec<_> = tokens.iter().map(|t| t.token.clone()).collect();
+        assert_eq!(
+            names,
+            ["word", "Line", "x1"].map(|name| Token::Identifier(name.to_string()))
+        );
+    }
 }
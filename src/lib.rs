@@ -1,16 +1,132 @@
+#![allow(clippy::result_large_err)]
+
 pub mod ast;
+#[cfg(feature = "rayon")]
+mod batch;
+mod bytes;
+pub mod cancel;
+mod cast;
+#[cfg(feature = "codegen")]
+pub mod codegen;
+mod compose;
+pub mod custom;
+#[cfg(feature = "differential")]
+pub mod differential;
+mod disambiguate;
 pub mod error;
+#[cfg(feature = "arbitrary")]
+mod generate;
+pub mod infer;
 pub mod lexer;
+pub mod line_index;
+pub mod lint;
+mod lower;
+mod normalize;
+mod number;
 pub mod parser;
+pub mod progress;
+pub mod query_set;
+pub mod records;
+mod scan;
+pub mod shape;
+mod simplify;
 mod solver;
+pub mod stdlib;
+mod suggest;
+pub mod table;
+pub mod visit;
 
 use crate::error::StrqlResult;
 pub use ast::{Builtin, Pattern, Program, Statement};
+pub use cancel::CancellationToken;
+pub use custom::CustomBuiltins;
+pub use disambiguate::Disambiguation;
+pub use line_index::{LineIndex, Location};
+pub use progress::Progress;
+pub use query_set::{BestMatch, QuerySet};
+pub use shape::{Plan, Shape};
+pub use solver::{
+    CaptureEventView, CaptureSpan, CompiledQuery, MatchResult, MatchStats, PathStep, SolverOptions,
+};
+pub use table::{to_csv, to_table};
+pub use visit::{Visitor, VisitorMut};
 
 pub fn evaluate_partition(source: &str, input: &str) -> StrqlResult<serde_json::Value> {
-    let program = parser::parse(source)?;
-    let mut solver = solver::Solver::new(&program)?;
-    solver.solve(input)
+    CompiledQuery::compile(source)?.evaluate(input)
+}
+
+/// Like [`evaluate_partition`], along with where each value was captured from; see
+/// [`CompiledQuery::evaluate_match`].
+pub fn evaluate_match(source: &str, input: &str) -> StrqlResult<MatchResult> {
+    CompiledQuery::compile(source)?.evaluate_match(input)
+}
+
+/// Like [`evaluate_partition`], but can be interrupted from another thread through `token`.
+///
+/// The solver checks the token as it explores the input and returns
+/// [`error::StrqlError::Cancelled`] once it is cancelled.
+pub fn evaluate_cancellable(
+    source: &str,
+    input: &str,
+    token: &CancellationToken,
+) -> StrqlResult<serde_json::Value> {
+    CompiledQuery::compile(source)?.evaluate_cancellable(input, token)
+}
+
+/// Like [`evaluate_partition`], for inputs mixing text with binary data; see
+/// [`CompiledQuery::evaluate_bytes`].
+pub fn evaluate_bytes(source: &str, input: &[u8]) -> StrqlResult<serde_json::Value> {
+    CompiledQuery::compile(source)?.evaluate_bytes(input)
+}
+
+/// Evaluates `source` against `input`, handing every capture to `sink` instead of building
+/// the output; see [`CompiledQuery::evaluate_with_sink`].
+pub fn evaluate_with_sink(
+    source: &str,
+    input: &str,
+    sink: impl FnMut(CaptureEventView<'_>),
+) -> StrqlResult<()> {
+    CompiledQuery::compile(source)?.evaluate_with_sink(input, sink)
+}
+
+/// Like [`evaluate_partition`], for inputs already split into segments; see
+/// [`CompiledQuery::evaluate_segments`].
+pub fn evaluate_segments(source: &str, segments: &[&str]) -> StrqlResult<serde_json::Value> {
+    CompiledQuery::compile(source)?.evaluate_segments(segments)
+}
+
+/// Evaluates many inputs against one query in parallel, preserving the order of `inputs`.
+///
+/// The query is compiled once; `parallelism` is the number of worker threads (`0` for one per core).
+/// Query errors fail the whole batch, while each input gets its own result.
+#[cfg(feature = "rayon")]
+pub fn evaluate_batch(
+    source: &str,
+    inputs: &[&str],
+    parallelism: usize,
+) -> StrqlResult<Vec<StrqlResult<serde_json::Value>>> {
+    CompiledQuery::compile(source)?.evaluate_batch(inputs, parallelism)
+}
+
+/// Evaluates `input` and deserializes the output into `T`, typically a struct generated
+/// from the same query (see `codegen::rust_types`).
+#[cfg(feature = "serde")]
+pub fn evaluate_into<T: serde::de::DeserializeOwned>(source: &str, input: &str) -> StrqlResult<T> {
+    CompiledQuery::compile(source)?.evaluate_into(input)
+}
+
+/// Infers the shape of the query's output from its captures and returns it as a JSON Schema.
+pub fn output_schema(source: &str) -> StrqlResult<serde_json::Value> {
+    Ok(Shape::infer(&parser::parse(source)?).to_json_schema())
+}
+
+/// Checks whether `input` conforms to the query without building its JSON output.
+///
+/// This skips capture tracking and replay entirely, so it is cheaper than
+/// [`evaluate_partition`] when only conformance matters. Inputs that don't match
+/// yield `Ok(false)`; query errors and ambiguous parses are still reported as errors.
+pub fn matches(source: &str, input: &str) -> StrqlResult<bool> {
+    CompiledQuery::compile(source)?.matches(input)
 }
 
 #[cfg(test)]
@@ -46,6 +162,153 @@ greeting = "hello" OR "hi" OR "hey"
         assert!(evaluate_partition(source, "bye").is_err());
     }
 
+    #[test]
+    fn test_matches() {
+        let source = r#"
+TEXT = name " " age
+name = WORD -> ADD name TO ROOT
+age = 1..N DIGIT -> ADD age TO ROOT
+"#;
+        assert!(matches(source, "Alice 25").unwrap());
+        assert!(!matches(source, "Alice").unwrap());
+        assert!(!matches(source, "25 Alice").unwrap());
+    }
+
+    #[test]
+    fn test_matches_reports_ambiguity() {
+        let source = r#"
+TEXT = w SPLITBY "."
+w = ANY -> ADD TO ROOT.results[]
+"#;
+        assert!(matches!(
+            matches(source, "a. b. c."),
+            Err(error::StrqlError::AmbiguousParse { .. })
+        ));
+    }
+
+    #[test]
+    fn test_evaluate_cancellable() {
+        let source = "TEXT = 1..N DIGIT -> ADD TO ROOT.digits";
+        let token = CancellationToken::new();
+        assert_eq!(
+            evaluate_cancellable(source, "42", &token).unwrap()["digits"],
+            "42"
+        );
+
+        token.clone().cancel();
+        assert!(matches!(
+            evaluate_cancellable(source, "42", &token),
+            Err(error::StrqlError::Cancelled)
+        ));
+        let options = SolverOptions {
+            cancellation: Some(token),
+            ..Default::default()
+        };
+        let query = CompiledQuery::compile(source).unwrap();
+        assert!(matches!(
+            query.matches_with("42", &options),
+            Err(error::StrqlError::Cancelled)
+        ));
+    }
+
+    #[test]
+    fn test_progress() {
+        let query = CompiledQuery::compile("TEXT = w SPLITBY \" \"\nw = WORD").unwrap();
+        let progress = Progress::new();
+        let options = SolverOptions {
+            progress: Some(progress.clone()),
+            ..Default::default()
+        };
+        assert!(query.matches_with("ab cd ef", &options).unwrap());
+        assert_eq!(progress.input_len(), 8);
+        assert!(progress.position() >= 6);
+
+        query.evaluate_with("gh", &options).unwrap();
+        assert_eq!(progress.input_len(), 2);
+        assert!(progress.position() <= 2);
+    }
+
+    #[test]
+    fn test_option_normalize_crlf() {
+        let source = r#"
+OPTION NORMALIZE_CRLF
+TEXT = l GREEDY SPLITBY NEWLINE
+l = WORD -> ADD TO ROOT.lines[]
+"#;
+        let result = evaluate_partition(source, "one\r\ntwo\r\nthree").unwrap();
+        assert_eq!(result["lines"], serde_json::json!(["one", "two", "three"]));
+        assert!(
+            evaluate_partition("TEXT = l GREEDY SPLITBY NEWLINE\nl = WORD", "one\r\ntwo").is_err()
+        );
+    }
+
+    #[test]
+    fn test_option_anycase() {
+        let source = r#"
+OPTION ANYCASE
+TEXT = "select " col " from " table
+col = "*" OR WORD
+table = WORD -> ADD TO ROOT.table
+"#;
+        let result = evaluate_partition(source, "SELECT * From Users").unwrap();
+        assert_eq!(result["table"], "Users");
+        assert!(
+            evaluate_partition(&source.replace("OPTION ANYCASE", ""), "SELECT * FROM t").is_err()
+        );
+
+        // explicit modifiers still apply inside
+        let source = "OPTION ANYCASE\nTEXT = \"id:\" UPPER \"x\"";
+        assert!(evaluate_partition(source, "ID:X").is_ok());
+        assert!(evaluate_partition(source, "ID:x").is_err());
+    }
+
+    #[test]
+    fn test_option_capture_raw() {
+        let source = r#"
+OPTION CAPTURE_RAW
+TEXT = level ": " LINE
+level = WORD -> ADD TO ROOT
+"#;
+        let result = evaluate_partition(source, "WARN: disk full").unwrap();
+        assert_eq!(
+            result,
+            serde_json::json!({"level": "WARN", "_raw": "WARN: disk full"})
+        );
+        let shape = shape::Shape::infer(&parser::parse(source).unwrap());
+        assert_eq!(
+            shape.to_json_schema()["properties"]["_raw"],
+            serde_json::json!({"type": "string"})
+        );
+    }
+
+    #[test]
+    fn test_option_skip_unmatched() {
+        let source = r#"
+OPTION SKIP_UNMATCHED
+TEXT = entry SPLITBY NEWLINE
+entry = (WORD -> ADD TO ROOT.keys[]) "=" (1..N DIGIT -> ADD TO ROOT.values[])
+"#;
+        let result = evaluate_partition(source, "a=1\nbroken\nb=2\nc=x").unwrap();
+        assert_eq!(
+            result,
+            serde_json::json!({
+                "keys": ["a", "b"],
+                "values": ["1", "2"],
+                "_unmatched": [
+                    {"text": "broken", "span": [4, 10]},
+                    {"text": "c=x", "span": [15, 18]}
+                ]
+            })
+        );
+        let strict = source.replace("OPTION SKIP_UNMATCHED", "");
+        assert!(evaluate_partition(&strict, "a=1\nbroken").is_err());
+        let shape = shape::Shape::infer(&parser::parse(source).unwrap());
+        assert_eq!(
+            shape.to_json_schema()["properties"]["_unmatched"]["items"]["properties"]["span"],
+            serde_json::json!({"type": "array", "items": {"type": "integer"}})
+        );
+    }
+
     #[test]
     fn test_quantifier_range() {
         let source = "TEXT = 2..4 DIGIT";
@@ -98,6 +361,542 @@ TEXT = ANYCASE "hello"
         assert!(evaluate_partition(source, "HeLLo").is_ok());
     }
 
+    #[test]
+    fn test_case_modifier_over_statements() {
+        // the modifier carries through every statement the expression refers to
+        let source = r#"
+TEXT = ANYCASE setting SPLITBY ", "
+setting = key "=" state -> ADD setting{} TO ROOT.settings[]
+key = "debug" OR "verbose" -> ADD TO setting.key
+state = "on" OR "off" -> ADD TO setting.state
+"#;
+        let result = evaluate_partition(source, "DEBUG=On, verbose=OFF").unwrap();
+        assert_eq!(
+            result,
+            serde_json::json!({"settings": [
+                {"key": "DEBUG", "state": "On"},
+                {"key": "verbose", "state": "OFF"}
+            ]})
+        );
+
+        // the innermost modifier wins, and the same statement can be matched under several
+        let source = "TEXT = ANYCASE (x \" \" LOWER x) \" \" x\nx = \"a\" LETTER";
+        assert!(evaluate_partition(source, "AB ab ab").is_ok());
+        assert!(evaluate_partition(source, "AB AB ab").is_err());
+        assert!(evaluate_partition(source, "ab ab AB").is_err());
+        let source = "TEXT = UPPER w\nw = 1..N LETTER";
+        assert!(evaluate_partition(source, "ABC").is_ok());
+        assert!(evaluate_partition(source, "AbC").is_err());
+    }
+
+    #[test]
+    fn test_unbounded_repetition_over_long_input() {
+        let input = "ab".repeat(50_000);
+        let result = evaluate_partition("TEXT = ANY", &input).unwrap();
+        assert_eq!(result, serde_json::json!({}));
+        let result = evaluate_partition("TEXT = ANY -> ADD TO ROOT.all", &input).unwrap();
+        assert_eq!(result["all"].as_str().unwrap().len(), input.len());
+
+        // past the minimum, the number of repetitions no longer needs tracking
+        let source = "TEXT = 3..N pair\npair = \"ab\"";
+        assert!(evaluate_partition(source, &input).is_ok());
+        assert!(evaluate_partition(source, "abab").is_err());
+        assert!(evaluate_partition("TEXT = 1..N DIGIT \"x\" 1..N DIGIT", "12x").is_err());
+    }
+
+    #[test]
+    fn test_anycase_unicode() {
+        let source = "TEXT = ANYCASE \"école\" \" \" ANYCASE \"ωmega\"";
+        assert!(evaluate_partition(source, "ÉCOLE ΩMEGA").is_ok());
+        assert!(evaluate_partition(source, "École ωMega").is_ok());
+        assert!(evaluate_partition(source, "ecole omega").is_err());
+
+        // the Kelvin sign is 3 bytes long but lowercases to `k`
+        let source = "TEXT = ANYCASE \"kb\" -> ADD TO ROOT.unit";
+        let result = evaluate_partition(source, "\u{212A}B").unwrap();
+        assert_eq!(result["unit"], "\u{212A}B");
+
+        let source = "TEXT = UPPER(\"é\") LOWER(\"É\")";
+        assert!(evaluate_partition(source, "Éé").is_ok());
+        assert!(evaluate_partition(source, "éÉ").is_err());
+    }
+
+    #[test]
+    fn test_capture_case_transform() {
+        let source = r#"
+TEXT = entry SPLITBY ", "
+entry = key "=" code
+key = WORD -> ADD LOWER(key) TO ROOT.keys[]
+code = WORD -> ADD UPPER(code) TO ROOT[key]
+"#;
+        let result = evaluate_partition(source, "Lang=en, COUNTRY=It").unwrap();
+        assert_eq!(result["lang"], "EN");
+        assert_eq!(result["country"], "IT");
+        assert_eq!(result["keys"][0], "lang");
+    }
+
+    #[test]
+    #[cfg(feature = "datetime")]
+    fn test_datetime_captures() {
+        let source = r#"
+TEXT = ip " - - [" time "]"
+ip = 1..N (DIGIT OR ".") -> ADD TO ROOT
+time = 1..N (LETTER OR DIGIT OR "/" OR ":" OR " " OR "-") -> ADD AS DATETIME("%d/%b/%Y:%H:%M:%S %z") TO ROOT
+"#;
+        let result =
+            evaluate_partition(source, "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700]").unwrap();
+        assert_eq!(result["time"], "2000-10-10T13:55:36-07:00");
+
+        let err =
+            evaluate_partition(source, "127.0.0.1 - - [31/Feb/2000:13:55:36 -0700]").unwrap_err();
+        assert!(
+            matches!(err, error::StrqlError::CaptureCastFailed { .. }),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_locale_numbers() {
+        let source = r#"
+TEXT = row SPLITBY NEWLINE
+row = WORD ": " amount " EUR"
+amount = 1..N (DIGIT OR "." OR ",") -> ADD amount AS NUMBER(locale = "de") TO ROOT.amounts[] SORTED
+"#;
+        let result =
+            evaluate_partition(source, "Miete: 1.250,00 EUR\nStrom: 89,5 EUR\nGEZ: 18 EUR")
+                .unwrap();
+        assert_eq!(result["amounts"], serde_json::json!([18, 89.5, 1250.0]));
+
+        let err = evaluate_partition(source, "GEZ: 18 EUR\nMiete: 1,250.00 EUR").unwrap_err();
+        assert!(
+            matches!(err, error::StrqlError::CaptureCastFailed { .. }),
+            "{}",
+            err
+        );
+        assert_eq!(err.spans(), vec![19..27]);
+        let location = err.location().unwrap();
+        assert_eq!((location.line, location.column), (2, 8));
+
+        let plain = "TEXT = x\nx = ANY -> ADD x AS NUMBER TO ROOT";
+        assert_eq!(evaluate_partition(plain, "-2.5e3").unwrap()["x"], -2500.0);
+        assert!(
+            parser::parse("TEXT = x\nx = ANY -> ADD x AS NUMBER(locale = \"xx\") TO ROOT").is_err()
+        );
+    }
+
+    #[test]
+    fn test_params() {
+        let source = r#"
+TEXT = field SPLITBY "${SEP}"
+field = WORD -> ADD TO ROOT.fields[]
+"#;
+        for (sep, input) in [(";", "a;b"), (" | ", "a | b")] {
+            let params = lexer::Params::from([("SEP".to_string(), sep.to_string())]);
+            let query = CompiledQuery::compile_with_params(source, &params).unwrap();
+            assert_eq!(
+                query.evaluate(input).unwrap()["fields"],
+                serde_json::json!(["a", "b"])
+            );
+        }
+        assert!(matches!(
+            CompiledQuery::compile(source),
+            Err(error::StrqlError::MissingParam { .. })
+        ));
+    }
+
+    #[test]
+    fn test_kv() {
+        let source = r#"
+TEXT = section SPLITBY (NEWLINE NEWLINE)
+section = name NEWLINE entry SPLITBY NEWLINE -> ADD section{} TO ROOT.sections[]
+name = "[" WORD "]"
+entry = KV(key = WORD, sep = " = ", value = 1..N DIGIT) -> ADD AS INT TO section
+"#;
+        let result = evaluate_partition(source, "[a]\nx = 1\ny = 22\n\n[b]\nz = 3").unwrap();
+        assert_eq!(
+            result["sections"],
+            serde_json::json!([{"x": 1, "y": 22}, {"z": 3}])
+        );
+    }
+
+    #[test]
+    fn test_anonymous_capture() {
+        let source = r#"
+TEXT = user SPLITBY NEWLINE
+user = "id=" (1..N DIGIT -> ADD AS INT TO user.id) " " (WORD -> ADD TO user.name) -> ADD user{} TO ROOT.users[]
+"#;
+        let result = evaluate_partition(source, "id=1 ann\nid=22 bob").unwrap();
+        assert_eq!(
+            result["users"],
+            serde_json::json!([{"id": 1, "name": "ann"}, {"id": 22, "name": "bob"}])
+        );
+    }
+
+    #[test]
+    fn test_positional_capture() {
+        let source = r#"
+TEXT = person SPLITBY NEWLINE
+person = WORD ": " 1..N DIGIT -> ADD $1, $3 TO ROOT.fields[]
+"#;
+        let result = evaluate_partition(source, "ann: 7\nbob: 42").unwrap();
+        assert_eq!(
+            result["fields"],
+            serde_json::json!(["ann", "7", "bob", "42"])
+        );
+    }
+
+    #[test]
+    fn test_compound_dynamic_key() {
+        let source = r#"
+TEXT = entry SPLITBY NEWLINE
+entry = host ":" port " " hits
+host = WORD
+port = 1..N DIGIT
+hits = 1..N DIGIT -> ADD TO ROOT.stats[host ":" port]
+"#;
+        let result = evaluate_partition(
+            source,
+            "db:5432 10
+web:80 7",
+        )
+        .unwrap();
+        assert_eq!(result["stats"]["db:5432"], "10");
+        assert_eq!(result["stats"]["web:80"], "7");
+    }
+
+    #[test]
+    fn test_group_by() {
+        let source = r#"
+TEXT = file SPLITBY NEWLINE
+file = name ": " row SPLITBY ", " -> ADD file{} TO ROOT.files[]
+name = WORD -> ADD TO file
+row = level " " code -> GROUP entry{} BY level TO file.by_level
+level = UPPER WORD -> ADD TO entry
+code = 1..N DIGIT -> ADD code AS INT TO entry
+"#;
+        let result = evaluate_partition(
+            source,
+            "a: WARN 1, ERROR 2, WARN 3
+b: ERROR 4",
+        )
+        .unwrap();
+
+        let a = &result["files"][0]["by_level"];
+        assert_eq!(a["WARN"].as_array().unwrap().len(), 2);
+        assert_eq!(a["WARN"][1]["code"], 3);
+        assert_eq!(a["ERROR"][0]["code"], 2);
+        assert_eq!(result["files"][1]["by_level"]["ERROR"][0]["code"], 4);
+    }
+
+    #[test]
+    fn test_unique_array_capture() {
+        let source = r#"
+TEXT = tag SPLITBY " "
+tag = WORD -> ADD LOWER(tag) TO ROOT.tags[] UNIQUE
+"#;
+        let result = evaluate_partition(source, "b a B c a").unwrap();
+        assert_eq!(result["tags"], serde_json::json!(["b", "a", "c"]));
+    }
+
+    #[test]
+    fn test_sorted_array_capture() {
+        let source = r#"
+TEXT = score SPLITBY ","
+score = 1..N DIGIT -> ADD TO ROOT.scores[] SORTED
+"#;
+        let result = evaluate_partition(source, "10,9,100,9").unwrap();
+        assert_eq!(result["scores"], serde_json::json!(["9", "9", "10", "100"]));
+
+        let source = r#"
+TEXT = label SPLITBY " "
+label = ALPHANUM -> ADD TO ROOT.labels[] SORTED DESC
+"#;
+        let result = evaluate_partition(source, "b 2 a 10").unwrap();
+        assert_eq!(result["labels"], serde_json::json!(["b", "a", "10", "2"]));
+    }
+
+    #[test]
+    fn test_json_value() {
+        let source = r#"
+TEXT = entry SPLITBY NEWLINE
+entry = level " " payload -> ADD entry{} TO ROOT.entries[]
+level = WORD -> ADD TO entry
+payload = JSONVALUE -> ADD payload AS JSON TO entry
+"#;
+        let input = "INFO {\"user\": \"a}b\", \"ids\": [1, 2]}\nWARN [true, null]\nDEBUG \"x\"\nERROR -1.5e3";
+        let result = evaluate_partition(source, input).unwrap();
+        assert_eq!(
+            result["entries"],
+            serde_json::json!([
+                {"level": "INFO", "payload": {"user": "a}b", "ids": [1, 2]}},
+                {"level": "WARN", "payload": [true, null]},
+                {"level": "DEBUG", "payload": "x"},
+                {"level": "ERROR", "payload": -1500.0},
+            ])
+        );
+
+        // values are only matched whole, and only when valid
+        assert!(evaluate_partition("TEXT = JSONVALUE \"}\"", "{\"a\": 1}}").is_ok());
+        assert!(evaluate_partition("TEXT = JSONVALUE ANY", "{\"a\": }").is_err());
+        assert!(evaluate_partition("TEXT = JSONVALUE", "[1, 2").is_err());
+        let plain = evaluate_partition("TEXT = j\nj = JSONVALUE -> ADD TO ROOT", "{}").unwrap();
+        assert_eq!(plain["j"], "{}");
+    }
+
+    #[test]
+    fn test_encoded_blobs() {
+        let source = r#"
+TEXT = header "." payload "." BASE64 " " key
+header = BASE64 -> ADD AS DECODED TO ROOT
+payload = BASE64 -> ADD AS DECODED TO ROOT
+key = HEXBLOB -> ADD AS DECODED TO ROOT
+"#;
+        let result =
+            evaluate_partition(source, "eyJhbGciOiJIUzI1NiJ9.eyJzdWIiOiI0MiJ9.c2ln 6b6579")
+                .unwrap();
+        assert_eq!(
+            result,
+            serde_json::json!({"header": r#"{"alg":"HS256"}"#, "payload": r#"{"sub":"42"}"#, "key": "key"})
+        );
+
+        // runs are matched whole, and must have a valid length
+        assert!(evaluate_partition("TEXT = HEXBLOB", "abc").is_err());
+        assert!(evaluate_partition("TEXT = HEXBLOB HEXBLOB", "abcd").is_err());
+        assert!(evaluate_partition("TEXT = BASE64", "aGVsbG8=").is_ok());
+        assert!(evaluate_partition("TEXT = BASE64", "aGVsb").is_err());
+
+        // well-formed runs can still encode something that isn't text
+        let err = evaluate_partition("TEXT = b\nb = BASE64 -> ADD AS DECODED TO ROOT", "/w==")
+            .unwrap_err();
+        assert!(
+            matches!(err, error::StrqlError::CaptureCastFailed { .. }),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_evaluate_bytes() {
+        // a length-prefixed frame: magic, payload length, payload, then a text trailer
+        let source = r#"
+TEXT = BYTE(0xCA) BYTE(0xFE) len payload "|" name NEWLINE
+len = BYTES(1) -> ADD TO ROOT
+payload = BYTES(3) -> ADD TO ROOT
+name = 1..N LETTER -> ADD TO ROOT
+"#;
+        let input = [0xca, 0xfe, 0x03, 0x00, 0xff, b'x', b'|', b'a', b'b', b'\n'];
+        let result = evaluate_bytes(source, &input).unwrap();
+        assert_eq!(
+            result,
+            serde_json::json!({"len": "\u{3}", "payload": [0, 255, 120], "name": "ab"})
+        );
+
+        // text inputs only hold whole characters
+        assert!(evaluate_partition("TEXT = BYTES(1) BYTES(1)", "é").is_err());
+        assert!(evaluate_partition("TEXT = BYTES(2)", "é").is_ok());
+        assert!(evaluate_partition("TEXT = BYTE(0xC3) BYTE(0xA9)", "é").is_err());
+        assert!(evaluate_bytes("TEXT = BYTE(0xC3) BYTE(0xA9)", "é".as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_evaluate_streaming() {
+        let source = r#"
+TEXT = host NEWLINE entry SPLITBY NEWLINE
+host = WORD -> ADD TO ROOT
+entry = level " " code -> ADD entry{} TO ROOT.log.entries[]
+level = WORD -> ADD TO entry
+code = 1..N DIGIT -> ADD AS INT TO entry.codes[]
+"#;
+        let query = CompiledQuery::compile(source).unwrap();
+        let mut records = Vec::new();
+        let rest = query
+            .evaluate_streaming("db\nWARN 1\nINFO 2", "ROOT.log.entries[]", |record| {
+                records.push(record)
+            })
+            .unwrap();
+        assert_eq!(
+            records,
+            [
+                serde_json::json!({"level": "WARN", "codes": [1]}),
+                serde_json::json!({"level": "INFO", "codes": [2]}),
+            ]
+        );
+        assert_eq!(rest, serde_json::json!({"host": "db", "log": {}}));
+    }
+
+    #[test]
+    fn test_evaluate_with_sink() {
+        let source = r#"
+TEXT = host NEWLINE entry SPLITBY NEWLINE
+host = WORD -> ADD TO ROOT
+entry = level " " code -> ADD entry{} TO ROOT.entries[]
+level = WORD -> ADD TO entry
+code = 1..N DIGIT -> ADD AS INT TO ROOT.by_level[level]
+"#;
+        let input = "db\nWARN 1\nINFO 2";
+        let mut events = Vec::new();
+        evaluate_with_sink(source, input, |event| {
+            events.push((
+                event.path.to_vec(),
+                event.value.clone(),
+                &input[event.span.clone()],
+                event.statement.to_string(),
+            ))
+        })
+        .unwrap();
+
+        let field = |name: &str| PathStep::Field(name.to_string());
+        assert_eq!(
+            events[..4],
+            [
+                (
+                    vec![field("host")],
+                    serde_json::json!("db"),
+                    "db",
+                    "host".into()
+                ),
+                (
+                    vec![field("entries"), PathStep::Index(0)],
+                    serde_json::json!({}),
+                    "WARN 1",
+                    "entry".into()
+                ),
+                (
+                    vec![field("entries"), PathStep::Index(0), field("level")],
+                    serde_json::json!("WARN"),
+                    "WARN",
+                    "level".into()
+                ),
+                (
+                    vec![field("by_level"), field("WARN")],
+                    serde_json::json!(1),
+                    "1",
+                    "code".into()
+                ),
+            ]
+        );
+        assert_eq!(events.len(), 7);
+    }
+
+    #[test]
+    fn test_evaluate_segments() {
+        let source = r#"
+TEXT = method path 0..1 query status
+method = WORD -> ADD TO ROOT
+path = 1..N ("/" WORD) -> ADD TO ROOT
+query = "?" ANY -> ADD TO ROOT
+status = 3..3 DIGIT -> ADD AS INT TO ROOT
+"#;
+        let result = evaluate_segments(source, &["GET", "/a/b", "?x=1", "200"]).unwrap();
+        assert_eq!(
+            result,
+            serde_json::json!({"method": "GET", "path": "/a/b", "query": "?x=1", "status": 200})
+        );
+        assert!(evaluate_segments(source, &["GET", "/a/b", "200"]).is_ok());
+        // `query` can only start a segment
+        assert!(evaluate_segments(source, &["GET", "/a/b?x=1", "200"]).is_err());
+
+        // statements can span segments, but literals can't
+        let source = "TEXT = pair\npair = WORD \"=\" WORD -> ADD TO ROOT";
+        assert!(evaluate_segments(source, &["a", "=", "b"]).is_ok());
+        assert!(evaluate_segments(source, &["a=", "b"]).is_ok());
+        assert!(evaluate_segments("TEXT = \"ab\"", &["a", "b"]).is_err());
+    }
+
+    #[test]
+    fn test_provenance() {
+        let source = r#"
+TEXT = row SPLITBY NEWLINE
+row = kind " " size -> GROUP row{} BY kind TO ROOT.rows
+kind = WORD -> ADD TO row
+size = 1..N DIGIT -> ADD size AS INT TO ROOT.sizes[] SORTED
+"#;
+        let query = CompiledQuery::compile(source).unwrap();
+        let options = SolverOptions {
+            provenance: true,
+            ..Default::default()
+        };
+        let result = query
+            .evaluate_with("disk 20\nram 8\ndisk 100", &options)
+            .unwrap();
+        assert_eq!(
+            result["rows"]["disk"][1]["kind"],
+            serde_json::json!({"value": "disk", "rule": "kind"})
+        );
+        let sizes: Vec<_> = result["sizes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|size| size["value"].clone())
+            .collect();
+        assert_eq!(sizes, [8, 20, 100]);
+        assert_eq!(result["sizes"][0]["rule"], "size");
+
+        let plain = query.evaluate("disk 20").unwrap();
+        assert_eq!(plain["rows"]["disk"][0]["kind"], "disk");
+    }
+
+    #[test]
+    fn test_nullable_capture() {
+        let source = r#"
+TEXT = row SPLITBY NEWLINE
+row = name ";" 0..1 age ";" note -> ADD row{} TO ROOT.rows[]
+name = WORD -> ADD TO row
+age = 1..N DIGIT -> ADD age AS INT TO row NULLABLE
+note = 0..N LETTER -> ADD TO row NULLABLE
+"#;
+        let result = evaluate_partition(source, "ann;31;hi\nbob;;").unwrap();
+        assert_eq!(
+            result["rows"],
+            serde_json::json!([
+                {"name": "ann", "age": 31, "note": "hi"},
+                {"name": "bob", "age": null, "note": null},
+            ])
+        );
+
+        let source = r#"
+TEXT = tag SPLITBY ","
+tag = 0..N LETTER -> ADD TO ROOT.tags[] NULLABLE
+"#;
+        let result = evaluate_partition(source, "a,,b").unwrap();
+        assert_eq!(result["tags"], serde_json::json!(["a", null, "b"]));
+    }
+
+    #[test]
+    fn test_once_capture() {
+        let source = r#"
+TEXT = field SPLITBY NEWLINE
+field = title OR author
+title = "title: " 1..N LETTER -> ADD TO ROOT.name ONCE
+author = "author: " 1..N LETTER -> ADD TO ROOT.name
+"#;
+        let result = evaluate_partition(source, "title: dune").unwrap();
+        assert_eq!(result["name"], "title: dune");
+
+        let input = "title: dune\nauthor: frank";
+        let error = evaluate_partition(source, input).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Field ROOT.name is captured twice, but is captured ONCE"
+        );
+        assert_eq!(error.spans(), vec![0..11, 12..25]);
+        let query = CompiledQuery::compile(source).unwrap();
+        assert!(query.evaluate_with_sink(input, |_| {}).is_err());
+
+        // the fields of different array elements are different fields
+        let source = r#"
+TEXT = row SPLITBY NEWLINE
+row = name -> ADD row{} TO ROOT.rows[]
+name = 1..N LETTER -> ADD TO row ONCE
+"#;
+        let result = evaluate_partition(source, "ann\nbob").unwrap();
+        assert_eq!(
+            result["rows"],
+            serde_json::json!([{"name": "ann"}, {"name": "bob"}])
+        );
+    }
+
     #[test]
     fn test_upper() {
         let source = r#"
@@ -165,6 +964,12 @@ fourth = "D"
         let source = "TEXT = LINE";
         assert!(evaluate_partition(source, "hello world").is_ok());
 
+        // PARAGRAPH
+        let source = "TEXT = p SPLITBY (NEWLINE NEWLINE)\np = PARAGRAPH -> ADD TO ROOT.p[]";
+        let result = evaluate_partition(source, "a\nb\n\nc").unwrap();
+        assert_eq!(result["p"], serde_json::json!(["a\nb", "c"]));
+        assert!(evaluate_partition("TEXT = UPPER PARAGRAPH", "A\nb").is_err());
+
         // NEWLINE
         let source = r#"TEXT = "a" NEWLINE "b""#;
         assert!(evaluate_partition(source, "a\nb").is_ok());
@@ -184,6 +989,66 @@ fourth = "D"
         assert!(evaluate_partition(source, "").is_ok());
     }
 
+    #[test]
+    fn test_radix_numbers() {
+        let source = "TEXT = HEXNUM";
+        assert!(evaluate_partition(source, "0xDEADbeef").is_ok());
+        assert!(evaluate_partition(source, "ff").is_ok());
+        assert!(evaluate_partition(source, "0xg").is_err());
+
+        let source = "TEXT = BINNUM";
+        assert!(evaluate_partition(source, "0b1010").is_ok());
+        assert!(evaluate_partition(source, "102").is_err());
+
+        let source = "TEXT = OCTNUM";
+        assert!(evaluate_partition(source, "0o755").is_ok());
+        assert!(evaluate_partition(source, "8").is_err());
+
+        let source = "TEXT = UPPER HEXNUM";
+        assert!(evaluate_partition(source, "FF").is_ok());
+        assert!(evaluate_partition(source, "ff").is_err());
+    }
+
+    #[test]
+    fn test_cast_as_int() {
+        let source = r#"
+TEXT = addr " " raw " " count
+addr = HEXNUM -> ADD addr AS INT TO ROOT
+raw = HEXNUM -> ADD raw AS INT(16) TO ROOT
+count = 1..N DIGIT -> ADD count AS INT TO ROOT
+"#;
+        let result = evaluate_partition(source, "0x1F ff 42").unwrap();
+        assert_eq!(result["addr"], 31);
+        assert_eq!(result["raw"], 255);
+        assert_eq!(result["count"], 42);
+
+        // `0b` is hex digits when a hex number is read
+        let result = evaluate_partition(source, "0x1F 0b1 42").unwrap();
+        assert_eq!(result["raw"], 0xb1);
+
+        let err = evaluate_partition(source, "0x1F 1ffffffffffffffff 42").unwrap_err();
+        assert!(matches!(err, error::StrqlError::CaptureCastFailed { .. }));
+    }
+
+    #[test]
+    fn test_cast_unprefixed_radix_numbers() {
+        let source = "TEXT = h -> ADD h AS INT TO ROOT\nh = HEXNUM";
+        assert_eq!(evaluate_partition(source, "10").unwrap()["h"], 16);
+        assert_eq!(evaluate_partition(source, "ff").unwrap()["h"], 255);
+        assert_eq!(evaluate_partition(source, "0x1f").unwrap()["h"], 31);
+
+        let source = "TEXT = b\nb = BINNUM -> ADD b AS INT TO ROOT";
+        assert_eq!(evaluate_partition(source, "101").unwrap()["b"], 5);
+        assert_eq!(evaluate_partition(source, "0b101").unwrap()["b"], 5);
+
+        let source = "TEXT = (OCTNUM -> ADD o AS INT TO ROOT)";
+        assert_eq!(evaluate_partition(source, "17").unwrap()["o"], 15);
+
+        // an explicit radix still wins
+        let source = "TEXT = b\nb = BINNUM -> ADD b AS INT(10) TO ROOT";
+        assert_eq!(evaluate_partition(source, "101").unwrap()["b"], 101);
+    }
+
     #[test]
     fn test_capture_to_root() {
         let source = r#"
@@ -261,4 +1126,254 @@ w = GREEDY ANY -> ADD TO ROOT.results[]
         assert_eq!(results.len(), 1, "LAZY SPLITBY should produce 1 element");
         assert_eq!(results[0], "a. b. c.");
     }
+
+    #[test]
+    fn test_alternation_biases() {
+        let query = |bias: &str| {
+            format!(
+                "TEXT = place rest\nplace = \"in\" {} OR \"inside\" -> ADD TO ROOT.place\n\
+                 rest = ANY -> ADD TO ROOT.rest",
+                bias
+            )
+        };
+        assert!(matches!(
+            evaluate_partition(&query(""), "inside"),
+            Err(error::StrqlError::AmbiguousParse { .. })
+        ));
+        assert_eq!(
+            evaluate_partition(&query("FIRST"), "inside").unwrap(),
+            serde_json::json!({"place": "in", "rest": "side"})
+        );
+        assert_eq!(
+            evaluate_partition(&query("LONGEST"), "inside").unwrap(),
+            serde_json::json!({"place": "inside", "rest": ""})
+        );
+    }
+
+    #[test]
+    fn test_alternation_weights() {
+        let query = |weights: (u32, u32)| {
+            format!(
+                "TEXT = level rest\n\
+                 level = \"ERR\" WEIGHT {} OR \"ERROR\" WEIGHT {} -> ADD TO ROOT.level\n\
+                 rest = ANY -> ADD TO ROOT.rest",
+                weights.0, weights.1
+            )
+        };
+        assert_eq!(
+            evaluate_partition(&query((1, 3)), "ERROR").unwrap(),
+            serde_json::json!({"level": "ERROR", "rest": ""})
+        );
+        assert_eq!(
+            evaluate_partition(&query((3, 1)), "ERROR").unwrap(),
+            serde_json::json!({"level": "ERR", "rest": "OR"})
+        );
+        assert!(matches!(
+            evaluate_partition(&query((2, 2)), "ERROR"),
+            Err(error::StrqlError::AmbiguousParse { .. })
+        ));
+    }
+
+    #[test]
+    fn test_preceded_by() {
+        let query = r#"
+TEXT = part SPLITBY " "
+part = 0..1 "$" amount
+amount = price FIRST OR count
+price = PRECEDEDBY "$" 1..N DIGIT -> ADD TO ROOT.prices[]
+count = 1..N DIGIT -> ADD TO ROOT.counts[]
+"#;
+        assert_eq!(
+            evaluate_partition(query, "$12 34 $5").unwrap(),
+            serde_json::json!({"prices": ["12", "5"], "counts": ["34"]})
+        );
+        // the lookbehind can't see past the start of the input
+        assert!(evaluate_partition("TEXT = PRECEDEDBY \"a\" \"b\"", "b").is_err());
+        assert!(evaluate_partition("TEXT = \"a\" PRECEDEDBY (ANYCASE \"A\") \"b\"", "ab").is_ok());
+        assert!(matches!(
+            evaluate_partition("TEXT = \"a\" PRECEDEDBY WORD \"b\"", "ab"),
+            Err(error::StrqlError::VariableLengthLookbehind { .. })
+        ));
+    }
+
+    #[test]
+    fn test_separated_repetition() {
+        let query =
+            "TEXT = 4..4 octet SEP \".\"\noctet = 1..3 DIGIT -> ADD AS INT TO ROOT.octets[]";
+        assert_eq!(
+            evaluate_partition(query, "10.0.0.255").unwrap(),
+            serde_json::json!({"octets": [10, 0, 0, 255]})
+        );
+        assert!(evaluate_partition(query, "10.0.0").is_err());
+        assert!(evaluate_partition(query, "10.0.0.1.2").is_err());
+
+        let query =
+            "TEXT = \"[\" 0..2 item SEP \", \" \"]\"\nitem = 1..N LETTER -> ADD TO ROOT.items[]";
+        assert_eq!(
+            evaluate_partition(query, "[]").unwrap(),
+            serde_json::json!({})
+        );
+        assert_eq!(
+            evaluate_partition(query, "[a, b]").unwrap(),
+            serde_json::json!({"items": ["a", "b"]})
+        );
+        assert!(evaluate_partition(query, "[a, b, c]").is_err());
+    }
+
+    #[test]
+    fn test_permute() {
+        let query = r#"
+TEXT = PERMUTE(host, port, user)
+host = "host=" (WORD -> ADD TO ROOT.host) NEWLINE
+port = "port=" (1..N DIGIT -> ADD AS INT TO ROOT.port) NEWLINE
+user = "user=" (WORD -> ADD TO ROOT.user) NEWLINE
+"#;
+        let expected = serde_json::json!({"host": "ex", "port": 80, "user": "bob"});
+        for input in [
+            "host=ex\nport=80\nuser=bob\n",
+            "port=80\nuser=bob\nhost=ex\n",
+            "user=bob\nhost=ex\nport=80\n",
+        ] {
+            assert_eq!(evaluate_partition(query, input).unwrap(), expected);
+        }
+        assert!(evaluate_partition(query, "port=80\nhost=ex\n").is_err());
+        assert!(evaluate_partition(query, "port=80\nhost=ex\nuser=a\nuser=b\n").is_err());
+    }
+
+    #[test]
+    fn test_fields() {
+        let query = r#"
+TEXT = FIELDS {
+    host = "Host: " (LINE -> ADD TO ROOT.host) NEWLINE
+    port = 0..1 ("Port: " (1..N DIGIT -> ADD AS INT TO ROOT.port) NEWLINE); agent = 0..1 ("Agent: " LINE NEWLINE)
+}
+"#;
+        assert_eq!(
+            evaluate_partition(query, "Port: 80\nHost: ex.com\n").unwrap(),
+            serde_json::json!({"host": "ex.com", "port": 80})
+        );
+        assert_eq!(
+            evaluate_partition(query, "Agent: x\nHost: ex.com\n").unwrap(),
+            serde_json::json!({"host": "ex.com"})
+        );
+        assert!(evaluate_partition(query, "Port: 80\n").is_err());
+        assert!(evaluate_partition(query, "Host: a\nHost: b\n").is_err());
+    }
+
+    #[test]
+    fn test_bof_eof() {
+        let query = r#"
+TEXT = item SPLITBY ","
+item = first FIRST OR last FIRST OR WORD
+first = BOF WORD -> ADD TO ROOT.first
+last = WORD EOF -> ADD TO ROOT.last
+"#;
+        assert_eq!(
+            evaluate_partition(query, "a,b,c").unwrap(),
+            serde_json::json!({"first": "a", "last": "c"})
+        );
+        assert!(evaluate_partition("TEXT = BOF EOF", "").is_ok());
+        assert!(evaluate_partition("TEXT = \"a\" BOF \"b\"", "ab").is_err());
+    }
+
+    #[test]
+    fn test_contline() {
+        let query = r#"
+TEXT = record SPLITBY NEWLINE
+record = (LINE -> ADD TO entry.message) (GREEDY 0..N CONTLINE -> ADD TO entry.trace) -> ADD entry{} TO ROOT[]
+"#;
+        let input = "start\nException: boom\n\tat a.B.c(B.java:1)\n  at d.E.f(E.java:2)\ndone";
+        assert_eq!(
+            evaluate_partition(query, input).unwrap(),
+            serde_json::json!([
+                {"message": "start", "trace": ""},
+                {"message": "Exception: boom", "trace": "\n\tat a.B.c(B.java:1)\n  at d.E.f(E.java:2)"},
+                {"message": "done", "trace": ""}
+            ])
+        );
+        assert!(evaluate_partition("TEXT = \"a\" CONTLINE", "a\nb").is_err());
+        assert!(evaluate_partition("TEXT = \"a\" CONTLINE", "a\n ").is_ok());
+    }
+
+    #[test]
+    fn test_evaluate_match() {
+        let source = "TEXT = key \"=\" value\n\
+                      key = 1..N LETTER -> ADD TO ROOT.key\n\
+                      value = 1..N DIGIT -> ADD TO ROOT.value";
+        let result = evaluate_match(source, "port=80").unwrap();
+        assert_eq!(
+            result.captures(),
+            [
+                CaptureSpan {
+                    path: vec![PathStep::Field("key".to_string())],
+                    span: 0..4,
+                    statement: "key".to_string(),
+                },
+                CaptureSpan {
+                    path: vec![PathStep::Field("value".to_string())],
+                    span: 5..7,
+                    statement: "value".to_string(),
+                },
+            ]
+        );
+        assert!(result.stats().memo_entries > 0);
+        assert_eq!(
+            result.into_json(),
+            evaluate_partition(source, "port=80").unwrap()
+        );
+
+        // the paths lead to the values once arrays are deduplicated, sorted and grouped
+        let source = "TEXT = entry SPLITBY \",\"\n\
+                      entry = kind \":\" num -> GROUP entry{} BY kind TO ROOT.by_kind\n\
+                      kind = k -> ADD TO entry.kind\n\
+                      k = 1..N LETTER -> ADD TO ROOT.kinds[] UNIQUE\n\
+                      num = 1..N DIGIT -> ADD AS INT TO ROOT.nums[] SORTED";
+        let input = "b:3,a:1,b:2";
+        let result = evaluate_match(source, input).unwrap();
+        assert_eq!(
+            result.value(),
+            &serde_json::json!({
+                "by_kind": {"b": [{"kind": "b"}, {"kind": "b"}], "a": [{"kind": "a"}]},
+                "kinds": ["b", "a"],
+                "nums": [1, 2, 3]
+            })
+        );
+        assert_eq!(result.captures().len(), 12);
+        for capture in result.captures() {
+            let mut value = result.value();
+            for step in &capture.path {
+                value = match step {
+                    PathStep::Field(name) => &value[name.as_str()],
+                    PathStep::Index(i) => &value[*i],
+                };
+            }
+            let text = &input[capture.span.clone()];
+            match value {
+                serde_json::Value::String(s) => assert_eq!(s, text),
+                serde_json::Value::Number(n) => assert_eq!(n.to_string(), text),
+                serde_json::Value::Object(entry) => assert_eq!(entry["kind"], text[..1]),
+                other => panic!("{:?} leads to {}", capture, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_peg_strategy() {
+        let source = "OPTION STRATEGY PEG\nTEXT = place rest\n\
+                      place = \"in\" OR \"inside\" -> ADD TO ROOT.place\n\
+                      rest = ANY -> ADD TO ROOT.rest";
+        assert_eq!(
+            evaluate_partition(source, "inside").unwrap(),
+            serde_json::json!({"place": "in", "rest": "side"})
+        );
+
+        // equally preferred parses, which only CFG reports
+        let source = "TEXT = x y\nx = 0..1 \"a\" -> ADD TO ROOT.x\ny = 0..1 \"a\" -> ADD TO ROOT.y";
+        assert!(matches!(
+            evaluate_partition(source, "a"),
+            Err(error::StrqlError::AmbiguousParse { .. })
+        ));
+        let peg = format!("OPTION STRATEGY PEG\n{}", source);
+        assert!(evaluate_partition(&peg, "a").is_ok());
+    }
 }
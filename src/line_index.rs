@@ -0,0 +1,55 @@
+/// A 1-based line and column in a text. Columns count characters, not bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Converts byte offsets of a text into [`Location`]s.
+#[derive(Debug, Clone)]
+pub struct LineIndex<'a> {
+    text: &'a str,
+    /// byte offset at which each line starts
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(text: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        LineIndex { text, line_starts }
+    }
+
+    /// The location of the character at `offset`, clamped to the end of the text.
+    pub fn locate(&self, offset: usize) -> Location {
+        let mut offset = offset.min(self.text.len());
+        while !self.text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let column = self.text[self.line_starts[line]..offset].chars().count();
+        Location {
+            line: line + 1,
+            column: column + 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locates_offsets() {
+        let index = LineIndex::new("ab\ncdé\n\nf");
+        let at = |line, column| Location { line, column };
+        assert_eq!(index.locate(0), at(1, 1));
+        assert_eq!(index.locate(2), at(1, 3));
+        assert_eq!(index.locate(3), at(2, 1));
+        assert_eq!(index.locate(7), at(2, 4));
+        assert_eq!(index.locate(8), at(3, 1));
+        assert_eq!(index.locate(9), at(4, 1));
+        assert_eq!(index.locate(100), at(4, 2));
+    }
+}
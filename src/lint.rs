@@ -0,0 +1,936 @@
+//! Checks for queries that are valid but likely not what was meant, like repetitions that
+//! make inputs ambiguous; see [`lint`].
+
+use crate::ast::{Builtin, PathSegment, Pattern, PatternKind, Program, QuantifierBias, Statement};
+use crate::error::StrqlResult;
+use crate::visit::{walk_pattern, walk_statement, Visitor};
+use crate::{CompiledQuery, CustomBuiltins};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// A check run by [`lint`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    /// two unbounded `ANY` repetitions in a row, which can split the text between them anywhere
+    AdjacentUnboundedAny,
+    /// a `SPLITBY` without `LAZY` or `GREEDY` whose items can start like its separator
+    SplitbyWithoutBias,
+    /// a capture to a field of `ROOT` that another statement captures to as well
+    ShadowedRootField,
+    /// a statement, other than `TEXT`, written as a repetition from 0 like `0..N DIGIT`, without
+    /// a capture: it can match empty text, where leaving it out would be clearer
+    EmptyMatch,
+    /// parentheses that don't change what the pattern in them matches, nor how it is
+    /// disambiguated
+    RedundantGroup,
+    /// a statement named like a custom builtin, which references no longer match, or an object
+    /// captured as `root`, which capture paths take to be `ROOT`
+    ShadowedName,
+    /// captures storing different kinds of things (values, objects or arrays) at the same
+    /// path, which replace each other
+    ConflictingCaptures,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 7] = [
+        Rule::AdjacentUnboundedAny,
+        Rule::SplitbyWithoutBias,
+        Rule::ShadowedRootField,
+        Rule::EmptyMatch,
+        Rule::RedundantGroup,
+        Rule::ShadowedName,
+        Rule::ConflictingCaptures,
+    ];
+
+    /// The name rules are configured by, like `splitby_without_bias`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::AdjacentUnboundedAny => "adjacent_unbounded_any",
+            Rule::SplitbyWithoutBias => "splitby_without_bias",
+            Rule::ShadowedRootField => "shadowed_root_field",
+            Rule::EmptyMatch => "empty_match",
+            Rule::RedundantGroup => "redundant_group",
+            Rule::ShadowedName => "shadowed_name",
+            Rule::ConflictingCaptures => "conflicting_captures",
+        }
+    }
+}
+
+/// The code of the rule, like `lint::splitby_without_bias`.
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "lint::{}", self.name())
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let name = name.strip_prefix("lint::").unwrap_or(name);
+        Rule::ALL
+            .into_iter()
+            .find(|rule| rule.name() == name)
+            .ok_or_else(|| {
+                let names: Vec<_> = Rule::ALL.iter().map(|rule| rule.name()).collect();
+                format!(
+                    "unknown lint rule '{}' (expected: {})",
+                    name,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Which rules [`lint`] runs: all of them unless allowed.
+#[derive(Debug, Clone, Default)]
+pub struct LintConfig {
+    pub allowed: HashSet<Rule>,
+}
+
+impl LintConfig {
+    pub fn allow(mut self, rule: Rule) -> Self {
+        self.allowed.insert(rule);
+        self
+    }
+}
+
+/// A finding of [`lint`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lint {
+    pub rule: Rule,
+    pub message: String,
+    /// byte range of the query
+    pub span: Range<usize>,
+    /// other parts of the query the finding is about, with a label for each
+    pub related: Vec<(Range<usize>, String)>,
+    pub help: String,
+    /// edits of the query that fix the problem, empty when it needs a decision only the
+    /// author can make; see [`apply_fixes`]
+    pub fix: Vec<Edit>,
+}
+
+/// A replacement of part of the query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    /// byte range of the query, empty for insertions
+    pub span: Range<usize>,
+    pub replacement: String,
+}
+
+impl Edit {
+    fn insert(at: usize, text: &str) -> Self {
+        Edit {
+            span: at..at,
+            replacement: text.to_string(),
+        }
+    }
+
+    fn overlaps(&self, other: &Edit) -> bool {
+        self.span.start == other.span.start
+            || (self.span.start < other.span.end && other.span.start < self.span.end)
+    }
+}
+
+/// Applies the fixes of `lints` to `source`, the query they were found in. The rest of the
+/// text, comments included, is kept as it is. Fixes overlapping one applied before are
+/// skipped, as linting the result again will tell.
+pub fn apply_fixes(source: &str, lints: &[Lint]) -> String {
+    let mut edits: Vec<&Edit> = Vec::new();
+    for lint in lints {
+        if !lint
+            .fix
+            .iter()
+            .any(|edit| edits.iter().any(|applied| applied.overlaps(edit)))
+        {
+            edits.extend(&lint.fix);
+        }
+    }
+    edits.sort_by_key(|edit| std::cmp::Reverse(edit.span.start));
+    let mut fixed = source.to_string();
+    for edit in edits {
+        fixed.replace_range(edit.span.clone(), &edit.replacement);
+    }
+    fixed
+}
+
+/// Runs the rules `config` doesn't allow on `program`, returning what they found ordered by
+/// position. The program has to compile, with `builtins` for the names it doesn't define.
+pub fn lint(
+    program: &Program,
+    builtins: &CustomBuiltins,
+    config: &LintConfig,
+) -> StrqlResult<Vec<Lint>> {
+    let query = CompiledQuery::with_builtins(program, builtins)?;
+    let mut linter = Linter {
+        statements: program.variable_map(),
+        lints: Vec::new(),
+        exclusive: HashSet::new(),
+        splits: HashMap::new(),
+    };
+    linter.visit_program(program);
+    let mut lints = linter.shadowed_fields(program);
+    lints.append(&mut linter.conflicting_captures(program, &lints));
+    lints.append(&mut shadowed_names(program, builtins));
+    lints.append(&mut linter.lints);
+
+    for tail in query.overlapping_splits() {
+        let (span, fix) = match linter.splits.get(&tail) {
+            Some(span) => (span.clone(), vec![Edit::insert(tail.start, "GREEDY ")]),
+            None => (tail, Vec::new()),
+        };
+        lints.push(Lint {
+            rule: Rule::SplitbyWithoutBias,
+            message: "The items of this SPLITBY can start like its separator, so the text can be \
+                      split into items in several ways"
+                .to_string(),
+            span,
+            help: "Add LAZY before SPLITBY to prefer fewer items, or GREEDY to prefer more"
+                .to_string(),
+            related: Vec::new(),
+            fix,
+        });
+    }
+
+    for name in query.nullable_statements() {
+        let Some(stmt) = program.statements.iter().find(|s| s.name == name) else {
+            continue;
+        };
+        // only the statements written as `0..<max> <pattern>`, without a capture: elsewhere
+        // empty text is what a builtin like LINE matches, or an empty value worth capturing
+        if name != "TEXT"
+            && written_in(program, stmt)
+            && stmt.capture.is_none()
+            && repeats_from_zero(program, &stmt.pattern)
+        {
+            lints.push(Lint {
+                rule: Rule::EmptyMatch,
+                message: format!("`{}` can match empty text", name),
+                span: stmt.span.clone(),
+                help: "Repeat at least once with `1..N`, or leave it out where it is optional \
+                       instead"
+                    .to_string(),
+                related: Vec::new(),
+                fix: Vec::new(),
+            });
+        }
+    }
+
+    lints.retain(|lint| !config.allowed.contains(&lint.rule));
+    // the items of SPLITBY are visited twice, as the parser repeats them
+    lints.sort_by_key(|lint| (lint.span.start, lint.span.end));
+    lints.dedup();
+    lints.sort_by_key(|lint| (lint.span.start, lint.span.end));
+    Ok(lints)
+}
+
+/// Whether `stmt` is written in the query, rather than imported with a grammar or made up by
+/// the parser for an inlined statement or a `KV`.
+fn written_in(program: &Program, stmt: &Statement) -> bool {
+    program
+        .source
+        .get(stmt.span.clone())
+        .is_some_and(|text| text.trim_start_matches('`').starts_with(stmt.name.as_str()))
+}
+
+/// Whether `pattern` is a repetition written with a minimum of 0, like `GREEDY 0..N DIGIT`.
+fn repeats_from_zero(program: &Program, pattern: &Pattern) -> bool {
+    if !matches!(pattern.node, PatternKind::Repetition { min: Some(0), .. }) {
+        return false;
+    }
+    let Some(text) = program.source.get(pattern.span.clone()) else {
+        return false;
+    };
+    let unbiased = ["GREEDY", "LAZY"]
+        .iter()
+        .find_map(|bias| {
+            text.get(..bias.len())
+                .filter(|word| word.eq_ignore_ascii_case(bias))
+                .map(|_| text[bias.len()..].trim_start())
+        })
+        .unwrap_or(text);
+    unbiased.starts_with("0..")
+}
+
+/// The statements named like a custom builtin, and the objects captured under the name `root`,
+/// which capture paths can't refer to. Statements named like a builtin, `TEXT` or `ROOT` are
+/// parse errors unless escaped, and escaping them is how the name is meant to be used.
+fn shadowed_names(program: &Program, builtins: &CustomBuiltins) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    for stmt in &program.statements {
+        let escaped = program
+            .source
+            .get(stmt.span.start..)
+            .is_some_and(|rest| rest.starts_with('`'));
+        if escaped || builtins.get(&stmt.name).is_none() {
+            continue;
+        }
+        lints.push(Lint {
+            rule: Rule::ShadowedName,
+            message: format!(
+                "`{}` shadows the custom builtin of the same name, which references to `{}` \
+                 no longer match",
+                stmt.name, stmt.name
+            ),
+            span: stmt.span.start..stmt.span.start + stmt.name.len(),
+            help: "Rename the statement".to_string(),
+            related: Vec::new(),
+            fix: Vec::new(),
+        });
+    }
+    for stmt in &program.statements {
+        let Some(capture) = &stmt.capture else {
+            continue;
+        };
+        if capture.is_object && capture.name.eq_ignore_ascii_case("root") {
+            lints.push(Lint {
+                rule: Rule::ShadowedName,
+                message: format!(
+                    "`{}` captures an object named `{}`, which capture paths take to be ROOT",
+                    stmt.name, capture.name
+                ),
+                span: stmt.span.clone(),
+                help: "Rename the object, so that the captures into it can name it".to_string(),
+                related: Vec::new(),
+                fix: Vec::new(),
+            });
+        }
+    }
+    lints
+}
+
+/// What a capture stores at a path, or what the paths of the captures under it make it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Node {
+    Value,
+    Object,
+    Array,
+}
+
+impl Node {
+    fn describe(self) -> &'static str {
+        match self {
+            Node::Value => "a value",
+            Node::Object => "an object",
+            Node::Array => "an array",
+        }
+    }
+}
+
+/// What to do about two captures storing different things at the same path. Evaluation replaces
+/// what a capture finds at its path, except for objects captured with `{}`, which keep it: then
+/// `kept`, the node the other statement stores and its name, is what the output holds.
+fn replacement_help(kept: Option<(Node, &str)>) -> String {
+    match kept {
+        Some((node, name)) => format!(
+            "Objects captured with `{{}}` keep what is already there, so the output holds {} \
+             `{}` stores whichever is captured first; capture them to different paths",
+            node.describe(),
+            name
+        ),
+        None => "Whichever is captured last replaces the other; capture them to different paths"
+            .to_string(),
+    }
+}
+
+/// `path` as it would be written in a query, like `ROOT.items[].name`.
+fn describe_path(path: &[&str]) -> String {
+    let mut described = String::from("ROOT");
+    for step in path {
+        if *step != "[]" {
+            described.push('.');
+        }
+        described.push_str(step);
+    }
+    described
+}
+
+/// Resolves capture paths without an input, as fields and array elements (`[]`) from `ROOT`,
+/// the way evaluation resolves them.
+struct CaptureResolver<'ast> {
+    /// the captures by the name they store under, `None` where several statements share one
+    captures: HashMap<&'ast str, Option<&'ast Statement>>,
+}
+
+impl<'ast> CaptureResolver<'ast> {
+    fn new(program: &'ast Program) -> Self {
+        let mut captures = HashMap::new();
+        for stmt in &program.statements {
+            if let Some(name) = Self::name(stmt) {
+                captures
+                    .entry(name)
+                    .and_modify(|shared| *shared = None)
+                    .or_insert(Some(stmt));
+            }
+        }
+        CaptureResolver { captures }
+    }
+
+    /// The name `stmt` captures under: the statement's, unless its capture names another.
+    fn name(stmt: &Statement) -> Option<&str> {
+        let capture = stmt.capture.as_ref()?;
+        Some(match capture.name.as_str() {
+            "" => &stmt.name,
+            name => name,
+        })
+    }
+
+    /// Where the captures of `stmt` store what they capture, unless it depends on the input,
+    /// like keyed fields do, or the path starts from a name several statements capture under.
+    fn stored_at(&self, stmt: &'ast Statement) -> Option<Vec<&'ast str>> {
+        let capture = stmt.capture.as_ref()?;
+        let mut path = self.resolve(stmt, self.captures.len())?;
+        // values land in a field of the object the path leads to, like objects captured to
+        // ROOT itself
+        let in_object = match path.last() {
+            None => true,
+            Some(&"[]") => !capture.is_object && !capture.path.ends_with_array(),
+            Some(_) => false,
+        };
+        if in_object {
+            path.push(Self::name(stmt)?);
+        }
+        Some(path)
+    }
+
+    /// The path the capture of `stmt` resolves to, which the paths starting with its name
+    /// continue from. `depth` bounds how many names are followed, for names referring to
+    /// each other.
+    fn resolve(&self, stmt: &'ast Statement, depth: usize) -> Option<Vec<&'ast str>> {
+        let capture = stmt.capture.as_ref()?;
+        if capture.group_by.is_some() {
+            return None;
+        }
+        let (mut path, rest) = match capture.path.segments.split_first() {
+            Some((PathSegment::Root, rest)) => (Vec::new(), rest),
+            Some((PathSegment::Field(name), rest)) => match self.captures.get(name.as_str()) {
+                Some(Some(named)) if named.capture.as_ref()?.is_object && depth > 0 => {
+                    (self.resolve(named, depth - 1)?, rest)
+                }
+                Some(_) => return None,
+                None => (Vec::new(), &capture.path.segments[..]),
+            },
+            _ => return None,
+        };
+        for segment in rest {
+            match segment {
+                PathSegment::Field(name) => path.push(name.as_str()),
+                PathSegment::ArrayAppend => path.push("[]"),
+                PathSegment::Root | PathSegment::DynamicField(_) => return None,
+            }
+        }
+        Some(path)
+    }
+}
+
+struct Linter<'ast> {
+    statements: HashMap<&'ast str, &'ast Pattern>,
+    lints: Vec<Lint>,
+    /// pairs of statements used by different alternatives of an `OR`, which never both match
+    exclusive: HashSet<(&'ast str, &'ast str)>,
+    /// the span of each `SPLITBY` by the span of its repeated tail, `[bias] SPLITBY <sep>`
+    splits: HashMap<Range<usize>, Range<usize>>,
+}
+
+impl<'ast> Linter<'ast> {
+    fn redundant_group(&mut self, group: &Pattern) {
+        let span = group.span.clone();
+        self.lints.push(Lint {
+            rule: Rule::RedundantGroup,
+            message: "These parentheses don't change what the pattern in them matches".to_string(),
+            span: span.clone(),
+            help: "Remove them".to_string(),
+            related: Vec::new(),
+            fix: vec![
+                Edit {
+                    span: span.start..span.start + 1,
+                    replacement: String::new(),
+                },
+                Edit {
+                    span: span.end - 1..span.end,
+                    replacement: String::new(),
+                },
+            ],
+        });
+    }
+
+    /// Whether `pattern` is an unbounded, unbiased repetition of `ANY`, or a statement that is.
+    fn is_unbounded_any(&self, pattern: &Pattern, resolve: bool) -> bool {
+        match &pattern.node {
+            PatternKind::Repetition {
+                max: None,
+                pattern,
+                bias: QuantifierBias::Neutral,
+                ..
+            } => {
+                // `ANY` is itself a repetition, which `1..N ANY` repeats
+                matches!(pattern.node, PatternKind::Builtin(Builtin::AnyChar))
+                    || self.is_unbounded_any(pattern, false)
+            }
+            PatternKind::Group(inner) => self.is_unbounded_any(inner, resolve),
+            // statements referring to each other mustn't send this in circles
+            PatternKind::Variable(name) if resolve => self
+                .statements
+                .get(name.as_str())
+                .is_some_and(|pattern| self.is_unbounded_any(pattern, false)),
+            _ => false,
+        }
+    }
+
+    /// The statements whose captures store a value at the same field of `ROOT` as an earlier
+    /// statement's, or where `OPTION CAPTURE_RAW` stores the input.
+    fn shadowed_fields(&self, program: &'ast Program) -> Vec<Lint> {
+        let mut lints = Vec::new();
+        let mut fields: Vec<(Vec<&str>, &str)> = Vec::new();
+        if let Some(raw) = &program.options.capture_raw {
+            fields.push((vec![raw.as_str()], "OPTION CAPTURE_RAW"));
+        }
+        for stmt in &program.statements {
+            let Some(capture) = &stmt.capture else {
+                continue;
+            };
+            let Some((PathSegment::Root, rest)) = capture.path.segments.split_first() else {
+                continue;
+            };
+            let mut field = Vec::new();
+            for segment in rest {
+                match segment {
+                    PathSegment::Field(name) => field.push(name.as_str()),
+                    // arrays and keyed fields hold a value per capture
+                    _ => break,
+                }
+            }
+            if field.len() < rest.len() {
+                continue;
+            }
+            if field.is_empty() {
+                field.push(&capture.name);
+            }
+            let earlier = fields.iter().find(|(other, name)| {
+                *other == field
+                    && *name != stmt.name
+                    && !self.exclusive.contains(&(name, stmt.name.as_str()))
+            });
+            if let Some((_, earlier)) = earlier {
+                let related = program
+                    .statements
+                    .iter()
+                    .filter(|other| other.name == *earlier)
+                    .map(|other| (other.span.clone(), "captures to it too".to_string()))
+                    .collect();
+                let earlier_is_object = program
+                    .statements
+                    .iter()
+                    .filter(|other| other.name == *earlier)
+                    .any(|other| other.capture.as_ref().is_some_and(|c| c.is_object));
+                let help = match (capture.is_object, earlier_is_object) {
+                    (true, false) => replacement_help(Some((Node::Value, earlier))),
+                    (false, true) => replacement_help(Some((Node::Value, &stmt.name))),
+                    _ => "The value captured last replaces the other; capture to another \
+                          field, or collect both into an array with `[]`"
+                        .to_string(),
+                };
+                let earlier = match *earlier {
+                    "OPTION CAPTURE_RAW" => earlier.to_string(),
+                    name => format!("`{}`", name),
+                };
+                lints.push(Lint {
+                    rule: Rule::ShadowedRootField,
+                    message: format!(
+                        "`{}` captures to ROOT.{}, which {} stores a value at too",
+                        stmt.name,
+                        field.join("."),
+                        earlier
+                    ),
+                    span: stmt.span.clone(),
+                    help,
+                    related,
+                    fix: Vec::new(),
+                });
+            }
+            fields.push((field, &stmt.name));
+        }
+        lints
+    }
+
+    /// The statements whose captures store a value, an object or an array where an earlier
+    /// statement's captures store another kind of thing, or something inside a value.
+    /// Pairs [`Linter::shadowed_fields`] reported already, in `shadowed`, are left out.
+    fn conflicting_captures(&self, program: &'ast Program, shadowed: &[Lint]) -> Vec<Lint> {
+        let resolver = CaptureResolver::new(program);
+        let mut lints = Vec::new();
+        let mut nodes: Vec<(Vec<&str>, Node, &Statement)> = Vec::new();
+        for stmt in &program.statements {
+            let Some(stored) = resolver.stored_at(stmt) else {
+                continue;
+            };
+            let capture = stmt.capture.as_ref().unwrap();
+            let kind = if capture.is_object {
+                Node::Object
+            } else {
+                Node::Value
+            };
+            // what lands at the path, and what the path implies about the nodes on the way
+            let mut placed: Vec<(Vec<&str>, Node)> = (1..stored.len())
+                .map(|i| {
+                    let node = match stored[i] {
+                        "[]" => Node::Array,
+                        _ => Node::Object,
+                    };
+                    (stored[..i].to_vec(), node)
+                })
+                .collect();
+            placed.push((stored, kind));
+            let conflict = placed.iter().find_map(|(path, node)| {
+                let (_, other_node, other) =
+                    nodes.iter().find(|(other_path, other_node, other)| {
+                        other_path == path
+                            && other_node != node
+                            && other.name != stmt.name
+                            && !self
+                                .exclusive
+                                .contains(&(other.name.as_str(), stmt.name.as_str()))
+                            && !shadowed.iter().any(|lint| {
+                                lint.span == stmt.span
+                                    && lint.related.iter().any(|(span, _)| *span == other.span)
+                            })
+                    })?;
+                Some((path, node, other_node, other))
+            });
+            if let Some((path, node, other_node, other)) = conflict {
+                // objects captured with `{}` are only created where there is nothing yet
+                let captured_object = |stmt: &Statement, node: Node| {
+                    node == Node::Object
+                        && resolver.stored_at(stmt).as_ref() == Some(path)
+                        && stmt.capture.as_ref().is_some_and(|c| c.is_object)
+                };
+                let kept = if captured_object(stmt, *node) {
+                    Some((*other_node, other.name.as_str()))
+                } else if captured_object(other, *other_node) {
+                    Some((*node, stmt.name.as_str()))
+                } else {
+                    None
+                };
+                lints.push(Lint {
+                    rule: Rule::ConflictingCaptures,
+                    message: format!(
+                        "`{}` stores {} at {}, where `{}` stores {}",
+                        stmt.name,
+                        node.describe(),
+                        describe_path(path),
+                        other.name,
+                        other_node.describe()
+                    ),
+                    span: stmt.span.clone(),
+                    related: vec![(
+                        other.span.clone(),
+                        format!("stores {} there", other_node.describe()),
+                    )],
+                    help: replacement_help(kept),
+                    fix: Vec::new(),
+                });
+            }
+            nodes.extend(placed.into_iter().map(|(path, node)| (path, node, stmt)));
+        }
+        lints
+    }
+}
+
+impl<'ast> Visitor<'ast> for Linter<'ast> {
+    fn visit_statement(&mut self, statement: &'ast Statement) {
+        if let PatternKind::Group(inner) = &statement.pattern.node {
+            if !inner.is_depth_sensitive() {
+                self.redundant_group(&statement.pattern);
+            }
+        }
+        walk_statement(self, statement);
+    }
+
+    fn visit_pattern(&mut self, pattern: &'ast Pattern) {
+        match &pattern.node {
+            PatternKind::Group(inner) => {
+                let atomic = matches!(
+                    inner.node,
+                    PatternKind::Literal(_)
+                        | PatternKind::Builtin(_)
+                        | PatternKind::Dict(_)
+                        | PatternKind::Group(_)
+                );
+                if atomic && !inner.is_depth_sensitive() {
+                    self.redundant_group(pattern);
+                }
+            }
+            PatternKind::Sequence(items) => {
+                if let [item, tail] = &items[..] {
+                    if matches!(tail.node, PatternKind::Repetition { .. })
+                        && tail.span.end == pattern.span.end
+                        && item.span.end <= tail.span.start
+                    {
+                        self.splits.insert(tail.span.clone(), pattern.span.clone());
+                    }
+                }
+                for pair in items.windows(2) {
+                    if self.is_unbounded_any(&pair[0], true)
+                        && self.is_unbounded_any(&pair[1], true)
+                    {
+                        self.lints.push(Lint {
+                            rule: Rule::AdjacentUnboundedAny,
+                            message: "Two unbounded repetitions of ANY in a row can split the \
+                                      text between them anywhere"
+                                .to_string(),
+                            span: pair[0].span.start..pair[1].span.end,
+                            help: "Put a literal between them, bound one of them, or add LAZY \
+                                   or GREEDY to one of them"
+                                .to_string(),
+                            related: Vec::new(),
+                            fix: match pair[0].node {
+                                PatternKind::Repetition { .. } => {
+                                    vec![Edit::insert(pair[0].span.start, "LAZY ")]
+                                }
+                                _ => Vec::new(),
+                            },
+                        });
+                    }
+                }
+            }
+            PatternKind::OrChain { alternatives, .. } => {
+                let names: Vec<Option<&str>> = alternatives
+                    .iter()
+                    .map(|alternative| match &alternative.node {
+                        PatternKind::Variable(name) => Some(name.as_str()),
+                        _ => None,
+                    })
+                    .collect();
+                for (i, a) in names.iter().enumerate() {
+                    for b in &names[i + 1..] {
+                        if let (Some(a), Some(b)) = (a, b) {
+                            self.exclusive.insert((a, b));
+                            self.exclusive.insert((b, a));
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        walk_pattern(self, pattern);
+    }
+}
+
+#[cfg(feature = "diagnostics")]
+mod report {
+    use super::Lint;
+    use crate::error::{query_to_named, SourceWindow};
+    use miette::{Diagnostic, LabeledSpan, NamedSource, Severity, SourceCode};
+    use std::fmt;
+
+    /// A [`Lint`] along with the query it points into, which miette renders as a warning.
+    #[derive(Debug)]
+    pub struct LintReport<'a> {
+        lint: &'a Lint,
+        src: NamedSource<SourceWindow>,
+    }
+
+    impl Lint {
+        pub fn report(&self, source: &str) -> LintReport<'_> {
+            LintReport {
+                lint: self,
+                src: query_to_named(source),
+            }
+        }
+    }
+
+    impl fmt::Display for LintReport<'_> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(&self.lint.message)
+        }
+    }
+
+    impl std::error::Error for LintReport<'_> {}
+
+    impl Diagnostic for LintReport<'_> {
+        fn code<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+            Some(Box::new(self.lint.rule))
+        }
+
+        fn severity(&self) -> Option<Severity> {
+            Some(Severity::Warning)
+        }
+
+        fn help<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+            Some(Box::new(&self.lint.help))
+        }
+
+        fn source_code(&self) -> Option<&dyn SourceCode> {
+            Some(&self.src)
+        }
+
+        fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+            let span = self.lint.span.clone();
+            let related =
+                self.lint.related.iter().map(|(span, label)| {
+                    LabeledSpan::new_with_span(Some(label.clone()), span.clone())
+                });
+            Some(Box::new(
+                std::iter::once(LabeledSpan::new_with_span(None, span)).chain(related),
+            ))
+        }
+    }
+}
+
+#[cfg(feature = "diagnostics")]
+pub use report::LintReport;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn rules(query: &str) -> Vec<(Rule, &str)> {
+        let program = parse(query).unwrap();
+        lint(&program, &CustomBuiltins::new(), &LintConfig::default())
+            .unwrap()
+            .into_iter()
+            .map(|lint| (lint.rule, &query[lint.span]))
+            .collect()
+    }
+
+    #[test]
+    fn finds_likely_mistakes() {
+        assert_eq!(
+            rules("TEXT = 1..N ANY rest \"!\"\nrest = 0..N ANY"),
+            [
+                (Rule::AdjacentUnboundedAny, "1..N ANY rest"),
+                (Rule::EmptyMatch, "rest = 0..N ANY"),
+            ]
+        );
+        // empty text matched by builtins, or captured as a value, is meant
+        assert!(rules("TEXT = a \" \" b\na = LINE\nb = 0..N DIGIT -> ADD TO ROOT.b").is_empty());
+        assert_eq!(
+            rules("TEXT = w SPLITBY \".\"\nw = 1..N ANYCHAR"),
+            [(Rule::SplitbyWithoutBias, "w SPLITBY \".\"")]
+        );
+        assert_eq!(
+            rules("TEXT = x \" \" y\nx = 1..N LETTER -> ADD TO ROOT.name\ny = 1..N DIGIT -> ADD name TO ROOT"),
+            [(
+                Rule::ShadowedRootField,
+                "y = 1..N DIGIT -> ADD name TO ROOT"
+            )]
+        );
+    }
+
+    #[test]
+    fn finds_conflicting_captures() {
+        assert_eq!(
+            rules("TEXT = x\nx = y -> ADD item{} TO ROOT.obj\ny = DIGIT -> ADD TO item"),
+            [(Rule::ConflictingCaptures, "y = DIGIT -> ADD TO item")]
+        );
+        assert_eq!(
+            rules("TEXT = a b\na = DIGIT -> ADD TO ROOT.tags[]\nb = DIGIT -> ADD tags TO ROOT"),
+            [(Rule::ConflictingCaptures, "b = DIGIT -> ADD tags TO ROOT")]
+        );
+
+        let query = "TEXT = a b\na = DIGIT -> ADD TO ROOT.v\nb = DIGIT -> ADD TO ROOT.v.x";
+        let program = parse(query).unwrap();
+        let lints = lint(&program, &CustomBuiltins::new(), &LintConfig::default()).unwrap();
+        assert_eq!(
+            lints[0].message,
+            "`b` stores an object at ROOT.v, where `a` stores a value"
+        );
+        assert_eq!(
+            &query[lints[0].related[0].0.clone()],
+            "a = DIGIT -> ADD TO ROOT.v"
+        );
+        assert!(lints[0].help.starts_with("Whichever is captured last"));
+
+        // `{}` keeps the value, which a ROOT field shadowed by an object reports once
+        let query = "TEXT = x y\nx = \"a\" -> ADD TO ROOT.v\ny = \"b\" -> ADD v{} TO ROOT";
+        assert_eq!(
+            rules(query),
+            [(Rule::ShadowedRootField, "y = \"b\" -> ADD v{} TO ROOT")]
+        );
+        let program = parse(query).unwrap();
+        let lints = lint(&program, &CustomBuiltins::new(), &LintConfig::default()).unwrap();
+        assert!(
+            lints[0]
+                .help
+                .contains("the output holds a value `x` stores"),
+            "{}",
+            lints[0].help
+        );
+        let query = "TEXT = x\nx = y -> ADD item{} TO ROOT.obj\ny = DIGIT -> ADD TO item";
+        let program = parse(query).unwrap();
+        let lints = lint(&program, &CustomBuiltins::new(), &LintConfig::default()).unwrap();
+        assert!(
+            lints[0]
+                .help
+                .contains("the output holds a value `y` stores"),
+            "{}",
+            lints[0].help
+        );
+
+        assert!(rules(
+            "TEXT = row SPLITBY \",\"\nrow = k v -> ADD row{} TO ROOT.rows[]\n\
+             k = LETTER -> ADD TO row\nv = DIGIT -> ADD TO row.v.n"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn finds_shadowed_names() {
+        assert!(
+            rules("TEXT = `line` \" \" `Root`\n`line` = 1..N DIGIT\n`Root` = 1..N LETTER")
+                .is_empty()
+        );
+        assert_eq!(
+            rules("TEXT = x\nx = DIGIT -> ADD root{} TO ROOT.x"),
+            [(Rule::ShadowedName, "x = DIGIT -> ADD root{} TO ROOT.x")]
+        );
+
+        let mut builtins = CustomBuiltins::new();
+        builtins.register("ipv4", |_: &str, pos: usize| Some(pos + 1));
+        let program = parse("TEXT = ipv4\nipv4 = 1..N DIGIT").unwrap();
+        let lints = lint(&program, &builtins, &LintConfig::default()).unwrap();
+        assert_eq!(lints.len(), 1);
+        assert_eq!(lints[0].span, 12..16);
+        let program = parse("TEXT = `ipv4`\n`ipv4` = 1..N DIGIT").unwrap();
+        assert!(lint(&program, &builtins, &LintConfig::default())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn leaves_deliberate_patterns_alone() {
+        assert!(rules("TEXT = w GREEDY SPLITBY \".\"\nw = 1..N ANYCHAR").is_empty());
+        assert!(rules("TEXT = w SPLITBY \",\"\nw = 1..N LETTER").is_empty());
+        assert!(rules("TEXT = 1..N ANY \":\" 1..N ANY").is_empty());
+        assert!(
+            rules("TEXT = a OR b\na = DIGIT -> ADD TO ROOT.v\nb = LETTER -> ADD TO ROOT.v")
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn fixes_keep_the_rest_of_the_query() {
+        let query = "// words\nTEXT = (w) SPLITBY (\".\") /* tail */ ANY ANY\nw = 1..N (ANYCHAR)";
+        let program = parse(query).unwrap();
+        let lints = lint(&program, &CustomBuiltins::new(), &LintConfig::default()).unwrap();
+        let fixed = apply_fixes(query, &lints);
+        assert_eq!(
+            fixed,
+            "// words\nTEXT = (w) GREEDY SPLITBY \".\" /* tail */ LAZY ANY ANY\nw = 1..N ANYCHAR"
+        );
+        assert!(rules(&fixed).is_empty());
+    }
+
+    #[test]
+    fn rules_can_be_allowed() {
+        let program = parse("TEXT = 1..N ANY 1..N ANY").unwrap();
+        let config = LintConfig::default().allow("adjacent_unbounded_any".parse().unwrap());
+        assert!(lint(&program, &CustomBuiltins::new(), &config)
+            .unwrap()
+            .is_empty());
+        assert!("lint::empty".parse::<Rule>().is_err());
+    }
+}
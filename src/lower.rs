@@ -0,0 +1,154 @@
+//! Lowering queries to regular expressions, for the ones that need nothing more.
+
+use crate::ast::{Builtin, Pattern, PatternKind, Program, QueryOptions};
+use std::collections::HashMap;
+
+impl Program {
+    /// A regular expression (in the syntax of the `regex` crate) matching the same inputs as
+    /// the `TEXT` of this program, anchored at both ends, or `None` if the program uses
+    /// something regular expressions don't have: recursive statements, `UPPER`/`LOWER`,
+    /// builtins like `LINE` or `JSONVALUE`, `PRECEDEDBY`, or input normalizing `OPTION`s.
+    ///
+    /// Only whether an input matches carries over: how ambiguous inputs are parsed and what
+    /// is captured don't.
+    pub fn to_regex(&self) -> Option<String> {
+        let options = &self.options;
+        let normalized = QueryOptions {
+            reorder_alternatives: false,
+            capture_raw: None,
+            strategy: None,
+            ..options.clone()
+        };
+        if normalized != QueryOptions::default() {
+            return None;
+        }
+        let statements = self.variable_map();
+        let mut lowering = Lowering {
+            statements: &statements,
+            stack: Vec::new(),
+        };
+        let text = lowering.pattern(statements.get("TEXT")?)?;
+        Some(format!("\\A{}\\z", text))
+    }
+}
+
+struct Lowering<'a> {
+    statements: &'a HashMap<&'a str, &'a Pattern>,
+    /// the statements being lowered, which referring to again would be recursive
+    stack: Vec<&'a str>,
+}
+
+impl<'a> Lowering<'a> {
+    fn pattern(&mut self, pattern: &'a Pattern) -> Option<String> {
+        Some(match &pattern.node {
+            PatternKind::Literal(text) => escape(text),
+            PatternKind::Variable(name) => {
+                let (&name, &pattern) = self.statements.get_key_value(name.as_str())?;
+                if self.stack.contains(&name) {
+                    return None;
+                }
+                self.stack.push(name);
+                let lowered = self.pattern(pattern);
+                self.stack.pop();
+                lowered?
+            }
+            PatternKind::Builtin(Builtin::Bof) => "\\A".to_string(),
+            PatternKind::Builtin(Builtin::Eof) => "\\z".to_string(),
+            PatternKind::Builtin(Builtin::ContLine) => "\\n[ \\t][^\\n]*".to_string(),
+            PatternKind::Builtin(builtin) => builtin_class(builtin)?.to_string(),
+            // every lowering can be followed by another as it is
+            PatternKind::Sequence(items) => items
+                .iter()
+                .map(|item| self.pattern(item))
+                .collect::<Option<String>>()?,
+            // a regular expression matches the same inputs whichever alternative it prefers
+            PatternKind::OrChain {
+                alternatives: items,
+                ..
+            } => {
+                let alternatives = items
+                    .iter()
+                    .map(|item| self.pattern(item))
+                    .collect::<Option<Vec<_>>>()?;
+                format!("(?:{})", alternatives.join("|"))
+            }
+            PatternKind::Repetition {
+                min, max, pattern, ..
+            } => {
+                let min = min.unwrap_or(0);
+                let bounds = match max {
+                    Some(max) => format!("{{{},{}}}", min, max),
+                    None => format!("{{{},}}", min),
+                };
+                format!("(?:{}){}", self.pattern(pattern)?, bounds)
+            }
+            // literals compare lowercased characters, like the case folding of `(?i)`
+            PatternKind::AnyCase(inner) => format!("(?i:{})", self.pattern(inner)?),
+            PatternKind::Group(inner) => self.pattern(inner)?,
+            PatternKind::Upper(_)
+            | PatternKind::Lower(_)
+            | PatternKind::Dict(_)
+            | PatternKind::Permute(_)
+            | PatternKind::Fields(_)
+            | PatternKind::PrecededBy(_)
+            | PatternKind::Unmatched(_) => return None,
+        })
+    }
+}
+
+/// The class of characters `builtin` matches one of, if it matches one character.
+fn builtin_class(builtin: &Builtin) -> Option<&'static str> {
+    Some(match builtin {
+        // ASCII letters only, even where `(?i)` would add the Kelvin sign
+        Builtin::Letter => "(?-i:[A-Za-z])",
+        Builtin::Digit => "[0-9]",
+        Builtin::HexDigit => "[0-9A-Fa-f]",
+        Builtin::BinDigit => "[01]",
+        Builtin::OctDigit => "[0-7]",
+        Builtin::AnyChar => "(?s:.)",
+        Builtin::Newline => "\\n",
+        Builtin::Space => "[\\s&&[^\\n]]",
+        _ => return None,
+    })
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\.+*?()|[]{}^$#&-~".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse;
+
+    #[test]
+    fn lowers_regular_queries() {
+        let program = parse("TEXT = num SPLITBY \".\"\nnum = 1..3 DIGIT OR \"x+\"").unwrap();
+        assert_eq!(
+            program.to_regex().unwrap(),
+            r"\A(?:(?:[0-9]){1,3}|x\+)(?:\.(?:(?:[0-9]){1,3}|x\+)){0,}\z"
+        );
+        assert_eq!(
+            parse("TEXT = BOF \"a\" EOF").unwrap().to_regex().unwrap(),
+            r"\A\Aa\z\z"
+        );
+        assert_eq!(
+            parse("TEXT = \"a\" 0..N CONTLINE")
+                .unwrap()
+                .to_regex()
+                .unwrap(),
+            r"\Aa(?:\n[ \t][^\n]*){0,}\z"
+        );
+        assert!(parse("TEXT = LINE").unwrap().to_regex().is_none());
+        assert!(parse("TEXT = p\np = \"(\" p \")\" OR \"x\"")
+            .unwrap()
+            .to_regex()
+            .is_none());
+    }
+}
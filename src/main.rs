@@ -1,98 +1,1280 @@
 //! String Equations CLI
 //!
 //! Usage:
-//!   strql <query_file> <input_file>
-//!   strql -e <query> <input_file>
-//!   strql --inline <query> <input>
+//!   strql [OPTIONS] <query_file> <input_file>
+//!   strql [OPTIONS] -e <query> <input_file>
+//!   strql [OPTIONS] --inline <query> <input>
+//!   strql [OPTIONS] -r <query_file> <directory> [--include <pattern>]
+//!   strql --describe <query_file>
+//!   strql --emit-schema <query_file>
+//!   strql --plan <query_file>
+//!   strql lint [--fix] <query_file> [--allow <rule>]
+//!   strql infer <samples_file>
+//!   strql init-example <name> <query_file> <input_file>
+//!
+//! Exit codes:
+//!   0  the input matched the query
+//!   1  the input does not match the query
+//!   2  the input ambiguously matches the query
+//!   3  the query itself is invalid
+//!      (`lint` exits with 1 when it finds problems in a valid query)
+//!   4  a file could not be read
+//!   5  the evaluation timed out (`--timeout`)
+//!   6  the input matched, but a captured value can't be stored as the query asks (a failed
+//!      `AS` cast, a `ONCE` field written twice)
+//!   64 the command line is malformed
+//!   70 a bug of strql
+
+#![allow(clippy::result_large_err)]
 
 use std::env;
 use std::fs;
+use std::io::{BufRead, BufReader, IsTerminal};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use miette::{GraphicalReportHandler, GraphicalTheme};
-use strql::error::{StrqlError, StrqlResult};
-use strql::evaluate_partition;
+use serde_json::{json, Value};
+use strql::ast::QuantifierBias;
+use strql::error::{ErrorKind, StrqlError};
+use strql::infer::infer;
+use strql::lexer::Params;
+use strql::lint::{apply_fixes, lint, LintConfig, Rule};
+use strql::records::{RecordCounts, RecordMode, RecordReader};
+use strql::shape::PlanNode;
+use strql::{
+    evaluate_partition, output_schema, parser, to_csv, to_table, CancellationToken, CompiledQuery,
+    CustomBuiltins, LineIndex, Location, Plan, Program, Progress, SolverOptions,
+};
+
+mod exit_code {
+    pub const SUCCESS: i32 = 0;
+    pub const NO_MATCH: i32 = 1;
+    pub const AMBIGUOUS: i32 = 2;
+    pub const QUERY_ERROR: i32 = 3;
+    pub const IO_ERROR: i32 = 4;
+    pub const TIMEOUT: i32 = 5;
+    pub const CAPTURE_ERROR: i32 = 6;
+    pub const USAGE: i32 = 64;
+    pub const INTERNAL: i32 = 70;
+}
+
+enum CliError {
+    Usage(String),
+    Io(String),
+    /// the output can't be printed in the requested format
+    Output(String),
+    Strql(Box<StrqlError>),
+    /// `lint` found this many problems, which it already printed
+    Lints(usize),
+    /// `--timeout` expired
+    Timeout {
+        limit: Duration,
+        /// how far into the input matching got
+        reached: Option<Location>,
+    },
+    /// an error evaluating one of the files of `--recursive`
+    File {
+        path: String,
+        error: Box<CliError>,
+    },
+    /// an error evaluating one of the records of `--records`, whose spans are relative to it
+    Record {
+        /// 1-based
+        index: usize,
+        line: usize,
+        error: Box<CliError>,
+    },
+}
+
+impl From<StrqlError> for CliError {
+    fn from(err: StrqlError) -> Self {
+        CliError::Strql(Box::new(err))
+    }
+}
+
+impl CliError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Usage(_) | CliError::Output(_) => exit_code::USAGE,
+            CliError::Io(_) => exit_code::IO_ERROR,
+            CliError::Lints(_) => exit_code::NO_MATCH,
+            CliError::Timeout { .. } => exit_code::TIMEOUT,
+            CliError::Record { error, .. } | CliError::File { error, .. } => error.exit_code(),
+            CliError::Strql(err) => match err.kind() {
+                ErrorKind::NoMatch => exit_code::NO_MATCH,
+                ErrorKind::Ambiguous => exit_code::AMBIGUOUS,
+                ErrorKind::Syntax | ErrorKind::Query => exit_code::QUERY_ERROR,
+                ErrorKind::Capture => exit_code::CAPTURE_ERROR,
+                // only `--timeout` cancels evaluations
+                ErrorKind::Cancelled => exit_code::TIMEOUT,
+                // the command line doesn't misuse the library, short of a bug
+                ErrorKind::Usage | ErrorKind::Internal => exit_code::INTERNAL,
+                // `ErrorKind` is non-exhaustive: kinds added later are bugs until given a code
+                _ => exit_code::INTERNAL,
+            },
+        }
+    }
+}
+
+struct Options {
+    quiet: bool,
+    /// `--dict <name>=<file>`: word lists for `DICT(name)`, one word per line
+    dicts: Vec<(String, String)>,
+    /// `--param <name>=<value>`: values of the query's `${name}` placeholders
+    params: Params,
+    solver: SolverOptions,
+    /// `--records <mode>`: evaluate each record of the input on its own
+    records: Option<RecordMode>,
+    /// `--min-match-ratio <ratio>`: the `--records` that don't match are left out, as long as
+    /// at least this fraction of them match
+    min_match_ratio: Option<f64>,
+    /// `--on-error <action>`: what to do with the `--records` that fail
+    on_error: OnError,
+    format: OutputFormat,
+    /// `--ndjson <array>`: print the elements of this array as JSON lines, as they complete
+    ndjson: Option<String>,
+    /// `--recursive`: the input is a directory, whose files are evaluated one by one
+    recursive: bool,
+    /// `--include <pattern>`: with `--recursive`, only evaluate the files matching one of these
+    include: Vec<String>,
+    /// whether to report `status` on stderr while evaluating: unless `--no-progress` or
+    /// `--quiet` is given, when stderr is a terminal
+    progress: bool,
+    status: Status,
+    /// `--timeout <duration>`: cancel evaluation after this long
+    timeout: Option<Duration>,
+    /// `--interactive`: on ambiguous parses, offer to pick one of them
+    interactive: bool,
+    /// `--allow <rule>`: lint rules not to run
+    allow: Vec<Rule>,
+    /// `--fix`: rewrite the linted query file with the fixes of the problems found
+    fix: bool,
+}
+
+/// How far the evaluation got, for the progress reports of [`with_progress`].
+#[derive(Default)]
+struct Status {
+    /// bytes of the `--records` done with, not counting the evaluation in progress
+    bytes: AtomicUsize,
+    /// records evaluated, or `--ndjson` lines printed
+    records: AtomicUsize,
+    /// `--recursive` files done, and how many there are
+    files: AtomicUsize,
+    files_total: AtomicUsize,
+    /// how far the evaluation in progress got
+    evaluation: Progress,
+}
+
+/// The input given on the command line.
+enum Input<'a> {
+    Text(String),
+    /// a file, or a directory with `--recursive`
+    Path(&'a str),
+}
+
+/// Parses of an ambiguous input `--interactive` offers to pick from, at most.
+const MAX_PICKED_PARSES: usize = 5;
+
+/// How outputs are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Json,
+    /// `--table`: an aligned text table of the output's records
+    Table,
+    /// `--csv`: the output's records as CSV, with a header row
+    Csv,
+}
+
+/// What to do with the records of `--records` that fail to evaluate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OnError {
+    /// stop with the record's error
+    Fail,
+    /// leave the record out of the output
+    Skip,
+    /// output `{"error": {...}, "line": <line>, "raw": <record>}` in place of the record
+    Emit,
+}
+
+impl FromStr for OnError {
+    type Err = String;
+
+    fn from_str(action: &str) -> Result<Self, Self::Err> {
+        match action {
+            "fail" => Ok(OnError::Fail),
+            "skip" => Ok(OnError::Skip),
+            "emit" => Ok(OnError::Emit),
+            _ => Err(format!(
+                "unknown --on-error action '{}' (expected: skip, emit, fail)",
+                action
+            )),
+        }
+    }
+}
 
 fn main() {
-    if let Err(e) = run() {
-        print_error(&e);
-        process::exit(1);
+    let (options, args) = match split_flags(env::args().skip(1).collect()) {
+        Ok(split) => split,
+        Err(e) => {
+            print_error(&e);
+            process::exit(e.exit_code());
+        }
+    };
+
+    match run(&options, &args) {
+        Ok(()) => process::exit(exit_code::SUCCESS),
+        Err(e) => {
+            if !options.quiet {
+                print_error(&e);
+            }
+            process::exit(e.exit_code());
+        }
     }
 }
 
-fn print_error(err: &StrqlError) {
-    let noder = GraphicalReportHandler::new_themed(GraphicalTheme::unicode_nocolor());
-    let mut output = String::new();
-    if let Err(_) = noder.render_report(&mut output, err) {
-        // Fallback to simple error message
-        eprintln!("Error: {}", err);
-    } else {
-        eprintln!("{}", output);
+/// Separates the flags that may appear anywhere from the positional arguments. The query and
+/// input of `--inline` and `-e` are taken as they are, and so is everything after `--`.
+fn split_flags(args: Vec<String>) -> Result<(Options, Vec<String>), CliError> {
+    let mut options = Options {
+        quiet: false,
+        dicts: Vec::new(),
+        params: Params::new(),
+        solver: SolverOptions::default(),
+        records: None,
+        min_match_ratio: None,
+        on_error: OnError::Fail,
+        format: OutputFormat::Json,
+        ndjson: None,
+        recursive: false,
+        include: Vec::new(),
+        progress: true,
+        status: Status::default(),
+        timeout: None,
+        interactive: false,
+        allow: Vec::new(),
+        fix: false,
+    };
+    let mut positional = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--" => positional.extend(args.by_ref()),
+            "--inline" | "-e" if positional.is_empty() => {
+                positional.push(arg);
+                positional.extend(args.by_ref().take(2));
+            }
+            "-q" | "--quiet" => options.quiet = true,
+            "--no-progress" => options.progress = false,
+            "-i" | "--interactive" => options.interactive = true,
+            "--timeout" => {
+                let limit = args.next().unwrap_or_default();
+                let Some(limit) = parse_duration(&limit) else {
+                    return Err(CliError::Usage(format!(
+                        "--timeout requires a duration like 5s, 500ms or 2m, not '{}'",
+                        limit
+                    )));
+                };
+                options.timeout = Some(limit);
+            }
+            "--provenance" => options.solver.provenance = true,
+            "--table" => options.format = OutputFormat::Table,
+            "--csv" => options.format = OutputFormat::Csv,
+            "--ndjson" => {
+                let Some(array) = args.next() else {
+                    return Err(CliError::Usage(
+                        "--ndjson requires the path of an array, like ROOT.items[]".to_string(),
+                    ));
+                };
+                options.ndjson = Some(array);
+            }
+            "-r" | "--recursive" => options.recursive = true,
+            "--include" => {
+                let Some(pattern) = args.next() else {
+                    return Err(CliError::Usage(
+                        "--include requires a file name pattern, like '*.log'".to_string(),
+                    ));
+                };
+                options.include.push(pattern);
+            }
+            "--lines" => options.records = Some(RecordMode::Line),
+            "--records" => {
+                let mode = args.next().unwrap_or_default();
+                options.records = Some(mode.parse().map_err(CliError::Usage)?);
+            }
+            "--on-error" => {
+                let action = args.next().unwrap_or_default();
+                options.on_error = action.parse().map_err(CliError::Usage)?;
+            }
+            "--min-match-ratio" => {
+                let ratio = args.next().unwrap_or_default();
+                match ratio.parse::<f64>() {
+                    Ok(ratio) if (0.0..=1.0).contains(&ratio) => {
+                        options.min_match_ratio = Some(ratio)
+                    }
+                    _ => {
+                        return Err(CliError::Usage(format!(
+                            "--min-match-ratio requires a number from 0 to 1, not '{}'",
+                            ratio
+                        )))
+                    }
+                }
+            }
+            "--allow" => {
+                let rule = args.next().unwrap_or_default();
+                options.allow.push(rule.parse().map_err(CliError::Usage)?);
+            }
+            "--fix" => options.fix = true,
+            "--dict" => {
+                let dict = args.next().unwrap_or_default();
+                let Some((name, path)) = dict.split_once('=') else {
+                    return Err(CliError::Usage(
+                        "--dict requires a <name>=<file> argument".to_string(),
+                    ));
+                };
+                options.dicts.push((name.to_string(), path.to_string()));
+            }
+            "--param" => {
+                let param = args.next().unwrap_or_default();
+                let Some((name, value)) = param.split_once('=') else {
+                    return Err(CliError::Usage(
+                        "--param requires a <name>=<value> argument".to_string(),
+                    ));
+                };
+                options.params.insert(name.to_string(), value.to_string());
+            }
+            _ => positional.push(arg),
+        }
+    }
+    if options.records.is_none() {
+        let flag = match (options.min_match_ratio, options.on_error) {
+            (Some(_), _) => Some("--min-match-ratio"),
+            (None, OnError::Fail) => None,
+            (None, _) => Some("--on-error"),
+        };
+        if let Some(flag) = flag {
+            return Err(CliError::Usage(format!(
+                "{} requires --records or --lines",
+                flag
+            )));
+        }
+    }
+    options.progress &= !options.quiet && std::io::stderr().is_terminal();
+    if options.progress || options.timeout.is_some() {
+        options.solver.progress = Some(options.status.evaluation.clone());
+    }
+    if options.timeout.is_some() {
+        options.solver.cancellation = Some(CancellationToken::new());
     }
+    Ok((options, positional))
 }
 
-fn run() -> StrqlResult<()> {
-    let args: Vec<String> = env::args().collect();
+/// Reads durations like `5s`, `1.5s`, `500ms`, `2m` or `1h`; plain numbers are seconds.
+fn parse_duration(text: &str) -> Option<Duration> {
+    let (number, unit) = match text.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(i) => text.split_at(i),
+        None => (text, "s"),
+    };
+    let seconds = match unit {
+        "ms" => 0.001,
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(number.parse::<f64>().ok()? * seconds).ok()
+}
 
-    if args.len() < 2 {
-        print_help();
-        process::exit(1);
+fn print_error(err: &CliError) {
+    match err {
+        CliError::Usage(message) => {
+            eprintln!("Error: {}", message);
+            print_help();
+        }
+        CliError::Io(message) | CliError::Output(message) => eprintln!("{}", message),
+        CliError::Lints(1) => eprintln!("Found 1 problem"),
+        CliError::Lints(count) => eprintln!("Found {} problems", count),
+        CliError::Timeout { limit, reached } => match reached {
+            Some(location) => eprintln!(
+                "Evaluation timed out after {:?}, having matched up to line {}, column {}",
+                limit, location.line, location.column
+            ),
+            None => eprintln!("Evaluation timed out after {:?}", limit),
+        },
+        CliError::File { path, error } => {
+            eprintln!("In file {}:", path);
+            print_error(error);
+        }
+        CliError::Record { index, line, error } => {
+            eprintln!("In record {} (starting at line {}):", index, line);
+            print_error(error);
+        }
+        CliError::Strql(err) => {
+            let noder = GraphicalReportHandler::new_themed(GraphicalTheme::unicode_nocolor());
+            let mut output = String::new();
+            if noder.render_report(&mut output, err.as_ref()).is_err() {
+                // Fallback to simple error message
+                eprintln!("Error: {}", err);
+            } else {
+                eprintln!("{}", output);
+            }
+        }
+    }
+}
+
+fn read_file(path: &str, what: &str) -> Result<String, CliError> {
+    fs::read_to_string(path)
+        .map_err(|e| CliError::Io(format!("Failed to read {} file '{}': {}", what, path, e)))
+}
+
+/// Opens the input file at `path`, decompressing `.gz` and `.zst` files as they are read.
+fn open_input(path: &str) -> Result<Box<dyn BufRead>, CliError> {
+    let file = fs::File::open(path)
+        .map_err(|e| CliError::Io(format!("Failed to read input file '{}': {}", path, e)))?;
+    if path.ends_with(".gz") {
+        #[cfg(feature = "gzip")]
+        return Ok(Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(
+            file,
+        ))));
+        #[cfg(not(feature = "gzip"))]
+        return Err(CliError::Io(format!(
+            "'{}' is compressed: reading .gz inputs requires the `gzip` feature",
+            path
+        )));
+    }
+    if path.ends_with(".zst") {
+        #[cfg(feature = "zstd")]
+        return zstd::Decoder::new(file)
+            .map(|decoder| Box::new(BufReader::new(decoder)) as Box<dyn BufRead>)
+            .map_err(|e| CliError::Io(format!("Failed to read input file '{}': {}", path, e)));
+        #[cfg(not(feature = "zstd"))]
+        return Err(CliError::Io(format!(
+            "'{}' is compressed: reading .zst inputs requires the `zstd` feature",
+            path
+        )));
+    }
+    Ok(Box::new(BufReader::new(file)))
+}
+
+fn read_error(error: std::io::Error) -> CliError {
+    CliError::Io(format!("Failed to read input: {}", error))
+}
+
+fn run(options: &Options, args: &[String]) -> Result<(), CliError> {
+    if args.is_empty() {
+        return Err(CliError::Usage("missing arguments".to_string()));
     }
 
     // Check for help flag
-    if args[1] == "-h" || args[1] == "--help" {
+    if args[0] == "-h" || args[0] == "--help" {
         print_help();
         return Ok(());
     }
 
-    let (query, input) = if args[1] == "--inline" {
-        // --inline <query> <input>
+    if args[0] == "--describe" {
+        // --describe <query_file>
+        if args.len() < 2 {
+            return Err(CliError::Usage(
+                "--describe requires a query file argument".to_string(),
+            ));
+        }
+        return describe(&read_file(&args[1], "query")?);
+    }
+
+    if args[0] == "--emit-schema" {
+        // --emit-schema <query_file>
+        if args.len() < 2 {
+            return Err(CliError::Usage(
+                "--emit-schema requires a query file argument".to_string(),
+            ));
+        }
+        let schema = output_schema(&read_file(&args[1], "query")?)?;
+        println!("{}", serde_json::to_string_pretty(&schema).unwrap());
+        return Ok(());
+    }
+
+    if args[0] == "--plan" {
+        // --plan <query_file>
+        if args.len() < 2 {
+            return Err(CliError::Usage(
+                "--plan requires a query file argument".to_string(),
+            ));
+        }
+        let program = parser::parse(&read_file(&args[1], "query")?)?;
+        print!("{}", Plan::infer(&program));
+        return Ok(());
+    }
+
+    if args[0] == "infer" {
+        // infer <samples_file>
+        if args.len() < 2 {
+            return Err(CliError::Usage(
+                "infer requires a file of sample lines".to_string(),
+            ));
+        }
+        print!("{}", infer(read_file(&args[1], "samples")?.lines()));
+        return Ok(());
+    }
+
+    if args[0] == "init-example" {
+        // init-example <name> <query_file> <input_file>
         if args.len() < 4 {
-            eprintln!("Error: --inline requires query and input arguments");
-            print_help();
-            process::exit(1);
+            return Err(CliError::Usage(
+                "init-example requires a name, a query file and an input file".to_string(),
+            ));
+        }
+        return init_example(&args[1], &args[2], &args[3]);
+    }
+
+    if args[0] == "lint" {
+        // lint <query_file>
+        if args.len() < 2 {
+            return Err(CliError::Usage(
+                "lint requires a query file argument".to_string(),
+            ));
+        }
+        return lint_query(options, &args[1]);
+    }
+
+    let (query, input) = if args[0] == "--inline" {
+        // --inline <query> <input>
+        if args.len() < 3 {
+            return Err(CliError::Usage(
+                "--inline requires query and input arguments".to_string(),
+            ));
         }
-        (args[2].clone(), args[3].clone())
-    } else if args[1] == "-e" {
+        (args[1].clone(), Input::Text(args[2].clone()))
+    } else if args[0] == "-e" {
         // -e <query> <input_file>
-        if args.len() < 4 {
-            eprintln!("Error: -e requires query and input file arguments");
-            print_help();
-            process::exit(1);
+        if args.len() < 3 {
+            return Err(CliError::Usage(
+                "-e requires query and input file arguments".to_string(),
+            ));
         }
-        let input = fs::read_to_string(&args[3]).unwrap_or_else(|e| {
-            eprintln!("Failed to read input file '{}': {}", args[3], e);
-            process::exit(1);
-        });
-        (args[2].clone(), input)
+        (args[1].clone(), Input::Path(&args[2]))
     } else {
         // <query_file> <input_file>
-        if args.len() < 3 {
-            eprintln!("Error: missing input file argument");
-            print_help();
-            process::exit(1);
+        if args.len() < 2 {
+            return Err(CliError::Usage("missing input file argument".to_string()));
         }
+        (read_file(&args[0], "query")?, Input::Path(&args[1]))
+    };
+
+    let builtins = load_builtins(options)?;
+    let program = parser::parse_with_params(&query, &options.params)?;
+    let query = CompiledQuery::with_builtins(&program, &builtins)?;
+    if let Some(array) = &options.ndjson {
+        if !captures_to_array(&Plan::infer(&program), array) {
+            return Err(CliError::Usage(format!(
+                "--ndjson needs an array the query adds to, and no capture adds to {}",
+                array
+            )));
+        }
+    }
 
-        let query = fs::read_to_string(&args[1]).unwrap_or_else(|e| {
-            eprintln!("Failed to read query file '{}': {}", args[1], e);
-            process::exit(1);
+    if let (Some(limit), Some(token)) = (options.timeout, &options.solver.cancellation) {
+        let token = token.clone();
+        // evaluations only stop at `process::exit` otherwise, so there's nothing to join
+        thread::spawn(move || {
+            thread::sleep(limit);
+            token.cancel();
         });
+    }
+    let output = with_progress(options, || match &input {
+        Input::Path(dir) if options.recursive => evaluate_recursive(options, &query, dir),
+        Input::Path(path) => evaluate(options, &query, open_input(path)?),
+        Input::Text(text) => evaluate(options, &query, text.as_bytes()),
+    });
+    let output = match output {
+        Err(CliError::Strql(error))
+            if options.interactive && matches!(*error, StrqlError::AmbiguousParse { .. }) =>
+        {
+            let input = match input {
+                Input::Path(path) => {
+                    let mut text = String::new();
+                    open_input(path)?
+                        .read_to_string(&mut text)
+                        .map_err(read_error)?;
+                    text
+                }
+                Input::Text(text) => text,
+            };
+            Some(pick_parse(&program, &builtins, &input, error)?)
+        }
+        output => output?,
+    };
+    match output {
+        Some(output) => print_output(options, &output),
+        None => Ok(()),
+    }
+}
+
+/// Lists the parses of `input` that a `LAZY` or `GREEDY` tells apart (see `error`, an
+/// ambiguous parse), with the part of the input they disagree on annotated, and returns the
+/// output of the one picked on stdin. Without one, `error` is returned as is.
+fn pick_parse(
+    program: &Program,
+    builtins: &CustomBuiltins,
+    input: &str,
+    error: Box<StrqlError>,
+) -> Result<Value, CliError> {
+    let StrqlError::AmbiguousParse {
+        _branches,
+        _suggestions,
+        ..
+    } = &*error
+    else {
+        return Err(CliError::Strql(error));
+    };
+    let mut parses = program.disambiguations(builtins, input, _suggestions, MAX_PICKED_PARSES);
+    if parses.is_empty() {
+        return Err(CliError::Strql(error));
+    }
+
+    let region = _branches.first().map_or(0..input.len(), |b| b.span.clone());
+    let annotated: Vec<String> = parses
+        .iter()
+        .map(|parse| annotate(input, region.clone(), &parse.captures))
+        .collect();
+    let width = annotated
+        .iter()
+        .map(|a| a.chars().count())
+        .max()
+        .unwrap_or(0);
+    eprintln!("{}; {} of them can be picked:", error, parses.len());
+    for (i, (parse, annotated)) in parses.iter().zip(&annotated).enumerate() {
+        let repetition = program
+            .source
+            .get(parse.suggestion.span.clone())
+            .unwrap_or("the repetition");
+        let bias = match parse.bias {
+            QuantifierBias::Lazy => "LAZY",
+            _ => "GREEDY",
+        };
+        eprintln!(
+            "  {}) {:<width$}  make `{}` in `{}` {}",
+            i + 1,
+            annotated,
+            repetition,
+            parse.suggestion.statement,
+            bias
+        );
+    }
+    eprint!("Print which parse? [1-{}] ", parses.len());
+
+    let mut choice = String::new();
+    std::io::stdin()
+        .read_line(&mut choice)
+        .map_err(read_error)?;
+    match choice.trim().parse::<usize>() {
+        Ok(i) if (1..=parses.len()).contains(&i) => Ok(parses.swap_remove(i - 1).output),
+        _ => Err(CliError::Strql(error)),
+    }
+}
+
+/// The part of `input` in `region`, with the outermost `captures` in it bracketed, like
+/// `[a. b.][ c.]`; newlines are shown as `↵`.
+fn annotate(input: &str, region: Range<usize>, captures: &[(String, Range<usize>)]) -> String {
+    let mut annotated = String::new();
+    let mut pos = region.start;
+    for (_, span) in captures {
+        // nested in the previous capture, or outside the region
+        if span.start < pos || span.end > region.end || span.is_empty() {
+            continue;
+        }
+        let (Some(before), Some(captured)) = (input.get(pos..span.start), input.get(span.clone()))
+        else {
+            continue;
+        };
+        annotated.push_str(before);
+        annotated.push('[');
+        annotated.push_str(captured);
+        annotated.push(']');
+        pos = span.end;
+    }
+    annotated.push_str(input.get(pos..region.end).unwrap_or_default());
+    annotated.replace('\n', "↵")
+}
+
+/// Evaluates the input read from `reader` as `options` say. Returns the output to print, if it
+/// isn't printed already (`--ndjson`) or unwanted (`--quiet`).
+fn evaluate(
+    options: &Options,
+    query: &CompiledQuery,
+    mut reader: impl BufRead,
+) -> Result<Option<Value>, CliError> {
+    if let Some(mode) = options.records {
+        return evaluate_records(options, query, reader, mode);
+    }
+
+    let mut input = String::new();
+    reader.read_to_string(&mut input).map_err(read_error)?;
+    evaluate_text(options, query, &input)
+}
+
+/// Evaluates `input`, one of the records of `--records` or the whole input otherwise, like
+/// [`evaluate`].
+fn evaluate_text(
+    options: &Options,
+    query: &CompiledQuery,
+    input: &str,
+) -> Result<Option<Value>, CliError> {
+    // `--quiet` runs build the captures too, so the casts and `ONCE`s that fail exit like
+    // they do otherwise
+    let output = if options.quiet {
+        query.evaluate_with(input, &options.solver).map(|_| None)
+    } else if let Some(array) = &options.ndjson {
+        query
+            .evaluate_streaming_with(input, array, &options.solver, |record| {
+                print_line(options, record)
+            })
+            .map(|rest| {
+                // the fields outside the array come last, on a line of their own
+                if rest.as_object().is_some_and(|fields| !fields.is_empty()) {
+                    print_line(options, rest);
+                }
+                None
+            })
+    } else {
+        query.evaluate_with(input, &options.solver).map(Some)
+    };
+
+    match (output, options.timeout) {
+        (Err(StrqlError::Cancelled), Some(limit)) => Err(CliError::Timeout {
+            limit,
+            // the files of `--recursive` are evaluated at the same time, so their progress
+            // is mixed up
+            reached: (!options.recursive)
+                .then(|| LineIndex::new(input).locate(options.status.evaluation.position())),
+        }),
+        (output, _) => Ok(output?),
+    }
+}
 
-        let input = fs::read_to_string(&args[2]).unwrap_or_else(|e| {
-            eprintln!("Failed to read input file '{}': {}", args[2], e);
-            process::exit(1);
+/// Whether some capture of the query adds to `array`, an `--ndjson` path like `ROOT.items[]`.
+fn captures_to_array(plan: &Plan, array: &str) -> bool {
+    let array = array.strip_prefix("ROOT.").unwrap_or(array);
+    let array = array.strip_suffix("[]").unwrap_or(array);
+    let mut plan = plan;
+    for field in array.split('.') {
+        let PlanNode::Object { fields, .. } = &plan.node else {
+            return false;
+        };
+        let Some(next) = fields.get(field) else {
+            return false;
+        };
+        plan = next;
+    }
+    matches!(plan.node, PlanNode::Array(_))
+}
+
+/// Prints one line of `--ndjson` output.
+fn print_line(options: &Options, record: Value) {
+    if options.progress {
+        // don't append to the progress report
+        eprint!("\r\x1b[K");
+    }
+    println!("{}", record);
+    options.status.records.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Runs `evaluation`, reporting how far it got on stderr every half second once it takes
+/// more than a second, if `options.progress` says so.
+fn with_progress<T>(options: &Options, evaluation: impl FnOnce() -> T) -> T {
+    if !options.progress {
+        return evaluation();
+    }
+    let done = AtomicBool::new(false);
+    thread::scope(|scope| {
+        let reporter = scope.spawn(|| {
+            let start = Instant::now();
+            let mut shown = false;
+            loop {
+                thread::park_timeout(Duration::from_millis(500));
+                if done.load(Ordering::Relaxed) {
+                    break;
+                }
+                if start.elapsed() >= Duration::from_secs(1) {
+                    eprint!("\r\x1b[Kstrql: {}", progress_report(options));
+                    shown = true;
+                }
+            }
+            if shown {
+                eprint!("\r\x1b[K");
+            }
         });
+        let result = evaluation();
+        done.store(true, Ordering::Relaxed);
+        reporter.thread().unpark();
+        result
+    })
+}
+
+/// Describes `options.status`, like `1.2 MB of 3.4 MB (35%)` or `12 of 40 files`.
+fn progress_report(options: &Options) -> String {
+    let status = &options.status;
+    if options.recursive {
+        return format!(
+            "{} of {} files",
+            status.files.load(Ordering::Relaxed),
+            status.files_total.load(Ordering::Relaxed)
+        );
+    }
+    let evaluation = &status.evaluation;
+    let bytes = status.bytes.load(Ordering::Relaxed) + evaluation.position();
+    if options.records.is_none() && options.ndjson.is_none() {
+        let total = evaluation.input_len().max(1);
+        return format!(
+            "{} of {} ({}%)",
+            format_bytes(bytes),
+            format_bytes(total),
+            bytes * 100 / total
+        );
+    }
+    format!(
+        "{}, {} records",
+        format_bytes(bytes),
+        status.records.load(Ordering::Relaxed)
+    )
+}
 
-        (query, input)
+fn format_bytes(bytes: usize) -> String {
+    match bytes {
+        0..1_000 => format!("{} B", bytes),
+        1_000..1_000_000 => format!("{:.1} kB", bytes as f64 / 1e3),
+        1_000_000..1_000_000_000 => format!("{:.1} MB", bytes as f64 / 1e6),
+        _ => format!("{:.1} GB", bytes as f64 / 1e9),
+    }
+}
+
+fn print_output(options: &Options, output: &Value) -> Result<(), CliError> {
+    let records = match options.format {
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(output).unwrap());
+            return Ok(());
+        }
+        OutputFormat::Table => to_table(output),
+        OutputFormat::Csv => to_csv(output),
+    };
+    let Some(records) = records else {
+        return Err(CliError::Output(
+            "--table and --csv need an output made of records: an array of objects, \
+             or an object whose only field is one"
+                .to_string(),
+        ));
     };
+    print!("{}", records);
+    Ok(())
+}
 
-    let result = evaluate_partition(&query, &input)?;
+/// Evaluates every record read from `reader` on its own, collecting their outputs into one
+/// array. Records are read as they are evaluated, so inputs never have to fit in memory.
+fn evaluate_records(
+    options: &Options,
+    query: &CompiledQuery,
+    reader: impl BufRead,
+    mode: RecordMode,
+) -> Result<Option<Value>, CliError> {
+    let skip_unmatched = query.options().skip_unmatched;
+    let tolerated = skip_unmatched || options.min_match_ratio.is_some();
+    let mut counts = RecordCounts::default();
+    let mut results = Vec::new();
+    for (i, record) in RecordReader::new(reader, mode).enumerate() {
+        let record = record.map_err(read_error)?;
+        let output = match evaluate_text(options, query, &record.text) {
+            // like the items of a `SPLITBY`, records that don't match are set aside
+            Err(CliError::Strql(error)) if tolerated && error.kind() == ErrorKind::NoMatch => {
+                counts.unmatched += 1;
+                let span = [record.offset, record.offset + record.text.len()];
+                let unmatched = json!({ "text": record.text, "span": span });
+                Ok((skip_unmatched && options.ndjson.is_none())
+                    .then(|| json!({ "_unmatched": [unmatched] })))
+            }
+            Err(CliError::Strql(error)) if options.on_error != OnError::Fail => {
+                counts.unmatched += 1;
+                let failed = json!({
+                    "error": error.to_json(),
+                    "line": record.line,
+                    "raw": record.text,
+                });
+                if options.on_error == OnError::Emit && !options.quiet {
+                    match options.ndjson {
+                        Some(_) => print_line(options, failed),
+                        None => results.push(failed),
+                    }
+                }
+                Ok(None)
+            }
+            output => {
+                counts.matched += 1;
+                output
+            }
+        }
+        .map_err(|error| CliError::Record {
+            index: i + 1,
+            line: record.line,
+            error: Box::new(error),
+        })?;
+        results.extend(output);
+        let status = &options.status;
+        status
+            .bytes
+            .fetch_add(record.text.len() + 1, Ordering::Relaxed);
+        if options.ndjson.is_none() {
+            status.records.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    if let Some(min_ratio) = options.min_match_ratio {
+        if !options.quiet {
+            if options.progress {
+                eprint!("\r\x1b[K");
+            }
+            eprintln!(
+                "strql: {} of {} records matched",
+                counts.matched,
+                counts.matched + counts.unmatched
+            );
+        }
+        counts.check(min_ratio)?;
+    }
+    let printed = options.quiet || options.ndjson.is_some();
+    Ok((!printed).then_some(Value::Array(results)))
+}
+
+/// Evaluates every file under `dir` (whose name matches one of the `--include` patterns, if
+/// any) in parallel, collecting their outputs into one object keyed by path.
+fn evaluate_recursive(
+    options: &Options,
+    query: &CompiledQuery,
+    dir: &str,
+) -> Result<Option<Value>, CliError> {
+    let mut files = Vec::new();
+    collect_files(Path::new(dir), &options.include, &mut files)?;
+    files.sort();
+    let status = &options.status;
+    status.files_total.store(files.len(), Ordering::Relaxed);
+
+    let next = AtomicUsize::new(0);
+    let workers = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut results: Vec<_> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers.min(files.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(path) = files.get(i) else {
+                            return results;
+                        };
+                        let path = path.display().to_string();
+                        let result = open_input(&path)
+                            .and_then(|input| evaluate(options, query, input))
+                            .map_err(|error| CliError::File {
+                                path: path.clone(),
+                                error: Box::new(error),
+                            });
+                        status.files.fetch_add(1, Ordering::Relaxed);
+                        results.push((i, path, result));
+                    }
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("evaluating a file panicked"))
+            .collect()
+    });
+
+    // errors are reported for the first failing file, whichever thread got to it first
+    results.sort_by_key(|(i, _, _)| *i);
+    let mut outputs = serde_json::Map::new();
+    for (_, path, result) in results {
+        if let Some(output) = result? {
+            outputs.insert(path, output);
+        }
+    }
+    let printed = options.quiet || options.ndjson.is_some();
+    Ok((!printed).then_some(Value::Object(outputs)))
+}
+
+/// Adds the files under `dir` whose name matches one of `include` (or all, if it's empty).
+fn collect_files(dir: &Path, include: &[String], files: &mut Vec<PathBuf>) -> Result<(), CliError> {
+    let entries = fs::read_dir(dir).map_err(|e| {
+        CliError::Io(format!(
+            "Failed to read directory '{}': {}",
+            dir.display(),
+            e
+        ))
+    })?;
+    for entry in entries {
+        let path = entry
+            .map_err(|e| {
+                CliError::Io(format!(
+                    "Failed to read directory '{}': {}",
+                    dir.display(),
+                    e
+                ))
+            })?
+            .path();
+        if path.is_dir() {
+            collect_files(&path, include, files)?;
+            continue;
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if include.is_empty() || include.iter().any(|pattern| glob_matches(pattern, &name)) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Whether `name` matches `pattern`, where `*` stands for any text and `?` for any character.
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // matches[j]: whether the pattern so far matches the first j characters of `name`
+    let mut matches = vec![false; name.len() + 1];
+    matches[0] = true;
+    for p in pattern {
+        let mut next = vec![false; name.len() + 1];
+        for j in 0..=name.len() {
+            next[j] = match p {
+                '*' => matches[j] || (j > 0 && next[j - 1]),
+                '?' => j > 0 && matches[j - 1],
+                c => j > 0 && matches[j - 1] && name[j - 1] == c,
+            };
+        }
+        matches = next;
+    }
+    matches[name.len()]
+}
+
+fn load_builtins(options: &Options) -> Result<CustomBuiltins, CliError> {
+    let mut builtins = CustomBuiltins::new();
+    for (name, path) in &options.dicts {
+        builtins.add_dict(name.as_str(), read_file(path, "dictionary")?.lines());
+    }
+    Ok(builtins)
+}
+
+/// Prints the problems the lint rules not `--allow`ed find in the query file at `path`,
+/// after fixing the ones that can be with `--fix`.
+fn lint_query(options: &Options, path: &str) -> Result<(), CliError> {
+    let builtins = load_builtins(options)?;
+    let config = options
+        .allow
+        .iter()
+        .fold(LintConfig::default(), |config, &rule| config.allow(rule));
+    let mut query = read_file(path, "query")?;
+    let mut lints = lint(
+        &parser::parse_with_params(&query, &options.params)?,
+        &builtins,
+        &config,
+    )?;
+    if options.fix && lints.iter().any(|found| !found.fix.is_empty()) {
+        let fixed = apply_fixes(&query, &lints);
+        // what's left is reported against the fixed query
+        let program = parser::parse_with_params(&fixed, &options.params)?;
+        let remaining = lint(&program, &builtins, &config)?;
+        fs::write(path, &fixed)
+            .map_err(|e| CliError::Io(format!("Failed to write query file '{}': {}", path, e)))?;
+        if !options.quiet {
+            eprintln!(
+                "Fixed {} of {} problems",
+                lints.len().saturating_sub(remaining.len()),
+                lints.len()
+            );
+        }
+        (query, lints) = (fixed, remaining);
+    }
+    let handler = GraphicalReportHandler::new_themed(GraphicalTheme::unicode_nocolor());
+    for found in lints.iter().filter(|_| !options.quiet) {
+        let mut output = String::new();
+        match handler.render_report(&mut output, &found.report(&query)) {
+            Ok(()) => eprintln!("{}", output),
+            Err(_) => eprintln!("Warning: {} [{}]", found.message, found.rule),
+        }
+    }
+    match lints.len() {
+        0 => Ok(()),
+        count => Err(CliError::Lints(count)),
+    }
+}
+
+/// Creates `examples/<name>` from a query and an input, for `tests/examples_tests.rs`: with
+/// the output as `expected.json`, or without one where the input is meant not to match.
+/// Nothing is written until the output is confirmed on stdin.
+fn init_example(name: &str, query_path: &str, input_path: &str) -> Result<(), CliError> {
+    let dir = Path::new("examples").join(name);
+    if dir.exists() {
+        return Err(CliError::Io(format!("'{}' already exists", dir.display())));
+    }
+    let query = read_file(query_path, "query")?;
+    let input = read_file(input_path, "input")?;
+    let expected = match evaluate_partition(&query, &input) {
+        Ok(output) => {
+            let expected = serde_json::to_string_pretty(&output).unwrap();
+            eprintln!("{}", expected);
+            Some(expected)
+        }
+        // what the example tests accept from an example without expected.json
+        Err(
+            err @ (StrqlError::AmbiguousParse { .. }
+            | StrqlError::PatternNoMatch { .. }
+            | StrqlError::PartialMatch { .. }),
+        ) => {
+            eprintln!("{}", err);
+            None
+        }
+        Err(err) => return Err(err.into()),
+    };
+    eprint!(
+        "Create {} {}? [y/N] ",
+        dir.display(),
+        match expected {
+            Some(_) => "expecting this output",
+            None => "expecting this error",
+        }
+    );
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .map_err(read_error)?;
+    if !answer.trim().eq_ignore_ascii_case("y") {
+        return Ok(());
+    }
 
-    println!("{}", serde_json::to_string_pretty(&result).unwrap());
+    let write = |file: &str, contents: &str| {
+        let path = dir.join(file);
+        fs::write(&path, contents)
+            .map_err(|e| CliError::Io(format!("Failed to write '{}': {}", path.display(), e)))
+    };
+    fs::create_dir_all(&dir)
+        .map_err(|e| CliError::Io(format!("Failed to create '{}': {}", dir.display(), e)))?;
+    write("query.strql", &query)?;
+    write("test.txt", &input)?;
+    if let Some(expected) = expected {
+        write("expected.json", &format!("{}\n", expected))?;
+    }
+    Ok(())
+}
 
+/// Lists every statement of the query along with its `///` documentation.
+fn describe(query: &str) -> Result<(), CliError> {
+    let program = parser::parse(query)?;
+    for (i, statement) in program.statements.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        match query.get(statement.span.clone()) {
+            Some(text) => println!("{}", text),
+            // statements of `IMPORT`ed grammars have their text in the source appended to
+            // the query, and a name prefixed with the grammar's
+            None => {
+                let text = program.source.get(statement.pattern.span.clone());
+                println!("{} = {}", statement.name, text.unwrap_or("..."));
+            }
+        }
+        for line in statement.doc.iter().flat_map(|doc| doc.lines()) {
+            println!("    {}", line);
+        }
+    }
     Ok(())
 }
 
 fn print_help() {
-    eprintln!("link to github once project is on github")
+    eprintln!(
+        "Usage:
+  strql [OPTIONS] <query_file> <input_file>
+  strql [OPTIONS] -e <query> <input_file>
+  strql [OPTIONS] --inline <query> <input>
+  strql [OPTIONS] -r <query_file> <directory> [--include <pattern>]
+  strql --describe <query_file>
+  strql --emit-schema <query_file>
+  strql --plan <query_file>
+  strql lint [--fix] <query_file> [--allow <rule>]
+  strql infer <samples_file>
+  strql init-example <name> <query_file> <input_file>
+
+Options:
+  -q, --quiet    Print nothing; only report the outcome through the exit code
+  -i, --interactive
+                 When the input is ambiguous, list the parses a LAZY or
+                 GREEDY would pick, and print the one chosen on stdin
+  --timeout <duration>
+                 Give up evaluating after this long, like 5s or 500ms,
+                 telling how far matching got
+  --no-progress  Don't report how far long evaluations got on stderr (only
+                 reported when stderr is a terminal)
+  --dict <name>=<file>
+                 Make the lines of <file> the words DICT(<name>) matches
+  --param <name>=<value>
+                 Fill in the ${{<name>}} placeholders of the query's literals
+  --provenance   Output every captured value with the statement that captured it
+  --records paragraph|line
+                 Match every block of lines between blank lines (or every
+                 line) on its own, and output the results as an array
+  --lines        Same as --records line
+  --min-match-ratio <ratio>
+                 With --records, leave out the records that don't match,
+                 failing only if fewer than this fraction of them (like
+                 0.95) match, and report how many did on stderr
+  --on-error skip|emit|fail
+                 With --records, leave out the records that fail to
+                 evaluate, or output {{\"error\": ..., \"line\": ..., \"raw\": ...}}
+                 in their place, instead of stopping (the default, fail)
+  -r, --recursive
+                 Take a directory as input, and evaluate each of its files
+                 (in parallel), outputting the results keyed by file path
+  --include <pattern>
+                 With --recursive, only evaluate the files whose name
+                 matches the pattern, like '*.log' (repeatable)
+  --table        Print the output's records (an array of objects) as a table
+  --csv          Print the output's records as CSV, with a header row
+  --ndjson <array>
+                 Print the elements of an array of the output, like
+                 ROOT.items[], as JSON lines, without collecting them; the
+                 rest of the output follows on a line of its own
+  --             Take the arguments that follow as they are, even those
+                 starting with - (the query and input of -e and --inline
+                 always are)
+  -h, --help     Show this message
+  --describe     List the statements of a query with their /// documentation
+  --emit-schema  Print the JSON Schema of the query's output
+  --plan         Print the tree of the query's output, with the statements
+                 capturing to each part of it
+  --allow <rule> With lint, don't report the problems of this rule
+                 (repeatable): adjacent_unbounded_any, splitby_without_bias,
+                 shadowed_root_field, empty_match, redundant_group,
+                 shadowed_name or conflicting_captures
+  --fix          With lint, rewrite the query file with the fixes of the
+                 problems that have one (comments are kept)
+
+infer prints a draft query matching every line of the samples file, capturing
+the parts the lines differ in (experimental).
+
+init-example creates examples/<name> from a query and an input, with the
+output (once confirmed) as expected.json, for the example tests.
+
+Input files ending in .gz or .zst are decompressed as they are read (when built
+with the gzip and zstd features).
+
+Exit codes:
+  0   the input matched the query
+  1   the input does not match the query
+  2   the input ambiguously matches the query
+  3   the query itself is invalid
+      (lint exits with 1 when it finds problems in a valid query)
+  4   a file could not be read
+  5   the evaluation timed out (--timeout)
+  6   the input matched, but a captured value can't be stored as the query asks
+      (a failed AS cast, a ONCE field written twice)
+  64  the command line is malformed
+  70  a bug of strql"
+    )
 }
@@ -0,0 +1,73 @@
+use crate::ast::QueryOptions;
+use std::borrow::Cow;
+use unicode_normalization::UnicodeNormalization;
+
+/// Applies the input preprocessing requested by the query's `OPTION` directives.
+///
+/// Steps run in a fixed order: CRLF normalization, trailing whitespace trimming, then NFC.
+pub(crate) fn normalize_input<'i>(options: &QueryOptions, input: &'i str) -> Cow<'i, str> {
+    let mut text = Cow::Borrowed(input);
+
+    if options.normalize_crlf && text.contains("\r\n") {
+        text = Cow::Owned(text.replace("\r\n", "\n"));
+    }
+
+    if options.trim_trailing_whitespace {
+        let trimmed = text
+            .split('\n')
+            .map(|line| line.trim_end())
+            .collect::<Vec<_>>()
+            .join("\n");
+        if trimmed != *text {
+            text = Cow::Owned(trimmed);
+        }
+    }
+
+    if options.nfc && !unicode_normalization::is_nfc(&text) {
+        text = Cow::Owned(text.nfc().collect());
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn untouched_without_options() {
+        let input = "a \r\nb";
+        assert!(matches!(
+            normalize_input(&QueryOptions::default(), input),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn crlf_and_trailing_whitespace() {
+        let options = QueryOptions {
+            normalize_crlf: true,
+            trim_trailing_whitespace: true,
+            ..Default::default()
+        };
+        assert_eq!(normalize_input(&options, "a  \r\nb\t\r\nc "), "a\nb\nc");
+    }
+
+    #[test]
+    fn trailing_whitespace_also_strips_carriage_returns() {
+        let options = QueryOptions {
+            trim_trailing_whitespace: true,
+            ..Default::default()
+        };
+        assert_eq!(normalize_input(&options, "a\r\nb"), "a\nb");
+    }
+
+    #[test]
+    fn nfc_composes_characters() {
+        let options = QueryOptions {
+            nfc: true,
+            ..Default::default()
+        };
+        assert_eq!(normalize_input(&options, "cafe\u{301}"), "caf\u{e9}");
+    }
+}
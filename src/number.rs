@@ -0,0 +1,99 @@
+//! Reading numbers written with the digit grouping and decimal mark of a locale, for
+//! `AS NUMBER(locale = "...")` captures.
+
+use serde_json::Value;
+
+/// The separators numbers are written with: any of `group` between digits of the integer
+/// part, and `decimal` before the fraction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Separators {
+    group: &'static [char],
+    decimal: char,
+}
+
+const SPACES: &[char] = &[' ', '\u{a0}', '\u{202f}'];
+
+/// The separators of `locale`, a language tag like `de` or `de-CH`, if it is known.
+pub(crate) fn separators(locale: &str) -> Option<Separators> {
+    let locale = locale.to_ascii_lowercase().replace('_', "-");
+    let language = locale.split('-').next().unwrap_or_default();
+    let (group, decimal) = match (language, locale.as_str()) {
+        (_, "de-ch" | "it-ch" | "fr-ch") => (&['\'', '’'][..], '.'),
+        ("en" | "ja" | "zh" | "ko" | "he" | "th", _) => (&[','][..], '.'),
+        ("de" | "nl" | "it" | "es" | "pt" | "da" | "id" | "tr" | "el" | "ro", _) => {
+            (&['.'][..], ',')
+        }
+        ("fr" | "ru" | "uk" | "pl" | "cs" | "sk" | "sv" | "fi" | "nb" | "no" | "hu" | "bg", _) => {
+            (SPACES, ',')
+        }
+        _ => return None,
+    };
+    Some(Separators { group, decimal })
+}
+
+/// Reads `value`, like `-1.234,5` in German, as an integer if it has no fraction and as a
+/// float otherwise.
+pub(crate) fn parse_number(value: &str, separators: Separators) -> Option<Value> {
+    let (negative, rest) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let (integer, fraction) = match rest.split_once(separators.decimal) {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (rest, None),
+    };
+
+    // groups can only separate digits
+    let mut digits = String::with_capacity(rest.len() + 1);
+    if negative {
+        digits.push('-');
+    }
+    let mut previous_digit = false;
+    for c in integer.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            previous_digit = true;
+        } else if separators.group.contains(&c) && previous_digit {
+            previous_digit = false;
+        } else {
+            return None;
+        }
+    }
+    if !previous_digit {
+        return None;
+    }
+
+    match fraction {
+        None => digits.parse::<i64>().ok().map(Value::from),
+        Some(fraction) if !fraction.is_empty() && fraction.bytes().all(|b| b.is_ascii_digit()) => {
+            digits.push('.');
+            digits.push_str(fraction);
+            digits.parse::<f64>().ok().map(Value::from)
+        }
+        Some(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(value: &str, locale: &str) -> Option<Value> {
+        parse_number(value, separators(locale).unwrap())
+    }
+
+    #[test]
+    fn locales() {
+        assert_eq!(parse("1,234.5", "en"), Some(json!(1234.5)));
+        assert_eq!(parse("-1.234,5", "de"), Some(json!(-1234.5)));
+        assert_eq!(parse("1.234", "de-DE"), Some(json!(1234)));
+        assert_eq!(parse("1\u{202f}234,75", "fr"), Some(json!(1234.75)));
+        assert_eq!(parse("1'234.5", "de-CH"), Some(json!(1234.5)));
+        assert_eq!(parse("1,234.5", "de"), None);
+        assert_eq!(parse("1..234", "de"), None);
+        assert_eq!(parse(".5", "en"), None);
+        assert_eq!(parse("5,", "de"), None);
+        assert!(separators("tlh").is_none());
+    }
+}
@@ -1,12 +1,21 @@
 use crate::ast::*;
 use crate::error::{NamedSourceExt, StrqlError, StrqlResult};
-use crate::lexer::{SpannedToken, Token};
+use crate::lexer::{Params, SpannedToken, Token};
+use crate::number::separators;
+use crate::stdlib;
+use crate::visit::{walk_pattern, Visitor};
+use std::collections::HashMap;
+
+/// How many patterns `PERMUTE` and `FIELDS` take at most: matching tries the subsets of them.
+pub(crate) const MAX_PERMUTED: usize = 16;
 
 pub struct Parser<'a> {
     source: &'a str,
     tokens: Vec<SpannedToken>,
     cursor: usize,
     inlined_statements: Vec<Statement>,
+    /// whether the query declares `OPTION SKIP_UNMATCHED`, which changes how `SPLITBY`s are read
+    skip_unmatched: bool,
 }
 
 impl<'a> NamedSourceExt<'a> for Parser<'a> {
@@ -17,54 +26,525 @@ impl<'a> NamedSourceExt<'a> for Parser<'a> {
 
 impl<'a> Parser<'a> {
     pub fn new(source: &'a str) -> StrqlResult<Self> {
+        Self::with_params(source, &Params::new())
+    }
+
+    /// A parser for queries whose literals use `${NAME}` parameters, with their values.
+    pub fn with_params(source: &'a str, params: &Params) -> StrqlResult<Self> {
         Ok(Self {
             source,
-            tokens: Token::vec_from(source)?,
+            tokens: Token::vec_with_params(source, params)?,
             cursor: 0,
             inlined_statements: Vec::new(),
+            skip_unmatched: false,
         })
     }
 
     pub fn parse(mut self) -> StrqlResult<Program> {
-        let mut statements = Vec::new();
+        let mut statements: Vec<Statement> = Vec::new();
+        let mut options = QueryOptions::default();
+        let mut defines: Vec<Define> = Vec::new();
+        let mut imports: Vec<String> = Vec::new();
+        // the option applies to the `SPLITBY`s written before it too
+        self.skip_unmatched = (0..self.tokens.len()).any(|index| {
+            self.is_directive_at(index, "OPTION")
+                && matches!(
+                    self.tokens.get(index + 1).map(|t| &t.token),
+                    Some(Token::Identifier(name)) if name.eq_ignore_ascii_case("SKIP_UNMATCHED")
+                )
+        });
         self.skip_newlines();
 
+        let mut errors = Vec::new();
         while !self.is_at_end() {
-            statements.push(self.parse_statement()?);
+            let doc_cursor = self.cursor;
+            let doc = self.parse_doc_comments();
+            if self.is_at_end() {
+                break;
+            }
+            let start = self.cursor;
+            if doc.is_some() {
+                if let Some(error) = self.misplaced_doc_comment(doc_cursor) {
+                    errors.push(error);
+                }
+            }
+            let parsed = if self.is_next_directive("OPTION") {
+                self.parse_option(&mut options)
+            } else if self.is_next_directive("DEFINE") {
+                self.parse_define().map(|define| defines.push(define))
+            } else if self.is_next_import() {
+                self.parse_import().map(|name| {
+                    // importing a grammar twice imports it once
+                    if !imports.contains(&name) {
+                        imports.push(name);
+                    }
+                })
+            } else {
+                self.parse_statement().map(|mut statement| {
+                    statement.doc = join_docs(doc, statement.doc.take());
+                    let base = statement
+                        .extends
+                        .then(|| statements.iter_mut().find(|s| s.name == statement.name))
+                        .flatten();
+                    match base {
+                        Some(base) => base.extend_with(statement),
+                        None => statements.push(statement),
+                    }
+                })
+            };
+            if let Err(error) = parsed {
+                errors.push(error);
+                self.skip_line(start);
+            }
             self.skip_newlines();
         }
+        match errors.len() {
+            0 => {}
+            1 => return Err(errors.remove(0)),
+            _ => return Err(StrqlError::SyntaxErrors { _errors: errors }),
+        }
+
+        statements.extend(std::mem::take(&mut self.inlined_statements));
+        self.expand_defines(&mut statements, defines)?;
+
+        let mut program = Program {
+            statements,
+            options,
+            source: self.source.to_string(),
+        };
+        for name in imports {
+            if let Some(grammar) = stdlib::import(&name) {
+                program.merge(grammar?)?;
+            }
+        }
+        Ok(program)
+    }
+
+    /// The error for the `///` lines from `doc_cursor` on, when they come before a directive
+    /// rather than a statement.
+    fn misplaced_doc_comment(&self, doc_cursor: usize) -> Option<StrqlError> {
+        let keyword = if self.is_next_directive("OPTION") {
+            "OPTION"
+        } else if self.is_next_directive("DEFINE") {
+            "DEFINE"
+        } else if self.is_next_import() {
+            "IMPORT"
+        } else {
+            return None;
+        };
+        let mut docs = self.tokens[doc_cursor..self.cursor]
+            .iter()
+            .filter(|t| matches!(t.token, Token::DocComment(_)))
+            .map(|t| t.span.clone());
+        let first = docs.next()?;
+        let end = docs.next_back().map_or(first.end, |last| last.end);
+        Some(StrqlError::MisplacedDocComment {
+            _keyword: keyword.to_string(),
+            _src: self.src_to_named(),
+            _span: (first.start..end).into(),
+        })
+    }
 
-        statements.extend(self.inlined_statements);
+    /// `IMPORT <name>` at the start of a line, the only place where `IMPORT` is a keyword.
+    fn is_next_import(&self) -> bool {
+        let next = |i: usize| self.tokens.get(self.cursor + i).map(|t| &t.token);
+        matches!(next(0), Some(Token::Identifier(word)) if word.eq_ignore_ascii_case("IMPORT"))
+            && matches!(next(1), Some(Token::Identifier(_)))
+            && matches!(next(2), None | Some(Token::NewlineChar | Token::CrLf))
+    }
 
-        Ok(Program { statements })
+    /// `IMPORT <name>`, naming one of the [`stdlib`] grammars.
+    fn parse_import(&mut self) -> StrqlResult<String> {
+        self.advance_cursor_and_get();
+        let name_cursor = self.cursor;
+        let name = self.expect_identifier()?;
+        if let Some(feature) = stdlib::missing_feature(&name) {
+            return Err(StrqlError::ImportNeedsFeature {
+                _name: name,
+                _feature: feature.to_string(),
+                _src: self.src_to_named(),
+                _span: self.span_from(name_cursor).into(),
+            });
+        }
+        if stdlib::source(&name).is_none() {
+            return Err(StrqlError::UnknownImport {
+                _name: name,
+                _help: format!(
+                    "The grammars that can be imported are: {}",
+                    stdlib::names().collect::<Vec<_>>().join(", ")
+                ),
+                _src: self.src_to_named(),
+                _span: self.span_from(name_cursor).into(),
+            });
+        }
+        Ok(name)
     }
 
-    fn parse_statement(&mut self) -> StrqlResult<Statement> {
+    /// `OPTION` and `DEFINE` at the start of a statement, which they are otherwise valid names
+    /// of: `option = ...` is not a directive.
+    fn is_next_directive(&self, keyword: &str) -> bool {
+        self.is_directive_at(self.cursor, keyword)
+    }
+
+    /// Whether the token at `index` starts a line with the directive `keyword`, like
+    /// [`Parser::is_next_directive`] does: in `row = option x`, `option` is a reference.
+    fn is_directive_at(&self, index: usize, keyword: &str) -> bool {
+        let starts_line = index == 0
+            || matches!(
+                self.tokens.get(index - 1).map(|t| &t.token),
+                Some(Token::NewlineChar | Token::CrLf | Token::DocComment(_))
+            );
+        starts_line
+            && self.is_keyword_at(index, keyword)
+            && !matches!(
+                self.tokens.get(index + 1).map(|t| &t.token),
+                Some(Token::Equals | Token::OrEquals)
+            )
+    }
+
+    /// `OPTION <name>`
+    fn parse_option(&mut self, options: &mut QueryOptions) -> StrqlResult<()> {
+        self.expect_keyword("OPTION")?;
+        let span = self.tokens.get(self.cursor).map(|t| t.span.clone());
+        let name = match self.peek() {
+            Some(Token::Identifier(name)) => name.to_ascii_uppercase(),
+            Some(Token::AnyCase) => "ANYCASE".to_string(),
+            _ => return Err(self.unexpected_token("option name")),
+        };
+        self.advance_cursor_and_get();
+
+        match name.as_str() {
+            "NORMALIZE_CRLF" => options.normalize_crlf = true,
+            "TRIM_TRAILING_WHITESPACE" => options.trim_trailing_whitespace = true,
+            "NFC" => options.nfc = true,
+            "ANYCASE" => options.anycase = true,
+            "REORDER_ALTERNATIVES" => options.reorder_alternatives = true,
+            "SKIP_UNMATCHED" => options.skip_unmatched = true,
+            "STRATEGY" => {
+                options.strategy = Some(match self.peek() {
+                    Some(Token::Identifier(name)) if name.eq_ignore_ascii_case("PEG") => {
+                        Strategy::Peg
+                    }
+                    Some(Token::Identifier(name)) if name.eq_ignore_ascii_case("CFG") => {
+                        Strategy::Cfg
+                    }
+                    _ => return Err(self.unexpected_token("PEG or CFG")),
+                });
+                self.advance_cursor_and_get();
+            }
+            "CAPTURE_RAW" => {
+                let field = if self.is_next_keyword("AS") {
+                    self.advance_cursor_and_get();
+                    self.expect_identifier()?
+                } else {
+                    "_raw".to_string()
+                };
+                options.capture_raw = Some(field);
+            }
+            _ => {
+                return Err(StrqlError::UnknownOption {
+                    _name: name,
+                    _src: self.src_to_named(),
+                    _span: span.unwrap_or_default().into(),
+                })
+            }
+        }
+        Ok(())
+    }
+
+    /// `DEFINE <name> = <pattern>`
+    fn parse_define(&mut self) -> StrqlResult<Define> {
         let start_cursor = self.cursor;
+        self.expect_keyword("DEFINE")?;
         let name = self.lvalue()?;
         self.expect(&Token::Equals)?;
         let pattern = self.parse_pattern()?;
+        if self.check(&[&Token::Arrow]) {
+            return Err(self.unexpected_token("end of DEFINE (defines cannot capture)"));
+        }
+        Ok(Define {
+            name,
+            pattern,
+            span: self.span_from(start_cursor),
+        })
+    }
 
-        let capture = if self.check(&[&Token::Arrow]) {
+    /// Substitutes every reference to a `DEFINE` with its pattern, so defines never reach the solver.
+    fn expand_defines(
+        &self,
+        statements: &mut [Statement],
+        defines: Vec<Define>,
+    ) -> StrqlResult<()> {
+        if defines.is_empty() {
+            return Ok(());
+        }
+        for (i, define) in defines.iter().enumerate() {
+            if let Some(first) = defines[..i].iter().find(|d| d.name == define.name) {
+                return Err(StrqlError::DuplicateDefine {
+                    _name: define.name.clone(),
+                    _src: self.src_to_named(),
+                    _first: first.span.clone().into(),
+                    _span: define.span.clone().into(),
+                });
+            }
+        }
+        if let Some(stmt) = statements
+            .iter()
+            .find(|s| defines.iter().any(|d| d.name == s.name))
+        {
+            return Err(StrqlError::DefineConflict {
+                _name: stmt.name.clone(),
+                _src: self.src_to_named(),
+                _span: stmt.span.clone().into(),
+            });
+        }
+
+        let defines: HashMap<String, Pattern> = defines
+            .into_iter()
+            .map(|define| (define.name, define.pattern))
+            .collect();
+        let mut expanding = Vec::new();
+        for stmt in statements.iter_mut() {
+            self.expand_in(&mut stmt.pattern, &defines, &mut expanding)?;
+        }
+        Ok(())
+    }
+
+    fn expand_in(
+        &self,
+        pattern: &mut Pattern,
+        defines: &HashMap<String, Pattern>,
+        expanding: &mut Vec<String>,
+    ) -> StrqlResult<()> {
+        match &mut pattern.node {
+            PatternKind::Variable(name) => {
+                let Some(body) = defines.get(name.as_str()) else {
+                    return Ok(());
+                };
+                if expanding.contains(name) {
+                    return Err(StrqlError::RecursiveDefine {
+                        _name: name.clone(),
+                        _src: self.src_to_named(),
+                        _span: pattern.span.clone().into(),
+                    });
+                }
+                let mut body = body.clone();
+                expanding.push(name.clone());
+                self.expand_in(&mut body, defines, expanding)?;
+                expanding.pop();
+                // keep the use site's span, so diagnostics point at the reference
+                pattern.node = body.node;
+            }
+            PatternKind::Sequence(items)
+            | PatternKind::Permute(items)
+            | PatternKind::Fields(items)
+            | PatternKind::OrChain {
+                alternatives: items,
+                ..
+            } => {
+                for item in items {
+                    self.expand_in(item, defines, expanding)?;
+                }
+            }
+            PatternKind::Repetition { pattern: inner, .. }
+            | PatternKind::AnyCase(inner)
+            | PatternKind::Upper(inner)
+            | PatternKind::Lower(inner)
+            | PatternKind::Group(inner)
+            | PatternKind::PrecededBy(inner)
+            | PatternKind::Unmatched(inner) => self.expand_in(inner, defines, expanding)?,
+            PatternKind::Literal(_) | PatternKind::Builtin(_) | PatternKind::Dict(_) => {}
+        }
+        Ok(())
+    }
+
+    fn parse_statement(&mut self) -> StrqlResult<Statement> {
+        let start_cursor = self.cursor;
+        if self.peek().is_some_and(is_builtin) {
+            let span = self.tokens[self.cursor].span.clone();
+            return Err(StrqlError::RedefinedBuiltin {
+                _name: self.source[span.clone()].to_string(),
+                _src: self.src_to_named(),
+                _span: span.into(),
+            });
+        }
+        let name = self.lvalue()?;
+        let extends = self.check(&[&Token::OrEquals]);
+        if extends {
             self.advance_cursor_and_get();
-            let mut cap = self.parse_capture_clause()?;
-            if cap.name.is_empty() {
-                cap.name = name.clone();
+        } else {
+            self.expect(&Token::Equals)?;
+        }
+        let (pattern, capture) = if extends {
+            let pattern = self.parse_pattern()?;
+            if self.check(&[&Token::Arrow]) {
+                // the added alternative is captured like the rest of the statement
+                return Err(self.unexpected_token("end of statement (`|=` cannot capture)"));
             }
-            Some(cap)
+            (pattern, None)
+        } else if self.is_next_call("KV") {
+            // the pairs are captured by the statements KV desugars into
+            (self.parse_kv(start_cursor, &name)?, None)
         } else {
-            None
+            let mut pattern = self.parse_pattern()?;
+            let positional = matches!(
+                self.tokens.get(self.cursor + 2).map(|t| &t.token),
+                Some(Token::Positional(_))
+            );
+            let capture = if positional && self.check(&[&Token::Arrow]) {
+                // the elements are captured by the statements they are moved into
+                self.parse_positional_captures(&name, &mut pattern)?;
+                None
+            } else if self.check(&[&Token::Arrow]) {
+                Some(self.parse_arrow_capture(&name, &pattern)?)
+            } else {
+                None
+            };
+            (pattern, capture)
         };
 
         let span = self.span_from(start_cursor);
+
+        // a doc comment on the same line documents the statement it follows
+        let doc = match self.peek() {
+            Some(Token::DocComment(text)) => {
+                let text = text.clone();
+                self.advance_cursor_and_get();
+                Some(text)
+            }
+            _ => None,
+        };
+
         Ok(Statement {
             name,
             pattern,
             capture,
             span,
+            doc,
+            extends,
+        })
+    }
+
+    /// `KV(key = <pattern>, sep = <pattern>, value = <pattern>) -> ADD TO <path>`, the whole
+    /// pattern (and capture) of statement `name`, with `sep` defaulting to `": "`.
+    ///
+    /// Desugars into `<name>.key <sep> <name>.value`, where the value is captured under the
+    /// key: `<name>.value = <pattern> -> ADD TO <path>[<name>.key]`. The capture's other
+    /// settings, like `AS INT`, apply to the values; its path can't end in `[]`.
+    fn parse_kv(&mut self, start_cursor: usize, name: &str) -> StrqlResult<Pattern> {
+        let kv_cursor = self.cursor;
+        self.expect_keyword("KV")?;
+        self.expect(&Token::LParen)?;
+        let (mut key, mut sep, mut value) = (None, None, None);
+        loop {
+            let arg = match self.peek() {
+                Some(Token::Identifier(arg)) => arg.to_ascii_lowercase(),
+                _ => String::new(),
+            };
+            let slot = match arg.as_str() {
+                "key" => &mut key,
+                "sep" => &mut sep,
+                "value" => &mut value,
+                _ => return Err(self.unexpected_token("`key`, `sep` or `value`")),
+            };
+            if slot.is_some() {
+                return Err(self.unexpected_token("each of `key`, `sep` and `value` at most once"));
+            }
+            self.advance_cursor_and_get();
+            self.expect(&Token::Equals)?;
+            *slot = Some(self.parse_pattern()?);
+            if !self.check(&[&Token::Comma]) {
+                break;
+            }
+            self.advance_cursor_and_get();
+        }
+        let (Some(key), Some(value)) = (key, value) else {
+            return Err(self.unexpected_token("both a `key` and a `value`"));
+        };
+        self.expect(&Token::RParen)?;
+        let kv_span = self.span_from(kv_cursor);
+        let sep = sep.unwrap_or_else(|| Pattern {
+            node: PatternKind::Literal(": ".into()),
+            span: kv_span.clone(),
+        });
+
+        let capture = if self.check(&[&Token::Arrow]) {
+            self.advance_cursor_and_get();
+            let capture_cursor = self.cursor;
+            let clause = self.parse_capture_clause()?;
+            if clause.path.ends_with_array() {
+                // point at the `[]`: the values are stored under their keys, not appended
+                self.cursor = (capture_cursor..self.cursor)
+                    .rev()
+                    .find(|&i| self.tokens[i].token == Token::LBracket)
+                    .unwrap_or(capture_cursor);
+                return Err(self.unexpected_token("a field path (without `[]`) for KV values"));
+            }
+            Some(clause)
+        } else if self.is_at_end()
+            || self.check(&[&Token::NewlineChar, &Token::CrLf, &Token::RParen])
+            || matches!(self.peek(), Some(Token::DocComment(_)))
+        {
+            None
+        } else {
+            return Err(self.unexpected_token("end of statement (KV is a whole pattern)"));
+        };
+
+        let key_name = format!("{}.key", name);
+        let value_name = format!("{}.value", name);
+        let value_capture = capture.map(|mut clause| {
+            clause.name = value_name.clone();
+            clause
+                .path
+                .segments
+                .push(PathSegment::DynamicField(vec![KeyPart::Variable(
+                    key_name.clone(),
+                )]));
+            clause
+        });
+        let span = self.span_from(start_cursor);
+        let statement = |name: String, pattern, capture| Statement {
+            name,
+            pattern,
+            capture,
+            span: span.clone(),
+            doc: None,
+            extends: false,
+        };
+        // the variables stand where the patterns they refer to were written
+        let key_variable = Pattern {
+            node: PatternKind::Variable(key_name.clone()),
+            span: key.span.clone(),
+        };
+        let value_variable = Pattern {
+            node: PatternKind::Variable(value_name.clone()),
+            span: value.span.clone(),
+        };
+        self.inlined_statements.push(statement(key_name, key, None));
+        self.inlined_statements
+            .push(statement(value_name, value, value_capture));
+        Ok(Pattern {
+            node: PatternKind::Sequence(vec![key_variable, sep, value_variable]),
+            span: kv_span,
         })
     }
 
+    /// Consumes consecutive `///` lines (and the blank lines around them).
+    fn parse_doc_comments(&mut self) -> Option<String> {
+        let mut lines = Vec::new();
+        loop {
+            self.skip_newlines();
+            match self.peek() {
+                Some(Token::DocComment(text)) => {
+                    lines.push(text.clone());
+                    self.advance_cursor_and_get();
+                }
+                _ => break,
+            }
+        }
+        (!lines.is_empty()).then(|| lines.join("\n"))
+    }
+
     fn span_from(&self, start_cursor: usize) -> std::ops::Range<usize> {
         let start = self
             .tokens
@@ -98,22 +578,93 @@ impl<'a> Parser<'a> {
 
     fn parse_alternation(&mut self) -> StrqlResult<Pattern> {
         let start_cursor = self.cursor;
-        let mut left = self.parse_sequence()?;
-
-        while self.check(&[&Token::Or]) {
-            self.advance_cursor_and_get();
-            let right = self.parse_sequence()?;
+        let first = self.parse_sequence()?;
 
-            left = match left.node {
-                PatternKind::OrChain(mut alts) => {
-                    alts.push(right);
-                    self.make_pattern(start_cursor, PatternKind::OrChain(alts))
+        let mut alternatives = vec![first];
+        let mut weights = vec![self.parse_weight()];
+        let mut chain_bias = None;
+        loop {
+            let bias_cursor = self.cursor;
+            let bias = match self.peek_alternation_bias() {
+                Some(bias) => {
+                    self.advance_cursor_and_get();
+                    bias
                 }
-                _ => self.make_pattern(start_cursor, PatternKind::OrChain(vec![left, right])),
+                None if self.check(&[&Token::Or]) => AlternationBias::Neutral,
+                None => break,
             };
+            if chain_bias.is_some_and(|chain_bias| chain_bias != bias) {
+                self.cursor = bias_cursor;
+                return Err(self.unexpected_token(
+                    "an OR with the same bias as the others (group them to mix biases)",
+                ));
+            }
+            chain_bias = Some(bias);
+            self.advance_cursor_and_get();
+            alternatives.push(self.parse_sequence()?);
+            weights.push(self.parse_weight());
+        }
+
+        if let Some(&(weight_cursor, _)) = weights.iter().flatten().next() {
+            if chain_bias != Some(AlternationBias::Neutral) {
+                self.cursor = weight_cursor;
+                return Err(self.unexpected_token(match chain_bias {
+                    None => "an OR after a WEIGHT (weights are for the alternatives of an OR)",
+                    Some(_) => "a plain OR (WEIGHT doesn't go with FIRST or LONGEST)",
+                }));
+            }
+            // alternatives without a weight weigh 1
+            let weights = weights.iter().map(|w| w.map_or(1, |(_, w)| w)).collect();
+            chain_bias = Some(AlternationBias::Weighted(weights));
+        }
+
+        match chain_bias {
+            Some(bias) => {
+                Ok(self.make_pattern(start_cursor, PatternKind::OrChain { alternatives, bias }))
+            }
+            None => Ok(alternatives.remove(0)),
+        }
+    }
+
+    /// `FIRST` or `LONGEST` right before an `OR`, the only place where they are keywords.
+    fn peek_alternation_bias(&self) -> Option<AlternationBias> {
+        let Some(Token::Identifier(word)) = self.peek() else {
+            return None;
+        };
+        if !matches!(
+            self.tokens.get(self.cursor + 1).map(|t| &t.token),
+            Some(Token::Or)
+        ) {
+            return None;
+        }
+        if word.eq_ignore_ascii_case("FIRST") {
+            Some(AlternationBias::First)
+        } else if word.eq_ignore_ascii_case("LONGEST") {
+            Some(AlternationBias::Longest)
+        } else {
+            None
         }
+    }
+
+    /// Whether the next tokens are `WEIGHT <n>`, the only place where `WEIGHT` is a keyword.
+    fn is_next_weight(&self) -> bool {
+        let next = |i: usize| self.tokens.get(self.cursor + i).map(|t| &t.token);
+        matches!(next(0), Some(Token::Identifier(word)) if word.eq_ignore_ascii_case("WEIGHT"))
+            && matches!(next(1), Some(Token::Number(_)))
+            && !matches!(next(2), Some(Token::DotDot))
+    }
 
-        Ok(left)
+    /// The `WEIGHT <n>` of an alternative, and where it starts.
+    fn parse_weight(&mut self) -> Option<(usize, u32)> {
+        if !self.is_next_weight() {
+            return None;
+        }
+        let cursor = self.cursor;
+        self.advance_cursor_and_get();
+        let Some(&Token::Number(weight)) = self.get_and_advance_cursor() else {
+            unreachable!("parse_weight: checked by is_next_weight");
+        };
+        Some((cursor, weight.min(u32::MAX as usize) as u32))
     }
 
     fn parse_sequence(&mut self) -> StrqlResult<Pattern> {
@@ -125,9 +676,16 @@ impl<'a> Parser<'a> {
                 &Token::Or,
                 &Token::Arrow,
                 &Token::RParen,
+                &Token::Comma,
+                &Token::Semicolon,
+                &Token::RBrace,
                 &Token::NewlineChar,
                 &Token::CrLf,
             ])
+            && !matches!(self.peek(), Some(Token::DocComment(_)))
+            // `first OR ...` starting an alternative refers to a statement `first`
+            && (items.is_empty()
+                || (self.peek_alternation_bias().is_none() && !self.is_next_weight()))
         {
             items.push(self.parse_quantified()?);
         }
@@ -143,11 +701,25 @@ impl<'a> Parser<'a> {
         let start_cursor = self.cursor;
         let bias = self.parse_bias();
 
+        let bounds_cursor = self.cursor;
         if let Ok(min) = self.parse_bound(true) {
             self.expect(&Token::DotDot)?;
 
             let max = self.parse_bound(false)?;
+            if let (Some(min), Some(max)) = (min, max) {
+                if min > max {
+                    return Err(StrqlError::InvalidQuantifierBounds {
+                        _min: min,
+                        _max: max,
+                        _src: self.src_to_named(),
+                        _span: self.span_from(bounds_cursor).into(),
+                    });
+                }
+            }
             let pattern = self.parse_primary(QuantifierBias::Neutral)?;
+            if self.is_next_sep() {
+                return self.parse_separated(start_cursor, min, max, pattern, bias);
+            }
             return Ok(self.make_pattern(
                 start_cursor,
                 PatternKind::Repetition {
@@ -162,6 +734,88 @@ impl<'a> Parser<'a> {
         self.parse_splitby(bias)
     }
 
+    /// Whether the next tokens are `SEP <separator>`, the only place where `SEP` is a keyword:
+    /// a statement `sep` can still be referred to after a repetition as `` `sep` ``.
+    fn is_next_sep(&self) -> bool {
+        let Some(SpannedToken {
+            token: Token::Identifier(word),
+            span,
+        }) = self.tokens.get(self.cursor)
+        else {
+            return false;
+        };
+        word.eq_ignore_ascii_case("SEP")
+            && !self.source[span.clone()].starts_with('`')
+            && !matches!(
+                self.tokens.get(self.cursor + 1).map(|t| &t.token),
+                None | Some(
+                    Token::Or
+                        | Token::Arrow
+                        | Token::RParen
+                        | Token::Comma
+                        | Token::NewlineChar
+                        | Token::CrLf
+                )
+            )
+    }
+
+    /// `<min>..<max> <item> SEP <separator>`: `item` repeated with `separator` between each
+    /// two, desugared to `item (<min>-1)..(<max>-1) (<separator> <item>)`.
+    fn parse_separated(
+        &mut self,
+        start_cursor: usize,
+        min: Bound,
+        max: Bound,
+        item: Pattern,
+        bias: QuantifierBias,
+    ) -> StrqlResult<Pattern> {
+        let sep_cursor = self.cursor;
+        self.advance_cursor_and_get();
+        let separator = self.parse_primary(QuantifierBias::Neutral)?;
+        if max == Some(0) {
+            return Ok(self.make_pattern(
+                start_cursor,
+                PatternKind::Repetition {
+                    min,
+                    max,
+                    pattern: Box::new(item),
+                    bias,
+                },
+            ));
+        }
+
+        let tail = self.make_pattern(
+            sep_cursor,
+            PatternKind::Sequence(vec![separator, item.clone()]),
+        );
+        let tail_quantifier = self.make_pattern(
+            sep_cursor,
+            PatternKind::Repetition {
+                min: Some(min.unwrap_or(0).saturating_sub(1)),
+                max: max.map(|max| max - 1),
+                pattern: Box::new(tail),
+                bias,
+            },
+        );
+        let list = self.make_pattern(
+            start_cursor,
+            PatternKind::Sequence(vec![item, tail_quantifier]),
+        );
+        if min.unwrap_or(0) > 0 {
+            return Ok(list);
+        }
+        // an empty list has no item either
+        Ok(self.make_pattern(
+            start_cursor,
+            PatternKind::Repetition {
+                min: Some(0),
+                max: Some(1),
+                pattern: Box::new(list),
+                bias,
+            },
+        ))
+    }
+
     fn parse_bias(&mut self) -> QuantifierBias {
         if self.check(&[&Token::Lazy]) {
             self.advance_cursor_and_get();
@@ -205,18 +859,36 @@ impl<'a> Parser<'a> {
         let start_cursor = self.cursor;
         let pattern = self.parse_modified(bias)?;
 
+        let bias_cursor = self.cursor;
         let bias = self.parse_bias();
 
         if self.check(&[&Token::SplitBy]) {
             self.advance_cursor_and_get();
             let separator = self.parse_primary(QuantifierBias::Neutral)?;
+            // items that don't match are skipped up to the next separator
+            let pattern = if self.skip_unmatched {
+                let unmatched = Pattern {
+                    node: PatternKind::Unmatched(Box::new(separator.clone())),
+                    span: pattern.span.clone(),
+                };
+                Pattern {
+                    span: pattern.span.clone(),
+                    node: PatternKind::OrChain {
+                        alternatives: vec![pattern, unmatched],
+                        bias: AlternationBias::First,
+                    },
+                }
+            } else {
+                pattern
+            };
 
+            // the repeated tail stands for `[bias] SPLITBY <sep>`
             let tail = self.make_pattern(
-                start_cursor,
+                bias_cursor,
                 PatternKind::Sequence(vec![separator, pattern.clone()]),
             );
             let tail_quantifier = self.make_pattern(
-                start_cursor,
+                bias_cursor,
                 PatternKind::Repetition {
                     min: Some(0),
                     max: None,
@@ -232,6 +904,11 @@ impl<'a> Parser<'a> {
         }
 
         if bias != QuantifierBias::Neutral {
+            if !self.is_at_end() && !self.check(&[&Token::NewlineChar, &Token::CrLf]) {
+                // the bias of the next item, like in `"a" LAZY 1..3 DIGIT`
+                self.cursor = bias_cursor;
+                return Ok(pattern);
+            }
             return Err(
                 self.unexpected_token("a quantifier (`SPLITBY`, `n..m`, `WORD`, `ANY`, etc.)")
             );
@@ -262,11 +939,37 @@ impl<'a> Parser<'a> {
 
     fn parse_primary(&mut self, bias: QuantifierBias) -> StrqlResult<Pattern> {
         let start_cursor = self.cursor;
+        // repetitions like `LAZY WORD` start at their bias
+        let biased_cursor = match start_cursor.checked_sub(1).map(|i| &self.tokens[i].token) {
+            Some(Token::Lazy | Token::Greedy) if bias != QuantifierBias::Neutral => {
+                start_cursor - 1
+            }
+            _ => start_cursor,
+        };
         let token = self.get_and_advance_cursor().cloned();
         match token {
             Some(Token::StringLiteral(s)) => {
                 Ok(self.make_pattern(start_cursor, PatternKind::Literal(s)))
             }
+            // `FIELDS` is only a keyword before `{`, since `fields` is a common capture name
+            Some(Token::Identifier(idf))
+                if idf.eq_ignore_ascii_case("FIELDS")
+                    && self.check(&[&Token::LBrace])
+                    && !self.source[self.tokens[start_cursor].span.clone()].starts_with('`') =>
+            {
+                self.parse_fields(start_cursor)
+            }
+            // `DICT(<name>)`, where `dict` is otherwise a statement like any other
+            Some(Token::Identifier(idf))
+                if idf.eq_ignore_ascii_case("DICT")
+                    && self.check(&[&Token::LParen])
+                    && !self.source[self.tokens[start_cursor].span.clone()].starts_with('`') =>
+            {
+                self.expect(&Token::LParen)?;
+                let name = self.expect_identifier()?;
+                self.expect(&Token::RParen)?;
+                Ok(self.make_pattern(start_cursor, PatternKind::Dict(name)))
+            }
             Some(Token::Identifier(idf)) => {
                 Ok(self.make_pattern(start_cursor, PatternKind::Variable(idf)))
             }
@@ -286,7 +989,7 @@ impl<'a> Parser<'a> {
                 Ok(self.make_pattern(start_cursor, PatternKind::Builtin(Builtin::Space)))
             }
             Some(Token::Any) => Ok(self.make_pattern(
-                start_cursor,
+                biased_cursor,
                 PatternKind::Repetition {
                     // desugar into 0..n ANYCHAR
                     min: Some(0),
@@ -298,10 +1001,10 @@ impl<'a> Parser<'a> {
                 },
             )),
             Some(Token::Word) => Ok(self.make_pattern(
-                start_cursor,
+                biased_cursor,
                 PatternKind::Repetition {
-                    // desugar into 0..n LETTER
-                    min: Some(0),
+                    // desugar into 1..n LETTER
+                    min: Some(1),
                     max: None,
                     pattern: Box::new(
                         self.make_pattern(start_cursor, PatternKind::Builtin(Builtin::Letter)),
@@ -310,17 +1013,26 @@ impl<'a> Parser<'a> {
                 },
             )),
             Some(Token::Alphanum) => Ok(self.make_pattern(
-                start_cursor,
+                biased_cursor,
                 PatternKind::Repetition {
-                    // desugar into 0..n (LETTER OR DIGIT)
-                    min: Some(0),
+                    // desugar into 1..n (LETTER OR DIGIT)
+                    min: Some(1),
                     max: None,
                     pattern: Box::new(self.make_pattern(
                         start_cursor,
-                        PatternKind::OrChain(vec![
-                            self.make_pattern(start_cursor, PatternKind::Builtin(Builtin::Letter)),
-                            self.make_pattern(start_cursor, PatternKind::Builtin(Builtin::Digit)),
-                        ]),
+                        PatternKind::OrChain {
+                            alternatives: vec![
+                                self.make_pattern(
+                                    start_cursor,
+                                    PatternKind::Builtin(Builtin::Letter),
+                                ),
+                                self.make_pattern(
+                                    start_cursor,
+                                    PatternKind::Builtin(Builtin::Digit),
+                                ),
+                            ],
+                            bias: AlternationBias::Neutral,
+                        },
                     )),
                     bias,
                 },
@@ -328,6 +1040,62 @@ impl<'a> Parser<'a> {
             Some(Token::Line) => {
                 Ok(self.make_pattern(start_cursor, PatternKind::Builtin(Builtin::Line)))
             }
+            Some(Token::Paragraph) => {
+                Ok(self.make_pattern(start_cursor, PatternKind::Builtin(Builtin::Paragraph)))
+            }
+            Some(Token::ContLine) => {
+                Ok(self.make_pattern(start_cursor, PatternKind::Builtin(Builtin::ContLine)))
+            }
+            Some(Token::HexNum) => {
+                Ok(self.radix_number(biased_cursor, "0x", Builtin::HexDigit, bias))
+            }
+            Some(Token::BinNum) => {
+                Ok(self.radix_number(biased_cursor, "0b", Builtin::BinDigit, bias))
+            }
+            Some(Token::OctNum) => {
+                Ok(self.radix_number(biased_cursor, "0o", Builtin::OctDigit, bias))
+            }
+            Some(Token::Byte) => {
+                let value = self.parenthesized_number("byte value from 0 to 0xFF", 0..=0xff)?;
+                Ok(self.make_pattern(
+                    start_cursor,
+                    PatternKind::Builtin(Builtin::Byte(value as u8)),
+                ))
+            }
+            Some(Token::Bytes) => {
+                let count = self.parenthesized_number("byte count", 0..=usize::MAX)?;
+                Ok(self.make_pattern(start_cursor, PatternKind::Builtin(Builtin::Bytes(count))))
+            }
+            Some(Token::Base64) => {
+                Ok(self.make_pattern(start_cursor, PatternKind::Builtin(Builtin::Base64)))
+            }
+            Some(Token::HexBlob) => {
+                Ok(self.make_pattern(start_cursor, PatternKind::Builtin(Builtin::HexBlob)))
+            }
+            Some(Token::JsonValue) => {
+                Ok(self.make_pattern(start_cursor, PatternKind::Builtin(Builtin::JsonValue)))
+            }
+            Some(Token::Bof) => {
+                Ok(self.make_pattern(start_cursor, PatternKind::Builtin(Builtin::Bof)))
+            }
+            Some(Token::Eof) => {
+                Ok(self.make_pattern(start_cursor, PatternKind::Builtin(Builtin::Eof)))
+            }
+            Some(Token::Permute) => {
+                self.expect(&Token::LParen)?;
+                let mut items = vec![self.parse_pattern()?];
+                while self.check(&[&Token::Comma]) {
+                    self.advance_cursor_and_get();
+                    items.push(self.parse_pattern()?);
+                }
+                self.expect(&Token::RParen)?;
+                self.check_permuted(start_cursor, items.len())?;
+                Ok(self.make_pattern(start_cursor, PatternKind::Permute(items)))
+            }
+            Some(Token::PrecededBy) => {
+                let inner = self.parse_primary(QuantifierBias::Neutral)?;
+                Ok(self.make_pattern(start_cursor, PatternKind::PrecededBy(Box::new(inner))))
+            }
             Some(Token::LParen) => {
                 if self.is_next_inlined_statement() {
                     let stmt = self.parse_statement()?;
@@ -337,6 +1105,9 @@ impl<'a> Parser<'a> {
                     Ok(self.make_pattern(start_cursor, PatternKind::Variable(name)))
                 } else {
                     let inner = self.parse_pattern()?;
+                    if self.check(&[&Token::Arrow]) {
+                        return self.parse_anonymous_capture(start_cursor, inner);
+                    }
                     self.expect(&Token::RParen)?;
                     Ok(self.make_pattern(start_cursor, PatternKind::Group(Box::new(inner))))
                 }
@@ -345,40 +1116,435 @@ impl<'a> Parser<'a> {
             _ => Err(self.unexpected_token("pattern")),
         }
     }
-    fn is_next_inlined_statement(&self) -> bool {
-        if self.cursor + 1 >= self.tokens.len() {
-            return false;
-        }
-        let should_be_assign = &self.tokens[self.cursor + 1].token;
-        self.try_get_lvalue().is_some() && matches!(should_be_assign, Token::Equals)
-    }
-
-    fn parse_capture_clause(&mut self) -> StrqlResult<CaptureClause> {
-        self.expect(&Token::Add)?;
 
-        let (name, is_object) = if self.check(&[&Token::To]) {
-            (String::new(), false)
-        } else {
-            let n = self.expect_identifier()?;
-            let obj = if self.check(&[&Token::LBrace]) {
+    /// `FIELDS { <statement>; ... }`, where the statements can also be on lines of their own.
+    fn parse_fields(&mut self, start_cursor: usize) -> StrqlResult<Pattern> {
+        self.expect(&Token::LBrace)?;
+        let mut items = Vec::new();
+        loop {
+            while self.check(&[&Token::Semicolon, &Token::NewlineChar, &Token::CrLf]) {
                 self.advance_cursor_and_get();
-                self.expect(&Token::RBrace)?;
-                true
-            } else {
-                false
-            };
-            (n, obj)
-        };
-
-        self.expect(&Token::To)?;
+            }
+            if self.check(&[&Token::RBrace]) {
+                self.advance_cursor_and_get();
+                break;
+            }
+            if !self.is_next_inlined_statement() {
+                return Err(self.unexpected_token("a field, like `name = <pattern>`"));
+            }
+            let field_cursor = self.cursor;
+            let stmt = self.parse_statement()?;
+            let name = stmt.name.clone();
+            self.inlined_statements.push(stmt);
+            items.push(self.make_pattern(field_cursor, PatternKind::Variable(name)));
+            if !self.check(&[
+                &Token::Semicolon,
+                &Token::NewlineChar,
+                &Token::CrLf,
+                &Token::RBrace,
+            ]) {
+                return Err(self.unexpected_token("`;` or `}` after a field"));
+            }
+        }
+        self.check_permuted(start_cursor, items.len())?;
+        Ok(self.make_pattern(start_cursor, PatternKind::Fields(items)))
+    }
 
-        let path = self.parse_capture_path()?;
+    /// Rejects `PERMUTE`s and `FIELDS` of more than [`MAX_PERMUTED`] patterns.
+    fn check_permuted(&self, start_cursor: usize, count: usize) -> StrqlResult<()> {
+        if count > MAX_PERMUTED {
+            return Err(StrqlError::TooManyPermuted {
+                _count: count,
+                _src: self.src_to_named(),
+                _span: self.span_from(start_cursor).into(),
+            });
+        }
+        Ok(())
+    }
 
-        Ok(CaptureClause {
-            name,
-            is_object,
-            path,
-        })
+    /// `-> <capture>` of the statement `name`, whose captures default to its name.
+    fn parse_arrow_capture(&mut self, name: &str, pattern: &Pattern) -> StrqlResult<CaptureClause> {
+        self.expect(&Token::Arrow)?;
+        let mut cap = self.parse_capture_clause()?;
+        if cap.name.is_empty() {
+            cap.name = name.to_string();
+        }
+        if let Some(CaptureCast::Decoded { encoding }) = &mut cap.cast {
+            *encoding = encoding_of(pattern);
+        }
+        Ok(cap)
+    }
+
+    /// `-> ADD $1, $3 <target>`, capturing elements of the sequence `pattern` of statement
+    /// `name` by position, under keys named after those positions.
+    ///
+    /// Each element is moved into an inlined statement `<name>.$<position>`, which captures
+    /// it to the target.
+    fn parse_positional_captures(&mut self, name: &str, pattern: &mut Pattern) -> StrqlResult<()> {
+        self.expect(&Token::Arrow)?;
+        self.expect(&Token::Add)?;
+        let mut positions = Vec::new();
+        loop {
+            let len = match &pattern.node {
+                PatternKind::Sequence(items) => items.len(),
+                _ => 1,
+            };
+            match self.peek() {
+                Some(&Token::Positional(n))
+                    if (1..=len).contains(&n) && !positions.contains(&n) =>
+                {
+                    positions.push(n)
+                }
+                _ => {
+                    return Err(self.unexpected_token(&format!(
+                        "a new position of the statement's elements, from $1 to ${}",
+                        len
+                    )))
+                }
+            }
+            self.advance_cursor_and_get();
+            if !self.check(&[&Token::Comma]) {
+                break;
+            }
+            self.advance_cursor_and_get();
+        }
+        let clause = self.parse_capture_target(String::new(), false, None)?;
+
+        for n in positions {
+            let element = match &mut pattern.node {
+                PatternKind::Sequence(items) => &mut items[n - 1],
+                _ => &mut *pattern,
+            };
+            let hidden = format!("{}.${}", name, n);
+            let variable = Pattern {
+                node: PatternKind::Variable(hidden.clone()),
+                span: element.span.clone(),
+            };
+            let element = std::mem::replace(element, variable);
+            let mut capture = CaptureClause {
+                name: n.to_string(),
+                ..clause.clone()
+            };
+            if let Some(CaptureCast::Decoded { encoding }) = &mut capture.cast {
+                *encoding = encoding_of(&element);
+            }
+            self.inlined_statements.push(Statement {
+                name: hidden,
+                span: element.span.clone(),
+                pattern: element,
+                capture: Some(capture),
+                doc: None,
+                extends: false,
+            });
+        }
+        Ok(())
+    }
+
+    /// The rest of `(<pattern> -> <capture>)`, capturing `pattern` through a hidden inlined
+    /// statement. Without a statement name to default to, the path has to name the key.
+    fn parse_anonymous_capture(
+        &mut self,
+        start_cursor: usize,
+        pattern: Pattern,
+    ) -> StrqlResult<Pattern> {
+        // a `.` keeps the name from colliding with (or being referenced by) a user statement
+        let name = format!("anonymous.{}", pattern.span.start);
+        let capture = self.parse_arrow_capture(&name, &pattern)?;
+        if capture.name == name && capture.path.segments.len() == 1 {
+            return Err(self.unexpected_token(
+                "a field to capture to, like ROOT.<field> (anonymous captures have no name)",
+            ));
+        }
+        self.expect(&Token::RParen)?;
+        let span = self.span_from(start_cursor);
+        self.inlined_statements.push(Statement {
+            name: name.clone(),
+            pattern,
+            capture: Some(capture),
+            span,
+            doc: None,
+            extends: false,
+        });
+        Ok(self.make_pattern(start_cursor, PatternKind::Variable(name)))
+    }
+
+    /// `(<number>)`, with the number in `range`.
+    fn parenthesized_number(
+        &mut self,
+        expected: &str,
+        range: std::ops::RangeInclusive<usize>,
+    ) -> StrqlResult<usize> {
+        self.expect(&Token::LParen)?;
+        let number = match self.peek() {
+            Some(&Token::Number(n)) if range.contains(&n) => n,
+            _ => return Err(self.unexpected_token(expected)),
+        };
+        self.advance_cursor_and_get();
+        self.expect(&Token::RParen)?;
+        Ok(number)
+    }
+
+    /// Desugars `HEXNUM` and friends into `0..1 ("0x" OR "0X") 1..N <digit>`.
+    fn radix_number(
+        &self,
+        start_cursor: usize,
+        prefix: &str,
+        digit: Builtin,
+        bias: QuantifierBias,
+    ) -> Pattern {
+        let prefixes = PatternKind::OrChain {
+            alternatives: vec![
+                self.make_pattern(start_cursor, PatternKind::Literal(prefix.to_string())),
+                self.make_pattern(
+                    start_cursor,
+                    PatternKind::Literal(prefix.to_ascii_uppercase()),
+                ),
+            ],
+            bias: AlternationBias::Neutral,
+        };
+        self.make_pattern(
+            start_cursor,
+            PatternKind::Sequence(vec![
+                self.make_pattern(
+                    start_cursor,
+                    PatternKind::Repetition {
+                        min: Some(0),
+                        max: Some(1),
+                        pattern: Box::new(self.make_pattern(start_cursor, prefixes)),
+                        bias: QuantifierBias::Neutral,
+                    },
+                ),
+                self.make_pattern(
+                    start_cursor,
+                    PatternKind::Repetition {
+                        min: Some(1),
+                        max: None,
+                        pattern: Box::new(
+                            self.make_pattern(start_cursor, PatternKind::Builtin(digit)),
+                        ),
+                        bias,
+                    },
+                ),
+            ]),
+        )
+    }
+
+    fn is_next_inlined_statement(&self) -> bool {
+        if self.cursor + 1 >= self.tokens.len() {
+            return false;
+        }
+        let should_be_assign = &self.tokens[self.cursor + 1].token;
+        self.try_get_lvalue().is_some() && matches!(should_be_assign, Token::Equals)
+    }
+
+    fn parse_capture_clause(&mut self) -> StrqlResult<CaptureClause> {
+        if self.is_next_keyword("GROUP") {
+            return self.parse_group_clause();
+        }
+        self.expect(&Token::Add)?;
+
+        let mut case = None;
+        let (name, is_object) = if self.check(&[&Token::To]) || self.is_next_cast() {
+            (String::new(), false)
+        } else if let Some(c) = self.peek_capture_case() {
+            self.advance_cursor_and_get();
+            self.expect(&Token::LParen)?;
+            let name = self.expect_identifier()?;
+            self.expect(&Token::RParen)?;
+            case = Some(c);
+            (name, false)
+        } else {
+            let n = self.expect_identifier()?;
+            let obj = if self.check(&[&Token::LBrace]) {
+                self.advance_cursor_and_get();
+                self.expect(&Token::RBrace)?;
+                true
+            } else {
+                false
+            };
+            (n, obj)
+        };
+        self.parse_capture_target(name, is_object, case)
+    }
+
+    /// The rest of a capture clause after what it adds: `[AS <cast>] TO <path> <modifiers>`.
+    fn parse_capture_target(
+        &mut self,
+        name: String,
+        is_object: bool,
+        case: Option<CaptureCase>,
+    ) -> StrqlResult<CaptureClause> {
+        let cast = if !is_object && self.is_next_keyword("AS") {
+            self.advance_cursor_and_get();
+            Some(self.parse_capture_cast()?)
+        } else {
+            None
+        };
+
+        self.expect(&Token::To)?;
+
+        let path = self.parse_capture_path()?;
+
+        let mut clause = CaptureClause {
+            name,
+            is_object,
+            path,
+            case,
+            cast,
+            group_by: None,
+            unique: false,
+            sort: None,
+            nullable: false,
+            once: false,
+        };
+        self.parse_capture_modifiers(&mut clause)?;
+        Ok(clause)
+    }
+
+    /// Trailing options: `UNIQUE`, `SORTED [DESC]` (arrays only), `NULLABLE` and `ONCE`
+    /// (fields only).
+    fn parse_capture_modifiers(&mut self, clause: &mut CaptureClause) -> StrqlResult<()> {
+        loop {
+            if matches!(self.peek(), Some(Token::Identifier(word)) if word.eq_ignore_ascii_case("ONCE"))
+            {
+                if clause.path.ends_with_array() {
+                    return Err(self.unexpected_token("a field path (without `[]`) before ONCE"));
+                }
+                self.advance_cursor_and_get();
+                clause.once = true;
+                continue;
+            }
+            if self.is_next_keyword("NULLABLE") {
+                if clause.is_object {
+                    return Err(self.unexpected_token("a value capture before NULLABLE"));
+                }
+                self.advance_cursor_and_get();
+                clause.nullable = true;
+                continue;
+            }
+            let unique = self.is_next_keyword("UNIQUE");
+            if !unique && !self.is_next_keyword("SORTED") {
+                break;
+            }
+            if !clause.path.ends_with_array() {
+                return Err(self.unexpected_token("an array path (`[]`) before this option"));
+            }
+            self.advance_cursor_and_get();
+            if unique {
+                clause.unique = true;
+            } else if self.is_next_keyword("DESC") {
+                self.advance_cursor_and_get();
+                clause.sort = Some(SortOrder::Descending);
+            } else {
+                clause.sort = Some(SortOrder::Ascending);
+            }
+        }
+        Ok(())
+    }
+
+    /// `GROUP <name>{} BY <field> TO <path>`
+    fn parse_group_clause(&mut self) -> StrqlResult<CaptureClause> {
+        self.expect_keyword("GROUP")?;
+        let name = self.expect_identifier()?;
+        self.expect(&Token::LBrace)?;
+        self.expect(&Token::RBrace)?;
+        self.expect_keyword("BY")?;
+        let group_by = self.expect_identifier()?;
+        self.expect(&Token::To)?;
+        let path = self.parse_capture_path()?;
+
+        Ok(CaptureClause {
+            name,
+            is_object: true,
+            path,
+            case: None,
+            cast: None,
+            group_by: Some(group_by),
+            unique: false,
+            sort: None,
+            nullable: false,
+            once: false,
+        })
+    }
+
+    /// `UPPER(` or `LOWER(`, as opposed to a variable that happens to be named `upper`.
+    fn peek_capture_case(&self) -> Option<CaptureCase> {
+        let case = match self.peek()? {
+            Token::Upper => CaptureCase::Upper,
+            Token::Lower => CaptureCase::Lower,
+            _ => return None,
+        };
+        matches!(
+            self.tokens.get(self.cursor + 1).map(|t| &t.token),
+            Some(Token::LParen)
+        )
+        .then_some(case)
+    }
+
+    fn parse_capture_cast(&mut self) -> StrqlResult<CaptureCast> {
+        let epoch = self.is_next_keyword("EPOCH");
+        if epoch || self.is_next_keyword("DATETIME") {
+            if !cfg!(feature = "datetime") {
+                return Err(self.unexpected_token("a cast (DATETIME needs the `datetime` feature)"));
+            }
+            self.advance_cursor_and_get();
+            self.expect(&Token::LParen)?;
+            let format = match self.peek() {
+                Some(Token::StringLiteral(format)) => format.clone(),
+                _ => return Err(self.unexpected_token("a date format like \"%Y-%m-%d\"")),
+            };
+            self.advance_cursor_and_get();
+            self.expect(&Token::RParen)?;
+            return Ok(CaptureCast::DateTime { format, epoch });
+        }
+        if self.is_next_keyword("NUMBER") {
+            self.advance_cursor_and_get();
+            let mut locale = None;
+            if self.check(&[&Token::LParen]) {
+                self.advance_cursor_and_get();
+                if !matches!(self.peek(), Some(Token::Identifier(arg)) if arg.eq_ignore_ascii_case("locale"))
+                {
+                    return Err(self.unexpected_token("`locale = \"<language>\"`"));
+                }
+                self.advance_cursor_and_get();
+                self.expect(&Token::Equals)?;
+                locale = match self.peek() {
+                    Some(Token::StringLiteral(tag)) if separators(tag).is_some() => {
+                        Some(tag.clone())
+                    }
+                    _ => return Err(self.unexpected_token("a supported locale, like \"de\"")),
+                };
+                self.advance_cursor_and_get();
+                self.expect(&Token::RParen)?;
+            }
+            return Ok(CaptureCast::Number { locale });
+        }
+        if self.is_next_keyword("JSON") {
+            self.advance_cursor_and_get();
+            return Ok(CaptureCast::Json);
+        }
+        if self.is_next_keyword("DECODED") {
+            self.advance_cursor_and_get();
+            // the statement's pattern decides the encoding, see `encoding_of`
+            return Ok(CaptureCast::Decoded {
+                encoding: Encoding::Base64,
+            });
+        }
+        if !self.is_next_keyword("INT") {
+            return Err(self.unexpected_token("a cast: INT, NUMBER, JSON, DECODED or DATETIME"));
+        }
+        self.advance_cursor_and_get();
+        let mut radix = None;
+        if self.check(&[&Token::LParen]) {
+            self.advance_cursor_and_get();
+            match self.peek() {
+                Some(&Token::Number(r)) if (2..=36).contains(&r) => radix = Some(r as u32),
+                _ => return Err(self.unexpected_token("radix between 2 and 36")),
+            }
+            self.advance_cursor_and_get();
+            self.expect(&Token::RParen)?;
+        }
+        Ok(CaptureCast::Int { radix })
     }
 
     fn parse_capture_path(&mut self) -> StrqlResult<CapturePath> {
@@ -402,9 +1568,17 @@ impl<'a> Parser<'a> {
                     self.advance_cursor_and_get();
                     segments.push(PathSegment::ArrayAppend);
                 } else {
-                    let var = self.expect_identifier()?;
-                    self.expect(&Token::RBracket)?;
-                    segments.push(PathSegment::DynamicField(var));
+                    let mut parts = Vec::new();
+                    while !self.check(&[&Token::RBracket]) {
+                        if let Some(Token::StringLiteral(text)) = self.peek() {
+                            parts.push(KeyPart::Literal(text.clone()));
+                            self.advance_cursor_and_get();
+                        } else {
+                            parts.push(KeyPart::Variable(self.expect_identifier()?));
+                        }
+                    }
+                    self.advance_cursor_and_get();
+                    segments.push(PathSegment::DynamicField(parts));
                 }
             } else {
                 break;
@@ -414,6 +1588,15 @@ impl<'a> Parser<'a> {
         Ok(CapturePath { segments })
     }
 
+    /// Skips what's left of a line that failed to parse from `start`, so that parsing can go
+    /// on from the next one.
+    fn skip_line(&mut self, start: usize) {
+        self.cursor = self.cursor.max(start + 1);
+        while !self.is_at_end() && !self.check(&[&Token::NewlineChar, &Token::CrLf]) {
+            self.advance_cursor_and_get();
+        }
+    }
+
     fn skip_newlines(&mut self) {
         while self.check(&[&Token::NewlineChar, &Token::CrLf]) {
             self.advance_cursor_and_get();
@@ -439,6 +1622,46 @@ impl<'a> Parser<'a> {
         self.peek().map(|t| expected.contains(&t)).unwrap_or(false)
     }
 
+    /// Whether the next token is the contextual keyword `word`, an identifier written in any
+    /// case and not escaped in backticks.
+    fn is_next_keyword(&self, word: &str) -> bool {
+        self.is_keyword_at(self.cursor, word)
+    }
+
+    fn is_keyword_at(&self, index: usize, word: &str) -> bool {
+        self.tokens.get(index).is_some_and(|t| {
+            matches!(&t.token, Token::Identifier(idf) if idf.eq_ignore_ascii_case(word))
+                && !self.source[t.span.clone()].starts_with('`')
+        })
+    }
+
+    fn expect_keyword(&mut self, word: &str) -> StrqlResult<()> {
+        if !self.is_next_keyword(word) {
+            return Err(self.unexpected_token(word));
+        }
+        self.advance_cursor_and_get();
+        Ok(())
+    }
+
+    /// `KV(`, as opposed to a statement named `kv`.
+    fn is_next_call(&self, word: &str) -> bool {
+        self.is_next_keyword(word)
+            && matches!(
+                self.tokens.get(self.cursor + 1).map(|t| &t.token),
+                Some(Token::LParen)
+            )
+    }
+
+    /// `AS` followed by the name of a cast, as opposed to a capture named `as`.
+    fn is_next_cast(&self) -> bool {
+        const CASTS: [&str; 6] = ["INT", "NUMBER", "JSON", "DECODED", "DATETIME", "EPOCH"];
+        self.is_next_keyword("AS")
+            && matches!(
+                self.tokens.get(self.cursor + 1).map(|t| &t.token),
+                Some(Token::Identifier(cast)) if CASTS.iter().any(|c| cast.eq_ignore_ascii_case(c))
+            )
+    }
+
     fn advance_cursor_and_get(&mut self) -> Option<&Token> {
         if !self.is_at_end() {
             self.cursor += 1;
@@ -468,6 +1691,7 @@ impl<'a> Parser<'a> {
             | Token::Add
             | Token::To
             | Token::SplitBy
+            | Token::PrecededBy
             | Token::AnyCase
             | Token::Upper
             | Token::Lower
@@ -476,13 +1700,26 @@ impl<'a> Parser<'a> {
             | Token::N
             | Token::Word
             | Token::Line
+            | Token::Paragraph
             | Token::Newline
             | Token::Space
             | Token::AnyChar
             | Token::Any
             | Token::Digit
             | Token::Letter
-            | Token::Alphanum => Some(tok.to_string().to_ascii_uppercase()),
+            | Token::Alphanum
+            | Token::HexNum
+            | Token::BinNum
+            | Token::OctNum
+            | Token::Byte
+            | Token::Bytes
+            | Token::Base64
+            | Token::HexBlob
+            | Token::JsonValue
+            | Token::ContLine
+            | Token::Bof
+            | Token::Eof
+            | Token::Permute => Some(tok.to_string().to_ascii_uppercase()),
             _ => None,
         })
     }
@@ -513,10 +1750,82 @@ impl<'a> Parser<'a> {
     }
 }
 
+/// Whether `token` is a builtin pattern, which references to a statement named like it
+/// would match instead.
+pub(crate) fn is_builtin(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Word
+            | Token::Line
+            | Token::Paragraph
+            | Token::ContLine
+            | Token::Newline
+            | Token::Space
+            | Token::AnyChar
+            | Token::Any
+            | Token::Digit
+            | Token::Letter
+            | Token::Alphanum
+            | Token::HexNum
+            | Token::BinNum
+            | Token::OctNum
+            | Token::Byte
+            | Token::Bytes
+            | Token::Base64
+            | Token::HexBlob
+            | Token::JsonValue
+            | Token::Bof
+            | Token::Eof
+    )
+}
+
+/// `DEFINE <name> = <pattern>`, spanning the whole line.
+struct Define {
+    name: String,
+    pattern: Pattern,
+    span: std::ops::Range<usize>,
+}
+
+fn join_docs(leading: Option<String>, trailing: Option<String>) -> Option<String> {
+    match (leading, trailing) {
+        (Some(leading), Some(trailing)) => Some(format!("{}\n{}", leading, trailing)),
+        (leading, trailing) => leading.or(trailing),
+    }
+}
+
+/// What `AS DECODED` decodes: hex for statements matching a `HEXBLOB` (and no `BASE64`)
+/// themselves, base64 otherwise.
+fn encoding_of(pattern: &Pattern) -> Encoding {
+    struct Blobs(Vec<Builtin>);
+    impl Visitor<'_> for Blobs {
+        fn visit_pattern(&mut self, pattern: &Pattern) {
+            match &pattern.node {
+                PatternKind::Builtin(b @ (Builtin::Base64 | Builtin::HexBlob)) => {
+                    self.0.push(b.clone())
+                }
+                _ => walk_pattern(self, pattern),
+            }
+        }
+    }
+    let mut blobs = Blobs(Vec::new());
+    blobs.visit_pattern(pattern);
+    if blobs.0.contains(&Builtin::HexBlob) && !blobs.0.contains(&Builtin::Base64) {
+        Encoding::Hex
+    } else {
+        Encoding::Base64
+    }
+}
+
 pub fn parse(source: &str) -> StrqlResult<Program> {
     Parser::new(source)?.parse()
 }
 
+/// Like [`parse`], filling in the `${NAME}` placeholders of literals from `params`. A
+/// placeholder without a value is a [`StrqlError::MissingParam`].
+pub fn parse_with_params(source: &str, params: &Params) -> StrqlResult<Program> {
+    Parser::with_params(source, params)?.parse()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -527,17 +1836,14 @@ mod tests {
         let program = parse(source).unwrap();
 
         assert_eq!(program.statements.len(), 1);
-        match &program.statements[0] {
-            Statement {
-                name,
-                pattern: _,
-                capture,
-                ..
-            } => {
-                assert_eq!(name, "name");
-                assert!(capture.is_none());
-            }
-        }
+        let Statement {
+            name,
+            pattern: _,
+            capture,
+            ..
+        } = &program.statements[0];
+        assert_eq!(name, "name");
+        assert!(capture.is_none());
     }
 
     #[test]
@@ -546,32 +1852,100 @@ mod tests {
         let program = parse(source).unwrap();
 
         assert_eq!(program.statements.len(), 1);
-        match &program.statements[0] {
-            Statement { capture, .. } => {
-                let capture = capture.as_ref().unwrap();
-                assert_eq!(capture.name, "name");
-                assert!(!capture.is_object);
-                assert!(matches!(
-                    capture.path.segments.as_slice(),
-                    [PathSegment::Root]
-                ));
-            }
-        }
+        let Statement { capture, .. } = &program.statements[0];
+        let capture = capture.as_ref().unwrap();
+        assert_eq!(capture.name, "name");
+        assert!(!capture.is_object);
+        assert!(matches!(
+            capture.path.segments.as_slice(),
+            [PathSegment::Root]
+        ));
+    }
+
+    #[test]
+    fn test_capture_cast() {
+        let program = parse("TEXT = HEXNUM -> ADD addr AS INT(16) TO ROOT").unwrap();
+        let capture = program.statements[0].capture.as_ref().unwrap();
+        assert_eq!(capture.name, "addr");
+        assert_eq!(capture.cast, Some(CaptureCast::Int { radix: Some(16) }));
+
+        let program = parse("TEXT = DIGIT -> ADD AS INT TO ROOT.d").unwrap();
+        let capture = program.statements[0].capture.as_ref().unwrap();
+        assert_eq!(capture.cast, Some(CaptureCast::Int { radix: None }));
+
+        assert!(parse("TEXT = DIGIT -> ADD d AS INT(1) TO ROOT").is_err());
+        assert!(parse("TEXT = DIGIT -> ADD d{} AS INT TO ROOT").is_err());
+    }
+
+    #[test]
+    fn test_capture_case() {
+        let program = parse("TEXT = WORD -> ADD LOWER(code) TO ROOT").unwrap();
+        let capture = program.statements[0].capture.as_ref().unwrap();
+        assert_eq!(capture.name, "code");
+        assert_eq!(capture.case, Some(CaptureCase::Lower));
+
+        // without parentheses, `upper` is just a variable name
+        let program = parse("upper = WORD -> ADD upper TO ROOT").unwrap();
+        let capture = program.statements[0].capture.as_ref().unwrap();
+        assert_eq!(capture.name, "UPPER");
+        assert_eq!(capture.case, None);
+    }
+
+    #[test]
+    fn test_group_clause() {
+        let program = parse("row = LINE -> GROUP item{} BY category TO ROOT.groups").unwrap();
+        let capture = program.statements[0].capture.as_ref().unwrap();
+        assert_eq!(capture.name, "item");
+        assert!(capture.is_object);
+        assert_eq!(capture.group_by.as_deref(), Some("category"));
+        assert!(!capture.path.ends_with_array());
+
+        assert!(parse("row = LINE -> GROUP item BY category TO ROOT.groups").is_err());
+    }
+
+    #[test]
+    fn test_unique_modifier() {
+        let program = parse("tag = WORD -> ADD TO ROOT.tags[] UNIQUE").unwrap();
+        assert!(program.statements[0].capture.as_ref().unwrap().unique);
+
+        assert!(parse("tag = WORD -> ADD TO ROOT.tag UNIQUE").is_err());
+    }
+
+    #[test]
+    fn test_nullable_modifier() {
+        let program = parse("note = LINE -> ADD TO item.note NULLABLE").unwrap();
+        assert!(program.statements[0].capture.as_ref().unwrap().nullable);
+
+        assert!(parse("row = LINE -> ADD row{} TO ROOT.rows[] NULLABLE").is_err());
+
+        let program = parse("name = LINE -> ADD TO ROOT.name NULLABLE once").unwrap();
+        let clause = program.statements[0].capture.as_ref().unwrap();
+        assert!(clause.once && clause.nullable);
+        assert!(parse("name = LINE -> ADD TO ROOT.names[] ONCE").is_err());
+    }
+
+    #[test]
+    fn test_sorted_modifier() {
+        let program = parse("s = WORD -> ADD TO ROOT.s[] SORTED").unwrap();
+        let capture = program.statements[0].capture.as_ref().unwrap();
+        assert_eq!(capture.sort, Some(SortOrder::Ascending));
+
+        let program = parse("s = WORD -> ADD TO ROOT.s[] SORTED DESC UNIQUE").unwrap();
+        let capture = program.statements[0].capture.as_ref().unwrap();
+        assert_eq!(capture.sort, Some(SortOrder::Descending));
+        assert!(capture.unique);
     }
 
     #[test]
     fn test_rule_with_object_capture() {
-        let source = "line = memberlist -> ADD item{} TO ROOT.items[]";
+        let source = "`line` = memberlist -> ADD item{} TO ROOT.items[]";
         let program = parse(source).unwrap();
 
-        match &program.statements[0] {
-            Statement { capture, .. } => {
-                let capture = capture.as_ref().unwrap();
-                assert_eq!(capture.name, "item");
-                assert!(capture.is_object);
-                assert!(capture.path.ends_with_array());
-            }
-        }
+        let Statement { capture, .. } = &program.statements[0];
+        let capture = capture.as_ref().unwrap();
+        assert_eq!(capture.name, "item");
+        assert!(capture.is_object);
+        assert!(capture.path.ends_with_array());
     }
 
     #[test]
@@ -579,11 +1953,8 @@ mod tests {
         let source = r#"sep = ", " OR " and ""#;
         let program = parse(source).unwrap();
 
-        match &program.statements[0] {
-            Statement { pattern, .. } => {
-                assert!(matches!(pattern.node, PatternKind::OrChain(_)));
-            }
-        }
+        let Statement { pattern, .. } = &program.statements[0];
+        assert!(matches!(pattern.node, PatternKind::OrChain { .. }));
     }
 
     #[test]
@@ -591,21 +1962,20 @@ mod tests {
         let source = "digits = 1..N DIGIT";
         let program = parse(source).unwrap();
 
-        match &program.statements[0] {
-            Statement { pattern, .. } => match &pattern.node {
-                PatternKind::Repetition {
-                    min,
-                    max,
-                    pattern,
-                    bias,
-                } => {
-                    assert!(matches!(min, Some(1)));
-                    assert!(matches!(max, None));
-                    assert!(matches!(pattern.node, PatternKind::Builtin(Builtin::Digit)));
-                    assert_eq!(*bias, QuantifierBias::Neutral);
-                }
-                _ => panic!("Expected quantifier"),
-            },
+        let Statement { pattern, .. } = &program.statements[0];
+        match &pattern.node {
+            PatternKind::Repetition {
+                min,
+                max,
+                pattern,
+                bias,
+            } => {
+                assert!(matches!(min, Some(1)));
+                assert!(max.is_none());
+                assert!(matches!(pattern.node, PatternKind::Builtin(Builtin::Digit)));
+                assert_eq!(*bias, QuantifierBias::Neutral);
+            }
+            _ => panic!("Expected quantifier"),
         }
     }
 
@@ -615,25 +1985,21 @@ mod tests {
         let program = parse(source).unwrap();
 
         // SPLITBY is now expanded to a Sequence: item (sep item)*
-        match &program.statements[0] {
-            Statement { pattern, .. } => {
-                assert!(matches!(pattern.node, PatternKind::Sequence(_)));
-            }
-        }
+        let Statement { pattern, .. } = &program.statements[0];
+        assert!(matches!(pattern.node, PatternKind::Sequence(_)));
     }
 
     #[test]
     fn test_sequence() {
-        let source = r#"line = name " is " value"#;
+        let source = r#"`line` = name " is " value"#;
         let program = parse(source).unwrap();
 
-        match &program.statements[0] {
-            Statement { pattern, .. } => match &pattern.node {
-                PatternKind::Sequence(items) => {
-                    assert_eq!(items.len(), 3);
-                }
-                _ => panic!("Expected sequence"),
-            },
+        let Statement { pattern, .. } = &program.statements[0];
+        match &pattern.node {
+            PatternKind::Sequence(items) => {
+                assert_eq!(items.len(), 3);
+            }
+            _ => panic!("Expected sequence"),
         }
     }
 
@@ -642,22 +2008,35 @@ mod tests {
         let source = "value = WORD -> ADD value TO item[key]";
         let program = parse(source).unwrap();
 
-        match &program.statements[0] {
-            Statement { capture, .. } => {
-                let capture = capture.as_ref().unwrap();
-                assert!(matches!(
-                    capture.path.segments.as_slice(),
-                    [PathSegment::Field(_), PathSegment::DynamicField(_)]
-                ));
-            }
-        }
+        let Statement { capture, .. } = &program.statements[0];
+        let capture = capture.as_ref().unwrap();
+        assert!(matches!(
+            capture.path.segments.as_slice(),
+            [PathSegment::Field(_), PathSegment::DynamicField(_)]
+        ));
+    }
+
+    #[test]
+    fn test_compound_dynamic_field() {
+        let source = r#"hits = DIGIT -> ADD TO ROOT.stats[host "_" port]"#;
+        let program = parse(source).unwrap();
+
+        let capture = program.statements[0].capture.as_ref().unwrap();
+        assert_eq!(
+            capture.path.segments[2],
+            PathSegment::DynamicField(vec![
+                KeyPart::Variable("host".to_string()),
+                KeyPart::Literal("_".to_string()),
+                KeyPart::Variable("port".to_string()),
+            ])
+        );
     }
 
     #[test]
     fn test_full_example() {
         let source = r#"
-TEXT = line SPLITBY NEWLINE
-line = memberlist " are " kind -> ADD item{} TO ROOT.items[]
+TEXT = `line` SPLITBY NEWLINE
+`line` = memberlist " are " kind -> ADD item{} TO ROOT.items[]
 memberlist = member SPLITBY sep
 member = WORD -> ADD member TO item.members[]
 sep = ", " OR " and "
@@ -680,18 +2059,183 @@ fourth = "D"
         assert_eq!(program.statements.len(), 5);
     }
 
+    #[test]
+    fn test_alternation_bias() {
+        let program = parse("TEXT = first FIRST OR \"b\" first OR \"c\"\nfirst = \"a\"").unwrap();
+        let PatternKind::OrChain { alternatives, bias } = &program.statements[0].pattern.node
+        else {
+            panic!("Expected alternation");
+        };
+        assert_eq!(*bias, AlternationBias::First);
+        assert_eq!(alternatives.len(), 3);
+        assert_eq!(
+            alternatives[0].node,
+            PatternKind::Variable("first".to_string())
+        );
+
+        let program = parse("TEXT = \"a\" LONGEST OR (\"b\" OR \"c\")").unwrap();
+        assert!(matches!(
+            program.statements[0].pattern.node,
+            PatternKind::OrChain {
+                bias: AlternationBias::Longest,
+                ..
+            }
+        ));
+        assert!(parse("TEXT = \"a\" FIRST OR \"b\" OR \"c\"").is_err());
+    }
+
+    #[test]
+    fn test_import() {
+        let program = parse("IMPORT ini\nTEXT = ini").unwrap();
+        assert!(program.statements.iter().any(|s| s.name == "ini"));
+        assert!(program.statements.iter().any(|s| s.name == "ini_section"));
+        // the spans of imported statements point into the grammar appended to the source
+        let section = program.statements.iter().find(|s| s.name == "ini_section");
+        let text = &program.source[section.unwrap().span.clone()];
+        assert!(text.starts_with("section = "), "{}", text);
+        let twice = parse("IMPORT ini\nIMPORT ini\nTEXT = ini").unwrap();
+        assert_eq!(twice.statements.len(), program.statements.len());
+        // `import` is only a keyword before the name of a grammar
+        assert!(parse("TEXT = import\nimport = \"x\"").is_ok());
+        assert!(matches!(
+            parse("IMPORT nope\nTEXT = nope"),
+            Err(StrqlError::UnknownImport { .. })
+        ));
+        if !cfg!(feature = "datetime") {
+            assert!(matches!(
+                parse("IMPORT accesslog\nTEXT = accesslog"),
+                Err(StrqlError::ImportNeedsFeature { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_permute() {
+        let program = parse("TEXT = PERMUTE(a, \"b\" c, d OR e)").unwrap();
+        let PatternKind::Permute(items) = &program.statements[0].pattern.node else {
+            panic!("Expected permute");
+        };
+        assert_eq!(items.len(), 3);
+        assert!(matches!(items[1].node, PatternKind::Sequence(_)));
+        assert!(matches!(items[2].node, PatternKind::OrChain { .. }));
+
+        let many = vec!["x"; MAX_PERMUTED + 1].join(", ");
+        assert!(matches!(
+            parse(&format!("TEXT = PERMUTE({})", many)),
+            Err(StrqlError::TooManyPermuted { _count: 17, .. })
+        ));
+    }
+
+    #[test]
+    fn test_fields() {
+        let program =
+            parse("TEXT = FIELDS { a = \"a\"; b = 0..1 \"b\" -> ADD TO ROOT.b;\n\n c = \"c\"\n }")
+                .unwrap();
+        let PatternKind::Fields(items) = &program.statements[0].pattern.node else {
+            panic!("Expected fields");
+        };
+        let names: Vec<_> = items
+            .iter()
+            .map(|item| match &item.node {
+                PatternKind::Variable(name) => name.as_str(),
+                _ => panic!("Expected field statement"),
+            })
+            .collect();
+        assert_eq!(names, ["a", "b", "c"]);
+        assert!(program
+            .statements
+            .iter()
+            .any(|s| s.name == "b" && s.capture.is_some()));
+        assert!(parse("TEXT = FIELDS { \"a\" }").is_err());
+        assert!(parse("TEXT = FIELDS { a = \"a\" b = \"b\" }").is_err());
+    }
+
+    #[test]
+    fn test_separated_repetition() {
+        let program = parse("TEXT = 2..5 x SEP \", \"").unwrap();
+        let PatternKind::Sequence(items) = &program.statements[0].pattern.node else {
+            panic!("Expected sequence");
+        };
+        assert_eq!(items[0].node, PatternKind::Variable("x".to_string()));
+        let PatternKind::Repetition { min, max, .. } = &items[1].node else {
+            panic!("Expected repetition");
+        };
+        assert_eq!((*min, *max), (Some(1), Some(4)));
+
+        let program = parse("TEXT = 0..N x SEP \",\"").unwrap();
+        assert!(matches!(
+            program.statements[0].pattern.node,
+            PatternKind::Repetition {
+                min: Some(0),
+                max: Some(1),
+                ..
+            }
+        ));
+
+        // a statement `sep` right after a repetition, escaped or at the end of the sequence
+        let program = parse("TEXT = 1..2 x `sep` y\nsep = \"-\"").unwrap();
+        assert!(
+            matches!(&program.statements[0].pattern.node, PatternKind::Sequence(items) if items.len() == 3)
+        );
+        let program = parse("TEXT = 1..2 x sep\nsep = \"-\"").unwrap();
+        assert!(
+            matches!(&program.statements[0].pattern.node, PatternKind::Sequence(items) if items.len() == 2)
+        );
+    }
+
+    #[test]
+    fn test_preceded_by() {
+        let program = parse("TEXT = \"$\" price\nprice = precededby \"$\" 1..N DIGIT").unwrap();
+        let PatternKind::Sequence(items) = &program.statements[1].pattern.node else {
+            panic!("Expected sequence");
+        };
+        assert_eq!(
+            items[0].node,
+            PatternKind::PrecededBy(Box::new(Pattern {
+                node: PatternKind::Literal("$".to_string()),
+                span: 36..39,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_alternation_weights() {
+        let program = parse("TEXT = \"a\" WEIGHT 3 OR weight OR \"c\" weight WEIGHT 0").unwrap();
+        let PatternKind::OrChain { alternatives, bias } = &program.statements[0].pattern.node
+        else {
+            panic!("Expected alternation");
+        };
+        assert_eq!(*bias, AlternationBias::Weighted(vec![3, 1, 0]));
+        assert_eq!(
+            alternatives[1].node,
+            PatternKind::Variable("weight".to_string())
+        );
+        assert!(matches!(&alternatives[2].node, PatternKind::Sequence(items) if items.len() == 2));
+
+        // `weight 1..2 x` is a repetition after a statement `weight`
+        let program = parse("TEXT = \"a\" weight 1..2 \"b\" OR \"c\"").unwrap();
+        assert!(matches!(
+            program.statements[0].pattern.node,
+            PatternKind::OrChain {
+                bias: AlternationBias::Neutral,
+                ..
+            }
+        ));
+        assert!(parse("TEXT = \"a\" WEIGHT 2").is_err());
+        assert!(parse("TEXT = \"a\" WEIGHT 2 FIRST OR \"b\"").is_err());
+    }
+
     #[test]
     fn test_lazy_quantifier() {
         let source = "digits = LAZY 1..N DIGIT";
         let program = parse(source).unwrap();
 
-        match &program.statements[0] {
-            Statement { pattern, .. } => match &pattern.node {
-                PatternKind::Repetition { bias, .. } => {
-                    assert_eq!(*bias, QuantifierBias::Lazy);
-                }
-                _ => panic!("Expected quantifier"),
-            },
+        let Statement { pattern, .. } = &program.statements[0];
+        match &pattern.node {
+            PatternKind::Repetition { bias, .. } => {
+                assert_eq!(*bias, QuantifierBias::Lazy);
+            }
+            _ => panic!("Expected quantifier"),
         }
     }
 
@@ -700,13 +2244,12 @@ fourth = "D"
         let source = "digits = GREEDY 1..N DIGIT";
         let program = parse(source).unwrap();
 
-        match &program.statements[0] {
-            Statement { pattern, .. } => match &pattern.node {
-                PatternKind::Repetition { bias, .. } => {
-                    assert_eq!(*bias, QuantifierBias::Greedy);
-                }
-                _ => panic!("Expected quantifier"),
-            },
+        let Statement { pattern, .. } = &program.statements[0];
+        match &pattern.node {
+            PatternKind::Repetition { bias, .. } => {
+                assert_eq!(*bias, QuantifierBias::Greedy);
+            }
+            _ => panic!("Expected quantifier"),
         }
     }
 
@@ -717,19 +2260,49 @@ fourth = "D"
 
         // SPLITBY is now expanded to: item (sep item)*
         // The quantifier should have Lazy bias
-        match &program.statements[0] {
-            Statement { pattern, .. } => match &pattern.node {
-                PatternKind::Sequence(parts) if parts.len() == 2 => match &parts[1].node {
-                    PatternKind::Repetition { bias, .. } => {
-                        assert_eq!(*bias, QuantifierBias::Lazy);
-                    }
-                    _ => panic!("Expected quantifier as second part"),
-                },
-                _ => panic!("Expected sequence"),
+        let Statement { pattern, .. } = &program.statements[0];
+        match &pattern.node {
+            PatternKind::Sequence(parts) if parts.len() == 2 => match &parts[1].node {
+                PatternKind::Repetition { bias, .. } => {
+                    assert_eq!(*bias, QuantifierBias::Lazy);
+                }
+                _ => panic!("Expected quantifier as second part"),
             },
+            _ => panic!("Expected sequence"),
         }
     }
 
+    #[test]
+    fn test_reports_every_syntax_error() {
+        let Err(StrqlError::SyntaxErrors { _errors }) =
+            parse("TEXT = a b\na = ) \"x\"\nb = \"a\"\nc = (\nOPTION BOGUS")
+        else {
+            panic!("Expected several syntax errors");
+        };
+        let lines: Vec<_> = _errors.iter().map(|e| e.location().unwrap().line).collect();
+        assert_eq!(lines, [2, 4, 5]);
+        assert!(matches!(
+            parse("TEXT = a\na = )"),
+            Err(StrqlError::UnexpectedToken { .. })
+        ));
+    }
+
+    #[test]
+    fn test_bias_of_a_later_item() {
+        let program = parse("TEXT = \"a\" LAZY 1..3 DIGIT").unwrap();
+        let PatternKind::Sequence(items) = &program.statements[0].pattern.node else {
+            panic!("Expected sequence");
+        };
+        assert!(matches!(
+            items[1].node,
+            PatternKind::Repetition {
+                bias: QuantifierBias::Lazy,
+                ..
+            }
+        ));
+        assert!(parse("TEXT = \"<\" GREEDY WORD \">\"").is_ok());
+    }
+
     #[test]
     fn test_greedy_splitby() {
         let source = "list = item GREEDY SPLITBY sep";
@@ -737,16 +2310,15 @@ fourth = "D"
 
         // SPLITBY is now expanded to: item (sep item)*
         // The quantifier should have Greedy bias
-        match &program.statements[0] {
-            Statement { pattern, .. } => match &pattern.node {
-                PatternKind::Sequence(parts) if parts.len() == 2 => match &parts[1].node {
-                    PatternKind::Repetition { bias, .. } => {
-                        assert_eq!(*bias, QuantifierBias::Greedy);
-                    }
-                    _ => panic!("Expected quantifier as second part"),
-                },
-                _ => panic!("Expected sequence"),
+        let Statement { pattern, .. } = &program.statements[0];
+        match &pattern.node {
+            PatternKind::Sequence(parts) if parts.len() == 2 => match &parts[1].node {
+                PatternKind::Repetition { bias, .. } => {
+                    assert_eq!(*bias, QuantifierBias::Greedy);
+                }
+                _ => panic!("Expected quantifier as second part"),
             },
+            _ => panic!("Expected sequence"),
         }
     }
 
@@ -755,20 +2327,19 @@ fourth = "D"
         // LAZY ANY should create a quantifier with Lazy bias
         let source = "w = LAZY ANY";
         let program = parse(source).unwrap();
-        match &program.statements[0] {
-            Statement { pattern, .. } => match &pattern.node {
-                PatternKind::Repetition {
-                    bias,
-                    min,
-                    max: _,
-                    pattern: inner,
-                } => {
-                    assert_eq!(*bias, QuantifierBias::Lazy);
-                    assert!(matches!(min, Some(0)));
-                    assert!(matches!(inner.node, PatternKind::Builtin(Builtin::AnyChar)));
-                }
-                _ => panic!("Expected quantifier"),
-            },
+        let Statement { pattern, .. } = &program.statements[0];
+        match &pattern.node {
+            PatternKind::Repetition {
+                bias,
+                min,
+                max: _,
+                pattern: inner,
+            } => {
+                assert_eq!(*bias, QuantifierBias::Lazy);
+                assert!(matches!(min, Some(0)));
+                assert!(matches!(inner.node, PatternKind::Builtin(Builtin::AnyChar)));
+            }
+            _ => panic!("Expected quantifier"),
         }
     }
 
@@ -778,13 +2349,12 @@ fourth = "D"
         let source = "w = GREEDY ANY";
         let program = parse(source).unwrap();
 
-        match &program.statements[0] {
-            Statement { pattern, .. } => match &pattern.node {
-                PatternKind::Repetition { bias, .. } => {
-                    assert_eq!(*bias, QuantifierBias::Greedy);
-                }
-                _ => panic!("Expected quantifier"),
-            },
+        let Statement { pattern, .. } = &program.statements[0];
+        match &pattern.node {
+            PatternKind::Repetition { bias, .. } => {
+                assert_eq!(*bias, QuantifierBias::Greedy);
+            }
+            _ => panic!("Expected quantifier"),
         }
     }
 
@@ -832,20 +2402,63 @@ fourth = "D"
         assert_eq!(w_stmt.capture.as_ref().unwrap().path.segments.len(), 3);
     }
 
+    #[test]
+    fn test_anonymous_capture() {
+        let program = parse("TEXT = \"id=\" (1..N DIGIT -> ADD TO ROOT.id)").unwrap();
+        assert_eq!(program.statements.len(), 2);
+        let hidden = &program.statements[1];
+        assert_eq!(hidden.name, "anonymous.14");
+        assert_eq!(
+            program.statements[0].pattern.node,
+            PatternKind::Sequence(vec![
+                Pattern {
+                    node: PatternKind::Literal("id=".to_string()),
+                    span: 7..12
+                },
+                Pattern {
+                    node: PatternKind::Variable(hidden.name.clone()),
+                    span: 13..43
+                },
+            ])
+        );
+
+        // there is no name to store the value under
+        assert!(parse("TEXT = (WORD -> ADD TO ROOT)").is_err());
+        assert!(parse("TEXT = (WORD -> ADD w TO ROOT)").is_ok());
+    }
+
+    #[test]
+    fn test_positional_capture() {
+        let program = parse("TEXT = WORD \" \" 1..N DIGIT -> ADD $1, $3 AS INT TO ROOT").unwrap();
+        let names: Vec<_> = program.statements.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["TEXT", "TEXT.$1", "TEXT.$3"]);
+        assert!(program.statements[0].capture.is_none());
+        let capture = program.statements[2].capture.as_ref().unwrap();
+        assert_eq!(capture.name, "3");
+        assert!(matches!(capture.cast, Some(CaptureCast::Int { .. })));
+
+        for source in [
+            "TEXT = WORD \" \" WORD -> ADD $4 TO ROOT",
+            "TEXT = WORD \" \" WORD -> ADD $0 TO ROOT",
+            "TEXT = WORD \" \" WORD -> ADD $1, $1 TO ROOT",
+        ] {
+            assert!(parse(source).is_err(), "{}", source);
+        }
+    }
+
     #[test]
     fn test_quantifier_numeric_bounds() {
         // Both bounds are numbers
         let source = "digits = 0..5 DIGIT";
         let program = parse(source).unwrap();
 
-        match &program.statements[0] {
-            Statement { pattern, .. } => match &pattern.node {
-                PatternKind::Repetition { min, max, .. } => {
-                    assert!(matches!(min, Some(0)));
-                    assert!(matches!(max, Some(5)));
-                }
-                _ => panic!("Expected quantifier"),
-            },
+        let Statement { pattern, .. } = &program.statements[0];
+        match &pattern.node {
+            PatternKind::Repetition { min, max, .. } => {
+                assert!(matches!(min, Some(0)));
+                assert!(matches!(max, Some(5)));
+            }
+            _ => panic!("Expected quantifier"),
         }
     }
 
@@ -855,14 +2468,13 @@ fourth = "D"
         let source = "digits = 1..n DIGIT";
         let program = parse(source).unwrap();
 
-        match &program.statements[0] {
-            Statement { pattern, .. } => match &pattern.node {
-                PatternKind::Repetition { min, max, .. } => {
-                    assert!(matches!(min, Some(1)));
-                    assert!(matches!(max, None));
-                }
-                _ => panic!("Expected quantifier"),
-            },
+        let Statement { pattern, .. } = &program.statements[0];
+        match &pattern.node {
+            PatternKind::Repetition { min, max, .. } => {
+                assert!(matches!(min, Some(1)));
+                assert!(max.is_none());
+            }
+            _ => panic!("Expected quantifier"),
         }
     }
 
@@ -881,4 +2493,412 @@ fourth = "D"
         let result = parse(source);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_quantifier_reversed_bounds_rejected() {
+        let source = "x = 5..2 DIGIT";
+        match parse(source) {
+            Err(StrqlError::InvalidQuantifierBounds {
+                _min: 5,
+                _max: 2,
+                _span,
+                ..
+            }) => assert_eq!(&source[_span.offset()..][.._span.len()], "5..2"),
+            _ => panic!("Expected InvalidQuantifierBounds"),
+        }
+        assert!(parse("x = 2..2 DIGIT").is_ok());
+    }
+
+    #[test]
+    fn test_dict() {
+        let program = parse("TEXT = DICT(cities) \"!\"").unwrap();
+        let PatternKind::Sequence(items) = &program.statements[0].pattern.node else {
+            panic!("expected a sequence");
+        };
+        assert_eq!(items[0].node, PatternKind::Dict("cities".to_string()));
+        // without parentheses, or escaped, `dict` names a statement
+        let program = parse("TEXT = dict cities").unwrap();
+        let PatternKind::Sequence(items) = &program.statements[0].pattern.node else {
+            panic!("expected a sequence");
+        };
+        assert_eq!(items[0].node, PatternKind::Variable("dict".to_string()));
+        let program = parse("TEXT = `dict`(cities)").unwrap();
+        assert!(program.statements[0].pattern.variables().contains(&"dict"));
+    }
+
+    #[test]
+    fn test_contextual_keywords() {
+        // the words of directives, casts and capture options still name statements
+        for name in [
+            "number", "int", "json", "option", "define", "as", "group", "by", "desc", "unique",
+            "sorted", "nullable", "decoded", "datetime", "epoch", "dict", "kv",
+        ] {
+            let source = format!(
+                "TEXT = {} \" \" x\n{} = 1..N DIGIT -> ADD {} AS INT TO ROOT\nx = WORD",
+                name, name, name
+            );
+            let program = parse(&source).unwrap_or_else(|e| panic!("{}: {:?}", name, e));
+            assert_eq!(program.statements[1].name, name);
+            let capture = program.statements[1].capture.as_ref().unwrap();
+            assert_eq!(capture.name, name);
+            assert_eq!(capture.cast, Some(CaptureCast::Int { radix: None }));
+        }
+        let program = parse("TEXT = as\nas = DIGIT -> ADD as TO ROOT.v[] UNIQUE").unwrap();
+        let capture = program.statements[1].capture.as_ref().unwrap();
+        assert_eq!(capture.name, "as");
+        assert!(capture.unique);
+        assert!(parse("OPTION nfc\nDEFINE d = DIGIT\nTEXT = d").is_ok());
+        assert!(parse("TEXT = d\nd = DIGIT -> ADD AS FLOAT TO ROOT").is_err());
+    }
+
+    #[test]
+    fn test_decoded_encoding() {
+        let source = "TEXT = a b\na = \"0x\" HEXBLOB -> ADD AS DECODED TO ROOT\nb = BASE64 -> ADD b AS DECODED TO ROOT";
+        let program = parse(source).unwrap();
+        let encodings: Vec<_> = program.statements[1..]
+            .iter()
+            .map(|s| s.capture.as_ref().unwrap().cast.clone())
+            .collect();
+        assert_eq!(
+            encodings,
+            [
+                Some(CaptureCast::Decoded {
+                    encoding: Encoding::Hex
+                }),
+                Some(CaptureCast::Decoded {
+                    encoding: Encoding::Base64
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_datetime_cast() {
+        let result = parse("TEXT = d\nd = LINE -> ADD AS EPOCH(\"%Y-%m-%d\") TO ROOT");
+        if cfg!(feature = "datetime") {
+            let capture = result.unwrap().statements[1].capture.clone().unwrap();
+            assert_eq!(
+                capture.cast,
+                Some(CaptureCast::DateTime {
+                    format: "%Y-%m-%d".to_string(),
+                    epoch: true
+                })
+            );
+        } else {
+            assert!(result.is_err());
+        }
+    }
+
+    /// The text each pattern of the first statement spans, outermost first.
+    fn spanned_texts(source: &str) -> Vec<&str> {
+        struct Texts<'a>(&'a str, Vec<&'a str>);
+        impl<'ast> crate::visit::Visitor<'ast> for Texts<'_> {
+            fn visit_pattern(&mut self, pattern: &'ast Pattern) {
+                self.1.push(&self.0[pattern.span.clone()]);
+                crate::visit::walk_pattern(self, pattern);
+            }
+        }
+        let program = parse(source).unwrap();
+        let mut texts = Texts(source, Vec::new());
+        crate::visit::Visitor::visit_pattern(&mut texts, &program.statements[0].pattern);
+        texts.1
+    }
+
+    #[test]
+    fn test_pattern_spans() {
+        assert_eq!(
+            spanned_texts("TEXT = \"a\" LAZY WORD (x OR y) // c\nx = \"x\"\ny = \"y\""),
+            [
+                "\"a\" LAZY WORD (x OR y)",
+                "\"a\"",
+                "LAZY WORD",
+                "WORD",
+                "(x OR y)",
+                "x OR y",
+                "x",
+                "y",
+            ]
+        );
+        // the repeated tail of a SPLITBY is what follows its first item
+        assert_eq!(
+            spanned_texts("TEXT = DIGIT LAZY SPLITBY \",\" /// digits"),
+            [
+                "DIGIT LAZY SPLITBY \",\"",
+                "DIGIT",
+                "LAZY SPLITBY \",\"",
+                "LAZY SPLITBY \",\"",
+                "\",\"",
+                "DIGIT",
+            ]
+        );
+        assert_eq!(
+            spanned_texts("TEXT = KV(key = WORD, value = LINE) -> ADD TO ROOT"),
+            [
+                "KV(key = WORD, value = LINE)",
+                "WORD",
+                "KV(key = WORD, value = LINE)",
+                "LINE",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_kv() {
+        let source = "TEXT = var\nvar = KV(key = WORD, value = LINE) -> ADD TO ROOT.env";
+        let program = parse(source).unwrap();
+        let names: Vec<_> = program.statements.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["TEXT", "var", "var.key", "var.value"]);
+        assert!(program.statements[1].capture.is_none());
+        let capture = program.statements[3].capture.as_ref().unwrap();
+        assert_eq!(
+            capture.path.segments.last(),
+            Some(&PathSegment::DynamicField(vec![KeyPart::Variable(
+                "var.key".to_string()
+            )]))
+        );
+
+        for source in [
+            "kv = KV(key = WORD)",
+            "kv = KV(key = WORD, value = LINE, key = WORD)",
+            "kv = KV(name = WORD, value = LINE)",
+            "kv = KV(key = WORD, value = LINE) \";\"",
+            "kv = KV(key = WORD, value = LINE) -> ADD TO ROOT.h[]",
+        ] {
+            assert!(parse(source).is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_extension() {
+        let source =
+            "TEXT = sep\nsep = \",\" OR \";\" -> ADD TO ROOT\nsep |= \" / \"\nother |= WORD";
+        let program = parse(source).unwrap();
+        let names: Vec<_> = program.statements.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["TEXT", "sep", "other"]);
+        let sep = &program.statements[1];
+        assert!(!sep.extends && sep.capture.is_some());
+        let PatternKind::OrChain { alternatives, .. } = &sep.pattern.node else {
+            panic!("expected alternatives");
+        };
+        assert_eq!(
+            alternatives[2].node,
+            PatternKind::Literal(" / ".to_string())
+        );
+        assert!(program.statements[2].extends);
+
+        assert!(parse("sep |= \" / \" -> ADD TO ROOT").is_err());
+    }
+
+    #[test]
+    fn test_bytes() {
+        let program = parse("TEXT = BYTE(0x0a) BYTES(4)").unwrap();
+        let PatternKind::Sequence(items) = &program.statements[0].pattern.node else {
+            panic!("expected a sequence");
+        };
+        assert_eq!(items[0].node, PatternKind::Builtin(Builtin::Byte(10)));
+        assert_eq!(items[1].node, PatternKind::Builtin(Builtin::Bytes(4)));
+        assert!(parse("TEXT = BYTE(256)").is_err());
+        assert!(parse("TEXT = BYTES").is_err());
+    }
+
+    #[test]
+    fn test_options() {
+        let source = "OPTION NORMALIZE_CRLF\noption nfc\nOPTION anycase\nTEXT = LINE";
+        let program = parse(source).unwrap();
+
+        assert_eq!(program.statements.len(), 1);
+        assert!(program.options.normalize_crlf);
+        assert!(program.options.nfc);
+        assert!(program.options.anycase);
+        assert!(!program.options.trim_trailing_whitespace);
+        assert!(!program.options.reorder_alternatives);
+
+        let program = parse("OPTION REORDER_ALTERNATIVES\nTEXT = LINE").unwrap();
+        assert!(program.options.reorder_alternatives);
+        assert_eq!(program.options.capture_raw, None);
+
+        let program = parse("OPTION CAPTURE_RAW\nTEXT = LINE").unwrap();
+        assert_eq!(program.options.capture_raw.as_deref(), Some("_raw"));
+        let program = parse("OPTION CAPTURE_RAW AS original\nTEXT = LINE").unwrap();
+        assert_eq!(program.options.capture_raw.as_deref(), Some("original"));
+
+        assert_eq!(program.options.strategy, None);
+        let program = parse("OPTION STRATEGY peg\nTEXT = LINE").unwrap();
+        assert_eq!(program.options.strategy, Some(Strategy::Peg));
+        let program = parse("OPTION STRATEGY CFG\nTEXT = LINE").unwrap();
+        assert_eq!(program.options.strategy, Some(Strategy::Cfg));
+        assert!(parse("OPTION STRATEGY LL\nTEXT = LINE").is_err());
+
+        // declared after the `SPLITBY` it applies to
+        let program = parse("TEXT = WORD SPLITBY \",\"\nOPTION SKIP_UNMATCHED").unwrap();
+        assert!(program.options.skip_unmatched);
+        let PatternKind::Sequence(items) = &program.statements[0].pattern.node else {
+            panic!("Expected sequence");
+        };
+        let PatternKind::OrChain { alternatives, bias } = &items[0].node else {
+            panic!("Expected the item or what's unmatched");
+        };
+        assert_eq!(*bias, AlternationBias::First);
+        assert!(matches!(alternatives[1].node, PatternKind::Unmatched(_)));
+
+        // statements named after the words of the option, referenced in a statement
+        let program = parse(
+            "TEXT = row SPLITBY \",\"\nrow = option skip_unmatched\n\
+             option = WORD\nskip_unmatched = DIGIT",
+        )
+        .unwrap();
+        assert!(!program.options.skip_unmatched);
+        let PatternKind::Sequence(items) = &program.statements[0].pattern.node else {
+            panic!("Expected sequence");
+        };
+        assert!(matches!(items[0].node, PatternKind::Variable(_)));
+    }
+
+    #[test]
+    fn test_option_keyword_as_statement_name() {
+        let program = parse("TEXT = \"y\"\nOPTION = \"x\"").unwrap();
+        assert_eq!(program.statements[1].name, "OPTION");
+        assert_eq!(program.options, QueryOptions::default());
+    }
+
+    #[test]
+    fn test_builtin_name_needs_escaping() {
+        let source = "TEXT = line SPLITBY NEWLINE\nline = LINE -> ADD TO ROOT.lines[]";
+        match parse(source) {
+            Err(StrqlError::RedefinedBuiltin { _name, _span, .. }) => {
+                assert_eq!(_name, "line");
+                assert_eq!(_span.offset(), 28);
+            }
+            other => panic!("Expected RedefinedBuiltin, got {:?}", other),
+        }
+
+        let program = parse("TEXT = `line` SPLITBY NEWLINE\n`line` = LINE").unwrap();
+        assert_eq!(program.statements[1].name, "line");
+        let PatternKind::Sequence(items) = &program.statements[0].pattern.node else {
+            panic!("Expected sequence");
+        };
+        assert_eq!(items[0].node, PatternKind::Variable("line".to_string()));
+
+        for name in ["bof", "EOF"] {
+            let source = format!("TEXT = {}\n{} = \"a\"", name, name);
+            assert!(
+                matches!(parse(&source), Err(StrqlError::RedefinedBuiltin { _name, .. }) if _name == name),
+                "{}",
+                name
+            );
+            let escaped = parse(&format!("TEXT = `{}`\n`{}` = \"a\"", name, name)).unwrap();
+            assert_eq!(escaped.statements[1].name, name);
+        }
+    }
+
+    #[test]
+    fn test_unknown_option() {
+        let result = parse("OPTION SHOUT\nTEXT = LINE");
+        assert!(matches!(result, Err(StrqlError::UnknownOption { .. })));
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        let source = r#"
+/// entry point
+/// one name per line
+TEXT = name SPLITBY NEWLINE
+
+// a plain comment
+name = WORD -> ADD TO ROOT.names[] /// extracts the user name
+other = "x"
+"#;
+        let program = parse(source).unwrap();
+
+        assert_eq!(
+            program.statements[0].doc.as_deref(),
+            Some("entry point\none name per line")
+        );
+        assert_eq!(
+            program.statements[1].doc.as_deref(),
+            Some("extracts the user name")
+        );
+        assert!(program.statements[1].capture.is_some());
+        assert_eq!(program.statements[2].doc, None);
+
+        // directives have no documentation to keep
+        for (source, keyword, doc) in [
+            (
+                "/// normalized\n/// line endings\nOPTION NORMALIZE_CRLF\nTEXT = LINE",
+                "OPTION",
+                "/// normalized\n/// line endings",
+            ),
+            (
+                "/// separator\nDEFINE sep = \",\"\nTEXT = WORD SPLITBY sep",
+                "DEFINE",
+                "/// separator",
+            ),
+            (
+                "/// sections\n\nIMPORT ini\nTEXT = ini",
+                "IMPORT",
+                "/// sections",
+            ),
+        ] {
+            let Err(StrqlError::MisplacedDocComment {
+                _keyword, _span, ..
+            }) = parse(source)
+            else {
+                panic!("{} should not parse", source);
+            };
+            assert_eq!(_keyword, keyword);
+            assert_eq!(&source[_span.offset().._span.offset() + _span.len()], doc);
+        }
+    }
+
+    #[test]
+    fn test_define_is_substituted() {
+        let source = r#"
+DEFINE sep = ", " OR "; "
+TEXT = item SPLITBY sep
+item = WORD
+"#;
+        let program = parse(source).unwrap();
+
+        assert_eq!(program.statements.len(), 2);
+        let PatternKind::Sequence(parts) = &program.statements[0].pattern.node else {
+            panic!("Expected sequence");
+        };
+        let PatternKind::Repetition { pattern: tail, .. } = &parts[1].node else {
+            panic!("Expected quantifier");
+        };
+        let PatternKind::Sequence(tail) = &tail.node else {
+            panic!("Expected sequence");
+        };
+        assert!(matches!(tail[0].node, PatternKind::OrChain { .. }));
+    }
+
+    #[test]
+    fn test_define_used_before_declaration_and_nested() {
+        let source = r#"
+TEXT = pair
+DEFINE pair = key "=" key
+DEFINE key = 1..N LETTER
+"#;
+        let program = parse(source).unwrap();
+        assert!(program.statements[0].pattern.variables().is_empty());
+    }
+
+    #[test]
+    fn test_define_errors() {
+        assert!(matches!(
+            parse("DEFINE a = \"x\" b\nDEFINE b = a\nTEXT = a"),
+            Err(StrqlError::RecursiveDefine { .. })
+        ));
+        assert!(matches!(
+            parse("DEFINE a = \"x\"\nTEXT = a\na = \"y\""),
+            Err(StrqlError::DefineConflict { .. })
+        ));
+        assert!(matches!(
+            parse("DEFINE a = WORD -> ADD TO ROOT\nTEXT = a"),
+            Err(StrqlError::UnexpectedToken { .. })
+        ));
+        let err = parse("DEFINE a = \"x\"\nDEFINE a = \"y\"\nTEXT = a").unwrap_err();
+        assert!(matches!(&err, StrqlError::DuplicateDefine { _name, .. } if _name == "a"));
+        assert_eq!(err.spans(), [0..14, 15..29]);
+    }
 }
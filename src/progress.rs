@@ -0,0 +1,44 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// A handle for watching how far a running evaluation got from another thread, e.g. to draw
+/// a progress bar; see [`crate::SolverOptions::progress`].
+///
+/// Clones share the same state. Positions are byte offsets into the input once the query's
+/// `OPTION`s normalized it.
+#[derive(Debug, Clone, Default)]
+pub struct Progress(Arc<State>);
+
+#[derive(Debug, Default)]
+struct State {
+    position: AtomicUsize,
+    input_len: AtomicUsize,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The furthest position of the input the solver got to so far.
+    pub fn position(&self) -> usize {
+        self.0.position.load(Ordering::Relaxed)
+    }
+
+    /// The length of the input being evaluated.
+    pub fn input_len(&self) -> usize {
+        self.0.input_len.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn start(&self, input_len: usize) {
+        self.0.position.store(0, Ordering::Relaxed);
+        self.0.input_len.store(input_len, Ordering::Relaxed);
+    }
+
+    pub(crate) fn reach(&self, position: usize) {
+        // most positions were reached already, and loads are cheaper than updates
+        if position > self.position() {
+            self.0.position.fetch_max(position, Ordering::Relaxed);
+        }
+    }
+}
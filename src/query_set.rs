@@ -0,0 +1,241 @@
+use crate::error::{ErrorKind, StrqlError, StrqlResult};
+use crate::solver::CompiledQuery;
+use serde_json::Value;
+
+/// A named collection of compiled queries, for dispatching inputs between several formats.
+///
+/// Queries are compiled once when added and tried in insertion order.
+#[derive(Clone, Default)]
+pub struct QuerySet {
+    queries: Vec<(String, CompiledQuery)>,
+}
+
+impl QuerySet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compiles `source` and registers it under `name`, replacing any query with the same name.
+    pub fn add(&mut self, name: impl Into<String>, source: &str) -> StrqlResult<()> {
+        self.insert_compiled(name, CompiledQuery::compile(source)?);
+        Ok(())
+    }
+
+    /// Registers an already compiled query under `name`, replacing any query with the same name.
+    pub fn insert_compiled(&mut self, name: impl Into<String>, query: CompiledQuery) {
+        let name = name.into();
+        match self.queries.iter_mut().find(|(n, _)| *n == name) {
+            Some(entry) => entry.1 = query,
+            None => self.queries.push((name, query)),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&CompiledQuery> {
+        self.queries.iter().find(|(n, _)| n == name).map(|(_, q)| q)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.queries.iter().map(|(n, _)| n.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.queries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queries.is_empty()
+    }
+
+    /// Returns the names of every query that uniquely matches `input`, in insertion order.
+    ///
+    /// Only conformance is checked (no captures are built). Queries that don't match `input`
+    /// are left out; any other error, like a query matching `input` ambiguously, is returned.
+    pub fn classify(&self, input: &str) -> StrqlResult<Vec<&str>> {
+        let mut names = Vec::new();
+        for (name, query) in &self.queries {
+            if query.matches(input)? {
+                names.push(name.as_str());
+            }
+        }
+        Ok(names)
+    }
+
+    /// Evaluates the first query (in insertion order) that matches `input`. Queries that
+    /// don't match are skipped; any other error, like an ambiguous match, is returned.
+    pub fn evaluate_first(&self, input: &str) -> StrqlResult<Option<(&str, Value)>> {
+        for (name, query) in &self.queries {
+            match query.evaluate(input) {
+                Ok(value) => return Ok(Some((name.as_str(), value))),
+                Err(err) if err.kind() == ErrorKind::NoMatch => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Evaluates every query on `input` and returns the one that matches the most of it,
+    /// preferring the queries that match all of it uniquely and build their captures, with any
+    /// other queries that match just as well. `None` if no query matches any of `input`.
+    pub fn best_match(&self, input: &str) -> Option<BestMatch<'_>> {
+        let mut best: Option<BestMatch> = None;
+        for (name, query) in &self.queries {
+            let result = query.evaluate(input);
+            let coverage = match &result {
+                Ok(_) => input.len(),
+                Err(err) if matches!(err.kind(), ErrorKind::Ambiguous | ErrorKind::Capture) => {
+                    input.len()
+                }
+                Err(StrqlError::PartialMatch { _matched, .. }) if *_matched > 0 => *_matched,
+                Err(_) => continue,
+            };
+            let candidate = BestMatch {
+                name,
+                result,
+                coverage,
+                ties: Vec::new(),
+            };
+            match &mut best {
+                Some(best) if best.rank() == candidate.rank() => best.ties.push(name),
+                Some(best) if best.rank() > candidate.rank() => {}
+                _ => best = Some(candidate),
+            }
+        }
+        best
+    }
+}
+
+/// The query of a [`QuerySet`] that matches an input best, from [`QuerySet::best_match`].
+#[derive(Debug)]
+pub struct BestMatch<'a> {
+    pub name: &'a str,
+    /// what evaluating the query gave: the captures, or why they couldn't be built
+    pub result: StrqlResult<Value>,
+    /// how many bytes of the input the query matches
+    pub coverage: usize,
+    /// the other queries that match as well, in insertion order
+    pub ties: Vec<&'a str>,
+}
+
+impl BestMatch<'_> {
+    fn rank(&self) -> (usize, bool) {
+        (self.coverage, self.result.is_ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn log_formats() -> QuerySet {
+        let mut set = QuerySet::new();
+        set.add(
+            "kv",
+            r#"
+TEXT = key "=" value
+key = WORD -> ADD TO ROOT
+value = 1..N DIGIT -> ADD TO ROOT
+"#,
+        )
+        .unwrap();
+        set.add(
+            "csv",
+            r#"
+TEXT = field GREEDY SPLITBY ","
+field = ALPHANUM -> ADD TO ROOT.fields[]
+"#,
+        )
+        .unwrap();
+        set.add("digits", "TEXT = 1..N DIGIT").unwrap();
+        set
+    }
+
+    #[test]
+    fn classify_reports_all_matching_queries() {
+        let set = log_formats();
+        assert_eq!(set.classify("port=80").unwrap(), vec!["kv"]);
+        assert_eq!(set.classify("a,b1,c").unwrap(), vec!["csv"]);
+        assert_eq!(set.classify("123").unwrap(), vec!["csv", "digits"]);
+        assert!(set.classify("???").unwrap().is_empty());
+    }
+
+    #[test]
+    fn evaluate_first_runs_earliest_match() {
+        let set = log_formats();
+        let (name, value) = set.evaluate_first("123").unwrap().unwrap();
+        assert_eq!(name, "csv");
+        assert_eq!(value, json!({"fields": ["123"]}));
+        assert!(set.evaluate_first("???").unwrap().is_none());
+    }
+
+    #[test]
+    fn errors_other_than_no_match_are_returned() {
+        let mut set = log_formats();
+        set.add(
+            "split",
+            "TEXT = a b\na = 1..N DIGIT -> ADD TO ROOT.a\nb = 1..N DIGIT -> ADD TO ROOT.b",
+        )
+        .unwrap();
+        assert_eq!(
+            set.classify("123").unwrap_err().kind(),
+            ErrorKind::Ambiguous
+        );
+        assert_eq!(set.evaluate_first("123").unwrap().unwrap().0, "csv");
+
+        let mut set = QuerySet::new();
+        set.add("digits", "TEXT = 1..N DIGIT").unwrap();
+        set.add("typed", "TEXT = WORD -> ADD AS INT TO ROOT.n")
+            .unwrap();
+        set.add("word", "TEXT = WORD").unwrap();
+        // matching casts nothing
+        assert_eq!(set.classify("ab").unwrap(), vec!["typed", "word"]);
+        let err = set.evaluate_first("ab").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Capture);
+    }
+
+    #[test]
+    fn best_match_prefers_the_most_coverage() {
+        let set = log_formats();
+        let best = set.best_match("port=80").unwrap();
+        assert_eq!(best.name, "kv");
+        assert_eq!(best.result.unwrap(), json!({"key": "port", "value": "80"}));
+        assert_eq!(best.coverage, 7);
+        assert!(best.ties.is_empty());
+
+        let best = set.best_match("123").unwrap();
+        assert_eq!(best.name, "csv");
+        assert_eq!(best.ties, vec!["digits"]);
+
+        // `kv` gets the furthest, to the missing digits
+        let best = set.best_match("port=x").unwrap();
+        assert_eq!(best.name, "kv");
+        assert_eq!(best.coverage, 5);
+        assert!(best.result.is_err());
+        assert!(set.best_match("???").is_none());
+    }
+
+    #[test]
+    fn best_match_counts_failed_captures_as_matches() {
+        let mut set = QuerySet::new();
+        set.add("typed", "TEXT = WORD -> ADD AS INT TO ROOT.n")
+            .unwrap();
+        let best = set.best_match("ab").unwrap();
+        assert_eq!(best.name, "typed");
+        assert_eq!(best.coverage, 2);
+        assert_eq!(best.result.unwrap_err().kind(), ErrorKind::Capture);
+
+        // below the queries that build their captures
+        set.add("word", "TEXT = WORD -> ADD TO ROOT.w").unwrap();
+        let best = set.best_match("ab").unwrap();
+        assert_eq!(best.name, "word");
+        assert!(best.ties.is_empty());
+    }
+
+    #[test]
+    fn add_replaces_same_name() {
+        let mut set = log_formats();
+        set.add("digits", "TEXT = 1..N LETTER").unwrap();
+        assert_eq!(set.len(), 3);
+        assert!(set.get("digits").unwrap().matches("abc").unwrap());
+    }
+}
@@ -0,0 +1,314 @@
+//! Splitting an input into records that are matched one at a time, so that large inputs made
+//! of independent stanzas don't have to be matched as one giant `TEXT`.
+
+use crate::error::{ErrorKind, StrqlError, StrqlResult};
+use crate::solver::CompiledQuery;
+use serde_json::{json, Value};
+use std::io::{self, BufRead};
+use std::str::FromStr;
+
+/// How an input is split into records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordMode {
+    /// blocks of lines separated by blank lines, like mbox messages or debian control stanzas
+    Paragraph,
+    /// every non-blank line, like the entries of most logs
+    Line,
+}
+
+impl FromStr for RecordMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "paragraph" => Ok(RecordMode::Paragraph),
+            "line" => Ok(RecordMode::Line),
+            _ => Err(format!(
+                "unknown record mode '{}' (expected: paragraph, line)",
+                mode
+            )),
+        }
+    }
+}
+
+/// A record of an input, along with where it starts in that input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record<'a> {
+    pub text: &'a str,
+    /// byte offset of `text` in the input
+    pub offset: usize,
+}
+
+/// The non-empty records of `input`, in order.
+pub fn split_records(input: &str, mode: RecordMode) -> impl Iterator<Item = Record<'_>> {
+    let separator = match mode {
+        RecordMode::Paragraph => "\n\n",
+        RecordMode::Line => "\n",
+    };
+    let mut offset = 0;
+    input.split(separator).filter_map(move |part| {
+        let start = offset;
+        offset += part.len() + separator.len();
+        // runs of blank lines leave newlines around the parts
+        let text = part.trim_start_matches('\n');
+        let skipped = part.len() - text.len();
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        let text = match mode {
+            RecordMode::Line => text.strip_suffix('\r').unwrap_or(text),
+            RecordMode::Paragraph => text,
+        };
+        (!text.is_empty()).then_some(Record {
+            text,
+            offset: start + skipped,
+        })
+    })
+}
+
+/// How many of the records of an input matched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecordCounts {
+    pub matched: usize,
+    pub unmatched: usize,
+}
+
+impl RecordCounts {
+    /// The fraction of the records that matched, 1 for inputs without records.
+    pub fn ratio(&self) -> f64 {
+        match self.matched + self.unmatched {
+            0 => 1.0,
+            total => self.matched as f64 / total as f64,
+        }
+    }
+
+    /// Fails with [`StrqlError::TooFewRecordsMatched`] if fewer than `min_ratio` of the
+    /// records matched.
+    pub fn check(&self, min_ratio: f64) -> StrqlResult<()> {
+        if self.ratio() >= min_ratio {
+            return Ok(());
+        }
+        Err(StrqlError::TooFewRecordsMatched {
+            _matched: self.matched,
+            _total: self.matched + self.unmatched,
+            _min_ratio: min_ratio,
+        })
+    }
+}
+
+/// The outputs of the records of an input, from [`CompiledQuery::evaluate_records`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordsOutput {
+    pub outputs: Vec<Value>,
+    pub counts: RecordCounts,
+}
+
+impl CompiledQuery {
+    /// Evaluates every record of `input` on its own.
+    ///
+    /// A record that doesn't match fails the evaluation, unless `min_match_ratio` is given or
+    /// the query has `OPTION SKIP_UNMATCHED`: then it is counted as unmatched (and added as
+    /// `{"_unmatched": [...]}` with the option), and evaluation only fails if fewer than
+    /// `min_match_ratio` of the records matched.
+    pub fn evaluate_records(
+        &self,
+        input: &str,
+        mode: RecordMode,
+        min_match_ratio: Option<f64>,
+    ) -> StrqlResult<RecordsOutput> {
+        let skip_unmatched = self.options().skip_unmatched;
+        let mut outputs = Vec::new();
+        let mut counts = RecordCounts::default();
+        for record in split_records(input, mode) {
+            match self.evaluate(record.text) {
+                Ok(output) => {
+                    counts.matched += 1;
+                    outputs.push(output);
+                }
+                Err(error)
+                    if error.kind() == ErrorKind::NoMatch
+                        && (skip_unmatched || min_match_ratio.is_some()) =>
+                {
+                    counts.unmatched += 1;
+                    if skip_unmatched {
+                        let span = [record.offset, record.offset + record.text.len()];
+                        outputs.push(json!({
+                            "_unmatched": [{ "text": record.text, "span": span }]
+                        }));
+                    }
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        if let Some(min_ratio) = min_match_ratio {
+            counts.check(min_ratio)?;
+        }
+        Ok(RecordsOutput { outputs, counts })
+    }
+}
+
+/// A record read by a [`RecordReader`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadRecord {
+    pub text: String,
+    /// 1-based line of the input `text` starts at
+    pub line: usize,
+    /// byte offset of `text` in the input
+    pub offset: usize,
+}
+
+/// The records of an input read line by line, like [`split_records`] but without holding more
+/// than one record in memory: for large or decompressed inputs.
+pub struct RecordReader<R> {
+    reader: R,
+    mode: RecordMode,
+    line: usize,
+    /// bytes read so far
+    offset: usize,
+}
+
+impl<R: BufRead> RecordReader<R> {
+    pub fn new(reader: R, mode: RecordMode) -> Self {
+        RecordReader {
+            reader,
+            mode,
+            line: 0,
+            offset: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for RecordReader<R> {
+    type Item = io::Result<ReadRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut record: Option<ReadRecord> = None;
+        let mut buffer = String::new();
+        loop {
+            buffer.clear();
+            let offset = self.offset;
+            match self.reader.read_line(&mut buffer) {
+                Ok(0) => return record.map(Ok),
+                Ok(read) => {
+                    self.line += 1;
+                    self.offset += read;
+                }
+                Err(e) => return Some(Err(e)),
+            }
+            let text = buffer.strip_suffix('\n').unwrap_or(&buffer);
+            let text = match self.mode {
+                RecordMode::Line => text.strip_suffix('\r').unwrap_or(text),
+                RecordMode::Paragraph => text,
+            };
+            if text.is_empty() {
+                if record.is_some() {
+                    return record.map(Ok);
+                }
+                continue;
+            }
+            match &mut record {
+                Some(record) => {
+                    record.text.push('\n');
+                    record.text.push_str(text);
+                }
+                None if self.mode == RecordMode::Line => {
+                    return Some(Ok(ReadRecord {
+                        text: text.to_string(),
+                        line: self.line,
+                        offset,
+                    }));
+                }
+                None => {
+                    record = Some(ReadRecord {
+                        text: text.to_string(),
+                        line: self.line,
+                        offset,
+                    })
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines() {
+        let input = "a 1\r\n\nb 2\n";
+        let records: Vec<_> = split_records(input, RecordMode::Line).collect();
+        let texts: Vec<_> = records.iter().map(|r| r.text).collect();
+        assert_eq!(texts, ["a 1", "b 2"]);
+        assert_eq!(records[1].offset, 6);
+    }
+
+    #[test]
+    fn paragraphs() {
+        let input = "\nPackage: a\nVersion: 1\n\n\n\nPackage: b\n\nPackage: c\n";
+        let records: Vec<_> = split_records(input, RecordMode::Paragraph).collect();
+        let texts: Vec<_> = records.iter().map(|r| r.text).collect();
+        assert_eq!(
+            texts,
+            ["Package: a\nVersion: 1", "Package: b", "Package: c"]
+        );
+        for record in records {
+            assert_eq!(&input[record.offset..][..record.text.len()], record.text);
+        }
+        assert_eq!(
+            "page".parse(),
+            Err::<RecordMode, _>(
+                "unknown record mode 'page' (expected: paragraph, line)".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn records_below_the_minimum_ratio_fail() {
+        let query = CompiledQuery::compile("TEXT = 1..N DIGIT -> ADD TO ROOT.num").unwrap();
+        let input = "1\n2\nx\n3\n";
+        let output = query
+            .evaluate_records(input, RecordMode::Line, Some(0.75))
+            .unwrap();
+        assert_eq!(
+            output.outputs,
+            [
+                json!({"num": "1"}),
+                json!({"num": "2"}),
+                json!({"num": "3"})
+            ]
+        );
+        assert_eq!(
+            output.counts,
+            RecordCounts {
+                matched: 3,
+                unmatched: 1
+            }
+        );
+        let error = query
+            .evaluate_records(input, RecordMode::Line, Some(0.8))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Only 3 of 4 records matched, fewer than the minimum ratio of 0.8"
+        );
+        assert!(query
+            .evaluate_records(input, RecordMode::Line, None)
+            .is_err());
+        assert_eq!(RecordCounts::default().ratio(), 1.0);
+    }
+
+    #[test]
+    fn reads_the_records_it_splits() {
+        let input = "\nPackage: a\r\nVersion: 1\n\n\n\nPackage: b\n\nPackage: c";
+        for mode in [RecordMode::Paragraph, RecordMode::Line] {
+            let read: Vec<_> = RecordReader::new(input.as_bytes(), mode)
+                .map(Result::unwrap)
+                .collect();
+            let texts: Vec<_> = read.iter().map(|r| (r.text.as_str(), r.offset)).collect();
+            let split: Vec<_> = split_records(input, mode)
+                .map(|r| (r.text, r.offset))
+                .collect();
+            assert_eq!(texts, split);
+            assert_eq!(read.last().unwrap().line, 9);
+        }
+    }
+}
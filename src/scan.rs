@@ -0,0 +1,62 @@
+//! Scanning inputs for literals and for runs of characters of a class. With the `simd`
+//! feature, literals are searched with `memchr`'s vectorized searches.
+
+/// How many bytes of ASCII characters [`run_end`] classifies at once.
+const CHUNK: usize = 16;
+
+/// The byte offset of the first `needle` in `haystack`.
+pub(crate) fn find(haystack: &str, needle: &str) -> Option<usize> {
+    #[cfg(feature = "simd")]
+    return memchr::memmem::find(haystack.as_bytes(), needle.as_bytes());
+    #[cfg(not(feature = "simd"))]
+    return haystack.find(needle);
+}
+
+/// Where the run of characters of `text` that `class` accepts, starting at `start`, ends.
+///
+/// ASCII text is classified a chunk at a time without branching on each byte, which the
+/// compiler turns into vector instructions.
+pub(crate) fn run_end(text: &str, start: usize, class: impl Fn(char) -> bool) -> usize {
+    let bytes = text.as_bytes();
+    let mut end = start;
+    while end < bytes.len() {
+        if let Some(chunk) = bytes.get(end..end + CHUNK) {
+            let all = chunk
+                .iter()
+                .fold(true, |all, &b| all & b.is_ascii() & class(b as char));
+            if all {
+                end += CHUNK;
+                continue;
+            }
+        }
+        // the run ends before the end of the chunk, or it has other characters than ASCII
+        let chunk_end = end + CHUNK;
+        for ch in text[end..].chars() {
+            if !class(ch) {
+                return end;
+            }
+            end += ch.len_utf8();
+            if end >= chunk_end {
+                break;
+            }
+        }
+    }
+    end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_end_at_the_first_character_out_of_class() {
+        let text = format!("ab{}x{}é1", "1".repeat(40), "2".repeat(3));
+        assert_eq!(run_end(&text, 2, |c| c.is_ascii_digit()), 42);
+        assert_eq!(run_end(&text, 43, |c| c.is_ascii_digit()), 46);
+        assert_eq!(run_end(&text, 0, |c| c != 'é'), 46);
+        assert_eq!(run_end(&text, 46, |c| c.is_alphabetic()), 48);
+        assert_eq!(run_end(&text, text.len(), |_| true), text.len());
+        assert_eq!(find(&text, "2é"), Some(45));
+        assert_eq!(find(&text, "3"), None);
+    }
+}
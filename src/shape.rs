@@ -0,0 +1,642 @@
+use crate::ast::{CaptureCast, CaptureClause, PathSegment, Program};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// The structure of the JSON a query produces, inferred from its capture clauses alone.
+///
+/// Fields are never required: whether a capture runs depends on the input.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    Object {
+        fields: BTreeMap<String, Shape>,
+        /// the values stored under dynamic keys (`item[key]`, `GROUP ... BY`), if any
+        additional: Option<Box<Shape>>,
+    },
+    Array(Box<Shape>),
+    String {
+        nullable: bool,
+    },
+    Integer {
+        nullable: bool,
+    },
+    Number {
+        nullable: bool,
+    },
+    /// captures that disagree on a type, or arrays nothing is ever added to
+    Any,
+}
+
+impl Shape {
+    /// Infers the shape of the objects `program` evaluates to.
+    pub fn infer(program: &Program) -> Shape {
+        let clauses = Builder::clauses(program);
+        Builder::build(program, &clauses).to_shape(ROOT, &mut Vec::new())
+    }
+
+    /// Renders the shape as a JSON Schema (draft 2020-12) document.
+    pub fn to_json_schema(&self) -> Value {
+        let mut schema = self.schema_node();
+        if let Value::Object(fields) = &mut schema {
+            fields.insert(
+                "$schema".to_string(),
+                json!("https://json-schema.org/draft/2020-12/schema"),
+            );
+        }
+        schema
+    }
+
+    fn schema_node(&self) -> Value {
+        let scalar = |name: &str, nullable: bool| {
+            if nullable {
+                json!({ "type": [name, "null"] })
+            } else {
+                json!({ "type": name })
+            }
+        };
+        match self {
+            Shape::Object { fields, additional } => {
+                let properties: Map<String, Value> = fields
+                    .iter()
+                    .map(|(name, shape)| (name.clone(), shape.schema_node()))
+                    .collect();
+                let additional = match additional {
+                    Some(shape) => shape.schema_node(),
+                    None => json!(false),
+                };
+                json!({
+                    "type": "object",
+                    "properties": properties,
+                    "additionalProperties": additional,
+                })
+            }
+            Shape::Array(items) => json!({ "type": "array", "items": items.schema_node() }),
+            Shape::String { nullable } => scalar("string", *nullable),
+            Shape::Integer { nullable } => scalar("integer", *nullable),
+            Shape::Number { nullable } => scalar("number", *nullable),
+            Shape::Any => json!({}),
+        }
+    }
+}
+
+/// The shape of a query's output along with the statements capturing to each part of it, for
+/// reviewing what a query outputs without an input; see [`Plan::infer`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    pub node: PlanNode,
+    /// the statements whose captures store or create this part
+    pub statements: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlanNode {
+    Object {
+        fields: BTreeMap<String, Plan>,
+        /// what is stored under dynamic keys, if anything
+        additional: Option<Box<Plan>>,
+    },
+    Array(Box<Plan>),
+    /// a value, with its [`Shape`]
+    Value(Shape),
+}
+
+impl Plan {
+    /// Infers the plan of the objects `program` evaluates to, like [`Shape::infer`].
+    pub fn infer(program: &Program) -> Plan {
+        let clauses = Builder::clauses(program);
+        Builder::build(program, &clauses).to_plan(ROOT, &mut Vec::new())
+    }
+
+    fn write(&self, f: &mut fmt::Formatter<'_>, name: &str, depth: usize) -> fmt::Result {
+        let kind = match &self.node {
+            PlanNode::Object { .. } => "object",
+            PlanNode::Array(_) => "array",
+            PlanNode::Value(Shape::String { nullable: false }) => "string",
+            PlanNode::Value(Shape::String { nullable: true }) => "string or null",
+            PlanNode::Value(Shape::Integer { nullable: false }) => "integer",
+            PlanNode::Value(Shape::Integer { nullable: true }) => "integer or null",
+            PlanNode::Value(Shape::Number { nullable: false }) => "number",
+            PlanNode::Value(Shape::Number { nullable: true }) => "number or null",
+            PlanNode::Value(_) => "any",
+        };
+        write!(f, "{:indent$}{}: {}", "", name, kind, indent = depth * 2)?;
+        if !self.statements.is_empty() {
+            write!(f, "  <- {}", self.statements.join(", "))?;
+        }
+        writeln!(f)?;
+        match &self.node {
+            PlanNode::Object { fields, additional } => {
+                for (field, plan) in fields {
+                    plan.write(f, field, depth + 1)?;
+                }
+                if let Some(plan) = additional {
+                    plan.write(f, "[key]", depth + 1)?;
+                }
+            }
+            PlanNode::Array(items) => items.write(f, "[]", depth + 1)?,
+            PlanNode::Value(_) => {}
+        }
+        Ok(())
+    }
+}
+
+/// The plan as an indented tree, one line per field or array items, like
+/// `results: array  <- line`.
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, "ROOT", 0)
+    }
+}
+
+type NodeId = usize;
+const ROOT: NodeId = 0;
+
+/// Graph form of [`Shape`]: objects are shared by every capture that writes into them.
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Object {
+        fields: BTreeMap<String, NodeId>,
+        additional: Option<NodeId>,
+    },
+    Array(NodeId),
+    String {
+        nullable: bool,
+    },
+    Integer {
+        nullable: bool,
+    },
+    Number {
+        nullable: bool,
+    },
+    /// nothing has been stored here yet
+    Empty,
+    Any,
+}
+
+impl Node {
+    fn object() -> Self {
+        Node::Object {
+            fields: BTreeMap::new(),
+            additional: None,
+        }
+    }
+}
+
+/// Where a capture stores its value, relative to the node its path leads to.
+enum Slot {
+    Field(String),
+    /// under a dynamic key
+    Additional,
+    /// the node itself (`ADD TO item[]` turns `item` into the array)
+    Itself,
+}
+
+struct Builder<'c> {
+    nodes: Vec<Node>,
+    /// node holding the fields of each `name{}` capture, once placed
+    objects: HashMap<String, NodeId>,
+    /// the capture clauses of the query, with the name of their statement
+    clauses: &'c [(&'c str, &'c CaptureClause)],
+    /// the statements capturing to each node
+    feeds: HashMap<NodeId, Vec<&'c str>>,
+}
+
+impl<'c> Builder<'c> {
+    fn clauses(program: &Program) -> Vec<(&str, &CaptureClause)> {
+        program
+            .statements
+            .iter()
+            .filter_map(|s| Some((s.name.as_str(), s.capture.as_ref()?)))
+            .collect()
+    }
+
+    fn build(program: &Program, clauses: &'c [(&'c str, &'c CaptureClause)]) -> Self {
+        let mut builder = Builder {
+            nodes: vec![Node::object()],
+            objects: HashMap::new(),
+            clauses,
+            feeds: HashMap::new(),
+        };
+        for &(statement, clause) in clauses {
+            builder.apply(statement, clause);
+        }
+        if let Some(field) = &program.options.capture_raw {
+            let node = builder.slot_node(ROOT, Slot::Field(field.clone()));
+            builder.merge(node, Node::String { nullable: false });
+            builder.feed(node, "OPTION CAPTURE_RAW");
+        }
+        if program.options.skip_unmatched {
+            // `{"text": ..., "span": [start, end]}` entries
+            let unmatched = builder.slot_node(ROOT, Slot::Field("_unmatched".to_string()));
+            let entry = builder.array_items(unmatched);
+            let text = builder.slot_node(entry, Slot::Field("text".to_string()));
+            builder.merge(text, Node::String { nullable: false });
+            let span = builder.slot_node(entry, Slot::Field("span".to_string()));
+            let bound = builder.array_items(span);
+            builder.merge(bound, Node::Integer { nullable: false });
+            builder.feed(entry, "OPTION SKIP_UNMATCHED");
+        }
+        builder
+    }
+
+    fn feed(&mut self, id: NodeId, statement: &'c str) {
+        let statements = self.feeds.entry(id).or_default();
+        if !statements.contains(&statement) {
+            statements.push(statement);
+        }
+    }
+
+    fn apply(&mut self, statement: &'c str, clause: &CaptureClause) {
+        if clause.is_object {
+            self.object_node(&clause.name, &mut Vec::new());
+            return;
+        }
+        let leaf = match &clause.cast {
+            Some(CaptureCast::Int { .. } | CaptureCast::DateTime { epoch: true, .. }) => {
+                Node::Integer {
+                    nullable: clause.nullable,
+                }
+            }
+            Some(CaptureCast::Decoded { .. } | CaptureCast::DateTime { epoch: false, .. })
+            | None => Node::String {
+                nullable: clause.nullable,
+            },
+            Some(CaptureCast::Json) => Node::Any,
+            Some(CaptureCast::Number { .. }) => Node::Number {
+                nullable: clause.nullable,
+            },
+        };
+        let (parent, slot, appends) = self.resolve(clause, &mut Vec::new());
+        let mut node = self.slot_node(parent, slot);
+        if appends {
+            node = self.array_items(node);
+        }
+        self.merge(node, leaf);
+        self.feed(node, statement);
+    }
+
+    /// Places the `name{}` capture (and whatever it's nested in) and returns its node.
+    fn object_node(&mut self, name: &str, placing: &mut Vec<String>) -> Option<NodeId> {
+        if let Some(&id) = self.objects.get(name) {
+            return Some(id);
+        }
+        if placing.iter().any(|n| n == name) {
+            return None;
+        }
+        let (statement, clause) = *self
+            .clauses
+            .iter()
+            .find(|(_, c)| c.is_object && c.name == name)?;
+        placing.push(name.to_string());
+        let (parent, slot, appends) = self.resolve(clause, placing);
+        placing.pop();
+
+        let mut id = self.slot_node(parent, slot);
+        if clause.group_by.is_some() {
+            // the collecting array becomes an object of arrays once replay is over
+            self.merge(id, Node::object());
+            id = self.slot_node(id, Slot::Additional);
+            id = self.array_items(id);
+        } else if appends {
+            id = self.array_items(id);
+        }
+        self.merge(id, Node::object());
+        self.feed(id, statement);
+        if clause.path.segments == [PathSegment::Root] {
+            // `ADD item{} TO ROOT` stores an empty object, but captures to `item` land in ROOT
+            id = ROOT;
+        }
+        self.objects.insert(name.to_string(), id);
+        Some(id)
+    }
+
+    /// Mirrors the path resolution done when replaying captures: returns the node the path
+    /// leads to, where in it the capture is stored, and whether it's appended to an array.
+    fn resolve(
+        &mut self,
+        clause: &CaptureClause,
+        placing: &mut Vec<String>,
+    ) -> (NodeId, Slot, bool) {
+        let segments = &clause.path.segments;
+        let (mut current, rest) = match segments.first() {
+            Some(PathSegment::Root) => (ROOT, &segments[1..]),
+            Some(PathSegment::Field(owner)) => match self.owner(owner, placing) {
+                Some(id) => (id, &segments[1..]),
+                None => (ROOT, &segments[..]),
+            },
+            _ => (ROOT, &segments[..]),
+        };
+
+        // `GROUP` always collects into an array; elsewhere only a trailing `[]` appends
+        let appends = clause.group_by.is_some() || clause.path.ends_with_array();
+        let steps: Vec<Slot> = rest
+            .iter()
+            .filter_map(|segment| match segment {
+                PathSegment::Field(name) => Some(Slot::Field(name.clone())),
+                PathSegment::DynamicField(_) => Some(Slot::Additional),
+                PathSegment::Root | PathSegment::ArrayAppend => None,
+            })
+            .collect();
+        let mut steps = steps.into_iter().peekable();
+        while let Some(step) = steps.next() {
+            if steps.peek().is_none() {
+                return (current, step, appends);
+            }
+            current = self.slot_node(current, step);
+            self.merge(current, Node::object());
+        }
+        let slot = if appends {
+            Slot::Itself
+        } else {
+            Slot::Field(clause.name.clone())
+        };
+        (current, slot, appends)
+    }
+
+    /// `name` as the first path segment: the named object if there is one.
+    fn owner(&mut self, name: &str, placing: &mut Vec<String>) -> Option<NodeId> {
+        if !self
+            .clauses
+            .iter()
+            .any(|(_, c)| c.is_object && c.name == name)
+        {
+            return None;
+        }
+        self.object_node(name, placing)
+    }
+
+    fn push(&mut self, node: Node) -> NodeId {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    /// The node stored at `slot` of `parent`, created empty if needed.
+    fn slot_node(&mut self, parent: NodeId, slot: Slot) -> NodeId {
+        if let Slot::Itself = slot {
+            return parent;
+        }
+        self.merge(parent, Node::object());
+        let existing = match (&self.nodes[parent], &slot) {
+            (Node::Object { fields, .. }, Slot::Field(name)) => fields.get(name).copied(),
+            (Node::Object { additional, .. }, _) => *additional,
+            _ => return parent,
+        };
+        if let Some(id) = existing {
+            return id;
+        }
+        let id = self.push(Node::Empty);
+        if let Node::Object { fields, additional } = &mut self.nodes[parent] {
+            match slot {
+                Slot::Field(name) => {
+                    fields.insert(name, id);
+                }
+                _ => *additional = Some(id),
+            }
+        }
+        id
+    }
+
+    /// The items of the array at `id`, turning it into one if needed.
+    fn array_items(&mut self, id: NodeId) -> NodeId {
+        if let Node::Array(items) = self.nodes[id] {
+            return items;
+        }
+        let items = self.push(Node::Empty);
+        self.nodes[id] = Node::Array(items);
+        items
+    }
+
+    fn merge(&mut self, id: NodeId, node: Node) {
+        self.nodes[id] = match (&self.nodes[id], node) {
+            (Node::Empty, node) => node,
+            (Node::Object { .. }, Node::Object { .. }) => return,
+            (Node::String { nullable: a }, Node::String { nullable: b }) => {
+                Node::String { nullable: *a || b }
+            }
+            (Node::Integer { nullable: a }, Node::Integer { nullable: b }) => {
+                Node::Integer { nullable: *a || b }
+            }
+            // integers are numbers too
+            (
+                Node::Integer { nullable: a } | Node::Number { nullable: a },
+                Node::Integer { nullable: b } | Node::Number { nullable: b },
+            ) => Node::Number { nullable: *a || b },
+            _ => Node::Any,
+        };
+    }
+
+    fn to_shape(&self, id: NodeId, visiting: &mut Vec<NodeId>) -> Shape {
+        if visiting.contains(&id) {
+            return Shape::Any;
+        }
+        visiting.push(id);
+        let shape = match &self.nodes[id] {
+            Node::Object { fields, additional } => Shape::Object {
+                fields: fields
+                    .iter()
+                    .map(|(name, &field)| (name.clone(), self.to_shape(field, visiting)))
+                    .collect(),
+                additional: additional.map(|a| Box::new(self.to_shape(a, visiting))),
+            },
+            Node::Array(items) => Shape::Array(Box::new(self.to_shape(*items, visiting))),
+            Node::String { nullable } => Shape::String {
+                nullable: *nullable,
+            },
+            Node::Integer { nullable } => Shape::Integer {
+                nullable: *nullable,
+            },
+            Node::Number { nullable } => Shape::Number {
+                nullable: *nullable,
+            },
+            Node::Empty | Node::Any => Shape::Any,
+        };
+        visiting.pop();
+        shape
+    }
+
+    fn to_plan(&self, id: NodeId, visiting: &mut Vec<NodeId>) -> Plan {
+        let statements = self
+            .feeds
+            .get(&id)
+            .map(|feeds| feeds.iter().map(|s| s.to_string()).collect())
+            .unwrap_or_default();
+        let scalar = !matches!(self.nodes[id], Node::Object { .. } | Node::Array(_));
+        if scalar || visiting.contains(&id) {
+            return Plan {
+                node: PlanNode::Value(self.to_shape(id, visiting)),
+                statements,
+            };
+        }
+        visiting.push(id);
+        let node = match &self.nodes[id] {
+            Node::Object { fields, additional } => PlanNode::Object {
+                fields: fields
+                    .iter()
+                    .map(|(name, &field)| (name.clone(), self.to_plan(field, visiting)))
+                    .collect(),
+                additional: additional.map(|a| Box::new(self.to_plan(a, visiting))),
+            },
+            Node::Array(items) => PlanNode::Array(Box::new(self.to_plan(*items, visiting))),
+            _ => unreachable!("to_plan: values are returned above"),
+        };
+        visiting.pop();
+        Plan { node, statements }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn infer(source: &str) -> Shape {
+        Shape::infer(&parse(source).unwrap())
+    }
+
+    fn object(fields: Vec<(&str, Shape)>) -> Shape {
+        Shape::Object {
+            fields: fields
+                .into_iter()
+                .map(|(name, shape)| (name.to_string(), shape))
+                .collect(),
+            additional: None,
+        }
+    }
+
+    const STRING: Shape = Shape::String { nullable: false };
+
+    #[test]
+    fn nested_objects_and_arrays() {
+        let shape = infer(
+            r#"
+TEXT = l SPLITBY NEWLINE
+l = members " are " kind -> ADD item{} TO ROOT.results[]
+members = member SPLITBY ", "
+member = WORD -> ADD TO item.members[]
+kind = WORD -> ADD TO item
+count = DIGIT -> ADD count AS INT TO ROOT.total NULLABLE
+"#,
+        );
+        assert_eq!(
+            shape,
+            object(vec![
+                (
+                    "results",
+                    Shape::Array(Box::new(object(vec![
+                        ("kind", STRING),
+                        ("members", Shape::Array(Box::new(STRING))),
+                    ])))
+                ),
+                ("total", Shape::Integer { nullable: true }),
+            ])
+        );
+    }
+
+    #[test]
+    fn dynamic_keys_and_groups() {
+        let shape = infer(
+            r#"
+TEXT = row SPLITBY NEWLINE
+row = key "=" value -> GROUP entry{} BY key TO ROOT.groups
+key = WORD -> ADD TO entry
+value = WORD -> ADD TO ROOT.latest[key]
+"#,
+        );
+        let Shape::Object { fields, .. } = &shape else {
+            panic!("expected an object, got {:?}", shape);
+        };
+        assert_eq!(
+            fields["groups"],
+            Shape::Object {
+                fields: BTreeMap::new(),
+                additional: Some(Box::new(Shape::Array(Box::new(object(vec![(
+                    "key", STRING
+                )]))))),
+            }
+        );
+        assert_eq!(
+            fields["latest"],
+            Shape::Object {
+                fields: BTreeMap::new(),
+                additional: Some(Box::new(STRING)),
+            }
+        );
+    }
+
+    #[test]
+    fn integers_widen_to_numbers() {
+        let shape = infer(
+            r#"
+TEXT = a b
+a = DIGIT -> ADD x AS INT TO ROOT.xs[]
+b = WORD -> ADD x AS NUMBER(locale = "de") TO ROOT.xs[]
+"#,
+        );
+        assert_eq!(
+            shape,
+            object(vec![(
+                "xs",
+                Shape::Array(Box::new(Shape::Number { nullable: false }))
+            )])
+        );
+    }
+
+    #[test]
+    fn conflicting_captures_are_any() {
+        let shape = infer(
+            r#"
+TEXT = a b
+a = DIGIT -> ADD x AS INT TO ROOT
+b = WORD -> ADD x TO ROOT
+"#,
+        );
+        assert_eq!(shape, object(vec![("x", Shape::Any)]));
+    }
+
+    #[test]
+    fn json_schema_document() {
+        let schema = infer("TEXT = WORD -> ADD TO ROOT.tags[]").to_json_schema();
+        assert_eq!(
+            schema,
+            json!({
+                "$schema": "https://json-schema.org/draft/2020-12/schema",
+                "type": "object",
+                "properties": {
+                    "tags": { "type": "array", "items": { "type": "string" } }
+                },
+                "additionalProperties": false,
+            })
+        );
+    }
+
+    #[test]
+    fn plan_names_the_statements_capturing_to_each_part() {
+        let program = parse(
+            r#"
+TEXT = l SPLITBY NEWLINE
+l = members " are " kind -> ADD item{} TO ROOT.results[]
+members = member SPLITBY ", "
+member = WORD -> ADD TO item.members[]
+kind = WORD -> ADD TO item
+count = DIGIT -> ADD count AS INT TO ROOT.total NULLABLE
+hosts = WORD -> ADD TO ROOT.hosts[kind]
+"#,
+        )
+        .unwrap();
+        let expected = [
+            "ROOT: object",
+            "  hosts: object",
+            "    [key]: string  <- hosts",
+            "  results: array",
+            "    []: object  <- l",
+            "      kind: string  <- kind",
+            "      members: array",
+            "        []: string  <- member",
+            "  total: integer or null  <- count",
+        ];
+        assert_eq!(
+            Plan::infer(&program).to_string(),
+            expected.join("\n") + "\n"
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use crate::ast::{AlternationBias, Pattern, PatternKind};
+use crate::visit::{walk_pattern, walk_pattern_mut, Visitor, VisitorMut};
+
+impl Pattern {
+    /// Rewrites the pattern into an equivalent one with fewer nodes: nested sequences and
+    /// alternations are flattened, adjacent literals joined and redundant groups removed.
+    ///
+    /// Nodes holding repetitions or variables are left in place, since how deep those are
+    /// nested decides which `LAZY`/`GREEDY` bias wins.
+    pub fn simplify(&mut self) {
+        Simplifier.visit_pattern_mut(self);
+    }
+
+    /// Whether moving this pattern to another depth could change which parse is preferred.
+    pub(crate) fn is_depth_sensitive(&self) -> bool {
+        let mut finder = DepthSensitive(false);
+        finder.visit_pattern(self);
+        finder.0
+    }
+}
+
+struct DepthSensitive(bool);
+
+impl Visitor<'_> for DepthSensitive {
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        match pattern.node {
+            PatternKind::Repetition { .. } | PatternKind::Variable(_) => self.0 = true,
+            PatternKind::OrChain { ref bias, .. } if *bias != AlternationBias::Neutral => {
+                self.0 = true
+            }
+            _ if !self.0 => walk_pattern(self, pattern),
+            _ => {}
+        }
+    }
+}
+
+/// Simplifies bottom-up, so children are already as small as they get.
+struct Simplifier;
+
+impl VisitorMut for Simplifier {
+    fn visit_pattern_mut(&mut self, pattern: &mut Pattern) {
+        walk_pattern_mut(self, pattern);
+        match &mut pattern.node {
+            PatternKind::Sequence(items) => {
+                let mut simplified: Vec<Pattern> = Vec::with_capacity(items.len());
+                for item in splice(std::mem::take(items), |node| {
+                    matches!(node, PatternKind::Sequence(_))
+                }) {
+                    match (simplified.last_mut(), &item.node) {
+                        (_, PatternKind::Literal(text)) if text.is_empty() => {}
+                        (
+                            Some(Pattern {
+                                node: PatternKind::Literal(previous),
+                                span,
+                            }),
+                            PatternKind::Literal(text),
+                        ) => {
+                            previous.push_str(text);
+                            span.end = item.span.end;
+                        }
+                        _ => simplified.push(item),
+                    }
+                }
+                if simplified.is_empty() {
+                    simplified.push(Pattern {
+                        node: PatternKind::Literal(String::new()),
+                        span: pattern.span.clone(),
+                    });
+                }
+                *items = simplified;
+            }
+            PatternKind::OrChain {
+                alternatives: items,
+                ..
+            } => {
+                *items = splice(std::mem::take(items), |node| {
+                    matches!(node, PatternKind::OrChain { .. })
+                });
+            }
+            _ => {}
+        }
+
+        let unwrapped = match &mut pattern.node {
+            PatternKind::Group(inner) if !inner.is_depth_sensitive() => {
+                Some(std::mem::replace(inner.as_mut(), empty()))
+            }
+            PatternKind::Sequence(items)
+            | PatternKind::OrChain {
+                alternatives: items,
+                ..
+            } if items.len() == 1 && !items[0].is_depth_sensitive() => items.pop(),
+            _ => None,
+        };
+        if let Some(inner) = unwrapped {
+            *pattern = inner;
+        }
+    }
+}
+
+/// Replaces the items of the same kind as their parent with their own items, when their
+/// depth doesn't matter.
+fn splice(items: Vec<Pattern>, same_kind: impl Fn(&PatternKind) -> bool) -> Vec<Pattern> {
+    let mut spliced = Vec::with_capacity(items.len());
+    for item in items {
+        if same_kind(&item.node) && !item.is_depth_sensitive() {
+            match item.node {
+                PatternKind::Sequence(inner)
+                | PatternKind::OrChain {
+                    alternatives: inner,
+                    ..
+                } => spliced.extend(inner),
+                _ => unreachable!("splice: item is not a sequence or alternation"),
+            }
+        } else {
+            spliced.push(item);
+        }
+    }
+    spliced
+}
+
+fn empty() -> Pattern {
+    Pattern {
+        node: PatternKind::Literal(String::new()),
+        span: 0..0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::{Builtin, PatternKind};
+    use crate::parser::parse;
+
+    fn simplified(source: &str) -> (PatternKind, PatternKind) {
+        let mut program = parse(source).unwrap();
+        let before = program.statements[0].pattern.node.clone();
+        program.statements[0].pattern.simplify();
+        (before, program.statements[0].pattern.node.clone())
+    }
+
+    #[test]
+    fn joins_and_flattens() {
+        let (_, after) = simplified("TEXT = \"a\" (\"b\" \"c\") \"\" \"d\"");
+        assert_eq!(after, PatternKind::Literal("abcd".to_string()));
+
+        let (_, after) = simplified("TEXT = \"a\" OR (\"b\" OR (DIGIT))");
+        let PatternKind::OrChain {
+            alternatives: items,
+            ..
+        } = after
+        else {
+            panic!("expected an alternation, got {:?}", after);
+        };
+        let items: Vec<_> = items.into_iter().map(|item| item.node).collect();
+        assert_eq!(
+            items,
+            vec![
+                PatternKind::Literal("a".to_string()),
+                PatternKind::Literal("b".to_string()),
+                PatternKind::Builtin(Builtin::Digit),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_nesting_around_repetitions_and_variables() {
+        for source in [
+            "TEXT = (GREEDY 0..N \"a\") (LAZY 0..N \"b\")",
+            "TEXT = \"(\" (x) \")\"\nx = DIGIT",
+        ] {
+            let (before, after) = simplified(source);
+            assert_eq!(before, after, "{}", source);
+        }
+    }
+}
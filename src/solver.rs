@@ -1,7 +1,23 @@
 use crate::ast::*;
-use crate::error::{NamedSourceExt, StrqlError, StrqlResult};
+use crate::cancel::CancellationToken;
+use crate::cast::apply_cast;
+use crate::custom::CustomBuiltins;
+use crate::error::{
+    query_to_named, AmbiguityBranch, AmbiguitySuggestion, NamedSourceExt, ParseCount, QueryNote,
+    StrqlError, StrqlResult,
+};
+use crate::lexer::Params;
+use crate::normalize::normalize_input;
+use crate::progress::Progress;
+use crate::scan;
+use crate::suggest::similar_names;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet};
+use std::ops::Range;
 use std::rc::Rc;
 
 type PatternId = usize;
@@ -52,7 +68,7 @@ impl Ord for Preference {
         for i in 0..len {
             let v1 = self.0.get(i).unwrap_or(&0);
             let v2 = other.0.get(i).unwrap_or(&0);
-            match v1.cmp(&v2) {
+            match v1.cmp(v2) {
                 std::cmp::Ordering::Equal => continue,
                 ord => return ord,
             }
@@ -68,13 +84,25 @@ struct Match {
     trace: MatchTrace,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 enum FlatPattern {
     Literal(String),
     Variable(PatternId),
     Builtin(Builtin),
+    /// a matcher from [`CustomBuiltins`], by name
+    Custom(String),
+    /// a word list from [`CustomBuiltins`], by name
+    Dict(String),
     Sequence(Vec<PatternId>),
-    Alternation(Vec<PatternId>),
+    /// each of the patterns once, in any order
+    Permute(Vec<PatternId>),
+    /// each of the patterns at most once, in any order, leaving out only nullable ones
+    Fields(Vec<PatternId>),
+    Alternation {
+        alternatives: Vec<PatternId>,
+        mode: AlternationBias,
+    },
     Quantifier {
         min: Bound,
         max: Bound,
@@ -85,13 +113,49 @@ enum FlatPattern {
     Upper(PatternId),
     Lower(PatternId),
     Group(PatternId),
+    /// a lookbehind of `pattern`, which matches `len` characters
+    PrecededBy {
+        pattern: PatternId,
+        len: usize,
+    },
+    /// the text up to the next match of the separator, output under `ROOT._unmatched[]`
+    Unmatched(PatternId),
+}
+
+impl FlatPattern {
+    fn children(&self) -> &[PatternId] {
+        match self {
+            FlatPattern::Sequence(ids)
+            | FlatPattern::Permute(ids)
+            | FlatPattern::Fields(ids)
+            | FlatPattern::Alternation {
+                alternatives: ids, ..
+            } => ids,
+            FlatPattern::Variable(id)
+            | FlatPattern::Quantifier { pattern: id, .. }
+            | FlatPattern::AnyCase(id)
+            | FlatPattern::Upper(id)
+            | FlatPattern::Lower(id)
+            | FlatPattern::Group(id)
+            | FlatPattern::PrecededBy { pattern: id, .. }
+            | FlatPattern::Unmatched(id) => std::slice::from_ref(id),
+            FlatPattern::Literal(_)
+            | FlatPattern::Builtin(_)
+            | FlatPattern::Custom(_)
+            | FlatPattern::Dict(_) => &[],
+        }
+    }
 }
 
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 struct FlatStatement {
     name: String,
     pattern: FlatPattern,
     capture: Option<CaptureClause>,
     depth: usize,
+    /// where the pattern is written in the query
+    span: Range<usize>,
 }
 
 #[derive(Debug, Clone)]
@@ -100,10 +164,104 @@ enum MatchOutcome {
     Ambiguous {
         best_score: i64,
         best_preference: Preference,
+        ambiguity: Ambiguity,
     },
 }
 
+/// Tied derivations are counted up to this many.
+const MAX_COUNTED_PARSES: usize = 10_000;
+/// At most this many branch points are reported for an ambiguous parse.
+const MAX_REPORTED_BRANCHES: usize = 8;
+
+/// What is known about the equally-preferred derivations of an ambiguous match.
+#[derive(Debug, Clone)]
+struct Ambiguity {
+    /// number of derivations, saturating at [`MAX_COUNTED_PARSES`]
+    count: usize,
+    /// the matches that have several derivations, ordered by position
+    branches: Vec<Branch>,
+}
+
+/// A pattern matching `start..end` in more than one equally-preferred way.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Branch {
+    start: usize,
+    end: usize,
+    pattern: PatternId,
+}
+
+impl Ambiguity {
+    fn merge_branches(&mut self, other: &[Branch]) {
+        self.branches.extend_from_slice(other);
+        self.branches.sort();
+        self.branches.dedup();
+        self.branches.truncate(MAX_REPORTED_BRANCHES);
+    }
+}
+
 impl MatchOutcome {
+    fn score(&self) -> i64 {
+        match self {
+            MatchOutcome::Unique(m) => m.score,
+            MatchOutcome::Ambiguous { best_score, .. } => *best_score,
+        }
+    }
+
+    fn preference(&self) -> &Preference {
+        match self {
+            MatchOutcome::Unique(m) => &m.preference,
+            MatchOutcome::Ambiguous {
+                best_preference, ..
+            } => best_preference,
+        }
+    }
+
+    fn preference_mut(&mut self) -> &mut Preference {
+        match self {
+            MatchOutcome::Unique(m) => &mut m.preference,
+            MatchOutcome::Ambiguous {
+                best_preference, ..
+            } => best_preference,
+        }
+    }
+
+    fn parse_count(&self) -> usize {
+        match self {
+            MatchOutcome::Unique(_) => 1,
+            MatchOutcome::Ambiguous { ambiguity, .. } => ambiguity.count,
+        }
+    }
+
+    fn branches(&self) -> &[Branch] {
+        match self {
+            MatchOutcome::Unique(_) => &[],
+            MatchOutcome::Ambiguous { ambiguity, .. } => &ambiguity.branches,
+        }
+    }
+
+    /// `extendee` followed by `sub`.
+    fn extend(extendee: &Self, sub: &Self) -> Self {
+        let ambiguity = match extendee {
+            MatchOutcome::Unique(m) => return Self::extending_with_sub(m, sub),
+            MatchOutcome::Ambiguous { ambiguity, .. } => ambiguity,
+        };
+        let mut preference = extendee.preference().clone();
+        preference.combine(sub.preference());
+        let mut ambiguity = Ambiguity {
+            count: ambiguity
+                .count
+                .saturating_mul(sub.parse_count())
+                .min(MAX_COUNTED_PARSES),
+            branches: ambiguity.branches.clone(),
+        };
+        ambiguity.merge_branches(sub.branches());
+        MatchOutcome::Ambiguous {
+            best_score: extendee.score() + sub.score(),
+            best_preference: preference,
+            ambiguity,
+        }
+    }
+
     fn extending_with_sub(extendee: &Match, sub: &Self) -> Self {
         match sub {
             MatchOutcome::Unique(sm) => {
@@ -120,53 +278,64 @@ impl MatchOutcome {
             MatchOutcome::Ambiguous {
                 best_score: bs,
                 best_preference: bp,
+                ambiguity,
             } => {
                 let mut new_pref = extendee.preference.clone();
                 new_pref.combine(bp);
                 MatchOutcome::Ambiguous {
                     best_score: extendee.score + bs,
                     best_preference: new_pref,
+                    ambiguity: ambiguity.clone(),
                 }
             }
         }
     }
+
+    /// Combines two equally-preferred outcomes of `branch` into an ambiguous one.
+    fn tie(&self, other: &Self, branch: Branch) -> Self {
+        let mut ambiguity = Ambiguity {
+            count: self
+                .parse_count()
+                .saturating_add(other.parse_count())
+                .min(MAX_COUNTED_PARSES),
+            branches: vec![branch],
+        };
+        ambiguity.merge_branches(self.branches());
+        ambiguity.merge_branches(other.branches());
+        MatchOutcome::Ambiguous {
+            best_score: self.score(),
+            best_preference: self.preference().clone(),
+            ambiguity,
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 struct MatchMap {
-    data: Vec<Option<MatchOutcome>>,
+    /// outcomes by the position they end at
+    data: HashMap<usize, MatchOutcome>,
+    /// keys of `data`, in insertion order
     active: Vec<usize>,
 }
 
 impl MatchMap {
-    fn new(len: usize) -> Self {
-        Self {
-            data: vec![None; len + 1],
-            active: Vec::new(),
-        }
+    fn get(&self, pos: usize) -> Option<&MatchOutcome> {
+        self.data.get(&pos)
     }
 
-    fn get(&self, pos: usize) -> Option<&MatchOutcome> {
-        if pos < self.data.len() {
-            self.data[pos].as_ref()
-        } else {
-            None
+    fn insert(&mut self, pos: usize, outcome: MatchOutcome) {
+        if self.data.insert(pos, outcome).is_none() {
+            self.active.push(pos);
         }
     }
 
     fn iter(&self) -> impl Iterator<Item = (&usize, &MatchOutcome)> {
-        // Invariant: all indices in active must have Some value in data
-        #[cfg(debug_assertions)]
-        for &idx in &self.active {
-            debug_assert!(
-                idx < self.data.len() && self.data[idx].is_some(),
-                "MatchMap invariant violated: active index {} has no data",
-                idx
-            );
-        }
-        self.active
-            .iter()
-            .map(|i| (i, self.data[*i].as_ref().unwrap()))
+        self.active.iter().map(|i| (i, &self.data[i]))
+    }
+
+    fn retain(&mut self, keep: impl Fn(usize) -> bool) {
+        self.data.retain(|&pos, _| keep(pos));
+        self.active.retain(|&pos| keep(pos));
     }
 }
 
@@ -176,7 +345,7 @@ enum VResult {
     Matches(Rc<MatchMap>),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
 enum CaseMode {
     #[default]
     Normal,
@@ -185,17 +354,218 @@ enum CaseMode {
     Lower,
 }
 
-pub struct Solver<'a> {
-    input: &'a str,
+const CASE_MODES: usize = 4;
+
+/// The results of [`Solver::viterbi`] in one case mode, indexed by
+/// `id * (input.len() + 1) + pos`. Allocated the first time the mode is used.
+#[derive(Default)]
+struct MemoTable {
+    results: Vec<VResult>,
+    /// which results are computed
+    set: Vec<bool>,
+}
+
+impl MemoTable {
+    fn get(&self, idx: usize) -> Option<&VResult> {
+        self.set
+            .get(idx)
+            .filter(|&&set| set)
+            .map(|_| &self.results[idx])
+    }
+}
 
-    indexed_statements: Vec<FlatStatement>,
+/// Identical sub-patterns flattened so far, so that they share one memo row.
+#[derive(Default)]
+struct Interner {
+    shared: HashMap<FlatPattern, PatternId>,
+    /// the patterns whose depth doesn't matter
+    shareable: HashSet<PatternId>,
+}
+
+/// What a match of a pattern can begin with, to skip positions where none can start.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct Lookahead {
+    nullable: bool,
+    /// bit `b` is set when a match can start with byte `b`
+    first: [u64; 4],
+}
+
+impl Lookahead {
+    fn add(&mut self, byte: u8) {
+        self.first[byte as usize / 64] |= 1 << (byte % 64);
+    }
+
+    fn add_all(&mut self, bytes: impl IntoIterator<Item = u8>) {
+        for byte in bytes {
+            self.add(byte);
+        }
+    }
+
+    /// Adds `other`'s first bytes, reporting whether any were new.
+    fn union(&mut self, other: &Lookahead) -> bool {
+        let mut changed = false;
+        for (mine, theirs) in self.first.iter_mut().zip(other.first) {
+            changed |= *mine | theirs != *mine;
+            *mine |= theirs;
+        }
+        changed
+    }
+
+    fn overlaps(&self, other: &Lookahead) -> bool {
+        self.first
+            .iter()
+            .zip(other.first)
+            .any(|(mine, theirs)| mine & theirs != 0)
+    }
+
+    /// How many bytes a match can start with.
+    fn width(&self) -> u32 {
+        self.first.iter().map(|bits| bits.count_ones()).sum()
+    }
+
+    /// Whether a match can start at the beginning of `rest`.
+    fn can_start(&self, rest: &[u8]) -> bool {
+        self.nullable
+            || rest
+                .first()
+                .is_some_and(|&b| self.first[b as usize / 64] & (1 << (b % 64)) != 0)
+    }
+}
+
+/// Bumped whenever the serialized layout of [`CompiledQuery`] changes.
+#[cfg(feature = "serde")]
+const COMPILED_FORMAT_VERSION: u32 = 26;
+
+/// A program flattened into indexed patterns, reusable across any number of inputs.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CompiledQuery {
+    statements: Vec<FlatStatement>,
     pattern_ids: HashMap<String, PatternId>,
+    max_preference_depth: usize,
+    options: QueryOptions,
+    /// the query text, for errors pointing into it
+    source: String,
+    /// indexed by pattern
+    lookahead: Vec<Lookahead>,
+    /// alternations of which at most one alternative can start at any position, indexed by
+    /// pattern; only found with `OPTION REORDER_ALTERNATIVES`
+    exclusive: Vec<bool>,
+    /// repetitions of a builtin matching one character, matched by the length of the run
+    /// of them; indexed by pattern
+    char_runs: Vec<bool>,
+    /// literals every input matching `TEXT` contains
+    required: Vec<String>,
+    /// matchers aren't serialized, so deserialized queries have none
+    #[cfg_attr(feature = "serde", serde(skip))]
+    custom: CustomBuiltins,
+}
+
+pub struct Solver<'a> {
+    input: &'a str,
+    query: &'a CompiledQuery,
 
-    memo: Vec<VResult>,  // size: indexed_statements.len() * (input.len() + 1)
-    memo_set: Vec<bool>, // tracking which memo entries are valid
+    /// one table per case mode, since a pattern can match differently in each
+    memo: [MemoTable; CASE_MODES],
     case_mode: CaseMode,
+    /// when false, no trace events are recorded (validation-only runs)
+    track_captures: bool,
+    /// statements whose matches are looked up by `[name]` keys, indexed by statement
+    keyed: Vec<bool>,
+    /// how far into the input a literal matched, even partially
+    furthest_literal: usize,
+    cancellation: Option<CancellationToken>,
+    options: SolverOptions,
+    /// whether the input holds one character per byte, see [`CompiledQuery::evaluate_bytes`]
+    binary: bool,
+    /// for inputs joined from segments, whether each position is where a segment starts or
+    /// ends; see [`CompiledQuery::evaluate_segments`]
+    boundaries: Option<Vec<bool>>,
+}
 
-    max_preference_depth: usize,
+/// One step of the path from ROOT to where a capture is stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathStep {
+    Field(String),
+    Index(usize),
+}
+
+/// A capture as it is replayed; see [`CompiledQuery::evaluate_with_sink`].
+#[derive(Debug, Clone, Copy)]
+pub struct CaptureEventView<'e> {
+    /// where the output would store the value
+    pub path: &'e [PathStep],
+    /// the value after casts: `{}` for `item{}` captures, and `null` for empty `NULLABLE` ones
+    pub value: &'e Value,
+    /// the bytes of the input the value was captured from
+    pub span: &'e Range<usize>,
+    /// the statement that captured it
+    pub statement: &'e str,
+}
+
+/// The output of an evaluation along with how the input matched; see
+/// [`CompiledQuery::evaluate_match`].
+#[derive(Debug, Clone)]
+pub struct MatchResult {
+    value: Value,
+    captures: Vec<CaptureSpan>,
+    stats: MatchStats,
+}
+
+/// Where a value of the output was captured from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureSpan {
+    /// where the output stores the value
+    pub path: Vec<PathStep>,
+    /// the bytes of the input the value was captured from
+    pub span: Range<usize>,
+    /// the statement that captured it
+    pub statement: String,
+}
+
+/// How much work an evaluation took.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub struct MatchStats {
+    /// the matches of a statement at a position the solver worked out
+    pub memo_entries: usize,
+    /// the statement matches and captures the parse is made of
+    pub trace_events: usize,
+}
+
+impl MatchResult {
+    /// The output, as [`CompiledQuery::evaluate`] returns it.
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+
+    pub fn into_json(self) -> Value {
+        self.value
+    }
+
+    /// Every capture, in the order the output is built in, with the path of `value` that holds
+    /// what it captured (after `UNIQUE`, `SORTED` and `GROUP BY` are applied).
+    pub fn captures(&self) -> &[CaptureSpan] {
+        &self.captures
+    }
+
+    pub fn stats(&self) -> MatchStats {
+        self.stats
+    }
+}
+
+/// Settings of a single evaluation, which unlike `OPTION`s leave the query itself unchanged.
+#[derive(Debug, Clone, Default)]
+pub struct SolverOptions {
+    /// wrap every captured value as `{"value": ..., "rule": "<statement>"}`, naming the
+    /// statement that captured it
+    pub provenance: bool,
+    /// where to report how far into the input matching got, for progress bars
+    pub progress: Option<Progress>,
+    /// makes evaluation stop with [`StrqlError::Cancelled`] once it is cancelled, like
+    /// [`CompiledQuery::evaluate_cancellable`]
+    pub cancellation: Option<CancellationToken>,
 }
 
 impl VResult {
@@ -216,151 +586,442 @@ impl VResult {
             max_preference_depth > 0,
             "VResult::single: max_preference_depth must be > 0"
         );
-        let mut matches = MatchMap::new(input_len);
-        matches.data[next_pos] = Some(MatchOutcome::Unique(Match {
-            score,
-            preference: Preference::with_size(max_preference_depth),
-            trace,
-        }));
-        matches.active.push(next_pos);
+        let mut matches = MatchMap::default();
+        matches.insert(
+            next_pos,
+            MatchOutcome::Unique(Match {
+                score,
+                preference: Preference::with_size(max_preference_depth),
+                trace,
+            }),
+        );
         VResult::Matches(Rc::new(matches))
     }
 }
 
-impl<'a> NamedSourceExt<'a> for Solver<'a> {
-    fn src(&self) -> &'a str {
-        self.input
+impl CompiledQuery {
+    /// Parses and compiles a query source.
+    pub fn compile(source: &str) -> StrqlResult<Self> {
+        Self::new(&crate::parser::parse(source)?)
     }
 
-    fn source_name(&self) -> &str {
-        "input"
+    /// Like [`CompiledQuery::compile`], for queries whose literals use `${NAME}` parameters.
+    pub fn compile_with_params(source: &str, params: &Params) -> StrqlResult<Self> {
+        Self::new(&crate::parser::parse_with_params(source, params)?)
     }
-}
 
-impl<'a> Solver<'a> {
-    fn merge_outcome(map: &mut MatchMap, next_pos: usize, new_outcome: MatchOutcome) {
-        debug_assert!(
-            next_pos < map.data.len(),
-            "merge_outcome: next_pos {} out of bounds (data.len={})",
-            next_pos,
-            map.data.len()
-        );
-        if let Some(existing) = &mut map.data[next_pos] {
-            let existing_score = match existing {
-                MatchOutcome::Unique(m) => m.score,
-                MatchOutcome::Ambiguous { best_score, .. } => *best_score,
-            };
-            let new_score = match &new_outcome {
-                MatchOutcome::Unique(m) => m.score,
-                MatchOutcome::Ambiguous { best_score, .. } => *best_score,
-            };
+    /// Like [`CompiledQuery::compile`], for queries using the matchers of `builtins`.
+    pub fn compile_with(source: &str, builtins: &CustomBuiltins) -> StrqlResult<Self> {
+        Self::with_builtins(&crate::parser::parse(source)?, builtins)
+    }
 
-            if new_score > existing_score {
-                *existing = new_outcome;
-                return;
-            }
+    /// Compiles `pattern` on its own, as the `TEXT` of a query with no other statements, e.g.
+    /// to compare a pattern built by hand with the one a query spells.
+    pub fn from_pattern(pattern: &Pattern) -> StrqlResult<Self> {
+        Self::new(&Program {
+            statements: vec![Statement {
+                name: "TEXT".to_string(),
+                pattern: pattern.clone(),
+                capture: None,
+                span: pattern.span.clone(),
+                doc: None,
+                extends: false,
+            }],
+            options: QueryOptions::default(),
+            source: String::new(),
+        })
+    }
 
-            if new_score < existing_score {
-                return;
-            }
+    pub fn evaluate(&self, input: &str) -> StrqlResult<Value> {
+        let input = normalize_input(&self.options, input);
+        Solver::from_compiled(self).solve(&input)
+    }
 
-            // Scores are equal, check preferences
-            let existing_pref = match existing {
-                MatchOutcome::Unique(m) => &m.preference,
-                MatchOutcome::Ambiguous {
-                    best_preference, ..
-                } => best_preference,
-            };
-            let new_pref = match &new_outcome {
-                MatchOutcome::Unique(m) => &m.preference,
-                MatchOutcome::Ambiguous {
-                    best_preference, ..
-                } => best_preference,
-            };
+    /// See [`crate::matches`].
+    pub fn matches(&self, input: &str) -> StrqlResult<bool> {
+        self.matches_with(input, &SolverOptions::default())
+    }
 
-            if new_pref > existing_pref {
-                *existing = new_outcome;
-            } else if new_pref == existing_pref {
-                let best_pref = match &new_outcome {
-                    MatchOutcome::Unique(m) => m.preference.clone(),
-                    MatchOutcome::Ambiguous {
-                        best_preference, ..
-                    } => best_preference.clone(),
-                };
-                *existing = MatchOutcome::Ambiguous {
-                    best_score: new_score,
-                    best_preference: best_pref,
-                };
-            }
-        } else {
-            map.data[next_pos] = Some(new_outcome);
-            map.active.push(next_pos);
+    /// Like [`Self::matches`], with the settings of [`Self::evaluate_with`].
+    pub fn matches_with(&self, input: &str, options: &SolverOptions) -> StrqlResult<bool> {
+        let input = normalize_input(&self.options, input);
+        Solver::from_compiled(self)
+            .with_options(options.clone())
+            .matches(&input)
+    }
+
+    /// Like [`Self::evaluate`], along with where each value was captured from and how much
+    /// work matching took.
+    pub fn evaluate_match(&self, input: &str) -> StrqlResult<MatchResult> {
+        self.evaluate_match_with(input, &SolverOptions::default())
+    }
+
+    /// Like [`Self::evaluate_match`], with the settings of [`Self::evaluate_with`].
+    pub fn evaluate_match_with(
+        &self,
+        input: &str,
+        options: &SolverOptions,
+    ) -> StrqlResult<MatchResult> {
+        let input = normalize_input(&self.options, input);
+        let mut solver = Solver::from_compiled(self).with_options(options.clone());
+        solver.track_captures = true;
+        let m = solver.find_unique_match(&input)?;
+        let mut captures = Vec::new();
+        let value = solver.build_output(&input, &m.trace, None, Some(&mut captures))?;
+        Ok(MatchResult {
+            value,
+            captures,
+            stats: MatchStats {
+                memo_entries: solver
+                    .memo
+                    .iter()
+                    .map(|table| table.set.iter().filter(|&&set| set).count())
+                    .sum(),
+                trace_events: m.trace.len(),
+            },
+        })
+    }
+
+    /// Like [`Self::evaluate`], but gives up with [`StrqlError::Cancelled`] once `token` is cancelled.
+    pub fn evaluate_cancellable(
+        &self,
+        input: &str,
+        token: &CancellationToken,
+    ) -> StrqlResult<Value> {
+        let input = normalize_input(&self.options, input);
+        Solver::from_compiled(self)
+            .with_cancellation(token.clone())
+            .solve(&input)
+    }
+
+    /// Like [`Self::evaluate`], with settings that change how the output is built.
+    pub fn evaluate_with(&self, input: &str, options: &SolverOptions) -> StrqlResult<Value> {
+        let input = normalize_input(&self.options, input);
+        Solver::from_compiled(self)
+            .with_options(options.clone())
+            .solve(&input)
+    }
+
+    /// Like [`Self::evaluate`], for inputs mixing text with binary data.
+    ///
+    /// Each byte of `input` is matched as one character (as if it were Latin-1 text), so
+    /// `BYTE(0xFF)` and `BYTES(4)` match single and counted bytes, and ASCII text matches as
+    /// usual. Captured bytes that are valid UTF-8 are stored as strings, others as arrays of
+    /// byte values.
+    pub fn evaluate_bytes(&self, input: &[u8]) -> StrqlResult<Value> {
+        let input = crate::bytes::decode(input);
+        let input = normalize_input(&self.options, &input);
+        let mut solver = Solver::from_compiled(self);
+        solver.binary = true;
+        let mut output = solver.solve(&input)?;
+        crate::bytes::encode_captures(&mut output);
+        Ok(output)
+    }
+
+    /// Like [`Self::evaluate`], handing every element of the array at `array` (like
+    /// `ROOT.items[]`, or just `items`) to `on_record` as soon as replay completes it, instead
+    /// of keeping it in the output. Returns the rest of the output.
+    ///
+    /// Elements are complete once the next one is added, so memory stays bounded by the
+    /// largest element however many there are. They are handed over in the order they are
+    /// matched, ignoring the `SORTED` and `GROUP` of the array.
+    pub fn evaluate_streaming(
+        &self,
+        input: &str,
+        array: &str,
+        on_record: impl FnMut(Value),
+    ) -> StrqlResult<Value> {
+        self.evaluate_streaming_with(input, array, &SolverOptions::default(), on_record)
+    }
+
+    /// Like [`Self::evaluate_streaming`], with the settings of [`Self::evaluate_with`].
+    pub fn evaluate_streaming_with(
+        &self,
+        input: &str,
+        array: &str,
+        options: &SolverOptions,
+        mut on_record: impl FnMut(Value),
+    ) -> StrqlResult<Value> {
+        let array = array.strip_prefix("ROOT.").unwrap_or(array);
+        let array = array.strip_suffix("[]").unwrap_or(array);
+        let mut stream = Stream {
+            path: std::iter::once(ResolvedSegment::Root)
+                .chain(
+                    array
+                        .split('.')
+                        .map(|f| ResolvedSegment::Field(f.to_string())),
+                )
+                .collect(),
+            on_record: &mut on_record,
+            emitted: 0,
+        };
+        let input = normalize_input(&self.options, input);
+        Solver::from_compiled(self)
+            .with_options(options.clone())
+            .solve_streaming(&input, Some(&mut stream))
+    }
+
+    /// Like [`Self::evaluate`], handing every capture to `sink` as it is replayed instead of
+    /// building the output, so that hosts can write values straight to a database or a
+    /// channel.
+    ///
+    /// Captures come in the order the output would be built in, with the paths it would
+    /// store them at; what only applies to the whole output (`UNIQUE`, `SORTED`, `GROUP` and
+    /// the nulls of absent `NULLABLE` fields) is left to the host.
+    pub fn evaluate_with_sink(
+        &self,
+        input: &str,
+        mut sink: impl FnMut(CaptureEventView<'_>),
+    ) -> StrqlResult<()> {
+        let input = normalize_input(&self.options, input);
+        let mut solver = Solver::from_compiled(self);
+        solver.track_captures = true;
+        let m = solver.find_unique_match(&input)?;
+        solver.replay_into_sink(&m.trace, &mut sink)
+    }
+
+    /// Like [`Self::evaluate`], for inputs already split into segments, like the fields of a
+    /// log record split upstream.
+    ///
+    /// Statements match whole segments: each starts where a segment starts and ends where
+    /// one ends, possibly spanning several, while literals and builtins never match across
+    /// segments. Statements are thus only tried at the start of segments. Errors point into
+    /// the segments joined without separators.
+    pub fn evaluate_segments(&self, segments: &[&str]) -> StrqlResult<Value> {
+        let mut input = String::new();
+        let mut ends = vec![0];
+        for segment in segments {
+            input.push_str(&normalize_input(&self.options, segment));
+            ends.push(input.len());
+        }
+        let mut boundaries = vec![false; input.len() + 1];
+        for end in ends {
+            boundaries[end] = true;
+        }
+        let mut solver = Solver::from_compiled(self);
+        solver.boundaries = Some(boundaries);
+        solver.solve(&input)
+    }
+
+    /// Like [`Self::evaluate`], deserializing the output into `T`.
+    #[cfg(feature = "serde")]
+    pub fn evaluate_into<T: serde::de::DeserializeOwned>(&self, input: &str) -> StrqlResult<T> {
+        serde_json::from_value(self.evaluate(input)?).map_err(|e| StrqlError::OutputMismatch {
+            _message: e.to_string(),
+        })
+    }
+
+    /// The `OPTION`s the query declares.
+    pub fn options(&self) -> &QueryOptions {
+        &self.options
+    }
+
+    /// Serializes the compiled query into a compact binary blob, loadable with [`Self::from_bytes`].
+    #[cfg(feature = "serde")]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(&(COMPILED_FORMAT_VERSION, self))
+            .expect("compiled queries only contain serializable data")
+    }
+
+    /// Loads a blob produced by [`Self::to_bytes`], without re-parsing the query.
+    #[cfg(feature = "serde")]
+    pub fn from_bytes(bytes: &[u8]) -> StrqlResult<Self> {
+        let (version, query): (u32, Self) =
+            bincode::deserialize(bytes).map_err(|e| StrqlError::InvalidCompiledQuery {
+                _message: e.to_string(),
+            })?;
+        if version != COMPILED_FORMAT_VERSION {
+            return Err(StrqlError::InvalidCompiledQuery {
+                _message: format!(
+                    "blob has format version {}, expected {}",
+                    version, COMPILED_FORMAT_VERSION
+                ),
+            });
         }
+        Ok(query)
     }
 
-    pub fn new(program: &'a Program) -> StrqlResult<Self> {
+    /// Flattens `program` into indexed patterns and resolves every variable reference.
+    pub fn new(program: &Program) -> StrqlResult<Self> {
+        Self::with_builtins(program, &CustomBuiltins::default())
+    }
+
+    /// Like [`CompiledQuery::new`], resolving the names no statement defines among `builtins`.
+    pub fn with_builtins(program: &Program, builtins: &CustomBuiltins) -> StrqlResult<Self> {
+        if let Some(stmt) = program.statements.iter().find(|s| s.extends) {
+            return Err(StrqlError::UndefinedExtension {
+                _name: stmt.name.clone(),
+                _src: query_to_named(&program.source),
+                _span: stmt.span.clone().into(),
+            });
+        }
         let mut name_to_id = HashMap::new();
         for (i, stmt) in program.statements.iter().enumerate() {
             name_to_id.insert(stmt.name.clone(), i);
         }
 
+        let patterns = program.variable_map();
         let mut indexed_statements = Vec::new();
         for stmt in &program.statements {
+            let mut capture = stmt.capture.clone();
+            if let Some(CaptureClause {
+                cast:
+                    Some(CaptureCast::Int {
+                        radix: radix @ None,
+                    }),
+                ..
+            }) = &mut capture
+            {
+                *radix = number_radix(&stmt.pattern, &patterns);
+            }
             indexed_statements.push(FlatStatement {
                 name: stmt.name.clone(),
                 pattern: FlatPattern::Builtin(Builtin::AnyChar), // placeholder
-                capture: stmt.capture.clone(),
+                capture,
                 depth: 0,
+                span: stmt.span.clone(),
             });
         }
 
-        let mut solver = Self {
-            input: "",
-            indexed_statements,
-            pattern_ids: name_to_id.clone(),
-            memo: Vec::new(),
-            memo_set: Vec::new(),
-            case_mode: CaseMode::Normal,
+        let mut query = Self {
+            statements: indexed_statements,
+            pattern_ids: name_to_id,
             max_preference_depth: 0,
+            options: program.options.clone(),
+            source: program.source.clone(),
+            lookahead: Vec::new(),
+            exclusive: Vec::new(),
+            char_runs: Vec::new(),
+            required: Vec::new(),
+            custom: builtins.clone(),
         };
 
+        let mut interner = Interner::default();
         for (i, stmt) in program.statements.iter().enumerate() {
-            let flat_id = solver.flatten_pattern(&stmt.pattern)?;
-            solver.indexed_statements[i].pattern = FlatPattern::Variable(flat_id);
+            let mut pattern = stmt.pattern.clone();
+            pattern.simplify();
+            let flat_id = query.flatten_pattern(&pattern, &mut interner)?;
+            query.statements[i].pattern = FlatPattern::Variable(flat_id);
         }
 
-        solver.compute_depths();
-        Ok(solver)
+        query.measure_lookbehinds()?;
+        query.compute_depths();
+        let nullable = query.nullable();
+        query.check_left_recursion(&nullable)?;
+        query.lookahead = query.lookahead(&nullable);
+        query.exclusive = vec![false; query.statements.len()];
+        query.char_runs = query
+            .statements
+            .iter()
+            .map(|stmt| match &stmt.pattern {
+                FlatPattern::Quantifier { pattern, .. } => matches!(
+                    &query.statements[*pattern].pattern,
+                    FlatPattern::Builtin(builtin) if matches_char(builtin, '\0', CaseMode::Normal, false).is_some()
+                ),
+                _ => false,
+            })
+            .collect();
+        if query.options.reorder_alternatives {
+            query.reorder_alternatives();
+        }
+        // under `OPTION ANYCASE` every literal matches in other cases too
+        let text_id = query
+            .pattern_ids
+            .get("TEXT")
+            .filter(|_| !query.options.anycase);
+        if let Some(&text_id) = text_id {
+            let mut found = vec![None; query.statements.len()];
+            let mut required = query.required_literals(text_id, &mut found);
+            // a literal found inside another needs no search of its own
+            let all = required.clone();
+            required.retain(|l| {
+                !all.iter()
+                    .any(|other| other != l && other.contains(l.as_str()))
+            });
+            query.required = required.into_iter().collect();
+        }
+        Ok(query)
     }
 
-    fn flatten_pattern(&mut self, p: &Pattern) -> StrqlResult<PatternId> {
+    fn flatten_pattern(&mut self, p: &Pattern, interner: &mut Interner) -> StrqlResult<PatternId> {
         let flat = match &p.node {
             PatternKind::Literal(s) => FlatPattern::Literal(s.clone()),
+            PatternKind::Variable(name)
+                if !self.pattern_ids.contains_key(name) && self.custom.get(name).is_some() =>
+            {
+                FlatPattern::Custom(name.clone())
+            }
             PatternKind::Variable(name) => {
                 return if let Some(&id) = self.pattern_ids.get(name) {
                     Ok(id)
                 } else {
+                    let names = self.pattern_ids.keys().map(String::as_str);
+                    let suggestions = similar_names(name, names.chain(self.custom.names()));
+                    let help = match suggestions.as_slice() {
+                        [] => format!("Define it with a statement: `{} = ...`", name),
+                        [only] => format!("Did you mean `{}`?", only),
+                        [rest @ .., last] => format!(
+                            "Did you mean {} or `{}`?",
+                            rest.iter()
+                                .map(|s| format!("`{}`", s))
+                                .collect::<Vec<_>>()
+                                .join(", "),
+                            last
+                        ),
+                    };
                     Err(StrqlError::UnboundVariable {
                         _name: name.clone(),
-                        _src: self.src_to_named(),
+                        _suggestions: suggestions,
+                        _help: help,
+                        _src: query_to_named(&self.source),
                         _span: p.span.clone().into(),
                     })
                 }
             }
             PatternKind::Builtin(b) => FlatPattern::Builtin(b.clone()),
+            PatternKind::Dict(name) => {
+                if self.custom.dict(name).is_none() {
+                    return Err(StrqlError::UnknownDict {
+                        _name: name.clone(),
+                        _src: query_to_named(&self.source),
+                        _span: p.span.clone().into(),
+                    });
+                }
+                FlatPattern::Dict(name.clone())
+            }
             PatternKind::Sequence(seq) => {
                 let ids = seq
                     .iter()
-                    .map(|child| self.flatten_pattern(child))
+                    .map(|child| self.flatten_pattern(child, interner))
                     .collect::<StrqlResult<Vec<_>>>()?;
                 FlatPattern::Sequence(ids)
             }
-            PatternKind::OrChain(alts) => {
-                let ids = alts
+            PatternKind::Permute(items) => {
+                let ids = items
+                    .iter()
+                    .map(|child| self.flatten_pattern(child, interner))
+                    .collect::<StrqlResult<Vec<_>>>()?;
+                FlatPattern::Permute(ids)
+            }
+            PatternKind::Fields(items) => {
+                let ids = items
+                    .iter()
+                    .map(|child| self.flatten_pattern(child, interner))
+                    .collect::<StrqlResult<Vec<_>>>()?;
+                FlatPattern::Fields(ids)
+            }
+            PatternKind::OrChain { alternatives, bias } => {
+                let ids = alternatives
                     .iter()
-                    .map(|child| self.flatten_pattern(child))
+                    .map(|child| self.flatten_pattern(child, interner))
                     .collect::<StrqlResult<Vec<_>>>()?;
-                FlatPattern::Alternation(ids)
+                FlatPattern::Alternation {
+                    alternatives: ids,
+                    mode: match (bias, self.options.strategy.unwrap_or_default()) {
+                        (AlternationBias::Neutral, Strategy::Peg) => AlternationBias::First,
+                        (bias, _) => bias.clone(),
+                    },
+                }
             }
             PatternKind::Repetition {
                 min,
@@ -368,94 +1029,521 @@ impl<'a> Solver<'a> {
                 pattern,
                 bias: mode,
             } => {
-                let id = self.flatten_pattern(pattern)?;
+                if let (Some(min), Some(max)) = (min, max) {
+                    if min > max {
+                        return Err(StrqlError::InvalidQuantifierBounds {
+                            _min: *min,
+                            _max: *max,
+                            _src: query_to_named(&self.source),
+                            _span: p.span.clone().into(),
+                        });
+                    }
+                }
+                let id = self.flatten_pattern(pattern, interner)?;
                 FlatPattern::Quantifier {
-                    min: min.clone(),
-                    max: max.clone(),
+                    min: *min,
+                    max: *max,
                     pattern: id,
-                    mode: *mode,
+                    mode: match (mode, self.options.strategy.unwrap_or_default()) {
+                        (QuantifierBias::Neutral, Strategy::Peg) => QuantifierBias::Greedy,
+                        (mode, _) => *mode,
+                    },
                 }
             }
             PatternKind::AnyCase(inner) => {
-                let id = self.flatten_pattern(inner)?;
+                let id = self.flatten_pattern(inner, interner)?;
                 FlatPattern::AnyCase(id)
             }
             PatternKind::Upper(inner) => {
-                let id = self.flatten_pattern(inner)?;
+                let id = self.flatten_pattern(inner, interner)?;
                 FlatPattern::Upper(id)
             }
             PatternKind::Lower(inner) => {
-                let id = self.flatten_pattern(inner)?;
+                let id = self.flatten_pattern(inner, interner)?;
                 FlatPattern::Lower(id)
             }
             PatternKind::Group(inner) => {
-                let id = self.flatten_pattern(inner)?;
+                let id = self.flatten_pattern(inner, interner)?;
                 FlatPattern::Group(id)
             }
+            PatternKind::PrecededBy(inner) => {
+                let id = self.flatten_pattern(inner, interner)?;
+                // the length is known once every statement is flattened
+                FlatPattern::PrecededBy {
+                    pattern: id,
+                    len: 0,
+                }
+            }
+            PatternKind::Unmatched(separator) => {
+                FlatPattern::Unmatched(self.flatten_pattern(separator, interner)?)
+            }
         };
 
-        let id = self.indexed_statements.len();
-        self.indexed_statements.push(FlatStatement {
+        // the depth of repetitions and biased alternations decides between their biases, so
+        // only the patterns without any (nor variables, which may hold some) are shared
+        let shareable = !matches!(
+            flat,
+            FlatPattern::Quantifier { .. }
+                | FlatPattern::Alternation {
+                    mode: AlternationBias::First
+                        | AlternationBias::Longest
+                        | AlternationBias::Weighted(_),
+                    ..
+                }
+        ) && flat
+            .children()
+            .iter()
+            .all(|child| interner.shareable.contains(child));
+        if shareable {
+            if let Some(&id) = interner.shared.get(&flat) {
+                return Ok(id);
+            }
+        }
+
+        let id = self.statements.len();
+        self.statements.push(FlatStatement {
             name: String::new(),
-            pattern: flat,
+            pattern: flat.clone(),
             capture: None,
             depth: 0,
+            span: p.span.clone(),
         });
+        if shareable {
+            interner.shared.insert(flat, id);
+            interner.shareable.insert(id);
+        }
         Ok(id)
     }
 
-    fn compute_depths(&mut self) {
-        let n = self.indexed_statements.len();
-        for i in 0..n {
-            self.indexed_statements[i].depth = usize::MAX;
+    /// The name of the statement `pattern` was flattened from.
+    fn statement_of(&self, pattern: PatternId) -> &str {
+        let named = self.pattern_ids.len();
+        if pattern < named {
+            return &self.statements[pattern].name;
         }
-
-        if let Some(&root_id) = self.pattern_ids.get("TEXT") {
-            let mut queue = std::collections::VecDeque::new();
-            queue.push_back((root_id, 0));
-            self.indexed_statements[root_id].depth = 0;
-
-            while let Some((id, d)) = queue.pop_front() {
-                let next_d = d + 1;
-                let pattern = &self.indexed_statements[id].pattern;
-
-                let mut children = Vec::new();
-                match pattern {
-                    FlatPattern::Variable(target) => children.push(*target),
-                    FlatPattern::Sequence(ids) | FlatPattern::Alternation(ids) => {
-                        for &child_id in ids {
-                            children.push(child_id);
-                        }
-                    }
-                    FlatPattern::Quantifier {
-                        pattern: child_id, ..
-                    } => {
-                        children.push(*child_id);
-                    }
-                    FlatPattern::AnyCase(child_id)
-                    | FlatPattern::Upper(child_id)
-                    | FlatPattern::Lower(child_id)
-                    | FlatPattern::Group(child_id) => {
-                        children.push(*child_id);
-                    }
-                    _ => {}
+        // a statement's patterns are flattened bottom-up, right before the next statement's
+        // ones, so the first statement whose top pattern comes after `pattern` holds it (or
+        // first used it, when shared)
+        let owner = self.statements[..named]
+            .iter()
+            .filter_map(|stmt| match stmt.pattern {
+                FlatPattern::Variable(top) if top >= named && top >= pattern => {
+                    Some((top, &stmt.name))
                 }
+                _ => None,
+            })
+            .min();
+        owner.map_or("", |(_, name)| name)
+    }
 
-                for child_id in children {
-                    if self.indexed_statements[child_id].depth > next_d {
-                        self.indexed_statements[child_id].depth = next_d;
-                        queue.push_back((child_id, next_d));
-                    }
-                }
+    /// The statements of the query that can match empty text.
+    pub(crate) fn nullable_statements(&self) -> Vec<&str> {
+        let nullable = self.nullable();
+        self.statements[..self.pattern_ids.len()]
+            .iter()
+            .zip(nullable)
+            .filter(|(_, nullable)| *nullable)
+            .map(|(stmt, _)| stmt.name.as_str())
+            .collect()
+    }
+
+    /// Where unbounded repetitions of `<separator> <item>` without `LAZY` or `GREEDY`, like
+    /// `SPLITBY`s, have items that can start like their separator, so that the input can be
+    /// split into items in several ways.
+    pub(crate) fn overlapping_splits(&self) -> Vec<Range<usize>> {
+        let mut spans: Vec<Range<usize>> = Vec::new();
+        for stmt in &self.statements {
+            let FlatPattern::Quantifier {
+                max: None,
+                pattern,
+                mode: QuantifierBias::Neutral,
+                ..
+            } = stmt.pattern
+            else {
+                continue;
+            };
+            let FlatPattern::Sequence(ids) = &self.statements[pattern].pattern else {
+                continue;
+            };
+            let Some(&item) = ids.last().filter(|_| ids.len() > 1) else {
+                continue;
+            };
+            if self.lookahead[pattern].overlaps(&self.lookahead[item])
+                && !spans.contains(&stmt.span)
+            {
+                spans.push(stmt.span.clone());
             }
         }
+        spans
+    }
 
-        // Ensure all have some reasonable depth if unreachable
+    /// Which patterns can match empty text, found by iterating to a fixed point.
+    fn nullable(&self) -> Vec<bool> {
+        let mut nullable = vec![false; self.statements.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (id, stmt) in self.statements.iter().enumerate() {
+                if nullable[id] {
+                    continue;
+                }
+                nullable[id] = match &stmt.pattern {
+                    FlatPattern::Literal(s) => s.is_empty(),
+                    FlatPattern::Builtin(b) => {
+                        matches!(
+                            b,
+                            Builtin::Line
+                                | Builtin::Paragraph
+                                | Builtin::Bytes(0)
+                                | Builtin::Bof
+                                | Builtin::Eof
+                        )
+                    }
+                    // empty custom matches are rejected, and dictionaries hold no empty words
+                    FlatPattern::Custom(_) | FlatPattern::Dict(_) => false,
+                    FlatPattern::Sequence(ids)
+                    | FlatPattern::Permute(ids)
+                    | FlatPattern::Fields(ids) => ids.iter().all(|&c| nullable[c]),
+                    FlatPattern::Alternation {
+                        alternatives: ids, ..
+                    } => ids.iter().any(|&c| nullable[c]),
+                    FlatPattern::Quantifier { min, pattern, .. } => {
+                        min.unwrap_or(0) == 0 || nullable[*pattern]
+                    }
+                    FlatPattern::Variable(c)
+                    | FlatPattern::AnyCase(c)
+                    | FlatPattern::Upper(c)
+                    | FlatPattern::Lower(c)
+                    | FlatPattern::Group(c) => nullable[*c],
+                    FlatPattern::PrecededBy { .. } => true,
+                    FlatPattern::Unmatched(_) => false,
+                };
+                changed |= nullable[id];
+            }
+        }
+        nullable
+    }
+
+    /// Rejects statements that can be re-entered at the same position, which would recurse
+    /// forever: the ones reachable from themselves through patterns that may match empty text.
+    fn check_left_recursion(&self, nullable: &[bool]) -> StrqlResult<()> {
+        // the patterns matched at the same position a pattern starts at
+        let leading = |id: PatternId| -> &[PatternId] {
+            match &self.statements[id].pattern {
+                FlatPattern::Sequence(ids) => {
+                    let first_solid = ids.iter().position(|&c| !nullable[c]);
+                    &ids[..first_solid.map_or(ids.len(), |i| i + 1)]
+                }
+                // lookbehinds match text before the position, and separators after it
+                FlatPattern::PrecededBy { .. } | FlatPattern::Unmatched(_) => &[],
+                pattern => pattern.children(),
+            }
+        };
+
+        // depth-first search for a cycle; 1 = on the current path, 2 = done
+        let mut state = vec![0u8; self.statements.len()];
+        for root in 0..self.pattern_ids.len() {
+            if state[root] != 0 {
+                continue;
+            }
+            state[root] = 1;
+            let mut stack = vec![(root, 0)];
+            while let Some((id, next_child)) = stack.last_mut() {
+                let id = *id;
+                let Some(&child) = leading(id).get(*next_child) else {
+                    state[id] = 2;
+                    stack.pop();
+                    continue;
+                };
+                *next_child += 1;
+                match state[child] {
+                    0 => {
+                        state[child] = 1;
+                        stack.push((child, 0));
+                    }
+                    1 => {
+                        // cycles only close through named statements, which come first
+                        let on_cycle = stack.iter().map(|&(id, _)| id);
+                        let named = on_cycle
+                            .skip_while(|&id| id != child)
+                            .find(|&id| id < self.pattern_ids.len())
+                            .unwrap_or(child);
+                        let stmt = &self.statements[named];
+                        return Err(StrqlError::LeftRecursion {
+                            _name: stmt.name.clone(),
+                            _src: query_to_named(&self.source),
+                            _span: stmt.span.clone().into(),
+                        });
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets how many characters each lookbehind matches, rejecting the ones that don't match
+    /// a fixed, non-zero number of them.
+    fn measure_lookbehinds(&mut self) -> StrqlResult<()> {
+        for id in 0..self.statements.len() {
+            let FlatPattern::PrecededBy { pattern, .. } = self.statements[id].pattern else {
+                continue;
+            };
+            let mut visiting = vec![false; self.statements.len()];
+            match self.fixed_len(pattern, &mut visiting) {
+                Some(fixed) if fixed > 0 => {
+                    if let FlatPattern::PrecededBy { len, .. } = &mut self.statements[id].pattern {
+                        *len = fixed;
+                    }
+                }
+                _ => {
+                    return Err(StrqlError::VariableLengthLookbehind {
+                        _src: query_to_named(&self.source),
+                        _span: self.statements[id].span.clone().into(),
+                    })
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// How many characters every match of `id` has, if they all have as many.
+    fn fixed_len(&self, id: PatternId, visiting: &mut [bool]) -> Option<usize> {
+        if std::mem::replace(&mut visiting[id], true) {
+            return None;
+        }
+        let len = match &self.statements[id].pattern {
+            FlatPattern::Literal(s) => Some(s.chars().count()),
+            FlatPattern::Builtin(Builtin::Bof | Builtin::Eof) => Some(0),
+            FlatPattern::Builtin(b) => matches_char(b, '\0', CaseMode::Normal, false).map(|_| 1),
+            // custom matches and words vary in length, and so do fields left out
+            FlatPattern::Custom(_)
+            | FlatPattern::Dict(_)
+            | FlatPattern::Fields(_)
+            | FlatPattern::Unmatched(_) => None,
+            FlatPattern::Sequence(ids) | FlatPattern::Permute(ids) => {
+                ids.iter().map(|&c| self.fixed_len(c, visiting)).sum()
+            }
+            FlatPattern::Alternation {
+                alternatives: ids, ..
+            } => {
+                let mut lens = ids.iter().map(|&c| self.fixed_len(c, visiting));
+                let first = lens.next()?;
+                lens.all(|len| len == first).then_some(first)?
+            }
+            FlatPattern::Quantifier {
+                min, max, pattern, ..
+            } if min.unwrap_or(0) == max.unwrap_or(usize::MAX) => {
+                Some(min.unwrap_or(0) * self.fixed_len(*pattern, visiting)?)
+            }
+            FlatPattern::Quantifier { .. } => None,
+            FlatPattern::Variable(c)
+            | FlatPattern::AnyCase(c)
+            | FlatPattern::Upper(c)
+            | FlatPattern::Lower(c)
+            | FlatPattern::Group(c) => self.fixed_len(*c, visiting),
+            FlatPattern::PrecededBy { .. } => Some(0),
+        };
+        visiting[id] = false;
+        len
+    }
+
+    /// The bytes each pattern's matches can start with, in any case mode, found by iterating
+    /// to a fixed point. Sets may be too large (e.g. all non-ASCII bytes), never too small.
+    fn lookahead(&self, nullable: &[bool]) -> Vec<Lookahead> {
+        const NON_ASCII: std::ops::RangeInclusive<u8> = 0x80..=0xff;
+        let mut lookahead: Vec<Lookahead> = nullable
+            .iter()
+            .map(|&nullable| Lookahead {
+                nullable,
+                ..Lookahead::default()
+            })
+            .collect();
+        for (id, stmt) in self.statements.iter().enumerate() {
+            let own = &mut lookahead[id];
+            match &stmt.pattern {
+                FlatPattern::Literal(s) => match s.chars().next() {
+                    // case-insensitive matches of a letter include e.g. the Kelvin sign for `k`
+                    Some(c) if c.is_ascii_alphabetic() => {
+                        own.add(c.to_ascii_lowercase() as u8);
+                        own.add(c.to_ascii_uppercase() as u8);
+                        own.add_all(NON_ASCII);
+                    }
+                    Some(c) if c.is_ascii() => own.add(c as u8),
+                    Some(_) => {
+                        own.add_all(NON_ASCII);
+                        own.add_all((b'a'..=b'z').chain(b'A'..=b'Z'));
+                    }
+                    None => {}
+                },
+                FlatPattern::Builtin(b) => match b {
+                    Builtin::Digit => own.add_all(b'0'..=b'9'),
+                    Builtin::Letter => own.add_all((b'a'..=b'z').chain(b'A'..=b'Z')),
+                    Builtin::HexDigit => {
+                        own.add_all((b'0'..=b'9').chain(b'a'..=b'f').chain(b'A'..=b'F'))
+                    }
+                    Builtin::BinDigit => own.add_all(b'0'..=b'1'),
+                    Builtin::OctDigit => own.add_all(b'0'..=b'7'),
+                    Builtin::Newline => own.add(b'\n'),
+                    Builtin::Space => {
+                        own.add_all([b' ', b'\t', b'\r', 0x0b, 0x0c]);
+                        own.add_all(NON_ASCII);
+                    }
+                    // byte inputs hold bytes past ASCII as two-byte characters
+                    Builtin::Byte(byte) if byte.is_ascii() => own.add(*byte),
+                    Builtin::Byte(byte) => own.add(char::from(*byte).to_string().as_bytes()[0]),
+                    Builtin::Base64 => own.add_all(
+                        (b'a'..=b'z')
+                            .chain(b'A'..=b'Z')
+                            .chain(b'0'..=b'9')
+                            .chain(*b"+/-_"),
+                    ),
+                    Builtin::HexBlob => {
+                        own.add_all((b'0'..=b'9').chain(b'a'..=b'f').chain(b'A'..=b'F'))
+                    }
+                    Builtin::JsonValue => own.add_all((b'0'..=b'9').chain(*b"{[\"-tfn")),
+                    Builtin::ContLine => own.add(b'\n'),
+                    Builtin::AnyChar | Builtin::Line | Builtin::Paragraph | Builtin::Bytes(_) => {
+                        own.add_all(0..=0xff)
+                    }
+                    Builtin::Bof | Builtin::Eof => {}
+                },
+                FlatPattern::Custom(_) | FlatPattern::Unmatched(_) => own.add_all(0..=0xff),
+                FlatPattern::Dict(name) => {
+                    if let Some(dict) = self.custom.dict(name) {
+                        for byte in dict.first_bytes() {
+                            own.add(byte.to_ascii_lowercase());
+                            own.add(byte.to_ascii_uppercase());
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (id, stmt) in self.statements.iter().enumerate() {
+                let leading = match &stmt.pattern {
+                    FlatPattern::Sequence(ids) => {
+                        let first_solid = ids.iter().position(|&c| !nullable[c]);
+                        &ids[..first_solid.map_or(ids.len(), |i| i + 1)]
+                    }
+                    FlatPattern::Quantifier { max: Some(0), .. }
+                    | FlatPattern::PrecededBy { .. }
+                    | FlatPattern::Unmatched(_) => &[],
+                    pattern => pattern.children(),
+                };
+                for &child in leading {
+                    if child != id {
+                        let child = lookahead[child].clone();
+                        changed |= lookahead[id].union(&child);
+                    }
+                }
+            }
+        }
+        lookahead
+    }
+
+    /// The literals every match of `id` contains, as long as literals are matched
+    /// case-sensitively (not under `OPTION ANYCASE`). `found` holds the
+    /// sets already computed, and an empty one for the patterns being computed, which is
+    /// enough for recursive statements since a smaller set is always correct.
+    fn required_literals(
+        &self,
+        id: PatternId,
+        found: &mut Vec<Option<BTreeSet<String>>>,
+    ) -> BTreeSet<String> {
+        if let Some(literals) = &found[id] {
+            return literals.clone();
+        }
+        found[id] = Some(BTreeSet::new());
+        let literals = match &self.statements[id].pattern {
+            FlatPattern::Literal(s) if !s.is_empty() => BTreeSet::from([s.clone()]),
+            FlatPattern::Sequence(ids) | FlatPattern::Permute(ids) => {
+                let mut literals = BTreeSet::new();
+                for &c in ids {
+                    literals.extend(self.required_literals(c, found));
+                }
+                literals
+            }
+            FlatPattern::Alternation {
+                alternatives: ids, ..
+            } => {
+                let mut alternatives = ids.iter().map(|&c| self.required_literals(c, found));
+                let first = alternatives.next().unwrap_or_default();
+                alternatives.fold(first, |common, literals| {
+                    common.intersection(&literals).cloned().collect()
+                })
+            }
+            FlatPattern::Quantifier {
+                min, max, pattern, ..
+            } if min.unwrap_or(0) > 0 && *max != Some(0) => self.required_literals(*pattern, found),
+            FlatPattern::Variable(c) | FlatPattern::Group(c) => self.required_literals(*c, found),
+            // case modifiers match literals in other cases too
+            _ => BTreeSet::new(),
+        };
+        found[id] = Some(literals.clone());
+        literals
+    }
+
+    /// Sorts the alternatives of every alternation that can only ever match one of them at a
+    /// position from the narrowest lookahead to the widest, and marks it exclusive.
+    fn reorder_alternatives(&mut self) {
+        for id in 0..self.statements.len() {
+            // the order of `FIRST OR` is what it prefers by, and weights follow the order
+            let FlatPattern::Alternation {
+                alternatives: alts,
+                mode: AlternationBias::Neutral | AlternationBias::Longest,
+            } = &mut self.statements[id].pattern
+            else {
+                continue;
+            };
+            let lookahead = &self.lookahead;
+            let exclusive = alts.iter().enumerate().all(|(i, &a)| {
+                !lookahead[a].nullable
+                    && alts[i + 1..]
+                        .iter()
+                        .all(|&b| !lookahead[a].overlaps(&lookahead[b]))
+            });
+            if exclusive {
+                alts.sort_by_key(|&a| lookahead[a].width());
+                self.exclusive[id] = true;
+            }
+        }
+    }
+
+    fn compute_depths(&mut self) {
+        let n = self.statements.len();
+        for i in 0..n {
+            self.statements[i].depth = usize::MAX;
+        }
+
+        if let Some(&root_id) = self.pattern_ids.get("TEXT") {
+            let mut queue = std::collections::VecDeque::new();
+            queue.push_back((root_id, 0));
+            self.statements[root_id].depth = 0;
+
+            while let Some((id, d)) = queue.pop_front() {
+                let next_d = d + 1;
+                let children = self.statements[id].pattern.children().to_vec();
+
+                for child_id in children {
+                    if self.statements[child_id].depth > next_d {
+                        self.statements[child_id].depth = next_d;
+                        queue.push_back((child_id, next_d));
+                    }
+                }
+            }
+        }
+
+        // Ensure all have some reasonable depth if unreachable
         let mut max_depth = 0;
         for i in 0..n {
-            let depth = self.indexed_statements[i].depth;
+            let depth = self.statements[i].depth;
             if depth == usize::MAX {
-                self.indexed_statements[i].depth = 0;
+                self.statements[i].depth = 0;
             } else {
                 max_depth = std::cmp::max(max_depth, depth);
             }
@@ -464,7 +1552,7 @@ impl<'a> Solver<'a> {
 
         // Verify invariant: all depths must be < max_preference_depth
         #[cfg(debug_assertions)]
-        for (i, stmt) in self.indexed_statements.iter().enumerate() {
+        for (i, stmt) in self.statements.iter().enumerate() {
             debug_assert!(
                 stmt.depth < self.max_preference_depth,
                 "compute_depths invariant violated: statement {} has depth {} >= max_preference_depth {}",
@@ -474,25 +1562,180 @@ impl<'a> Solver<'a> {
             );
         }
     }
+}
+
+impl<'a> NamedSourceExt<'a> for Solver<'a> {
+    fn src(&self) -> &'a str {
+        self.input
+    }
+
+    fn source_name(&self) -> &str {
+        "input"
+    }
+}
+
+impl<'a> Solver<'a> {
+    /// Keeps the preferred of `new_outcome` and what already ends at `next_pos`, as matched
+    /// by `pattern` from `start`.
+    fn merge_outcome(
+        &self,
+        map: &mut MatchMap,
+        next_pos: usize,
+        new_outcome: MatchOutcome,
+        (pattern, start): (PatternId, usize),
+    ) {
+        if let Some(existing) = map.data.get_mut(&next_pos) {
+            let existing_score = existing.score();
+            let new_score = new_outcome.score();
+
+            if new_score > existing_score {
+                *existing = new_outcome;
+                return;
+            }
+
+            if new_score < existing_score {
+                return;
+            }
+
+            // Scores are equal, check preferences
+            let existing_pref = existing.preference();
+            let new_pref = new_outcome.preference();
+
+            if new_pref > existing_pref {
+                *existing = new_outcome;
+            } else if new_pref == existing_pref
+                && self.query.options.strategy.unwrap_or_default() == Strategy::Cfg
+            {
+                let branch = Branch {
+                    start,
+                    end: next_pos,
+                    pattern,
+                };
+                *existing = existing.tie(&new_outcome, branch);
+            }
+        } else {
+            map.insert(next_pos, new_outcome);
+        }
+    }
+
+    pub fn from_compiled(query: &'a CompiledQuery) -> Self {
+        Self {
+            input: "",
+            query,
+            memo: Default::default(),
+            case_mode: CaseMode::Normal,
+            track_captures: true,
+            keyed: key_variables(query),
+            furthest_literal: 0,
+            cancellation: None,
+            options: SolverOptions::default(),
+            binary: false,
+            boundaries: None,
+        }
+    }
+
+    /// Makes evaluation stop with [`StrqlError::Cancelled`] once `token` is cancelled.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    pub fn with_options(mut self, options: SolverOptions) -> Self {
+        if let Some(token) = &options.cancellation {
+            self.cancellation = Some(token.clone());
+        }
+        self.options = options;
+        self
+    }
 
     pub fn solve(&mut self, input: &'a str) -> StrqlResult<Value> {
+        self.solve_streaming(input, None)
+    }
+
+    fn solve_streaming(
+        &mut self,
+        input: &'a str,
+        stream: Option<&mut Stream<'_>>,
+    ) -> StrqlResult<Value> {
+        self.track_captures = true;
+        let m = self.find_unique_match(input)?;
+        self.build_output(input, &m.trace, stream, None)
+    }
+
+    /// The output the captures of `trace` make up, adding where each value was captured from
+    /// to `spans`, if given.
+    fn build_output(
+        &self,
+        input: &str,
+        trace: &MatchTrace,
+        stream: Option<&mut Stream<'_>>,
+        mut spans: Option<&mut Vec<CaptureSpan>>,
+    ) -> StrqlResult<Value> {
+        let mut output = self.replay_captures(trace, stream, spans.as_deref_mut())?;
+        if let (Some(field), Value::Object(fields)) = (&self.query.options.capture_raw, &mut output)
+        {
+            fields.insert(field.clone(), Value::String(input.to_string()));
+            if let Some(spans) = spans {
+                spans.push(CaptureSpan {
+                    path: vec![PathStep::Field(field.clone())],
+                    span: 0..input.len(),
+                    statement: "TEXT".to_string(),
+                });
+            }
+        }
+        Ok(output)
+    }
+
+    /// Checks whether `input` matches the program, without recording or replaying captures.
+    ///
+    /// Inputs that don't match (fully or at all) yield `Ok(false)`; ambiguity is still an error.
+    pub fn matches(&mut self, input: &'a str) -> StrqlResult<bool> {
+        self.track_captures = false;
+        match self.find_unique_match(input) {
+            Ok(_) => Ok(true),
+            Err(StrqlError::PatternNoMatch { .. } | StrqlError::PartialMatch { .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn find_unique_match(&mut self, input: &'a str) -> StrqlResult<Match> {
         self.input = input;
-        let size = self.indexed_statements.len() * (input.len() + 1);
-        self.memo = vec![VResult::NoMatch; size];
-        self.memo_set = vec![false; size];
+        self.memo = Default::default();
+        self.furthest_literal = 0;
+        if let Some(progress) = &self.options.progress {
+            progress.start(input.len());
+        }
+        self.case_mode = if self.query.options.anycase {
+            CaseMode::AnyCase
+        } else {
+            CaseMode::Normal
+        };
 
-        let text_id = if let Some(&id) = self.pattern_ids.get("TEXT") {
+        let text_id = if let Some(&id) = self.query.pattern_ids.get("TEXT") {
             id
         } else {
             return Err(StrqlError::NoTextStatement {
-                _src: self.src_to_named(),
+                _src: query_to_named(&self.query.source),
             });
         };
 
+        // validation runs don't report how far the input matched, so they can give up early
+        if !self.track_captures
+            && self
+                .query
+                .required
+                .iter()
+                .any(|literal| scan::find(input, literal).is_none())
+        {
+            return Err(StrqlError::PatternNoMatch {
+                _src: self.src_window(0..0),
+            });
+        }
+
         match self.viterbi(text_id, 0)? {
             VResult::NoMatch => {
-                let mut max_pos = 0;
-                for res in &self.memo {
+                let mut max_pos = self.furthest_literal;
+                for res in self.memo.iter().flat_map(|table| &table.results) {
                     if let VResult::Matches(map) = res {
                         for &pos in &map.active {
                             if pos > max_pos {
@@ -506,40 +1749,153 @@ impl<'a> Solver<'a> {
                     Err(StrqlError::PartialMatch {
                         _matched: max_pos,
                         _total: input.len(),
-                        _src: self.src_to_named(),
-                        _span: (0..max_pos).into(),
+                        _src: self.src_window(max_pos..max_pos),
+                        _span: (max_pos..max_pos).into(),
                     })
                 } else {
                     Err(StrqlError::PatternNoMatch {
-                        _src: self.src_to_named(),
+                        _src: self.src_window(0..0),
                     })
                 }
             }
 
             VResult::Matches(matches) => match matches.get(input.len()) {
-                Some(MatchOutcome::Unique(m)) => Ok(self.replay_captures(&m.trace)),
-                Some(MatchOutcome::Ambiguous { .. }) => Err(StrqlError::AmbiguousParse {
-                    _src: self.src_to_named(),
-                }),
+                Some(MatchOutcome::Unique(m)) => Ok(m.clone()),
+                Some(MatchOutcome::Ambiguous { ambiguity, .. }) => {
+                    Err(self.ambiguity_error(ambiguity))
+                }
                 None => {
-                    let max_pos = matches.active.iter().max().cloned().unwrap_or(0);
+                    let max_pos = matches
+                        .active
+                        .iter()
+                        .copied()
+                        .chain([self.furthest_literal])
+                        .max()
+                        .unwrap_or(0);
                     Err(StrqlError::PartialMatch {
                         _matched: max_pos,
                         _total: input.len(),
-                        _src: self.src_to_named(),
-                        _span: (0..max_pos).into(),
+                        _src: self.src_window(max_pos..max_pos),
+                        _span: (max_pos..max_pos).into(),
                     })
                 }
             },
         }
     }
 
+    fn ambiguity_error(&self, ambiguity: &Ambiguity) -> StrqlError {
+        let branches: Vec<AmbiguityBranch> = ambiguity
+            .branches
+            .iter()
+            .map(|branch| AmbiguityBranch {
+                statement: self.query.statement_of(branch.pattern).to_string(),
+                span: branch.start..branch.end,
+            })
+            .collect();
+        let suggestions = self.ambiguity_suggestions(ambiguity);
+        let help = if suggestions.is_empty() {
+            "Add LAZY or GREEDY to repetitions, or FIRST, LONGEST or WEIGHT to ORs, to refine your \
+             statement set"
+                .to_string()
+        } else {
+            let mut statements: Vec<String> = Vec::new();
+            for suggestion in &suggestions {
+                let statement = format!("`{}`", suggestion.statement);
+                if !statements.contains(&statement) {
+                    statements.push(statement);
+                }
+            }
+            format!(
+                "Add LAZY or GREEDY to the repetitions without one in {}",
+                statements.join(", ")
+            )
+        };
+        // hand-built queries have no text to point into
+        let notes = if self.query.source.is_empty() {
+            Vec::new()
+        } else {
+            suggestions
+                .iter()
+                .map(|s| QueryNote {
+                    _message: format!("`{}` repeats here without LAZY or GREEDY", s.statement),
+                    _src: query_to_named(&self.query.source),
+                    _span: s.span.clone().into(),
+                })
+                .collect()
+        };
+        let first = branches.first().map_or(0..0, |b| b.span.clone());
+        StrqlError::AmbiguousParse {
+            _parses: if ambiguity.count < MAX_COUNTED_PARSES {
+                ParseCount::Exactly(ambiguity.count)
+            } else {
+                ParseCount::AtLeast(ambiguity.count)
+            },
+            _branches: branches,
+            _suggestions: suggestions,
+            _help: help,
+            _src: self.src_window(first.clone()),
+            _span: Some(first.into()),
+            _notes: notes,
+        }
+    }
+
+    /// The unbiased repetitions under each branch of `ambiguity` that could stop at more than
+    /// one place inside it, according to what the solver memoized: adding a bias to them is
+    /// what breaks such ties.
+    fn ambiguity_suggestions(&self, ambiguity: &Ambiguity) -> Vec<AmbiguitySuggestion> {
+        let mut suggested = HashSet::new();
+        let mut suggestions = Vec::new();
+        for branch in &ambiguity.branches {
+            let mut visited = HashSet::new();
+            let mut stack = vec![branch.pattern];
+            while let Some(id) = stack.pop() {
+                if !visited.insert(id) {
+                    continue;
+                }
+                let pattern = &self.query.statements[id].pattern;
+                stack.extend_from_slice(pattern.children());
+                let unbiased = matches!(
+                    pattern,
+                    FlatPattern::Quantifier {
+                        mode: QuantifierBias::Neutral,
+                        ..
+                    }
+                );
+                if unbiased && self.has_split_choice(id, branch) && suggested.insert(id) {
+                    suggestions.push(AmbiguitySuggestion {
+                        statement: self.query.statement_of(id).to_string(),
+                        span: self.query.statements[id].span.clone(),
+                    });
+                }
+            }
+        }
+        suggestions
+    }
+
+    /// Whether `id` matched from somewhere in `branch` up to several different places in it.
+    fn has_split_choice(&self, id: PatternId, branch: &Branch) -> bool {
+        let row = id * (self.input.len() + 1);
+        (branch.start..=branch.end).any(|pos| {
+            self.memo.iter().any(|table| match table.get(row + pos) {
+                Some(VResult::Matches(matches)) => {
+                    matches
+                        .active
+                        .iter()
+                        .filter(|&&end| end <= branch.end)
+                        .count()
+                        > 1
+                }
+                _ => false,
+            })
+        })
+    }
+
     fn viterbi(&mut self, id: PatternId, pos: usize) -> StrqlResult<VResult> {
         debug_assert!(
-            id < self.indexed_statements.len(),
+            id < self.query.statements.len(),
             "viterbi: pattern id {} out of bounds (len {})",
             id,
-            self.indexed_statements.len()
+            self.query.statements.len()
         );
         debug_assert!(
             pos <= self.input.len(),
@@ -549,67 +1905,115 @@ impl<'a> Solver<'a> {
         );
 
         let idx = id * (self.input.len() + 1) + pos;
-        debug_assert!(
-            idx < self.memo.len(),
-            "viterbi: memo index {} out of bounds (len {})",
-            idx,
-            self.memo.len()
-        );
+        let table = &mut self.memo[self.case_mode as usize];
+        if table.set.is_empty() {
+            let size = self.query.statements.len() * (self.input.len() + 1);
+            table.results = vec![VResult::NoMatch; size];
+            table.set = vec![false; size];
+        }
+        if let Some(res) = table.get(idx) {
+            return Ok(res.clone());
+        }
 
-        if self.memo_set[idx] {
-            return Ok(self.memo[idx].clone());
+        if let Some(token) = &self.cancellation {
+            if token.is_cancelled() {
+                return Err(StrqlError::Cancelled);
+            }
+        }
+        if let Some(progress) = &self.options.progress {
+            progress.reach(pos);
         }
 
         let res = self.eval_pattern(id, pos)?;
 
-        self.memo[idx] = res.clone();
-        self.memo_set[idx] = true;
+        let table = &mut self.memo[self.case_mode as usize];
+        table.results[idx] = res.clone();
+        table.set[idx] = true;
         Ok(res)
     }
 
     fn eval_pattern(&mut self, id: PatternId, pos: usize) -> StrqlResult<VResult> {
         let input_len = self.input.len();
-        let pattern_type = self.indexed_statements[id].pattern.clone();
+        let is_statement = id < self.query.pattern_ids.len();
+        if let Some(boundaries) = &self.boundaries {
+            if is_statement && !boundaries[pos] {
+                return Ok(VResult::NoMatch);
+            }
+        }
+        let pattern_type = self.query.statements[id].pattern.clone();
         let mut res = match &pattern_type {
-            FlatPattern::Literal(s) => {
-                let matched = match self.case_mode {
-                    CaseMode::Normal => self.input[pos..].starts_with(s),
-                    CaseMode::AnyCase => self.input[pos..]
-                        .get(..s.len())
-                        .map(|sub| sub.eq_ignore_ascii_case(s))
-                        .unwrap_or(false),
-                    CaseMode::Upper => self.input[pos..]
-                        .get(..s.len())
-                        .map(|sub| {
-                            sub.eq_ignore_ascii_case(s) && !sub.chars().any(|c| c.is_lowercase())
-                        })
-                        .unwrap_or(false),
-                    CaseMode::Lower => self.input[pos..]
-                        .get(..s.len())
-                        .map(|sub| {
-                            sub.eq_ignore_ascii_case(s) && !sub.chars().any(|c| c.is_uppercase())
-                        })
-                        .unwrap_or(false),
-                };
+            FlatPattern::Literal(s) => match match_literal(&self.input[pos..], s, self.case_mode) {
+                Ok(len) => VResult::single(
+                    pos + len,
+                    len as i64,
+                    MatchTrace::default(),
+                    input_len,
+                    self.query.max_preference_depth,
+                ),
+                Err(matched) => {
+                    self.furthest_literal = self.furthest_literal.max(pos + matched);
+                    VResult::NoMatch
+                }
+            },
+
+            FlatPattern::Variable(target_id) => self.viterbi(*target_id, pos)?,
+
+            FlatPattern::Builtin(_) => self.eval_builtin(id, pos)?,
+
+            FlatPattern::Custom(name) => self.eval_custom(name, pos)?,
+
+            FlatPattern::Dict(name) => self.eval_dict(name, pos)?,
+
+            FlatPattern::Group(inner_id) => self.viterbi(*inner_id, pos)?,
 
-                if matched {
+            // anchored at both ends: the pattern must match from `len` characters back up to
+            // exactly `pos`
+            FlatPattern::PrecededBy { pattern, len } => {
+                let start = self.input[..pos].char_indices().rev().nth(len - 1);
+                let ends_here = match start {
+                    Some((start, _)) => match self.viterbi(*pattern, start)? {
+                        VResult::Matches(matches) => matches.get(pos).is_some(),
+                        VResult::NoMatch => false,
+                    },
+                    None => false,
+                };
+                if ends_here {
                     VResult::single(
-                        pos + s.len(),
-                        s.len() as i64,
+                        pos,
+                        0,
                         MatchTrace::default(),
                         input_len,
-                        self.max_preference_depth,
+                        self.query.max_preference_depth,
                     )
                 } else {
                     VResult::NoMatch
                 }
             }
 
-            FlatPattern::Variable(target_id) => self.viterbi(*target_id, pos)?,
-
-            FlatPattern::Builtin(_) => self.eval_builtin(id, pos)?,
-
-            FlatPattern::Group(inner_id) => self.viterbi(*inner_id, pos)?,
+            // the shortest text the separator matches after, or the rest of the input; it
+            // scores nothing, so that parses matching more of the input are preferred
+            FlatPattern::Unmatched(separator) => {
+                let mut end = pos;
+                while let Some(c) = self.input[end..].chars().next() {
+                    end += c.len_utf8();
+                    if end == input_len
+                        || matches!(self.viterbi(*separator, end)?, VResult::Matches(_))
+                    {
+                        break;
+                    }
+                }
+                if end == pos {
+                    VResult::NoMatch
+                } else {
+                    VResult::single(
+                        end,
+                        0,
+                        MatchTrace::default(),
+                        input_len,
+                        self.query.max_preference_depth,
+                    )
+                }
+            }
 
             FlatPattern::AnyCase(inner_id) => {
                 let old = self.case_mode;
@@ -641,55 +2045,29 @@ impl<'a> Solver<'a> {
                     0,
                     MatchTrace::default(),
                     input_len,
-                    self.max_preference_depth,
+                    self.query.max_preference_depth,
                 );
 
-                for &p_id in seq {
-                    let mut next_results_map = MatchMap::new(input_len);
+                for (i, &p_id) in seq.iter().enumerate() {
+                    let next = seq
+                        .get(i + 1)
+                        .map(|&next_id| &self.query.lookahead[next_id]);
+                    let mut next_results_map = MatchMap::default();
                     if let VResult::Matches(matches) = current_results {
                         for (&cur_pos, outcome) in matches.iter() {
-                            match outcome {
-                                MatchOutcome::Unique(m) => {
-                                    let res = self.viterbi(p_id, cur_pos)?;
-                                    if let VResult::Matches(sub_matches) = res {
-                                        for (&next_pos, sub) in sub_matches.iter() {
-                                            let new_outcome = MatchOutcome::extending_with_sub(m, sub);
-                                            Self::merge_outcome(
-                                                &mut next_results_map,
-                                                next_pos,
-                                                new_outcome,
-                                            );
-                                        }
-                                    }
-                                }
-                                MatchOutcome::Ambiguous {
-                                    best_score: bs,
-                                    best_preference: bp,
-                                } => {
-                                    let res = self.viterbi(p_id, cur_pos)?;
-                                    if let VResult::Matches(sub_matches) = res {
-                                        for (&next_pos, sub_outcome) in sub_matches.iter() {
-                                            let (sub_score, sub_pref) = match sub_outcome {
-                                                MatchOutcome::Unique(sm) => {
-                                                    (sm.score, &sm.preference)
-                                                }
-                                                MatchOutcome::Ambiguous {
-                                                    best_score: s,
-                                                    best_preference: p,
-                                                } => (*s, p),
-                                            };
-                                            let mut new_pref = bp.clone();
-                                            new_pref.combine(sub_pref);
-                                            Self::merge_outcome(
-                                                &mut next_results_map,
-                                                next_pos,
-                                                MatchOutcome::Ambiguous {
-                                                    best_score: bs + sub_score,
-                                                    best_preference: new_pref,
-                                                },
-                                            );
-                                        }
+                            if let VResult::Matches(sub_matches) = self.viterbi(p_id, cur_pos)? {
+                                for (&next_pos, sub) in sub_matches.iter() {
+                                    // dead ends: the rest of the sequence can't start there
+                                    let rest = &self.input.as_bytes()[next_pos..];
+                                    if next.is_some_and(|next| !next.can_start(rest)) {
+                                        continue;
                                     }
+                                    self.merge_outcome(
+                                        &mut next_results_map,
+                                        next_pos,
+                                        MatchOutcome::extend(outcome, sub),
+                                        (id, pos),
+                                    );
                                 }
                             }
                         }
@@ -704,15 +2082,45 @@ impl<'a> Solver<'a> {
                 current_results
             }
 
-            FlatPattern::Alternation(alts) => {
-                let mut combined_map = MatchMap::new(input_len);
-                for &p_id in alts {
+            FlatPattern::Permute(items) => self.eval_permute(id, pos, items, false)?,
+
+            FlatPattern::Fields(items) => self.eval_permute(id, pos, items, true)?,
+
+            FlatPattern::Alternation {
+                alternatives: alts,
+                mode,
+            } => {
+                let mut combined_map = MatchMap::default();
+                let exclusive = self.query.exclusive[id];
+                let rest = &self.input.as_bytes()[pos..];
+                let depth = self.query.statements[id].depth;
+                for (i, &p_id) in alts.iter().enumerate() {
+                    if exclusive && !self.query.lookahead[p_id].can_start(rest) {
+                        continue;
+                    }
                     let res = self.viterbi(p_id, pos)?;
                     if let VResult::Matches(matches) = res {
                         for (&next_pos, outcome) in matches.iter() {
-                            Self::merge_outcome(&mut combined_map, next_pos, outcome.clone());
+                            // like the bias of repetitions, added to the preference of the
+                            // alternative: earlier ones win for FIRST, longer ones for LONGEST,
+                            // heavier ones for WEIGHT
+                            let bias = match mode {
+                                AlternationBias::Neutral => 0,
+                                AlternationBias::First => -(i as i64),
+                                AlternationBias::Longest => (next_pos - pos) as i64,
+                                AlternationBias::Weighted(weights) => weights[i] as i64,
+                            };
+                            let mut outcome = outcome.clone();
+                            if bias != 0 {
+                                outcome.preference_mut().add_at(depth, bias);
+                            }
+                            self.merge_outcome(&mut combined_map, next_pos, outcome, (id, pos));
                         }
                     }
+                    // none of the others can start here
+                    if exclusive {
+                        break;
+                    }
                 }
                 if combined_map.active.is_empty() {
                     VResult::NoMatch
@@ -726,54 +2134,58 @@ impl<'a> Solver<'a> {
                 max,
                 pattern: _,
                 mode,
-            } => {
-                let min_val = min.unwrap_or(0);
-                let max_val = max.unwrap_or(input_len - pos);
-                self.eval_quantifier(id, min_val.min(max_val), max_val.max(min_val), *mode, pos)?
-            }
+            } => self.eval_quantifier(id, min.unwrap_or(0), *max, *mode, pos)?,
         };
 
+        if let (Some(boundaries), VResult::Matches(matches)) = (&self.boundaries, &res) {
+            let terminal = matches!(
+                pattern_type,
+                FlatPattern::Literal(_)
+                    | FlatPattern::Builtin(_)
+                    | FlatPattern::Custom(_)
+                    | FlatPattern::Dict(_)
+            );
+            let keep = |end: usize| {
+                if is_statement {
+                    boundaries[end]
+                } else {
+                    !terminal || end <= pos || !boundaries[pos + 1..end].contains(&true)
+                }
+            };
+            if !matches.active.iter().all(|&end| keep(end)) {
+                let mut matches = (**matches).clone();
+                matches.retain(keep);
+                res = if matches.active.is_empty() {
+                    VResult::NoMatch
+                } else {
+                    VResult::Matches(Rc::new(matches))
+                };
+            }
+        }
+
         // Track variable matches and captures
         if let VResult::Matches(matches_rc) = res {
-            let stmt_name = self.indexed_statements[id].name.clone();
-            let has_name = !stmt_name.is_empty();
-            let has_capture = self.indexed_statements[id].capture.is_some();
+            let has_name = self.keyed[id];
+            let has_capture = self.query.statements[id].capture.is_some()
+                || matches!(self.query.statements[id].pattern, FlatPattern::Unmatched(_));
 
-            if has_name || has_capture {
+            if self.track_captures && (has_name || has_capture) {
                 let mut matches = (*matches_rc).clone();
                 for &next_pos in &matches.active {
-                    let outcome = matches.data[next_pos].as_mut().unwrap();
-                    let matched_text = &self.input[pos..next_pos];
-
-                    match outcome {
+                    match matches.data.get_mut(&next_pos).unwrap() {
                         MatchOutcome::Unique(m) => {
-                            // Always track named variable matches for dynamic field resolution
+                            // named matches resolve the dynamic fields keyed by them
                             if has_name {
-                                m.trace.events.insert(
-                                    0,
-                                    TraceEvent::VariableMatch {
-                                        name: stmt_name.clone(),
-                                        value: matched_text.to_string(),
-                                    },
-                                );
+                                m.trace.prepend(TraceEvent::VariableMatch {
+                                    statement: id,
+                                    span: pos..next_pos,
+                                });
                             }
-
-                            // Add capture event if there's a capture clause
-                            if let Some(ref capture_clause) = self.indexed_statements[id].capture {
-                                let mut clause = capture_clause.clone();
-                                let explicit_name = !clause.name.is_empty();
-                                if clause.name.is_empty() {
-                                    clause.name = stmt_name.clone();
-                                }
-
-                                m.trace.events.insert(
-                                    0,
-                                    TraceEvent::Capture {
-                                        value: matched_text.to_string(),
-                                        clause,
-                                        explicit_name,
-                                    },
-                                );
+                            if has_capture {
+                                m.trace.prepend(TraceEvent::Capture {
+                                    statement: id,
+                                    span: pos..next_pos,
+                                });
                             }
                         }
                         MatchOutcome::Ambiguous { .. } => {
@@ -790,24 +2202,30 @@ impl<'a> Solver<'a> {
         Ok(res)
     }
 
+    /// Repetitions are expanded one at a time while their count matters, i.e. up to `min`
+    /// (and up to `max`, when bounded). Past that, only the preferred way of reaching each
+    /// position is extended, so unbounded repetitions cost no more than a single pass.
     fn eval_quantifier(
         &mut self,
         id: PatternId,
         min: usize,
-        max: usize,
+        max: Option<usize>,
         mode: QuantifierBias,
         pos: usize,
     ) -> StrqlResult<VResult> {
-        debug_assert!(min <= max, "eval_quantifier: min {} > max {}", min, max);
+        debug_assert!(
+            max.is_none_or(|max| min <= max),
+            "eval_quantifier: min {} > max {:?}",
+            min,
+            max
+        );
         debug_assert!(
             pos <= self.input.len(),
             "eval_quantifier: pos {} exceeds input length {}",
             pos,
             self.input.len()
         );
-
-        let input_len = self.input.len();
-        let sub_pattern_id = match &self.indexed_statements[id].pattern {
+        let sub_pattern_id = match &self.query.statements[id].pattern {
             FlatPattern::Quantifier { pattern, .. } => *pattern,
             _ => {
                 return Err(StrqlError::Internal {
@@ -817,149 +2235,253 @@ impl<'a> Solver<'a> {
         };
 
         debug_assert!(
-            sub_pattern_id < self.indexed_statements.len(),
+            sub_pattern_id < self.query.statements.len(),
             "eval_quantifier: sub_pattern_id {} out of bounds",
             sub_pattern_id
         );
 
-        let mut results_by_k: Vec<VResult> = Vec::new();
-        results_by_k.push(VResult::single(
+        // every repetition adds the bias to the preference, so greedy ones favor more of them
+        let bias = match mode {
+            QuantifierBias::Greedy => 1,
+            QuantifierBias::Lazy => -1,
+            QuantifierBias::Neutral => 0,
+        };
+        let depth = self.query.statements[id].depth;
+        if self.query.char_runs[id] {
+            if let Some(end) = self.char_run(sub_pattern_id, pos) {
+                return Ok(self.char_run_matches(pos, end, min, max, bias, depth));
+            }
+        }
+
+        let mut final_map = MatchMap::default();
+        let mut layer = MatchMap::default();
+        layer.insert(
             pos,
-            0,
-            MatchTrace::default(),
-            input_len,
-            self.max_preference_depth,
-        ));
+            MatchOutcome::Unique(Match {
+                score: 0,
+                preference: Preference::with_size(self.query.max_preference_depth),
+                trace: MatchTrace::default(),
+            }),
+        );
 
-        for k in 1..=max {
-            let mut next_results_map = MatchMap::new(input_len);
-            if let VResult::Matches(prev_matches) = &results_by_k[k - 1] {
-                for (&cur_pos, outcome) in prev_matches.iter() {
-                    let res = self.viterbi(sub_pattern_id, cur_pos)?;
-                    if let VResult::Matches(sub_matches) = res {
-                        for (&next_pos, sub) in sub_matches.iter() {
-                            let new_outcome = match outcome {
-                                MatchOutcome::Unique(m) => MatchOutcome::extending_with_sub(m, sub),
-                                MatchOutcome::Ambiguous {
-                                    best_score: bs,
-                                    best_preference: bp,
-                                } => {
-                                    let (sub_score, sub_pref) = match sub {
-                                        MatchOutcome::Unique(sm) => (sm.score, &sm.preference),
-                                        MatchOutcome::Ambiguous {
-                                            best_score: s,
-                                            best_preference: p,
-                                        } => (*s, p),
-                                    };
-                                    let mut new_pref = bp.clone();
-                                    new_pref.combine(sub_pref);
-                                    MatchOutcome::Ambiguous {
-                                        best_score: bs + sub_score,
-                                        best_preference: new_pref,
-                                    }
-                                }
-                            };
-                            Self::merge_outcome(&mut next_results_map, next_pos, new_outcome);
+        // `layer` holds the ways of making exactly `reps` repetitions that each consume input;
+        // repetitions matching empty text only make up for a missing minimum, so an empty
+        // match can't be repeated forever nor placed between the others in several ways
+        let unbounded = max.is_none();
+        let max_reps = max.unwrap_or(min);
+        let mut reps = 0;
+        loop {
+            let mut next_layer = MatchMap::default();
+            for (&cur_pos, outcome) in layer.iter() {
+                if reps >= min && !unbounded {
+                    self.merge_outcome(&mut final_map, cur_pos, outcome.clone(), (id, pos));
+                }
+                if reps >= max_reps && reps >= min {
+                    continue;
+                }
+                let VResult::Matches(sub_matches) = self.viterbi(sub_pattern_id, cur_pos)? else {
+                    continue;
+                };
+                if reps < min {
+                    if let Some(empty) = sub_matches.get(cur_pos) {
+                        let mut padded = outcome.clone();
+                        for _ in reps..min {
+                            padded = MatchOutcome::extend(&padded, empty);
+                            padded.preference_mut().add_at(depth, bias);
+                        }
+                        self.merge_outcome(&mut final_map, cur_pos, padded, (id, pos));
+                    }
+                }
+                if reps < max_reps {
+                    for (&next_pos, sub) in sub_matches.iter() {
+                        if next_pos == cur_pos {
+                            continue;
+                        }
+                        let mut new_outcome = MatchOutcome::extend(outcome, sub);
+                        new_outcome.preference_mut().add_at(depth, bias);
+                        self.merge_outcome(&mut next_layer, next_pos, new_outcome, (id, pos));
+                    }
+                }
+            }
+            if reps == max_reps || next_layer.active.is_empty() {
+                break;
+            }
+            layer = next_layer;
+            reps += 1;
+        }
+
+        if unbounded && reps == min {
+            // any number of further repetitions: extend positions in increasing order, each
+            // once all the ways of reaching it are known
+            let mut pending: BinaryHeap<Reverse<usize>> =
+                layer.active.iter().map(|&p| Reverse(p)).collect();
+            while let Some(Reverse(cur_pos)) = pending.pop() {
+                let Some(outcome) = layer.get(cur_pos).cloned() else {
+                    continue;
+                };
+                if let VResult::Matches(sub_matches) = self.viterbi(sub_pattern_id, cur_pos)? {
+                    for (&next_pos, sub) in sub_matches.iter() {
+                        if next_pos == cur_pos {
+                            continue;
+                        }
+                        let mut new_outcome = MatchOutcome::extend(&outcome, sub);
+                        new_outcome.preference_mut().add_at(depth, bias);
+                        if layer.get(next_pos).is_none() {
+                            pending.push(Reverse(next_pos));
                         }
+                        self.merge_outcome(&mut layer, next_pos, new_outcome, (id, pos));
                     }
                 }
             }
-            if next_results_map.active.is_empty() {
-                break;
+            for (&next_pos, outcome) in layer.iter() {
+                self.merge_outcome(&mut final_map, next_pos, outcome.clone(), (id, pos));
             }
-            results_by_k.push(VResult::Matches(Rc::new(next_results_map)));
         }
-
-        // Collect results for k in min..=max
-        let mut pos_to_k_outcomes: HashMap<usize, Vec<(usize, MatchOutcome)>> = HashMap::new();
-        for k in min..results_by_k.len() {
-            if let VResult::Matches(matches) = &results_by_k[k] {
-                for (&next_pos, outcome) in matches.iter() {
-                    pos_to_k_outcomes
-                        .entry(next_pos)
-                        .or_default()
-                        .push((k, outcome.clone()));
-                }
-            }
+        if final_map.active.is_empty() {
+            Ok(VResult::NoMatch)
+        } else {
+            Ok(VResult::Matches(Rc::new(final_map)))
         }
+    }
 
-        let mut final_map = MatchMap::new(input_len);
-        for (next_pos, k_outcomes) in pos_to_k_outcomes {
-            let mut best_k_outcomes: Vec<(usize, MatchOutcome)> = Vec::new();
-
-            for (k, mut outcome) in k_outcomes {
-                // Apply local preference for this k
-                let k_pref = match mode {
-                    QuantifierBias::Greedy => k as i64,
-                    QuantifierBias::Lazy => -(k as i64),
-                    QuantifierBias::Neutral => 0,
-                };
-                let depth = self.indexed_statements[id].depth;
-                match &mut outcome {
-                    MatchOutcome::Unique(m) => m.preference.add_at(depth, k_pref),
-                    MatchOutcome::Ambiguous {
-                        best_preference, ..
-                    } => best_preference.add_at(depth, k_pref),
-                }
+    /// Where the run of characters pattern `id` matches from `pos` ends, if it is a builtin
+    /// matching one character at a time and nothing is recorded of its matches.
+    fn char_run(&self, id: PatternId, pos: usize) -> Option<usize> {
+        let FlatPattern::Builtin(builtin) = &self.query.statements[id].pattern else {
+            return None;
+        };
+        if self.boundaries.is_some()
+            || self.keyed[id]
+            || self.query.statements[id].capture.is_some()
+        {
+            return None;
+        }
+        let (mode, binary) = (self.case_mode, self.binary);
+        matches_char(builtin, '\0', mode, binary)?;
+        Some(scan::run_end(self.input, pos, |ch| {
+            matches_char(builtin, ch, mode, binary) == Some(true)
+        }))
+    }
 
-                if best_k_outcomes.is_empty() {
-                    best_k_outcomes.push((k, outcome));
-                } else {
-                    let (_, existing_outcome) = &best_k_outcomes[0];
-                    let existing_pref = match existing_outcome {
-                        MatchOutcome::Unique(m) => &m.preference,
-                        MatchOutcome::Ambiguous {
-                            best_preference, ..
-                        } => best_preference,
-                    };
-                    let new_pref = match &outcome {
-                        MatchOutcome::Unique(m) => &m.preference,
-                        MatchOutcome::Ambiguous {
-                            best_preference, ..
-                        } => best_preference,
-                    };
+    /// The matches of a repetition of single characters from `pos`, `min` to `max` of the
+    /// ones up to `end`: one per count, preferred by `bias` times the count, as if each
+    /// character were matched one at a time.
+    fn char_run_matches(
+        &self,
+        pos: usize,
+        end: usize,
+        min: usize,
+        max: Option<usize>,
+        bias: i64,
+        depth: usize,
+    ) -> VResult {
+        let ends = std::iter::once(pos).chain(
+            self.input[pos..end]
+                .char_indices()
+                .map(|(i, ch)| pos + i + ch.len_utf8()),
+        );
+        let mut matches = MatchMap::default();
+        for (count, next_pos) in ends.enumerate().skip(min) {
+            if max.is_some_and(|max| count > max) {
+                break;
+            }
+            let mut preference = Preference::with_size(self.query.max_preference_depth);
+            if bias != 0 {
+                preference.add_at(depth, bias * count as i64);
+            }
+            matches.insert(
+                next_pos,
+                MatchOutcome::Unique(Match {
+                    score: (next_pos - pos) as i64,
+                    preference,
+                    trace: MatchTrace::default(),
+                }),
+            );
+        }
+        if matches.active.is_empty() {
+            VResult::NoMatch
+        } else {
+            VResult::Matches(Rc::new(matches))
+        }
+    }
 
-                    if new_pref > existing_pref {
-                        best_k_outcomes.clear();
-                        best_k_outcomes.push((k, outcome));
-                    } else if new_pref == existing_pref {
-                        best_k_outcomes.push((k, outcome));
+    /// Matches `items` in every order, one more of them at a time: the matches of each subset
+    /// of them that can come first, by where they end, are what the next item extends.
+    ///
+    /// With `optional`, the items that can match empty text are left out instead, so the
+    /// subsets holding every other item end a match too.
+    fn eval_permute(
+        &mut self,
+        id: PatternId,
+        pos: usize,
+        items: &[PatternId],
+        optional: bool,
+    ) -> StrqlResult<VResult> {
+        let mut start = MatchMap::default();
+        start.insert(
+            pos,
+            MatchOutcome::Unique(Match {
+                score: 0,
+                preference: Preference::with_size(self.query.max_preference_depth),
+                trace: MatchTrace::default(),
+            }),
+        );
+        let required = items
+            .iter()
+            .enumerate()
+            .filter(|&(_, &item)| !(optional && self.query.lookahead[item].nullable))
+            .fold(0usize, |mask, (i, _)| mask | (1 << i));
+        let mut matched = MatchMap::default();
+        let mut layer = BTreeMap::from([(0usize, start)]);
+        for size in 0..=items.len() {
+            for (mask, matches) in &layer {
+                if mask & required == required {
+                    for (&end, outcome) in matches.iter() {
+                        self.merge_outcome(&mut matched, end, outcome.clone(), (id, pos));
                     }
                 }
             }
-
-            if best_k_outcomes.len() > 1 {
-                let score = match &best_k_outcomes[0].1 {
-                    MatchOutcome::Unique(m) => m.score,
-                    MatchOutcome::Ambiguous { best_score, .. } => *best_score,
-                };
-                let pref = match &best_k_outcomes[0].1 {
-                    MatchOutcome::Unique(m) => m.preference.clone(),
-                    MatchOutcome::Ambiguous {
-                        best_preference, ..
-                    } => best_preference.clone(),
-                };
-                final_map.data[next_pos] = Some(MatchOutcome::Ambiguous {
-                    best_score: score,
-                    best_preference: pref,
-                });
-                final_map.active.push(next_pos);
-            } else if !best_k_outcomes.is_empty() {
-                final_map.data[next_pos] = Some(best_k_outcomes.remove(0).1);
-                final_map.active.push(next_pos);
+            if size == items.len() {
+                break;
+            }
+            let mut next_layer: BTreeMap<usize, MatchMap> = BTreeMap::new();
+            for (mask, matches) in &layer {
+                for (i, &item) in items.iter().enumerate() {
+                    if mask & (1 << i) != 0 {
+                        continue;
+                    }
+                    let next_map = next_layer.entry(mask | (1 << i)).or_default();
+                    for (&cur_pos, outcome) in matches.iter() {
+                        if let VResult::Matches(sub_matches) = self.viterbi(item, cur_pos)? {
+                            for (&next_pos, sub) in sub_matches.iter() {
+                                // a left out item is not matched empty too
+                                if optional && next_pos == cur_pos {
+                                    continue;
+                                }
+                                self.merge_outcome(
+                                    next_map,
+                                    next_pos,
+                                    MatchOutcome::extend(outcome, sub),
+                                    (id, pos),
+                                );
+                            }
+                        }
+                    }
+                }
             }
+            next_layer.retain(|_, matches| !matches.active.is_empty());
+            layer = next_layer;
         }
-
-        if final_map.active.is_empty() {
-            Ok(VResult::NoMatch)
+        Ok(if matched.active.is_empty() {
+            VResult::NoMatch
         } else {
-            Ok(VResult::Matches(Rc::new(final_map)))
-        }
+            VResult::Matches(Rc::new(matched))
+        })
     }
 
     fn eval_builtin(&self, id: PatternId, pos: usize) -> StrqlResult<VResult> {
         let input_len = self.input.len();
-        let b = match &self.indexed_statements[id].pattern {
+        let b = match &self.query.statements[id].pattern {
             FlatPattern::Builtin(b) => b,
             _ => {
                 return Err(StrqlError::Internal {
@@ -970,120 +2492,100 @@ impl<'a> Solver<'a> {
         let input = self.input;
         let rest = &input[pos..];
 
+        if let Builtin::Bof | Builtin::Eof = b {
+            let at = if *b == Builtin::Bof { 0 } else { input_len };
+            return Ok(if pos == at {
+                VResult::single(
+                    pos,
+                    0,
+                    MatchTrace::default(),
+                    input_len,
+                    self.query.max_preference_depth,
+                )
+            } else {
+                VResult::NoMatch
+            });
+        }
         if rest.is_empty() {
             return Ok(VResult::NoMatch);
         }
 
-        match b {
-            Builtin::Letter => {
-                let ch = rest.chars().next().unwrap();
-                let matched = match self.case_mode {
-                    CaseMode::Normal | CaseMode::AnyCase => ch.is_ascii_alphabetic(),
-                    CaseMode::Upper => ch.is_ascii_uppercase(),
-                    CaseMode::Lower => ch.is_ascii_lowercase(),
-                };
-                if matched {
-                    let len = ch.len_utf8();
-                    Ok(VResult::single(
-                        pos + len,
-                        len as i64,
-                        MatchTrace::default(),
-                        input_len,
-                        self.max_preference_depth,
-                    ))
-                } else {
-                    Ok(VResult::NoMatch)
-                }
-            }
-
-            Builtin::Digit => {
-                let ch = rest.chars().next().unwrap();
-                if ch.is_ascii_digit() {
-                    let len = ch.len_utf8();
-                    Ok(VResult::single(
-                        pos + len,
-                        len as i64,
-                        MatchTrace::default(),
-                        input_len,
-                        self.max_preference_depth,
-                    ))
-                } else {
-                    Ok(VResult::NoMatch)
-                }
-            }
+        let ch = rest.chars().next().unwrap();
+        if let Some(matched) = matches_char(b, ch, self.case_mode, self.binary) {
+            return Ok(if matched {
+                let len = ch.len_utf8();
+                VResult::single(
+                    pos + len,
+                    len as i64,
+                    MatchTrace::default(),
+                    input_len,
+                    self.query.max_preference_depth,
+                )
+            } else {
+                VResult::NoMatch
+            });
+        }
 
-            Builtin::Space => {
-                let ch = rest.chars().next().unwrap();
-                if ch.is_whitespace() && ch != '\n' {
-                    let len = ch.len_utf8();
-                    Ok(VResult::single(
-                        pos + len,
-                        len as i64,
-                        MatchTrace::default(),
-                        input_len,
-                        self.max_preference_depth,
-                    ))
+        match b {
+            Builtin::Bytes(count) => {
+                let end = if self.binary {
+                    rest.char_indices()
+                        .map(|(i, _)| i)
+                        .chain([rest.len()])
+                        .nth(*count)
                 } else {
-                    Ok(VResult::NoMatch)
-                }
-            }
-
-            Builtin::Newline => {
-                if rest.starts_with('\n') {
-                    Ok(VResult::single(
-                        pos + 1,
-                        1,
+                    // counted bytes of text can't split characters
+                    Some(*count).filter(|&count| rest.is_char_boundary(count))
+                };
+                match end {
+                    Some(end) => Ok(VResult::single(
+                        pos + end,
+                        end as i64,
                         MatchTrace::default(),
                         input_len,
-                        self.max_preference_depth,
-                    ))
-                } else {
-                    Ok(VResult::NoMatch)
+                        self.query.max_preference_depth,
+                    )),
+                    None => Ok(VResult::NoMatch),
                 }
             }
 
-            Builtin::AnyChar => {
-                let ch = rest.chars().next().unwrap();
-                let ok = match self.case_mode {
-                    CaseMode::Normal | CaseMode::AnyCase => true,
-                    CaseMode::Upper => !ch.is_ascii_lowercase(),
-                    CaseMode::Lower => !ch.is_ascii_uppercase(),
+            Builtin::Base64 | Builtin::HexBlob | Builtin::JsonValue => {
+                let len = match b {
+                    Builtin::Base64 => base64_len(rest.as_bytes()),
+                    Builtin::HexBlob => hex_blob_len(rest.as_bytes()),
+                    _ => json_value_len(rest),
                 };
-                if ok {
-                    let len = ch.len_utf8();
-                    Ok(VResult::single(
+                match len {
+                    Some(len) => Ok(VResult::single(
                         pos + len,
                         len as i64,
                         MatchTrace::default(),
                         input_len,
-                        self.max_preference_depth,
-                    ))
-                } else {
-                    Ok(VResult::NoMatch)
+                        self.query.max_preference_depth,
+                    )),
+                    None => Ok(VResult::NoMatch),
                 }
             }
 
-            Builtin::Line => {
-                let mut end = pos;
-                let mut has_lowercase = false;
-                let mut has_uppercase = false;
-                for ch in rest.chars() {
-                    if ch == '\n' {
-                        break;
-                    }
-                    if ch.is_ascii_lowercase() {
-                        has_lowercase = true;
-                    }
-                    if ch.is_ascii_uppercase() {
-                        has_uppercase = true;
+            Builtin::Line | Builtin::Paragraph | Builtin::ContLine => {
+                let text = match b {
+                    // a paragraph ends at the first blank line
+                    Builtin::Paragraph => &rest[..scan::find(rest, "\n\n").unwrap_or(rest.len())],
+                    Builtin::ContLine => {
+                        if !rest.starts_with("\n ") && !rest.starts_with("\n\t") {
+                            return Ok(VResult::NoMatch);
+                        }
+                        let line = &rest[1..];
+                        &rest[..1 + scan::find(line, "\n").unwrap_or(line.len())]
                     }
-                    end += ch.len_utf8();
-                }
+                    _ => &rest[..scan::find(rest, "\n").unwrap_or(rest.len())],
+                };
+                let end = pos + text.len();
 
                 let ok = match self.case_mode {
                     CaseMode::Normal | CaseMode::AnyCase => true,
-                    CaseMode::Upper => !has_lowercase,
-                    CaseMode::Lower => !has_uppercase,
+                    CaseMode::Upper => !text.bytes().any(|c| c.is_ascii_lowercase()),
+                    CaseMode::Lower => !text.bytes().any(|c| c.is_ascii_uppercase()),
                 };
 
                 if ok {
@@ -1092,69 +2594,394 @@ impl<'a> Solver<'a> {
                         (end - pos) as i64,
                         MatchTrace::default(),
                         input_len,
-                        self.max_preference_depth,
+                        self.query.max_preference_depth,
                     ))
                 } else {
                     Ok(VResult::NoMatch)
                 }
             }
+
+            _ => Err(StrqlError::Internal {
+                _message: "single character builtin not matched by matches_char",
+            }),
+        }
+    }
+
+    fn eval_custom(&self, name: &str, pos: usize) -> StrqlResult<VResult> {
+        let Some(matcher) = self.query.custom.get(name) else {
+            return Err(StrqlError::UnregisteredBuiltin {
+                _name: name.to_string(),
+            });
+        };
+        match matcher(self.input, pos) {
+            None => Ok(VResult::NoMatch),
+            Some(end) if end == pos => Ok(VResult::NoMatch),
+            Some(end) if end > pos && self.input.is_char_boundary(end) => Ok(VResult::single(
+                end,
+                (end - pos) as i64,
+                MatchTrace::default(),
+                self.input.len(),
+                self.query.max_preference_depth,
+            )),
+            Some(end) => Err(StrqlError::InvalidBuiltinMatch {
+                _name: name.to_string(),
+                _pos: pos,
+                _end: end,
+            }),
+        }
+    }
+
+    /// Every word of the dictionary found at `pos`, in the current case mode.
+    fn eval_dict(&self, name: &str, pos: usize) -> StrqlResult<VResult> {
+        let Some(dict) = self.query.custom.dict(name) else {
+            return Err(StrqlError::UnregisteredBuiltin {
+                _name: format!("DICT({})", name),
+            });
+        };
+        let mut matches = MatchMap::default();
+        for end in dict.ends(self.input, pos, self.case_mode != CaseMode::Normal) {
+            let mut text = self.input[pos..end].chars();
+            let allowed = match self.case_mode {
+                CaseMode::Upper => !text.any(char::is_lowercase),
+                CaseMode::Lower => !text.any(char::is_uppercase),
+                CaseMode::Normal | CaseMode::AnyCase => true,
+            };
+            if allowed {
+                matches.insert(
+                    end,
+                    MatchOutcome::Unique(Match {
+                        score: (end - pos) as i64,
+                        preference: Preference::with_size(self.query.max_preference_depth),
+                        trace: MatchTrace::default(),
+                    }),
+                );
+            }
+        }
+        if matches.active.is_empty() {
+            Ok(VResult::NoMatch)
+        } else {
+            Ok(VResult::Matches(Rc::new(matches)))
         }
     }
 
     // ---------------- CAPTURE REPLAY ----------------
 
-    fn replay_captures(&self, trace: &MatchTrace) -> Value {
+    fn replay_captures(
+        &self,
+        trace: &MatchTrace,
+        mut stream: Option<&mut Stream<'_>>,
+        spans: Option<&mut Vec<CaptureSpan>>,
+    ) -> StrqlResult<Value> {
         let mut root = json!({});
+        // where each capture was stored, with its span and statement, while `spans` is given
+        let mut stored: Option<Vec<StoredCapture>> = spans.is_some().then(Vec::new);
         let mut named_paths: HashMap<String, Vec<ResolvedSegment>> = HashMap::new();
         let mut captured_values: HashMap<String, String> = HashMap::new();
+        let mut writes = FieldWrites::default();
+        // arrays to reshape once every capture is in
+        let mut finalizers: Vec<(Vec<ResolvedSegment>, Finalizer)> = Vec::new();
+        // NULLABLE fields are present as soon as the object holding them is
+        let nullable_slots: Vec<(Option<&str>, &str)> = self
+            .query
+            .statements
+            .iter()
+            .filter_map(|s| s.capture.as_ref())
+            .filter(|c| c.nullable)
+            .filter_map(nullable_slot)
+            .collect();
+        fill_nulls(&mut root, &nullable_slots, None);
 
-        for event in &trace.events {
+        for event in trace.events() {
             match event {
-                TraceEvent::VariableMatch { name, value } => {
+                TraceEvent::VariableMatch { statement, span } => {
                     // Track variable matches for dynamic field resolution
-                    captured_values.insert(name.clone(), value.clone());
+                    let stmt = &self.query.statements[*statement];
+                    captured_values.insert(stmt.name.clone(), self.variable_value(stmt, span));
                 }
-                TraceEvent::Capture {
-                    value,
-                    clause,
-                    explicit_name,
-                } => {
-                    // Store the captured value first so it's available for dynamic fields
-                    if !clause.name.is_empty() {
-                        captured_values.insert(clause.name.clone(), value.to_string());
+                TraceEvent::Capture { statement, span } => {
+                    let stmt = &self.query.statements[*statement];
+                    if let (FlatPattern::Unmatched(_), Value::Object(fields)) =
+                        (&stmt.pattern, &mut root)
+                    {
+                        let unmatched = fields.entry("_unmatched").or_insert_with(|| json!([]));
+                        if let Value::Array(unmatched) = unmatched {
+                            unmatched.push(self.unmatched_value(span));
+                            if let Some(stored) = &mut stored {
+                                let path = vec![
+                                    ResolvedSegment::Root,
+                                    ResolvedSegment::Field("_unmatched".to_string()),
+                                    ResolvedSegment::Index(unmatched.len() - 1),
+                                ];
+                                stored.push((path, span, &stmt.name));
+                            }
+                        }
+                        continue;
                     }
-                    self.apply_capture(
+                    let Some(clause) = applied_clause(stmt) else {
+                        continue;
+                    };
+                    let group_by = stmt.capture.as_ref().and_then(|c| c.group_by.as_deref());
+                    let clause = clause.as_ref();
+                    let value = self.captured_value(clause, span);
+                    // Store the captured value first so it's available for dynamic fields
+                    captured_values.insert(clause.name.clone(), value.clone());
+                    let written = self.apply_capture(
                         &mut root,
                         &mut named_paths,
                         &captured_values,
-                        value,
+                        span,
                         clause,
-                        *explicit_name,
-                    );
+                        &stmt.name,
+                    )?;
+                    if let Some(path) = &written {
+                        self.record_write(&mut writes, clause, path, span)?;
+                        if let Some(stored) = &mut stored {
+                            let mut path = path.clone();
+                            if !clause.path.ends_with_array()
+                                && matches!(
+                                    path.last(),
+                                    Some(ResolvedSegment::Root | ResolvedSegment::Index(_))
+                                )
+                            {
+                                // values land in a field of the object the path leads to
+                                path.push(ResolvedSegment::Field(clause.name.clone()));
+                            }
+                            stored.push((path, span, &stmt.name));
+                        }
+                    }
+
+                    if let (true, Some(path)) = (clause.is_object, &written) {
+                        if let Some(object) = value_at(&mut root, path) {
+                            fill_nulls(object, &nullable_slots, Some(&clause.name));
+                        }
+                    }
+
+                    if let Some(stream) = stream.as_deref_mut() {
+                        stream.emit_complete(&mut root);
+                    }
+
+                    let finalizer = match (group_by, clause.sort) {
+                        (Some(by), _) => Finalizer::Group(by),
+                        (None, Some(order)) => Finalizer::Sort(order),
+                        (None, None) => continue,
+                    };
+                    if let Some(mut array_path) = written {
+                        array_path.pop();
+                        if !finalizers.iter().any(|(p, _)| *p == array_path) {
+                            finalizers.push((array_path, finalizer));
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(stream) = stream {
+            stream.finish(&mut root);
+        }
+
+        // deepest first, so that the indices in the enclosing paths stay valid
+        finalizers.sort_by_key(|(path, _)| std::cmp::Reverse(path.len()));
+        for (path, finalizer) in finalizers {
+            let Some(array) = value_at(&mut root, &path) else {
+                continue;
+            };
+            let moves = finalizer.apply(array, self.options.provenance);
+            // the paths through the array now lead to where its items moved
+            for (stored, _, _) in stored.iter_mut().flatten() {
+                if !stored.starts_with(&path) {
+                    continue;
                 }
+                let Some(&ResolvedSegment::Index(old)) = stored.get(path.len()) else {
+                    continue;
+                };
+                let (key, index) = &moves[old];
+                let mut moved = key
+                    .iter()
+                    .cloned()
+                    .map(ResolvedSegment::Field)
+                    .collect::<Vec<_>>();
+                moved.push(ResolvedSegment::Index(*index));
+                stored.splice(path.len()..=path.len(), moved);
             }
         }
 
-        root
+        if let (Some(spans), Some(stored)) = (spans, stored) {
+            spans.extend(
+                stored
+                    .into_iter()
+                    .map(|(path, span, statement)| CaptureSpan {
+                        path: path_steps(&path),
+                        span: span.clone(),
+                        statement: statement.to_string(),
+                    }),
+            );
+        }
+        Ok(root)
     }
 
-    fn apply_capture(
+    /// Replays the captures of `trace` into `sink`, tracking only what resolving their paths
+    /// needs: named paths, key values and the lengths of arrays.
+    fn replay_into_sink(
         &self,
-        root: &mut Value,
-        named_paths: &mut HashMap<String, Vec<ResolvedSegment>>,
-        captured_values: &HashMap<String, String>,
-        value: &str,
+        trace: &MatchTrace,
+        sink: &mut dyn FnMut(CaptureEventView<'_>),
+    ) -> StrqlResult<()> {
+        let mut named_paths: HashMap<String, Vec<ResolvedSegment>> = HashMap::new();
+        let mut captured_values: HashMap<String, String> = HashMap::new();
+        let mut lengths: HashMap<Vec<ResolvedSegment>, usize> = HashMap::new();
+        let mut writes = FieldWrites::default();
+        let mut unmatched = 0;
+
+        for event in trace.events() {
+            match event {
+                TraceEvent::VariableMatch { statement, span } => {
+                    let stmt = &self.query.statements[*statement];
+                    captured_values.insert(stmt.name.clone(), self.variable_value(stmt, span));
+                }
+                TraceEvent::Capture { statement, span } => {
+                    let stmt = &self.query.statements[*statement];
+                    if let FlatPattern::Unmatched(_) = stmt.pattern {
+                        sink(CaptureEventView {
+                            path: &[
+                                PathStep::Field("_unmatched".to_string()),
+                                PathStep::Index(unmatched),
+                            ],
+                            value: &self.unmatched_value(span),
+                            span,
+                            statement: &stmt.name,
+                        });
+                        unmatched += 1;
+                        continue;
+                    }
+                    let Some(clause) = applied_clause(stmt) else {
+                        continue;
+                    };
+                    let clause = clause.as_ref();
+                    let text = self.captured_value(clause, span);
+                    captured_values.insert(clause.name.clone(), text.clone());
+
+                    let mut path = Self::resolve_segments(clause, &named_paths, &captured_values);
+                    let mut stored_at = None;
+                    if clause.path.ends_with_array() {
+                        if !clause.is_object && !clause.nullable && text.is_empty() {
+                            continue;
+                        }
+                        let length = lengths.entry(path.clone()).or_default();
+                        path.push(ResolvedSegment::Index(*length));
+                        *length += 1;
+                    } else if matches!(
+                        path.last(),
+                        Some(ResolvedSegment::Root | ResolvedSegment::Index(_))
+                    ) {
+                        // values land in a field of the object the path leads to
+                        let mut field = path.clone();
+                        field.push(ResolvedSegment::Field(clause.name.clone()));
+                        stored_at = Some(field);
+                    }
+                    self.record_write(&mut writes, clause, &path, span)?;
+                    let value = self.capture_value(clause, &text, span)?;
+                    sink(CaptureEventView {
+                        path: &path_steps(stored_at.as_ref().unwrap_or(&path)),
+                        value: &value,
+                        span,
+                        statement: &stmt.name,
+                    });
+                    named_paths.insert(clause.name.clone(), path);
+                }
+            }
+        }
+        if let Some(field) = &self.query.options.capture_raw {
+            sink(CaptureEventView {
+                path: &[PathStep::Field(field.clone())],
+                value: &Value::String(self.input.to_string()),
+                span: &(0..self.input.len()),
+                statement: "TEXT",
+            });
+        }
+        Ok(())
+    }
+
+    /// Records that `clause` wrote the field `path` leads to, failing with
+    /// [`StrqlError::CapturedTwice`] if the field was written before and either capture is
+    /// `ONCE`.
+    fn record_write(
+        &self,
+        writes: &mut FieldWrites,
         clause: &CaptureClause,
-        _explicit_name: bool,
-    ) {
+        path: &[ResolvedSegment],
+        span: &Range<usize>,
+    ) -> StrqlResult<()> {
+        if clause.path.ends_with_array() {
+            return Ok(());
+        }
+        let mut field = path.to_vec();
+        if matches!(
+            field.last(),
+            Some(ResolvedSegment::Root | ResolvedSegment::Index(_))
+        ) {
+            // values land in a field of the object the path leads to
+            field.push(ResolvedSegment::Field(clause.name.clone()));
+        }
+        let Some((first, once)) = writes.insert(field.clone(), (span.clone(), clause.once)) else {
+            return Ok(());
+        };
+        if !(once || clause.once) {
+            return Ok(());
+        }
+        let window = first.start.min(span.start)..first.end.max(span.end);
+        Err(StrqlError::CapturedTwice {
+            _path: describe_path(&field),
+            _src: self.src_window(window),
+            _first: first.into(),
+            _span: span.clone().into(),
+        })
+    }
+
+    /// What `ROOT._unmatched[]` holds for the text of `span` that `OPTION SKIP_UNMATCHED`
+    /// skipped.
+    fn unmatched_value(&self, span: &Range<usize>) -> Value {
+        json!({ "text": &self.input[span.clone()], "span": [span.start, span.end] })
+    }
+
+    /// The text a match of `stmt` over `span` stands for in `[stmt]` keys.
+    fn variable_value(&self, stmt: &FlatStatement, span: &Range<usize>) -> String {
+        let matched = &self.input[span.clone()];
+        // `ADD UPPER(x)` on statement `x` also normalizes `[x]` keys
+        let case = stmt
+            .capture
+            .as_ref()
+            .filter(|c| c.name.is_empty() || c.name == stmt.name)
+            .and_then(|c| c.case);
+        match case {
+            Some(case) => case.apply(matched),
+            None => matched.to_string(),
+        }
+    }
+
+    /// The text `clause` captures from `span`, before any cast.
+    fn captured_value(&self, clause: &CaptureClause, span: &Range<usize>) -> String {
+        let matched = &self.input[span.clone()];
+        match clause.case {
+            Some(case) => case.apply(matched),
+            None => matched.to_string(),
+        }
+    }
+
+    /// Where `clause` writes, as far as its path says: the named capture it starts from, then
+    /// its fields and keys (but not the element an `[]` appends).
+    fn resolve_segments(
+        clause: &CaptureClause,
+        named_paths: &HashMap<String, Vec<ResolvedSegment>>,
+        captured_values: &HashMap<String, String>,
+    ) -> Vec<ResolvedSegment> {
         let mut segments = Vec::new();
         let mut i = 0;
 
         // 1. Resolve starting point
-        if let Some(PathSegment::Root) = clause.path.segments.get(0) {
+        if let Some(PathSegment::Root) = clause.path.segments.first() {
             segments.push(ResolvedSegment::Root);
             i = 1;
-        } else if let Some(PathSegment::Field(name)) = clause.path.segments.get(0) {
+        } else if let Some(PathSegment::Field(name)) = clause.path.segments.first() {
             if let Some(path) = named_paths.get(name) {
                 segments.extend(path.clone());
                 i = 1;
@@ -1170,19 +2997,67 @@ impl<'a> Solver<'a> {
             match segment {
                 PathSegment::Root => {}
                 PathSegment::Field(name) => segments.push(ResolvedSegment::Field(name.clone())),
-                PathSegment::DynamicField(var) => {
-                    let name = captured_values.get(var).cloned().unwrap_or_default();
+                PathSegment::DynamicField(parts) => {
+                    let name = parts
+                        .iter()
+                        .map(|part| match part {
+                            KeyPart::Variable(var) => captured_values
+                                .get(var)
+                                .map(String::as_str)
+                                .unwrap_or_default(),
+                            KeyPart::Literal(text) => text.as_str(),
+                        })
+                        .collect();
                     segments.push(ResolvedSegment::Field(name));
                 }
                 PathSegment::ArrayAppend => {}
             }
         }
+        segments
+    }
+
+    /// The value `clause` stores for the captured text `value`, matched at `span`.
+    fn capture_value(
+        &self,
+        clause: &CaptureClause,
+        value: &str,
+        span: &Range<usize>,
+    ) -> StrqlResult<Value> {
+        let is_array_append = clause.path.ends_with_array();
+        Ok(match &clause.cast {
+            _ if clause.is_object => json!({}),
+            _ if clause.nullable && value.is_empty() => Value::Null,
+            // empty array captures are dropped below, so there is nothing to convert
+            Some(cast) if !(is_array_append && value.is_empty()) => apply_cast(cast, value)
+                .ok_or_else(|| StrqlError::CaptureCastFailed {
+                    _name: clause.name.clone(),
+                    _value: value.to_string(),
+                    _target: cast.describe(),
+                    _src: self.src_window(span.clone()),
+                    _span: span.clone().into(),
+                })?,
+            _ => Value::String(value.to_string()),
+        })
+    }
 
+    fn apply_capture(
+        &self,
+        root: &mut Value,
+        named_paths: &mut HashMap<String, Vec<ResolvedSegment>>,
+        captured_values: &HashMap<String, String>,
+        span: &Range<usize>,
+        clause: &CaptureClause,
+        rule: &str,
+    ) -> StrqlResult<Option<Vec<ResolvedSegment>>> {
+        let segments = Self::resolve_segments(clause, named_paths, captured_values);
         let is_array_append = clause.path.ends_with_array();
-        let val_to_insert = if clause.is_object {
-            json!({})
+        // the caller stores the captured value before applying the capture
+        let value = captured_values[&clause.name].as_str();
+        let val_to_insert = self.capture_value(clause, value, span)?;
+        let val_to_insert = if self.options.provenance && !clause.is_object {
+            json!({ "value": val_to_insert, "rule": rule })
         } else {
-            Value::String(value.to_string())
+            val_to_insert
         };
 
         let mut current = root;
@@ -1300,56 +3175,536 @@ impl<'a> Solver<'a> {
             }
         }
 
-        if is_array_append {
-            if !clause.is_object && value.is_empty() {
-                return;
-            }
+        if is_array_append {
+            if !clause.is_object && !clause.nullable && value.is_empty() {
+                return Ok(None);
+            }
+
+            if !current.is_array() {
+                *current = json!([]);
+            }
+            let arr = current.as_array_mut().unwrap();
+            // objects are still empty here, so only plain values are deduplicated
+            let unique = clause.unique && !clause.is_object;
+            match arr.iter().position(|v| unique && *v == val_to_insert) {
+                Some(existing) => current_path.push(ResolvedSegment::Index(existing)),
+                None => {
+                    arr.push(val_to_insert);
+                    current_path.push(ResolvedSegment::Index(arr.len() - 1));
+                }
+            }
+        }
+
+        if !clause.name.is_empty() {
+            named_paths.insert(clause.name.clone(), current_path.clone());
+        }
+        Ok(Some(current_path))
+    }
+}
+
+/// The radix of the `HEXNUM`, `BINNUM` or `OCTNUM` a statement matches, directly or through
+/// variables naming one, so that `AS INT` reads its unprefixed values in that radix.
+fn number_radix(pattern: &Pattern, patterns: &HashMap<&str, &Pattern>) -> Option<u32> {
+    let mut pattern = pattern;
+    // bounded, as the variables may refer to each other
+    for _ in 0..patterns.len() {
+        match &pattern.node {
+            PatternKind::Variable(name) => pattern = patterns.get(name.as_str())?,
+            PatternKind::Group(inner) => pattern = inner,
+            _ => return pattern.number_radix(),
+        }
+    }
+    None
+}
+
+/// The clause a capture of `stmt` is applied with: named after the statement when it doesn't
+/// say what it adds, and appending to an array when grouped.
+fn applied_clause(stmt: &FlatStatement) -> Option<Cow<'_, CaptureClause>> {
+    let clause = stmt.capture.as_ref()?;
+    let mut applied = Cow::Borrowed(clause);
+    if clause.name.is_empty() {
+        applied.to_mut().name = stmt.name.clone();
+    }
+    if clause.group_by.is_some() && !clause.path.ends_with_array() {
+        let applied = applied.to_mut();
+        applied.path = applied.path.clone().add_array_append();
+    }
+    Some(applied)
+}
+
+/// The object (`None` for ROOT, or the name of an `item{}` capture) and key a NULLABLE clause
+/// writes to, when that can be told without running the query.
+fn nullable_slot(clause: &CaptureClause) -> Option<(Option<&str>, &str)> {
+    match clause.path.segments.as_slice() {
+        [PathSegment::Root] => Some((None, &clause.name)),
+        [PathSegment::Root, PathSegment::Field(key)] => Some((None, key)),
+        [PathSegment::Field(owner)] => Some((Some(owner), &clause.name)),
+        [PathSegment::Field(owner), PathSegment::Field(key)] => Some((Some(owner), key)),
+        _ => None,
+    }
+}
+
+fn fill_nulls(object: &mut Value, slots: &[(Option<&str>, &str)], owner: Option<&str>) {
+    if let Value::Object(fields) = object {
+        for (_, key) in slots.iter().filter(|(o, _)| *o == owner) {
+            fields.entry(*key).or_insert(Value::Null);
+        }
+    }
+}
+
+fn value_at<'v>(root: &'v mut Value, path: &[ResolvedSegment]) -> Option<&'v mut Value> {
+    let mut current = root;
+    for seg in path {
+        current = match seg {
+            ResolvedSegment::Root => current,
+            ResolvedSegment::Field(name) => current.get_mut(name.as_str())?,
+            ResolvedSegment::Index(idx) => current.get_mut(*idx)?,
+        };
+    }
+    Some(current)
+}
+
+/// The elements of an array of the output, handed over one at a time as replay completes
+/// them; see [`CompiledQuery::evaluate_streaming`].
+struct Stream<'f> {
+    /// fields leading from ROOT to the array
+    path: Vec<ResolvedSegment>,
+    on_record: &'f mut dyn FnMut(Value),
+    /// how many elements were handed over; their slots are left empty, so that the indices
+    /// the paths of `item{}` captures hold stay valid
+    emitted: usize,
+}
+
+impl Stream<'_> {
+    /// Hands over the elements before the last one, which captures can't reach anymore.
+    fn emit_complete(&mut self, root: &mut Value) {
+        let Some(Value::Array(items)) = value_at(root, &self.path) else {
+            return;
+        };
+        let complete = items.len().saturating_sub(1);
+        for item in items.iter_mut().take(complete).skip(self.emitted) {
+            (self.on_record)(item.take());
+        }
+        self.emitted = self.emitted.max(complete);
+    }
+
+    /// Hands over the remaining elements and removes the array from the output.
+    fn finish(&mut self, root: &mut Value) {
+        let Some((ResolvedSegment::Field(name), parent)) = self.path.split_last() else {
+            return;
+        };
+        let Some(Value::Object(fields)) = value_at(root, parent) else {
+            return;
+        };
+        if let Some(Value::Array(items)) = fields.remove(name) {
+            for item in items.into_iter().skip(self.emitted) {
+                (self.on_record)(item);
+            }
+        }
+    }
+}
+
+/// Where a capture is stored in the output, with the span it was captured from and the
+/// statement that captured it.
+type StoredCapture<'t> = (Vec<ResolvedSegment>, &'t Range<usize>, &'t str);
+
+/// Post-processing of a captured array, applied after replay.
+enum Finalizer<'c> {
+    /// `GROUP ... BY field`: becomes an object of arrays, keyed by each element's `field`
+    Group(&'c str),
+    /// `SORTED [DESC]`
+    Sort(SortOrder),
+}
+
+impl Finalizer<'_> {
+    /// With `provenance`, values are compared by what they wrap. Returns where each item
+    /// moved, by its index before: the key of its group, if grouped, and its index.
+    fn apply(self, array: &mut Value, provenance: bool) -> Vec<(Option<String>, usize)> {
+        let Value::Array(items) = array else {
+            return Vec::new();
+        };
+        let leaf = |value: &Value| -> Value {
+            match value.get("value") {
+                Some(inner) if provenance => inner.clone(),
+                _ => value.clone(),
+            }
+        };
+        match self {
+            Finalizer::Group(by) => {
+                let mut groups = serde_json::Map::new();
+                let mut moves = Vec::with_capacity(items.len());
+                for item in std::mem::take(items) {
+                    let key = match item.get(by).map(leaf).as_ref() {
+                        Some(Value::String(s)) => s.clone(),
+                        Some(Value::Null) | None => String::new(),
+                        Some(other) => other.to_string(),
+                    };
+                    let bucket = groups.entry(key.clone()).or_insert_with(|| json!([]));
+                    if let Value::Array(bucket) = bucket {
+                        bucket.push(item);
+                        moves.push((Some(key), bucket.len() - 1));
+                    }
+                }
+                *array = Value::Object(groups);
+                moves
+            }
+            Finalizer::Sort(order) => {
+                let mut sorted: Vec<(usize, Value)> =
+                    std::mem::take(items).into_iter().enumerate().collect();
+                sorted.sort_by(|(_, a), (_, b)| match order {
+                    SortOrder::Ascending => compare_values(&leaf(a), &leaf(b)),
+                    SortOrder::Descending => compare_values(&leaf(b), &leaf(a)),
+                });
+                let mut moves = vec![(None, 0); sorted.len()];
+                for (new, (old, item)) in sorted.into_iter().enumerate() {
+                    moves[old].1 = new;
+                    items.push(item);
+                }
+                moves
+            }
+        }
+    }
+}
+
+/// The steps of `path` from the root of the output.
+fn path_steps(path: &[ResolvedSegment]) -> Vec<PathStep> {
+    path.iter()
+        .filter_map(|segment| match segment {
+            ResolvedSegment::Root => None,
+            ResolvedSegment::Field(name) => Some(PathStep::Field(name.clone())),
+            ResolvedSegment::Index(i) => Some(PathStep::Index(*i)),
+        })
+        .collect()
+}
+
+/// Whether `builtin` matches `ch`, for the builtins matching one character at a time; `None`
+/// for the others.
+fn matches_char(builtin: &Builtin, ch: char, mode: CaseMode, binary: bool) -> Option<bool> {
+    // case modifiers rule out the letters of the other case
+    let in_case = match mode {
+        CaseMode::Normal | CaseMode::AnyCase => true,
+        CaseMode::Upper => !ch.is_ascii_lowercase(),
+        CaseMode::Lower => !ch.is_ascii_uppercase(),
+    };
+    Some(match builtin {
+        Builtin::Letter => ch.is_ascii_alphabetic() && in_case,
+        Builtin::Digit => ch.is_ascii_digit(),
+        Builtin::HexDigit => ch.is_ascii_hexdigit() && in_case,
+        Builtin::BinDigit => ch.is_digit(2),
+        Builtin::OctDigit => ch.is_digit(8),
+        Builtin::Space => ch.is_whitespace() && ch != '\n',
+        Builtin::Newline => ch == '\n',
+        Builtin::AnyChar => in_case,
+        // in text, bytes past ASCII are only parts of characters
+        Builtin::Byte(byte) => ch == char::from(*byte) && (binary || byte.is_ascii()),
+        _ => return None,
+    })
+}
+
+/// The length of the prefix of `input` matching `literal` under `mode`, or on a mismatch the
+/// length of the part that matched before it.
+///
+/// Case-insensitive modes compare character by character, so the matched text can have a
+/// different byte length than the literal (`"k"` matches the Kelvin sign `"\u{212A}"`).
+fn match_literal(input: &str, literal: &str, mode: CaseMode) -> Result<usize, usize> {
+    if mode == CaseMode::Normal {
+        if input.starts_with(literal) {
+            return Ok(literal.len());
+        }
+        let mut common = input
+            .bytes()
+            .zip(literal.bytes())
+            .take_while(|(a, b)| a == b)
+            .count();
+        while !input.is_char_boundary(common) {
+            common -= 1;
+        }
+        return Err(common);
+    }
+    let mut chars = input.char_indices();
+    let mut len = 0;
+    for expected in literal.chars() {
+        let Some((i, c)) = chars.next() else {
+            return Err(len);
+        };
+        let case_allowed = match mode {
+            CaseMode::Upper => !c.is_lowercase(),
+            CaseMode::Lower => !c.is_uppercase(),
+            CaseMode::Normal | CaseMode::AnyCase => true,
+        };
+        if !case_allowed || (c != expected && !c.to_lowercase().eq(expected.to_lowercase())) {
+            return Err(len);
+        }
+        len = i + c.len_utf8();
+    }
+    Ok(len)
+}
+
+/// The length of the base64 run `input` starts with, with its padding if it's valid, or `None`
+/// if the run's length can't be that of encoded bytes.
+fn base64_len(input: &[u8]) -> Option<usize> {
+    let run = input
+        .iter()
+        .take_while(|&&b| b.is_ascii_alphanumeric() || b"+/-_".contains(&b))
+        .count();
+    let padding = input[run..]
+        .iter()
+        .take(2)
+        .take_while(|&&b| b == b'=')
+        .count();
+    if run > 0 && padding > 0 && (run + padding).is_multiple_of(4) {
+        Some(run + padding)
+    } else {
+        (run % 4 != 1).then_some(run).filter(|&run| run > 0)
+    }
+}
 
-            if !current.is_array() {
-                *current = json!([]);
+/// The length of the run of hex digits `input` starts with, if it encodes whole bytes.
+fn hex_blob_len(input: &[u8]) -> Option<usize> {
+    let run = input.iter().take_while(|b| b.is_ascii_hexdigit()).count();
+    (run > 0 && run.is_multiple_of(2)).then_some(run)
+}
+
+/// The length of the JSON value `input` starts with, if it's valid JSON.
+///
+/// Objects, arrays and strings end where their brackets or quotes balance; numbers take all
+/// the characters they could be made of.
+fn json_value_len(input: &str) -> Option<usize> {
+    let bytes = input.as_bytes();
+    let len = match *bytes.first()? {
+        b'{' | b'[' | b'"' => {
+            let (mut depth, mut in_string, mut escaped) = (0usize, false, false);
+            let mut end = None;
+            for (i, &b) in bytes.iter().enumerate() {
+                if in_string {
+                    match b {
+                        _ if escaped => escaped = false,
+                        b'\\' => escaped = true,
+                        b'"' => in_string = false,
+                        _ => {}
+                    }
+                } else {
+                    match b {
+                        b'"' => in_string = true,
+                        b'{' | b'[' => depth += 1,
+                        b'}' | b']' => depth = depth.checked_sub(1)?,
+                        _ => {}
+                    }
+                }
+                if depth == 0 && !in_string {
+                    end = Some(i + 1);
+                    break;
+                }
             }
-            let arr = current.as_array_mut().unwrap();
-            arr.push(val_to_insert);
-            current_path.push(ResolvedSegment::Index(arr.len() - 1));
+            end?
         }
+        b'-' | b'0'..=b'9' => bytes
+            .iter()
+            .take_while(|b| b.is_ascii_digit() || b"+-.eE".contains(b))
+            .count(),
+        _ => ["true", "false", "null"]
+            .into_iter()
+            .find(|literal| input.starts_with(literal))?
+            .len(),
+    };
+    serde_json::from_str::<Value>(&input[..len])
+        .is_ok()
+        .then_some(len)
+}
 
-        if !clause.name.is_empty() {
-            named_paths.insert(clause.name.clone(), current_path);
+/// Numbers (and numeric strings) by value, before everything else, which is compared as text.
+fn compare_values(a: &Value, b: &Value) -> std::cmp::Ordering {
+    fn as_number(v: &Value) -> Option<f64> {
+        match v {
+            Value::Number(n) => n.as_f64(),
+            Value::String(s) => s.parse().ok(),
+            _ => None,
+        }
+    }
+    fn as_text(v: &Value) -> std::borrow::Cow<'_, str> {
+        match v {
+            Value::String(s) => s.as_str().into(),
+            other => other.to_string().into(),
         }
     }
+    match (as_number(a), as_number(b)) {
+        (Some(x), Some(y)) => x.total_cmp(&y),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => as_text(a).cmp(&as_text(b)),
+    }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 enum ResolvedSegment {
     Root,
     Field(String),
     Index(usize),
 }
 
-#[derive(Clone, Default, PartialEq, Debug)]
+/// The fields written by the captures replayed so far, with the span of the last capture
+/// writing each and whether it was `ONCE`.
+type FieldWrites = HashMap<Vec<ResolvedSegment>, (Range<usize>, bool)>;
+
+/// `path` as it would be written in a query, like `ROOT.items[0].name`.
+fn describe_path(path: &[ResolvedSegment]) -> String {
+    let mut described = String::from("ROOT");
+    for segment in path {
+        match segment {
+            ResolvedSegment::Root => {}
+            ResolvedSegment::Field(name) => {
+                described.push('.');
+                described.push_str(name);
+            }
+            ResolvedSegment::Index(i) => described.push_str(&format!("[{}]", i)),
+        }
+    }
+    described
+}
+
+/// The events of a match, in order. Traces share the ones they are built from, so the match
+/// of each repetition extends the one before without copying its events.
+#[derive(Clone, Default, Debug)]
 struct MatchTrace {
-    events: Vec<TraceEvent>,
+    root: Option<Rc<TraceNode>>,
+}
+
+#[derive(Debug)]
+enum TraceNode {
+    Event(TraceEvent),
+    /// the events of `first`, then those of `second`
+    Concat {
+        len: usize,
+        first: MatchTrace,
+        second: MatchTrace,
+    },
 }
 
 impl MatchTrace {
+    fn len(&self) -> usize {
+        match self.root.as_deref() {
+            None => 0,
+            Some(TraceNode::Event(_)) => 1,
+            Some(TraceNode::Concat { len, .. }) => *len,
+        }
+    }
+
+    fn concat(first: MatchTrace, second: MatchTrace) -> MatchTrace {
+        if first.root.is_none() {
+            return second;
+        }
+        if second.root.is_none() {
+            return first;
+        }
+        MatchTrace {
+            root: Some(Rc::new(TraceNode::Concat {
+                len: first.len() + second.len(),
+                first,
+                second,
+            })),
+        }
+    }
+
     fn extend(&mut self, other: MatchTrace) {
-        self.events.extend(other.events);
+        *self = Self::concat(std::mem::take(self), other);
+    }
+
+    fn prepend(&mut self, event: TraceEvent) {
+        let event = MatchTrace {
+            root: Some(Rc::new(TraceNode::Event(event))),
+        };
+        *self = Self::concat(event, std::mem::take(self));
+    }
+
+    fn events(&self) -> TraceEvents<'_> {
+        TraceEvents {
+            stack: self.root.as_deref().into_iter().collect(),
+        }
+    }
+}
+
+impl Drop for MatchTrace {
+    // unlinks the nodes no other trace shares one at a time, as dropping a long chain of them
+    // recursively could overflow the stack
+    fn drop(&mut self) {
+        let mut stack = Vec::new();
+        let mut next = self.root.take();
+        while let Some(node) = next {
+            next = match Rc::try_unwrap(node) {
+                Ok(TraceNode::Concat {
+                    mut first,
+                    mut second,
+                    ..
+                }) => {
+                    stack.extend(second.root.take());
+                    first.root.take()
+                }
+                _ => None,
+            }
+            .or_else(|| stack.pop());
+        }
+    }
+}
+
+struct TraceEvents<'t> {
+    stack: Vec<&'t TraceNode>,
+}
+
+impl<'t> Iterator for TraceEvents<'t> {
+    type Item = &'t TraceEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.stack.pop()? {
+                TraceNode::Event(event) => return Some(event),
+                TraceNode::Concat { first, second, .. } => {
+                    self.stack.extend(second.root.as_deref());
+                    self.stack.extend(first.root.as_deref());
+                }
+            }
+        }
     }
 }
 
+/// Events refer to the statement and the input it matched, resolved only when replayed.
 #[derive(Clone, PartialEq, Debug)]
 enum TraceEvent {
+    /// the capture clause of `statement` applies to `span`
     Capture {
-        value: String,
-        clause: CaptureClause,
-        explicit_name: bool,
+        statement: PatternId,
+        span: Range<usize>,
     },
+    /// `statement` matched `span`
     VariableMatch {
-        name: String,
-        value: String,
+        statement: PatternId,
+        span: Range<usize>,
     },
 }
 
+/// Marks the statements some capture path uses as a `[key]`.
+fn key_variables(query: &CompiledQuery) -> Vec<bool> {
+    let names: HashSet<&str> = query
+        .statements
+        .iter()
+        .filter_map(|s| s.capture.as_ref())
+        .flat_map(|c| &c.path.segments)
+        .filter_map(|segment| match segment {
+            PathSegment::DynamicField(parts) => Some(parts),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(|part| match part {
+            KeyPart::Variable(name) => Some(name.as_str()),
+            KeyPart::Literal(_) => None,
+        })
+        .collect();
+    query
+        .statements
+        .iter()
+        .map(|s| !s.name.is_empty() && names.contains(s.name.as_str()))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1365,7 +3720,8 @@ mod tests {
         )
         .unwrap();
 
-        let mut solver = Solver::new(&program).unwrap();
+        let query = CompiledQuery::new(&program).unwrap();
+        let mut solver = Solver::from_compiled(&query);
         let res = solver.solve("hello");
 
         assert!(res.is_ok());
@@ -1382,7 +3738,8 @@ mod tests {
         )
         .unwrap();
 
-        let mut solver = Solver::new(&program).unwrap();
+        let query = CompiledQuery::new(&program).unwrap();
+        let mut solver = Solver::from_compiled(&query);
         let res = solver.solve("a, b, c");
 
         assert!(res.is_ok());
@@ -1404,7 +3761,8 @@ mod tests {
         )
         .unwrap();
 
-        let mut solver = Solver::new(&program).unwrap();
+        let query = CompiledQuery::new(&program).unwrap();
+        let mut solver = Solver::from_compiled(&query);
         let res = solver.solve("cats is animals\ndogs is pets");
 
         assert!(res.is_ok());
@@ -1424,7 +3782,8 @@ mod tests {
         )
         .unwrap();
 
-        let mut solver = Solver::new(&program).unwrap();
+        let query = CompiledQuery::new(&program).unwrap();
+        let mut solver = Solver::from_compiled(&query);
         let res = solver.solve("a. b. c.");
 
         assert!(res.is_err());
@@ -1434,6 +3793,176 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn ambiguity_degree_and_branches() {
+        let query = CompiledQuery::compile(
+            r#"
+            TEXT = pair "," pair
+            pair = left right
+            left = 0..N DIGIT
+            right = 0..N DIGIT
+        "#,
+        )
+        .unwrap();
+
+        match query.evaluate("123,4567").unwrap_err() {
+            StrqlError::AmbiguousParse {
+                _parses, _branches, ..
+            } => {
+                assert_eq!(_parses, ParseCount::Exactly(4 * 5));
+                let branches: Vec<_> = _branches
+                    .iter()
+                    .map(|b| (b.statement.as_str(), b.span.clone()))
+                    .collect();
+                assert_eq!(branches, vec![("pair", 0..3), ("pair", 4..8)]);
+            }
+            e => panic!("Expected AmbiguousParse, got {:?}", e),
+        }
+
+        let query = CompiledQuery::compile("TEXT = 1..N part\npart = 1..N LETTER").unwrap();
+        match query.evaluate(&"a".repeat(20)).unwrap_err() {
+            StrqlError::AmbiguousParse { _parses, .. } => {
+                assert_eq!(_parses, ParseCount::AtLeast(MAX_COUNTED_PARSES))
+            }
+            e => panic!("Expected AmbiguousParse, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn ambiguity_suggestions() {
+        let source = "TEXT = num \" \" w SPLITBY \".\"\nnum = 1..N DIGIT\nw = ANY";
+        let query = CompiledQuery::compile(source).unwrap();
+        match query.evaluate("12 a. b. c.").unwrap_err() {
+            StrqlError::AmbiguousParse {
+                _suggestions,
+                _help,
+                ..
+            } => {
+                let suggested: Vec<_> = _suggestions
+                    .iter()
+                    .map(|s| (s.statement.as_str(), &source[s.span.clone()]))
+                    .collect();
+                // `num` repeats too, but not inside the ambiguous part
+                assert_eq!(suggested, vec![("TEXT", "SPLITBY \".\""), ("w", "ANY")]);
+                assert!(_help.ends_with("`TEXT`, `w`"));
+            }
+            e => panic!("Expected AmbiguousParse, got {:?}", e),
+        }
+
+        // a statement with several repetitions is named once
+        let query = CompiledQuery::compile("TEXT = 0..N (0..N \"a\")").unwrap();
+        match query.evaluate("aa").unwrap_err() {
+            StrqlError::AmbiguousParse {
+                _suggestions,
+                _help,
+                ..
+            } => {
+                assert_eq!(_suggestions.len(), 2);
+                assert!(_help.ends_with(" in `TEXT`"), "{}", _help);
+            }
+            e => panic!("Expected AmbiguousParse, got {:?}", e),
+        }
+    }
+
+    #[cfg(feature = "diagnostics")]
+    fn render(error: &StrqlError) -> String {
+        let mut rendered = String::new();
+        miette::GraphicalReportHandler::new_themed(miette::GraphicalTheme::unicode_nocolor())
+            .render_report(&mut rendered, error)
+            .unwrap();
+        rendered
+    }
+
+    #[test]
+    #[cfg(feature = "diagnostics")]
+    fn errors_point_into_their_source() {
+        // errors about the query show the query, even when found while compiling it
+        let Err(err) = CompiledQuery::compile("TEXT = LETTER\nx = nmae") else {
+            panic!("expected an unbound variable");
+        };
+        let rendered = render(&err);
+        assert!(rendered.contains("[strql:2:5]"), "{}", rendered);
+        assert!(rendered.contains("x = nmae"), "{}", rendered);
+
+        let source = "TEXT = num \" \" w SPLITBY \".\"\nnum = 1..N DIGIT\nw = ANY";
+        let query = CompiledQuery::compile(source).unwrap();
+        let rendered = render(&query.evaluate("12 a. b. c.").unwrap_err());
+        // the ambiguous part of the input, then the repetitions to disambiguate in the query
+        assert!(rendered.contains("[input:1:4]"), "{}", rendered);
+        assert!(rendered.contains("[strql:3:5]"), "{}", rendered);
+        assert!(rendered.contains("`w` repeats here"), "{}", rendered);
+    }
+
+    #[test]
+    fn unbound_variable_suggestions() {
+        let Err(err) = CompiledQuery::compile("TEXT = nmae \":\" WROD\nname = LETTER") else {
+            panic!("expected an unbound variable");
+        };
+        match err {
+            StrqlError::UnboundVariable {
+                _name,
+                _suggestions,
+                _help,
+                ..
+            } => {
+                assert_eq!(_name, "nmae");
+                assert_eq!(_suggestions, vec!["name"]);
+                assert_eq!(_help, "Did you mean `name`?");
+            }
+            e => panic!("Expected UnboundVariable, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn reversed_quantifier_bounds() {
+        // programs built by hand skip the parser's check
+        let mut program = parse("TEXT = 2..5 DIGIT").unwrap();
+        if let PatternKind::Repetition { min, .. } = &mut program.statements[0].pattern.node {
+            *min = Some(7);
+        }
+        assert!(matches!(
+            CompiledQuery::new(&program),
+            Err(StrqlError::InvalidQuantifierBounds {
+                _min: 7,
+                _max: 5,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn repeated_empty_matches() {
+        // empty repetitions only make up the minimum, so they add no parses
+        let query = CompiledQuery::compile("TEXT = 2..N y\ny = 0..1 \"a\"").unwrap();
+        for input in ["", "a", "aa", "aaaa"] {
+            assert_eq!(
+                query.evaluate(input).unwrap(),
+                json!({}),
+                "input {:?}",
+                input
+            );
+        }
+        let query = CompiledQuery::compile("TEXT = 0..N y\ny = LINE NEWLINE").unwrap();
+        assert!(query.evaluate("a\n\nb\n").is_ok());
+    }
+
+    #[test]
+    fn left_recursion_rejected() {
+        for source in [
+            "TEXT = x\nx = x \"a\" OR \"a\"",
+            "TEXT = 0..N x\nx = y \"a\"\ny = 0..1 \"b\" x",
+        ] {
+            match CompiledQuery::compile(source) {
+                Err(StrqlError::LeftRecursion { _name, .. }) => assert_eq!(_name, "x"),
+                Err(e) => panic!("Expected LeftRecursion, got {:?}", e),
+                Ok(_) => panic!("Expected LeftRecursion for {:?}", source),
+            }
+        }
+        // consuming input first makes recursion fine
+        let query = CompiledQuery::compile("TEXT = x\nx = \"(\" 0..1 x \")\"").unwrap();
+        assert!(query.evaluate("((()))").is_ok());
+    }
+
     #[test]
     fn no_match() {
         let program = parse(
@@ -1443,7 +3972,8 @@ mod tests {
         )
         .unwrap();
 
-        let mut solver = Solver::new(&program).unwrap();
+        let query = CompiledQuery::new(&program).unwrap();
+        let mut solver = Solver::from_compiled(&query);
         let res = solver.solve("abc");
 
         assert!(res.is_err());
@@ -1463,7 +3993,8 @@ mod tests {
         )
         .unwrap();
 
-        let mut solver = Solver::new(&program).unwrap();
+        let query = CompiledQuery::new(&program).unwrap();
+        let mut solver = Solver::from_compiled(&query);
 
         let res1 = solver.solve("hello");
         assert!(res1.is_ok());
@@ -1483,7 +4014,8 @@ mod tests {
         )
         .unwrap();
 
-        let mut solver = Solver::new(&program).unwrap();
+        let query = CompiledQuery::new(&program).unwrap();
+        let mut solver = Solver::from_compiled(&query);
         let res = solver.solve("ABCXYZ");
 
         assert!(res.is_err());
@@ -1497,4 +4029,302 @@ mod tests {
             e => panic!("Expected PartialMatch, got {:?}", e),
         }
     }
+
+    #[test]
+    fn patterns_simplified_before_flattening() {
+        let query =
+            CompiledQuery::compile("TEXT = (\"a\" \"b\") (\"c\" OR (\"d\" OR \"e\"))").unwrap();
+        // TEXT, the sequence, "ab", the alternation and its three literals
+        assert_eq!(query.statements.len(), 7);
+        assert!(query.evaluate("abd").is_ok());
+        match query.evaluate("abx").unwrap_err() {
+            StrqlError::PartialMatch { _matched, .. } => assert_eq!(_matched, 2),
+            e => panic!("Expected PartialMatch, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn case_modes_memoized_apart() {
+        // `x` is matched at the same position both inside and outside ANYCASE
+        for source in [
+            "TEXT = (ANYCASE x) OR x\nx = \"a\"",
+            "TEXT = x OR (ANYCASE x)\nx = \"a\"",
+        ] {
+            let query = CompiledQuery::compile(source).unwrap();
+            assert!(query.evaluate("A").is_ok(), "{}", source);
+            assert!(matches!(
+                query.evaluate("a"),
+                Err(StrqlError::AmbiguousParse { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn char_runs_match_like_repetitions() {
+        let query = |item: &str| {
+            format!(
+                "TEXT = a b c\na = GREEDY 1..3 {0} -> ADD TO ROOT.a\n\
+                 b = LAZY 2..N {0} -> ADD TO ROOT.b\nc = ANY -> ADD TO ROOT.c\nd = DIGIT",
+                item
+            )
+        };
+        let runs = CompiledQuery::compile(&query("DIGIT")).unwrap();
+        let plain = CompiledQuery::compile(&query("d")).unwrap();
+        assert_eq!(runs.char_runs.iter().filter(|&&run| run).count(), 3);
+        // only the `ANY` of `c`
+        assert_eq!(plain.char_runs.iter().filter(|&&run| run).count(), 1);
+        for input in ["12345x", "123", "1234", "12é", ""] {
+            assert_eq!(
+                runs.evaluate(input).map_err(|e| e.kind()),
+                plain.evaluate(input).map_err(|e| e.kind()),
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn exclusive_alternatives_reordered() {
+        let plain =
+            "TEXT = x SPLITBY \" \"\nx = 1..N LETTER OR \"+\" OR DIGIT OR \"%\" (\"a\" OR \"ab\")";
+        let query =
+            CompiledQuery::compile(&format!("OPTION REORDER_ALTERNATIVES\n{}", plain)).unwrap();
+        let alternations: Vec<(PatternId, &Vec<PatternId>)> = (0..query.statements.len())
+            .filter_map(|id| match &query.statements[id].pattern {
+                FlatPattern::Alternation {
+                    alternatives: alts, ..
+                } => Some((id, alts)),
+                _ => None,
+            })
+            .collect();
+        for (id, alts) in alternations {
+            let widths: Vec<u32> = alts.iter().map(|&a| query.lookahead[a].width()).collect();
+            if alts.len() == 4 {
+                assert!(query.exclusive[id]);
+                assert_eq!(widths[..2], [1, 1]);
+                assert!(widths.windows(2).all(|w| w[0] <= w[1]), "{:?}", widths);
+            } else {
+                // both `"a"` and `"ab"` can start with `a`
+                assert!(!query.exclusive[id]);
+            }
+        }
+
+        let plain = CompiledQuery::compile(plain).unwrap();
+        for input in ["ab + 7 %ab %a", "x", "+ + 3"] {
+            assert_eq!(
+                query.evaluate(input).unwrap(),
+                plain.evaluate(input).unwrap()
+            );
+        }
+        assert!(!query.matches("ab - 7").unwrap());
+    }
+
+    #[test]
+    fn required_literals_checked_before_validating() {
+        let query = CompiledQuery::compile(
+            "TEXT = \"<\" 1..N (x OR \"k=\" x) (\">>\" OR \">\" \";\") 0..N \"?\" ANYCASE \"!\" tail\ntail = \"end\" OR 1..3 \"end\" tail\nx = \"id\" DIGIT",
+        )
+        .unwrap();
+        // not `>`, which is only part of `>>`, nor the optional `?` and case-insensitive `!`
+        assert_eq!(query.required, vec!["<", "end", "id"]);
+
+        assert!(query.matches("<k=id1>;!end").unwrap());
+        assert!(!query.matches("<k=id1>;!en").unwrap());
+        match query.evaluate("<k=id1>;!en").unwrap_err() {
+            StrqlError::PartialMatch { _matched, .. } => assert_eq!(_matched, 11),
+            e => panic!("Expected PartialMatch, got {:?}", e),
+        }
+
+        let query = CompiledQuery::compile("OPTION ANYCASE\nTEXT = \"select \" WORD").unwrap();
+        assert!(query.required.is_empty());
+        assert!(query.matches("SELECT x").unwrap());
+        assert!(query.evaluate("SELECT x").is_ok());
+    }
+
+    #[test]
+    fn custom_builtins() {
+        let mut builtins = CustomBuiltins::new();
+        builtins.register("IPV4", |input: &str, pos: usize| {
+            let len = input[pos..]
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(input.len() - pos);
+            let mut octets = input[pos..pos + len].split('.');
+            let valid = octets.clone().count() == 4 && octets.all(|o| o.parse::<u8>().is_ok());
+            valid.then_some(pos + len)
+        });
+        builtins.register("BROKEN", |input: &str, _| Some(input.len() + 1));
+
+        let source = "TEXT = a SPLITBY \", \"\na = IPV4 -> ADD TO ROOT.hosts[]";
+        let query = CompiledQuery::compile_with(source, &builtins).unwrap();
+        assert_eq!(
+            query.evaluate("10.0.0.1, 192.168.1.254").unwrap(),
+            json!({"hosts": ["10.0.0.1", "192.168.1.254"]})
+        );
+        assert!(!query.matches("10.0.0.256").unwrap());
+
+        // statements shadow builtins, and unknown names are still reported
+        let query = CompiledQuery::compile_with("TEXT = IPV4\nIPV4 = \"x\"", &builtins).unwrap();
+        assert!(query.matches("x").unwrap());
+        match CompiledQuery::compile_with("TEXT = IPV6", &builtins) {
+            Err(StrqlError::UnboundVariable { _suggestions, .. }) => {
+                assert_eq!(_suggestions, ["IPV4"])
+            }
+            _ => panic!("Expected UnboundVariable"),
+        }
+        assert!(matches!(
+            CompiledQuery::compile_with("TEXT = BROKEN", &builtins)
+                .unwrap()
+                .evaluate("a"),
+            Err(StrqlError::InvalidBuiltinMatch { _end: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn dict_words() {
+        let mut builtins = CustomBuiltins::new();
+        builtins.add_dict("cities", ["New York", "New", "York", "Paris"]);
+        let source = "TEXT = c SPLITBY \", \"\nc = DICT(cities) -> ADD TO ROOT.cities[]";
+        let query = CompiledQuery::compile_with(source, &builtins).unwrap();
+        assert_eq!(
+            query.evaluate("New York, New, Paris").unwrap(),
+            json!({"cities": ["New York", "New", "Paris"]})
+        );
+        assert!(!query.matches("New Paris").unwrap());
+
+        let query = CompiledQuery::compile_with("TEXT = ANYCASE DICT(cities)", &builtins).unwrap();
+        assert!(query.matches("new york").unwrap());
+        let query = CompiledQuery::compile_with("TEXT = UPPER DICT(cities)", &builtins).unwrap();
+        assert!(query.matches("PARIS").unwrap());
+        assert!(!query.matches("Paris").unwrap());
+
+        assert!(matches!(
+            CompiledQuery::compile("TEXT = DICT(cities)"),
+            Err(StrqlError::UnknownDict { .. })
+        ));
+    }
+
+    #[test]
+    fn sequences_skip_dead_positions() {
+        let query = CompiledQuery::compile("TEXT = 0..N LETTER \"-\" DIGIT").unwrap();
+        let dash = (0..query.statements.len())
+            .find(|&id| query.statements[id].pattern == FlatPattern::Literal("-".into()))
+            .unwrap();
+        let mut solver = Solver::from_compiled(&query);
+        assert_eq!(solver.solve("abcdef-1").unwrap(), json!({}));
+        let row = dash * ("abcdef-1".len() + 1);
+        let tried: Vec<usize> = (0..=8)
+            .filter(|&pos| solver.memo[0].get(row + pos).is_some())
+            .collect();
+        assert_eq!(tried, vec![6]);
+
+        // lookahead covers what matches without regard to case
+        let query = CompiledQuery::compile("TEXT = 0..N DIGIT ANYCASE \"k\"").unwrap();
+        assert!(query.matches("12K").unwrap());
+        assert!(query.matches("12\u{212a}").unwrap());
+    }
+
+    #[test]
+    fn repetitions_share_their_traces() {
+        let query = CompiledQuery::compile(
+            "TEXT = item GREEDY SPLITBY NEWLINE\nitem = LINE -> ADD TO ROOT.items[]",
+        )
+        .unwrap();
+        // the trace nodes the memo holds, which grow with the square of the lines when every
+        // repetition copies the trace of the ones before
+        let nodes = |lines: usize| {
+            let input = vec!["line"; lines].join("\n");
+            let mut solver = Solver::from_compiled(&query);
+            assert_eq!(solver.solve(&input).unwrap()["items"][lines - 1], "line");
+            let mut stack: Vec<&TraceNode> = solver
+                .memo
+                .iter()
+                .flat_map(|table| &table.results)
+                .filter_map(|res| match res {
+                    VResult::Matches(matches) => Some(matches.data.values()),
+                    VResult::NoMatch => None,
+                })
+                .flatten()
+                .filter_map(|outcome| match outcome {
+                    MatchOutcome::Unique(m) => m.trace.root.as_deref(),
+                    MatchOutcome::Ambiguous { .. } => None,
+                })
+                .collect();
+            let mut seen = HashSet::new();
+            while let Some(node) = stack.pop() {
+                if let (true, TraceNode::Concat { first, second, .. }) =
+                    (seen.insert(node as *const TraceNode), node)
+                {
+                    stack.extend(first.root.as_deref());
+                    stack.extend(second.root.as_deref());
+                }
+            }
+            seen.len()
+        };
+        let (small, large) = (nodes(200), nodes(400));
+        assert!(
+            large <= 2 * small + 16,
+            "{} then {} trace nodes",
+            small,
+            large
+        );
+    }
+
+    #[test]
+    fn identical_subpatterns_shared() {
+        let query = CompiledQuery::compile("TEXT = DIGIT \",\" DIGIT \",\" DIGIT").unwrap();
+        // TEXT, the sequence, DIGIT and ","
+        assert_eq!(query.statements.len(), 4);
+        assert!(query.evaluate("1,2,3").is_ok());
+
+        // each case mode has its own memo, so patterns are shared across them too
+        let query = CompiledQuery::compile("TEXT = (ANYCASE \"a\") OR \"a\"").unwrap();
+        assert_eq!(query.statements.len(), 4);
+        assert!(query.evaluate("A").is_ok());
+
+        // repetitions stay apart, their depth decides between biases
+        let query = CompiledQuery::compile("TEXT = 0..N DIGIT \" \" 0..N DIGIT").unwrap();
+        assert_eq!(query.statements.len(), 6);
+    }
+
+    #[test]
+    fn compiled_query_reused_across_inputs() {
+        let query = CompiledQuery::compile(
+            r#"
+            TEXT = w GREEDY SPLITBY ", "
+            w = WORD -> ADD TO ROOT.items[]
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            query.evaluate("a, b").unwrap(),
+            json!({"items": ["a", "b"]})
+        );
+        assert_eq!(query.evaluate("c").unwrap(), json!({"items": ["c"]}));
+        assert!(query.matches("d, e, f").unwrap());
+        assert!(!query.matches("1, 2").unwrap());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn compiled_query_bytes_roundtrip() {
+        let query = CompiledQuery::compile(
+            r#"
+            TEXT = key ": " value
+            key = WORD -> ADD TO ROOT
+            value = 1..N DIGIT -> ADD TO ROOT
+        "#,
+        )
+        .unwrap();
+
+        let loaded = CompiledQuery::from_bytes(&query.to_bytes()).unwrap();
+        assert_eq!(
+            loaded.evaluate("port: 8080").unwrap(),
+            json!({"key": "port", "value": "8080"})
+        );
+        assert!(matches!(
+            CompiledQuery::from_bytes(b"not a query"),
+            Err(StrqlError::InvalidCompiledQuery { .. })
+        ));
+    }
 }
@@ -0,0 +1,122 @@
+//! Grammars shipped with strql, which queries bring in with `IMPORT <name>`.
+//!
+//! Importing a grammar adds its statements with their names prefixed by `<name>_`, and its
+//! `TEXT` as the statement `<name>`: `IMPORT ini` followed by `TEXT = ini` parses INI files.
+//! Captures keep the paths the grammar gives them.
+
+use crate::ast::Program;
+use crate::error::StrqlResult;
+
+/// The grammars by name, with their query text.
+const GRAMMARS: &[(&str, &str)] = &[
+    // its timestamps are `DATETIME` captures
+    #[cfg(feature = "datetime")]
+    ("accesslog", include_str!("stdlib/accesslog.strql")),
+    ("ini", include_str!("stdlib/ini.strql")),
+    ("markdown", include_str!("stdlib/markdown.strql")),
+    ("rfc3164", include_str!("stdlib/rfc3164.strql")),
+    ("rfc5424", include_str!("stdlib/rfc5424.strql")),
+];
+
+/// The grammars left out of builds without the feature they need, with that feature.
+const GATED: &[(&str, &str)] = &[("accesslog", "datetime")];
+
+/// The feature the grammar `name` needs, if it exists but was left out of this build.
+pub fn missing_feature(name: &str) -> Option<&'static str> {
+    GATED
+        .iter()
+        .find(|(grammar, _)| *grammar == name && source(name).is_none())
+        .map(|(_, feature)| *feature)
+}
+
+/// The names of the grammars that can be imported.
+pub fn names() -> impl Iterator<Item = &'static str> {
+    GRAMMARS.iter().map(|(name, _)| *name)
+}
+
+/// The query text of the grammar `name`, a complete query of its own.
+pub fn source(name: &str) -> Option<&'static str> {
+    GRAMMARS
+        .iter()
+        .find(|(grammar, _)| *grammar == name)
+        .map(|(_, source)| *source)
+}
+
+/// The statements `IMPORT <name>` adds to a program. Their spans point into the grammar's
+/// text, until [`Program::merge`] moves them into the source it appends that text to.
+pub(crate) fn import(name: &str) -> Option<StrqlResult<Program>> {
+    let source = source(name)?;
+    Some(crate::parser::parse(source).map(|mut program| {
+        program.rename_prefix(&format!("{}_", name));
+        let text = format!("{}_TEXT", name);
+        if let Some(stmt) = program.statements.iter_mut().find(|s| s.name == text) {
+            stmt.name = name.to_string();
+        }
+        program
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CompiledQuery;
+    use serde_json::json;
+
+    #[test]
+    fn grammars_compile() {
+        for name in names() {
+            CompiledQuery::compile(source(name).unwrap()).unwrap();
+            let program = import(name).unwrap().unwrap();
+            assert!(program.statements.iter().any(|s| s.name == name));
+        }
+    }
+
+    #[test]
+    fn gated_grammars_name_their_feature() {
+        assert_eq!(missing_feature("ini"), None);
+        assert_eq!(missing_feature("nope"), None);
+        let expected = (!cfg!(feature = "datetime")).then_some("datetime");
+        assert_eq!(missing_feature("accesslog"), expected);
+    }
+
+    #[test]
+    fn ini_sections_hold_their_entries() {
+        let query = CompiledQuery::compile("IMPORT ini\nTEXT = ini").unwrap();
+        let input = "name = demo\n; comment\n\n[server]\nhost = example.com\nport: 8080\n\
+                     # note\n[client] ; trailing\nretries=3\nempty =";
+        assert_eq!(
+            query.evaluate(input).unwrap(),
+            json!({
+                "name": "demo",
+                "server": {"host": "example.com", "port": "8080"},
+                "client": {"retries": "3", "empty": ""}
+            })
+        );
+        assert_eq!(query.evaluate("").unwrap(), json!({}));
+        assert!(query.evaluate("[server\nhost = x").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "datetime")]
+    fn accesslog_entries_are_typed() {
+        let query = CompiledQuery::compile("IMPORT accesslog\nTEXT = accesslog").unwrap();
+        let input = "127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] \"GET /a.gif HTTP/1.0\" 200 2326\n\
+                     ::1 - - [01/Jan/2024:00:00:01 +0000] \"POST /b?c=1 HTTP/2.0\" 201 - \"-\" \"curl/8\"\n";
+        assert_eq!(
+            query.evaluate(input).unwrap(),
+            json!({"entries": [
+                {
+                    "ip": "127.0.0.1", "timestamp": "2000-10-10T13:55:36-07:00",
+                    "method": "GET", "path": "/a.gif", "status": 200, "bytes": 2326
+                },
+                {
+                    "ip": "::1", "timestamp": "2024-01-01T00:00:01Z", "method": "POST",
+                    "path": "/b?c=1", "status": 201, "referer": "-", "agent": "curl/8"
+                }
+            ]})
+        );
+        assert!(query
+            .evaluate("127.0.0.1 - - [yesterday] \"GET / HTTP/1.0\" 200 1")
+            .is_err());
+    }
+}
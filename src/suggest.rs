@@ -0,0 +1,95 @@
+/// Names of the builtins, as written in queries.
+const BUILTIN_NAMES: &[&str] = &[
+    "WORD",
+    "LINE",
+    "CONTLINE",
+    "PARAGRAPH",
+    "NEWLINE",
+    "SPACE",
+    "ANYCHAR",
+    "ANY",
+    "DIGIT",
+    "LETTER",
+    "ALPHANUM",
+    "HEXNUM",
+    "BINNUM",
+    "OCTNUM",
+    "BYTE",
+    "BYTES",
+    "BASE64",
+    "HEXBLOB",
+    "JSONVALUE",
+    "BOF",
+    "EOF",
+];
+
+/// The statement names and builtins closest to `name`, best first, for "did you mean" hints.
+pub(crate) fn similar_names<'a>(
+    name: &str,
+    statements: impl IntoIterator<Item = &'a str>,
+) -> Vec<String> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    let upper = name.to_uppercase();
+    let mut candidates: Vec<(usize, String)> = statements
+        .into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate.to_string()))
+        .chain(
+            BUILTIN_NAMES
+                .iter()
+                .map(|builtin| (edit_distance(&upper, builtin), builtin.to_string())),
+        )
+        .filter(|(distance, candidate)| *distance <= max_distance && candidate != name)
+        .collect();
+    candidates.sort();
+    candidates.dedup_by(|a, b| a.1 == b.1);
+    candidates.truncate(3);
+    candidates.into_iter().map(|(_, name)| name).collect()
+}
+
+/// Levenshtein distance that also counts swapping two adjacent characters as one edit.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // rows[i][j]: distance between the first i characters of a and the first j of b
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    rows[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = distance;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distances() {
+        assert_eq!(edit_distance("name", "name"), 0);
+        assert_eq!(edit_distance("nmae", "name"), 1);
+        assert_eq!(edit_distance("nam", "name"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn suggestions() {
+        let statements = ["name", "names", "value", "TEXT"];
+        assert_eq!(similar_names("nmae", statements), vec!["name"]);
+        assert_eq!(similar_names("nams", statements), vec!["name", "names"]);
+        assert_eq!(similar_names("wrod", statements), vec!["WORD"]);
+        assert_eq!(similar_names("jsonvalu", statements), vec!["JSONVALUE"]);
+        assert!(similar_names("xyz", statements).is_empty());
+    }
+}
@@ -0,0 +1,132 @@
+//! Rendering outputs made of flat records, like `ROOT.items[]` of `item{}` captures, as text
+//! tables or CSV.
+
+use serde_json::Value;
+
+/// The records of `value`: its items if it is an array, or those of its only field when that
+/// is an array, as long as every record is an object.
+fn records(value: &Value) -> Option<&[Value]> {
+    let items = match value {
+        Value::Array(items) => items,
+        Value::Object(fields) if fields.len() == 1 => fields.values().next()?.as_array()?,
+        _ => return None,
+    };
+    items
+        .iter()
+        .all(Value::is_object)
+        .then_some(items.as_slice())
+}
+
+/// The columns of the records, in the order their fields are first listed, and their cells.
+fn cells(value: &Value) -> Option<(Vec<&str>, Vec<Vec<String>>)> {
+    let records = records(value)?;
+    let mut columns: Vec<&str> = Vec::new();
+    for record in records {
+        for key in record.as_object()?.keys() {
+            if !columns.contains(&key.as_str()) {
+                columns.push(key);
+            }
+        }
+    }
+    let rows = records
+        .iter()
+        .map(|record| {
+            columns
+                .iter()
+                .map(|column| match record.get(column) {
+                    None | Some(Value::Null) => String::new(),
+                    Some(Value::String(text)) => text.clone(),
+                    // nested values stay readable as compact JSON
+                    Some(other) => other.to_string(),
+                })
+                .collect()
+        })
+        .collect();
+    Some((columns, rows))
+}
+
+/// Renders `value` as a table with a header row and columns aligned with spaces, or `None`
+/// if it isn't made of records (see [`to_csv`]).
+pub fn to_table(value: &Value) -> Option<String> {
+    let (columns, rows) = cells(value)?;
+    let mut widths: Vec<usize> = columns.iter().map(|c| c.chars().count()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut table = String::new();
+    let mut push_row = |cells: &mut dyn Iterator<Item = &str>| {
+        let mut line = String::new();
+        for (i, (cell, width)) in cells.zip(&widths).enumerate() {
+            if i > 0 {
+                line.push_str("  ");
+            }
+            line.push_str(cell);
+            line.push_str(&" ".repeat(width - cell.chars().count()));
+        }
+        table.push_str(line.trim_end());
+        table.push('\n');
+    };
+    push_row(&mut columns.iter().copied());
+    let rules: Vec<String> = widths.iter().map(|&w| "-".repeat(w)).collect();
+    push_row(&mut rules.iter().map(String::as_str));
+    for row in &rows {
+        push_row(&mut row.iter().map(String::as_str));
+    }
+    Some(table)
+}
+
+/// Renders `value` as CSV with a header row, or `None` if it isn't made of records: an array
+/// of objects, or an object whose only field is one. Fields missing from a record are empty.
+pub fn to_csv(value: &Value) -> Option<String> {
+    let (columns, rows) = cells(value)?;
+    let mut csv = String::new();
+    let mut push_row = |cells: &mut dyn Iterator<Item = &str>| {
+        for (i, cell) in cells.enumerate() {
+            if i > 0 {
+                csv.push(',');
+            }
+            if cell.contains([',', '"', '\n', '\r']) {
+                csv.push('"');
+                csv.push_str(&cell.replace('"', "\"\""));
+                csv.push('"');
+            } else {
+                csv.push_str(cell);
+            }
+        }
+        csv.push('\n');
+    };
+    push_row(&mut columns.iter().copied());
+    for row in &rows {
+        push_row(&mut row.iter().map(String::as_str));
+    }
+    Some(csv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn renders_records() {
+        let output = json!({"items": [
+            {"name": "ann", "age": 7},
+            {"name": "bob, jr", "note": "says \"hi\""},
+        ]});
+        assert_eq!(
+            to_table(&output).unwrap(),
+            "age  name     note\n---  -------  ---------\n7    ann\n     bob, jr  says \"hi\"\n"
+        );
+        assert_eq!(
+            to_csv(&output).unwrap(),
+            "age,name,note\n7,ann,\n,\"bob, jr\",\"says \"\"hi\"\"\"\n"
+        );
+        assert_eq!(to_csv(&output["items"]), to_csv(&output));
+
+        assert!(to_table(&json!({"a": [], "b": []})).is_none());
+        assert!(to_table(&json!(["text"])).is_none());
+    }
+}
@@ -0,0 +1,175 @@
+//! Traversal of parsed [`Program`]s, for tools that inspect or rewrite queries.
+//!
+//! Implement [`Visitor`] (or [`VisitorMut`] to rewrite in place) and override the methods for
+//! the nodes of interest. Overrides call the matching `walk_*` function to keep descending.
+
+use crate::ast::{Pattern, PatternKind, Program, Statement};
+
+/// Read-only traversal; `'ast` lets visitors keep references into the program.
+pub trait Visitor<'ast> {
+    fn visit_program(&mut self, program: &'ast Program) {
+        walk_program(self, program);
+    }
+
+    fn visit_statement(&mut self, statement: &'ast Statement) {
+        walk_statement(self, statement);
+    }
+
+    fn visit_pattern(&mut self, pattern: &'ast Pattern) {
+        walk_pattern(self, pattern);
+    }
+}
+
+pub fn walk_program<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, program: &'ast Program) {
+    for statement in &program.statements {
+        visitor.visit_statement(statement);
+    }
+}
+
+pub fn walk_statement<'ast, V: Visitor<'ast> + ?Sized>(
+    visitor: &mut V,
+    statement: &'ast Statement,
+) {
+    visitor.visit_pattern(&statement.pattern);
+}
+
+/// Visits the direct sub-patterns of `pattern`.
+pub fn walk_pattern<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, pattern: &'ast Pattern) {
+    match &pattern.node {
+        PatternKind::Sequence(items)
+        | PatternKind::Permute(items)
+        | PatternKind::Fields(items)
+        | PatternKind::OrChain {
+            alternatives: items,
+            ..
+        } => {
+            for item in items {
+                visitor.visit_pattern(item);
+            }
+        }
+        PatternKind::Repetition { pattern: inner, .. }
+        | PatternKind::AnyCase(inner)
+        | PatternKind::Upper(inner)
+        | PatternKind::Lower(inner)
+        | PatternKind::Group(inner)
+        | PatternKind::PrecededBy(inner)
+        | PatternKind::Unmatched(inner) => visitor.visit_pattern(inner),
+        PatternKind::Literal(_)
+        | PatternKind::Variable(_)
+        | PatternKind::Builtin(_)
+        | PatternKind::Dict(_) => {}
+    }
+}
+
+/// Traversal that can rewrite the program in place.
+pub trait VisitorMut {
+    fn visit_program_mut(&mut self, program: &mut Program) {
+        walk_program_mut(self, program);
+    }
+
+    fn visit_statement_mut(&mut self, statement: &mut Statement) {
+        walk_statement_mut(self, statement);
+    }
+
+    fn visit_pattern_mut(&mut self, pattern: &mut Pattern) {
+        walk_pattern_mut(self, pattern);
+    }
+}
+
+pub fn walk_program_mut<V: VisitorMut + ?Sized>(visitor: &mut V, program: &mut Program) {
+    for statement in &mut program.statements {
+        visitor.visit_statement_mut(statement);
+    }
+}
+
+pub fn walk_statement_mut<V: VisitorMut + ?Sized>(visitor: &mut V, statement: &mut Statement) {
+    visitor.visit_pattern_mut(&mut statement.pattern);
+}
+
+/// Visits the direct sub-patterns of `pattern`.
+pub fn walk_pattern_mut<V: VisitorMut + ?Sized>(visitor: &mut V, pattern: &mut Pattern) {
+    match &mut pattern.node {
+        PatternKind::Sequence(items)
+        | PatternKind::Permute(items)
+        | PatternKind::Fields(items)
+        | PatternKind::OrChain {
+            alternatives: items,
+            ..
+        } => {
+            for item in items {
+                visitor.visit_pattern_mut(item);
+            }
+        }
+        PatternKind::Repetition { pattern: inner, .. }
+        | PatternKind::AnyCase(inner)
+        | PatternKind::Upper(inner)
+        | PatternKind::Lower(inner)
+        | PatternKind::Group(inner)
+        | PatternKind::PrecededBy(inner)
+        | PatternKind::Unmatched(inner) => visitor.visit_pattern_mut(inner),
+        PatternKind::Literal(_)
+        | PatternKind::Variable(_)
+        | PatternKind::Builtin(_)
+        | PatternKind::Dict(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::QuantifierBias;
+    use crate::parser::parse;
+
+    #[derive(Default)]
+    struct Literals(Vec<String>);
+
+    impl Visitor<'_> for Literals {
+        fn visit_pattern(&mut self, pattern: &Pattern) {
+            if let PatternKind::Literal(text) = &pattern.node {
+                self.0.push(text.clone());
+            }
+            walk_pattern(self, pattern);
+        }
+    }
+
+    /// Makes every unbiased repetition lazy.
+    struct Lazify;
+
+    impl VisitorMut for Lazify {
+        fn visit_pattern_mut(&mut self, pattern: &mut Pattern) {
+            if let PatternKind::Repetition { bias, .. } = &mut pattern.node {
+                if *bias == QuantifierBias::Neutral {
+                    *bias = QuantifierBias::Lazy;
+                }
+            }
+            walk_pattern_mut(self, pattern);
+        }
+    }
+
+    #[test]
+    fn visits_nested_patterns() {
+        let program = parse("TEXT = \"a\" (x OR ANYCASE \"b\")\nx = 1..3 (\"c\" \"d\")").unwrap();
+        let mut literals = Literals::default();
+        literals.visit_program(&program);
+        assert_eq!(literals.0, vec!["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn rewrites_in_place() {
+        struct Biases(Vec<QuantifierBias>);
+        impl Visitor<'_> for Biases {
+            fn visit_pattern(&mut self, pattern: &Pattern) {
+                if let PatternKind::Repetition { bias, .. } = &pattern.node {
+                    self.0.push(*bias);
+                }
+                walk_pattern(self, pattern);
+            }
+        }
+
+        let mut program = parse("TEXT = 0..N (GREEDY 1..N DIGIT \" \")").unwrap();
+        Lazify.visit_program_mut(&mut program);
+        let mut biases = Biases(Vec::new());
+        biases.visit_program(&program);
+        assert_eq!(biases.0, vec![QuantifierBias::Lazy, QuantifierBias::Greedy]);
+    }
+}
@@ -0,0 +1,53 @@
+//! Compile-time checked strql queries.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, LitByteStr, LitStr};
+
+/// Compiles a query at compile time and evaluates to a `&'static strql::CompiledQuery`.
+///
+/// The compiled query is embedded in the binary as the blob of `CompiledQuery::to_bytes`,
+/// loaded the first time the expression runs without parsing the query again, so `strql`
+/// needs its `serde` feature. Syntax errors, unknown variables and the like are reported as
+/// compile errors on the literal:
+///
+/// ```
+/// let query = strql_macros::strql!("TEXT = greeting\ngreeting = WORD -> ADD TO ROOT");
+/// assert_eq!(query.evaluate("hello").unwrap()["greeting"], "hello");
+/// ```
+///
+/// ```compile_fail
+/// let query = strql_macros::strql!("TEXT = WORD -> ADD TO");
+/// ```
+#[proc_macro]
+pub fn strql(input: TokenStream) -> TokenStream {
+    let literal = parse_macro_input!(input as LitStr);
+    let source = literal.value();
+
+    let query = match strql::CompiledQuery::compile(&source) {
+        Ok(query) => query,
+        Err(err) => {
+            let mut report = String::new();
+            let handler = miette::GraphicalReportHandler::new_themed(
+                miette::GraphicalTheme::unicode_nocolor(),
+            );
+            if handler.render_report(&mut report, &err).is_err() {
+                report = err.to_string();
+            }
+            let message = format!("invalid strql query\n{}", report);
+            return syn::Error::new(literal.span(), message)
+                .to_compile_error()
+                .into();
+        }
+    };
+    let blob = LitByteStr::new(&query.to_bytes(), literal.span());
+
+    quote! {{
+        static QUERY: ::std::sync::OnceLock<::strql::CompiledQuery> = ::std::sync::OnceLock::new();
+        QUERY.get_or_init(|| {
+            ::strql::CompiledQuery::from_bytes(#blob)
+                .expect("the strql! call was compiled with the same version of strql")
+        })
+    }}
+    .into()
+}
@@ -0,0 +1,24 @@
+use strql_macros::strql;
+
+#[test]
+fn evaluates_embedded_query() {
+    let query = strql!(
+        r#"
+TEXT = name " " age
+name = WORD -> ADD name TO ROOT
+age = 1..N DIGIT -> ADD age AS INT TO ROOT
+"#
+    );
+    let result = query.evaluate("Alice 25").unwrap();
+    assert_eq!(result["name"], "Alice");
+    assert_eq!(result["age"], 25);
+}
+
+#[test]
+fn same_call_site_loads_once() {
+    fn query() -> &'static strql::CompiledQuery {
+        strql!("TEXT = 1..N DIGIT")
+    }
+    assert!(std::ptr::eq(query(), query()));
+    assert!(query().matches("42").unwrap());
+}
@@ -0,0 +1,421 @@
+#![cfg(feature = "diagnostics")]
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+const PEOPLE: &str = "TEXT = row SPLITBY NEWLINE
+row = name \",\" age -> ADD person{} TO ROOT.people[]
+name = 1..N (LETTER OR \" \" OR \"\\\"\") -> ADD name TO person.name
+age = 1..N DIGIT -> ADD age AS INT TO person.age
+";
+
+fn strql() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_strql"))
+}
+
+/// Writes `contents` to `name` under `dir`, creating the directories on the way.
+fn write(dir: &Path, name: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+    let path = dir.join(name);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+/// A directory with the `PEOPLE` query as `query.strql`, and an input for it as `people.txt`.
+fn people() -> TempDir {
+    let dir = TempDir::new().unwrap();
+    write(dir.path(), "query.strql", PEOPLE);
+    write(dir.path(), "people.txt", "Ann,31\nBob \"B\",42");
+    dir
+}
+
+#[test]
+fn quiet_runs_exit_like_full_runs() {
+    let query = "OPTION ANYCASE\nTEXT = \"select \" WORD";
+    for (input, code) in [("SELECT x", 0), ("select x", 0), ("drop x", 1)] {
+        strql().args(["--inline", query, input]).assert().code(code);
+        strql()
+            .args(["-q", "--inline", query, input])
+            .assert()
+            .code(code)
+            .stdout("");
+    }
+}
+
+#[test]
+fn prints_json() {
+    let dir = people();
+    strql()
+        .current_dir(dir.path())
+        .args(["query.strql", "people.txt"])
+        .assert()
+        .success()
+        .stdout(
+            "{\n  \"people\": [\n    {\n      \"age\": 31,\n      \"name\": \"Ann\"\n    },\n    \
+             {\n      \"age\": 42,\n      \"name\": \"Bob \\\"B\\\"\"\n    }\n  ]\n}\n",
+        );
+}
+
+#[test]
+fn prints_tables() {
+    let dir = people();
+    strql()
+        .current_dir(dir.path())
+        .args(["--table", "query.strql", "people.txt"])
+        .assert()
+        .success()
+        .stdout("age  name\n---  -------\n31   Ann\n42   Bob \"B\"\n");
+
+    // not records
+    strql()
+        .args(["--table", "--inline", "TEXT = WORD -> ADD TO ROOT.w", "ab"])
+        .assert()
+        .code(64)
+        .stdout("")
+        .stderr(predicate::str::contains("--table and --csv need"));
+}
+
+#[test]
+fn prints_csv_with_quoted_fields() {
+    let dir = people();
+    strql()
+        .current_dir(dir.path())
+        .args(["--csv", "query.strql", "people.txt"])
+        .assert()
+        .success()
+        .stdout("age,name\n31,Ann\n42,\"Bob \"\"B\"\"\"\n");
+}
+
+#[test]
+fn prints_ndjson() {
+    let dir = people();
+    strql()
+        .current_dir(dir.path())
+        .args(["--ndjson", "ROOT.people[]", "query.strql", "people.txt"])
+        .assert()
+        .success()
+        .stdout("{\"age\":31,\"name\":\"Ann\"}\n{\"age\":42,\"name\":\"Bob \\\"B\\\"\"}\n");
+
+    strql()
+        .args(["--inline", "TEXT = WORD", "ab", "--ndjson"])
+        .assert()
+        .code(64)
+        .stdout("");
+
+    // the array has to be one the query adds to
+    strql()
+        .current_dir(dir.path())
+        .args(["--ndjson", "ROOT.nothere[]", "query.strql", "people.txt"])
+        .assert()
+        .code(64)
+        .stdout("")
+        .stderr(predicate::str::contains(
+            "no capture adds to ROOT.nothere[]",
+        ));
+
+    // the rest of the output comes last
+    let query = "TEXT = title \":\" item SPLITBY \",\"\n\
+                 title = WORD -> ADD TO ROOT.title\n\
+                 item = 1..N DIGIT -> ADD AS INT TO ROOT.items[]";
+    strql()
+        .args(["--ndjson", "items", "--inline", query, "ab:1,2"])
+        .assert()
+        .success()
+        .stdout("1\n2\n{\"title\":\"ab\"}\n");
+}
+
+#[test]
+fn walks_directories() {
+    let dir = TempDir::new().unwrap();
+    write(
+        dir.path(),
+        "query.strql",
+        "TEXT = 1..N DIGIT -> ADD TO ROOT.v",
+    );
+    write(dir.path(), "logs/a.log", "1");
+    write(dir.path(), "logs/nested/b.log", "22");
+    write(dir.path(), "logs/c.txt", "x");
+
+    strql()
+        .current_dir(dir.path())
+        .args(["-r", "query.strql", "logs", "--include", "*.log"])
+        .assert()
+        .success()
+        .stdout(
+            "{\n  \"logs/a.log\": {\n    \"v\": \"1\"\n  },\n  \
+             \"logs/nested/b.log\": {\n    \"v\": \"22\"\n  }\n}\n",
+        );
+
+    // without --include, c.txt is evaluated too, and doesn't match
+    strql()
+        .current_dir(dir.path())
+        .args(["--recursive", "query.strql", "logs"])
+        .assert()
+        .code(1)
+        .stdout("")
+        .stderr(predicate::str::contains("In file logs/c.txt:"));
+}
+
+#[test]
+fn evaluates_lines_on_their_own() {
+    let dir = TempDir::new().unwrap();
+    write(
+        dir.path(),
+        "query.strql",
+        "TEXT = 1..N DIGIT -> ADD TO ROOT.v",
+    );
+    write(dir.path(), "input.txt", "1\n22\n");
+    strql()
+        .current_dir(dir.path())
+        .args(["--lines", "query.strql", "input.txt"])
+        .assert()
+        .success()
+        .stdout("[\n  {\n    \"v\": \"1\"\n  },\n  {\n    \"v\": \"22\"\n  }\n]\n");
+
+    write(dir.path(), "input.txt", "1\nx\n");
+    strql()
+        .current_dir(dir.path())
+        .args(["--lines", "query.strql", "input.txt"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "In record 2 (starting at line 2):",
+        ));
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn reads_gzip_inputs() {
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    let dir = people();
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(b"Ann,31\nBob \"B\",42").unwrap();
+    write(dir.path(), "people.txt.gz", encoder.finish().unwrap());
+    strql()
+        .current_dir(dir.path())
+        .args(["--csv", "query.strql", "people.txt.gz"])
+        .assert()
+        .success()
+        .stdout("age,name\n31,Ann\n42,\"Bob \"\"B\"\"\"\n");
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn reads_zstd_inputs() {
+    let dir = people();
+    let compressed = zstd::encode_all(&b"Ann,31\nBob \"B\",42"[..], 0).unwrap();
+    write(dir.path(), "people.txt.zst", compressed);
+    strql()
+        .current_dir(dir.path())
+        .args(["--csv", "query.strql", "people.txt.zst"])
+        .assert()
+        .success()
+        .stdout("age,name\n31,Ann\n42,\"Bob \"\"B\"\"\"\n");
+}
+
+#[cfg(not(feature = "gzip"))]
+#[test]
+fn compressed_inputs_need_their_feature() {
+    let dir = people();
+    write(dir.path(), "people.txt.gz", [0x1f, 0x8b]);
+    strql()
+        .current_dir(dir.path())
+        .args(["query.strql", "people.txt.gz"])
+        .assert()
+        .code(4)
+        .stdout("")
+        .stderr(predicate::str::contains("requires the `gzip` feature"));
+}
+
+/// A directory with a query that takes more than the second before progress is reported
+/// to evaluate `big.txt`.
+fn slow() -> TempDir {
+    let dir = TempDir::new().unwrap();
+    write(
+        dir.path(),
+        "query.strql",
+        "TEXT = a SPLITBY NEWLINE\na = 1..N LETTER -> ADD TO ROOT.a[]",
+    );
+    write(
+        dir.path(),
+        "big.txt",
+        vec!["x".repeat(30); 5_000].join("\n"),
+    );
+    dir
+}
+
+#[test]
+fn reports_no_progress_when_stderr_is_not_a_terminal() {
+    let dir = slow();
+    strql()
+        .current_dir(dir.path())
+        .args(["query.strql", "big.txt"])
+        .assert()
+        .success()
+        .stderr("");
+
+    // the count of --min-match-ratio is reported all the same
+    write(dir.path(), "lines.txt", "x\n1\ny\n");
+    strql()
+        .current_dir(dir.path())
+        .args([
+            "--lines",
+            "--min-match-ratio",
+            "0.5",
+            "query.strql",
+            "lines.txt",
+        ])
+        .assert()
+        .success()
+        .stderr("strql: 2 of 3 records matched\n");
+}
+
+#[test]
+fn timeouts_exit_with_5() {
+    let dir = slow();
+    strql()
+        .current_dir(dir.path())
+        .args(["--timeout", "10ms", "query.strql", "big.txt"])
+        .assert()
+        .code(5)
+        .stdout("")
+        .stderr(predicate::str::starts_with(
+            "Evaluation timed out after 10ms",
+        ));
+
+    strql()
+        .args(["--timeout", "soon", "--inline", "TEXT = WORD", "ab"])
+        .assert()
+        .code(64)
+        .stderr(predicate::str::contains("--timeout requires a duration"));
+}
+
+#[test]
+fn takes_arguments_that_look_like_flags() {
+    let dir = TempDir::new().unwrap();
+    write(dir.path(), "-q", "-q");
+
+    strql()
+        .args(["--inline", "TEXT = \"-q\"", "-q"])
+        .assert()
+        .success()
+        .stdout("{}\n");
+    strql()
+        .current_dir(dir.path())
+        .args(["-e", "TEXT = \"-q\"", "-q"])
+        .assert()
+        .success()
+        .stdout("{}\n");
+
+    write(dir.path(), "query.strql", "TEXT = \"-q\"");
+    strql()
+        .current_dir(dir.path())
+        .args(["query.strql", "--", "-q"])
+        .assert()
+        .success()
+        .stdout("{}\n");
+}
+
+#[test]
+fn exit_codes_tell_outcomes_apart() {
+    let dir = TempDir::new().unwrap();
+    let ambiguous = "TEXT = a b\na = 1..N DIGIT -> ADD TO ROOT.a\nb = 1..N DIGIT -> ADD TO ROOT.b";
+    let cast = "TEXT = WORD -> ADD AS INT TO ROOT.n";
+    let cases: [(&[&str], i32); 8] = [
+        (&["--inline", "TEXT = WORD", "ab"], 0),
+        (&["--inline", "TEXT = WORD", "12"], 1),
+        (&["--inline", ambiguous, "123"], 2),
+        (&["--inline", "TEXT = (WORD", "ab"], 3),
+        (&["query.strql", "input.txt"], 4),
+        (&["--inline", cast, "ab"], 6),
+        (&["-q", "--inline", cast, "ab"], 6),
+        (&["--inline", "TEXT = WORD"], 64),
+    ];
+    for (args, code) in cases {
+        strql()
+            .current_dir(dir.path())
+            .args(args)
+            .assert()
+            .code(code);
+    }
+}
+
+#[test]
+fn interactive_runs_print_the_picked_parse() {
+    let dir = TempDir::new().unwrap();
+    write(
+        dir.path(),
+        "query.strql",
+        "TEXT = a b\na = 1..N DIGIT -> ADD TO ROOT.a\nb = 1..N DIGIT -> ADD TO ROOT.b",
+    );
+    write(dir.path(), "input.txt", "123");
+    strql()
+        .current_dir(dir.path())
+        .args(["-i", "query.strql", "input.txt"])
+        .write_stdin("2\n")
+        .assert()
+        .success()
+        .stdout("{\n  \"a\": \"12\",\n  \"b\": \"3\"\n}\n")
+        .stderr(predicate::str::contains("1) [1][23]").and(predicate::str::contains("2) [12][3]")));
+
+    // a choice out of range leaves the input ambiguous
+    strql()
+        .current_dir(dir.path())
+        .args(["--interactive", "query.strql", "input.txt"])
+        .write_stdin("9\n")
+        .assert()
+        .code(2)
+        .stdout("");
+}
+
+#[test]
+fn lint_fixes_the_query_file() {
+    let dir = TempDir::new().unwrap();
+    let query = write(
+        dir.path(),
+        "query.strql",
+        "// words\nTEXT = w SPLITBY (\".\")\nw = 1..N LETTER -> ADD TO ROOT.w[]\n",
+    );
+    strql()
+        .args(["lint"])
+        .arg(&query)
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("lint::redundant_group"));
+
+    strql()
+        .args(["lint", "--fix"])
+        .arg(&query)
+        .assert()
+        .success()
+        .stderr("Fixed 1 of 1 problems\n");
+    assert_eq!(
+        fs::read_to_string(&query).unwrap(),
+        "// words\nTEXT = w SPLITBY \".\"\nw = 1..N LETTER -> ADD TO ROOT.w[]\n"
+    );
+    strql().args(["lint"]).arg(&query).assert().success();
+}
+
+#[test]
+fn describes_imported_statements() {
+    let dir = TempDir::new().unwrap();
+    write(
+        dir.path(),
+        "query.strql",
+        "IMPORT ini\n/// the file\nTEXT = ini\n",
+    );
+    strql()
+        .current_dir(dir.path())
+        .args(["--describe", "query.strql"])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::starts_with("TEXT = ini\n    the file\n\n")
+                .and(predicate::str::contains("\nini_section = \"[\" name \"]\"")),
+        );
+}
@@ -0,0 +1,15 @@
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ItemsItem {
+    #[serde(default)]
+    pub comment: Option<String>,
+    #[serde(default)]
+    pub kind: Option<String>,
+    #[serde(default)]
+    pub members: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Basic {
+    #[serde(default)]
+    pub items: Vec<ItemsItem>,
+}
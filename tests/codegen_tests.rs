@@ -0,0 +1,43 @@
+#![cfg(all(feature = "codegen", feature = "serde"))]
+
+use strql::codegen::rust_types;
+use strql::evaluate_into;
+
+const QUERY: &str = include_str!("../examples/basic/query.strql");
+const INPUT: &str = include_str!("../examples/basic/test.txt");
+
+mod generated {
+    include!("codegen/basic.rs");
+}
+
+#[test]
+fn generated_types_are_up_to_date() {
+    assert_eq!(
+        rust_types(QUERY, "Basic").unwrap(),
+        include_str!("codegen/basic.rs")
+    );
+}
+
+#[test]
+fn evaluate_into_generated_types() {
+    let output: generated::Basic = evaluate_into(QUERY, INPUT).unwrap();
+
+    assert_eq!(output.items.len(), 3);
+    assert_eq!(output.items[0].members, ["cats", "dogs", "mice"]);
+    assert_eq!(output.items[1].comment, None);
+    assert_eq!(output.items[2].comment.as_deref(), Some("not fruits"));
+}
+
+#[test]
+fn evaluate_into_reports_mismatches() {
+    #[derive(serde::Deserialize, Debug)]
+    #[allow(dead_code)]
+    struct Wrong {
+        items: String,
+    }
+    let err = evaluate_into::<Wrong>(QUERY, INPUT).unwrap_err();
+    assert!(matches!(
+        err,
+        strql::error::StrqlError::OutputMismatch { .. }
+    ));
+}
@@ -0,0 +1,154 @@
+//! The sugar of the query language against the patterns it stands for, built by hand: both
+//! have to match the same inputs, the same way.
+
+use proptest::prelude::*;
+use serde_json::Value;
+use std::mem::{discriminant, Discriminant};
+use strql::ast::{AlternationBias, Bound, PatternKind, QuantifierBias};
+use strql::error::StrqlError;
+use strql::{Builtin, CompiledQuery, Pattern};
+
+fn node(node: PatternKind) -> Pattern {
+    Pattern { node, span: 0..0 }
+}
+
+fn literal(text: &str) -> Pattern {
+    node(PatternKind::Literal(text.to_string()))
+}
+
+fn builtin(builtin: Builtin) -> Pattern {
+    node(PatternKind::Builtin(builtin))
+}
+
+fn repeat(min: usize, max: Bound, pattern: Pattern, bias: QuantifierBias) -> Pattern {
+    node(PatternKind::Repetition {
+        min: Some(min),
+        max,
+        pattern: Box::new(pattern),
+        bias,
+    })
+}
+
+fn group(pattern: Pattern) -> Pattern {
+    node(PatternKind::Group(Box::new(pattern)))
+}
+
+fn sequence(items: Vec<Pattern>) -> Pattern {
+    node(PatternKind::Sequence(items))
+}
+
+/// What evaluating `input` gives, with errors told apart by kind only.
+fn outcome(query: &CompiledQuery, input: &str) -> Result<Value, Discriminant<StrqlError>> {
+    query.evaluate(input).map_err(|e| discriminant(&e))
+}
+
+fn assert_same_outcomes(sugar: &str, expanded: Pattern, input: &str) -> Result<(), TestCaseError> {
+    let sugar_query = CompiledQuery::compile(sugar).unwrap();
+    let expanded_query = CompiledQuery::from_pattern(&expanded).unwrap();
+    prop_assert_eq!(
+        outcome(&sugar_query, input),
+        outcome(&expanded_query, input),
+        "`{}` on {:?}",
+        sugar,
+        input
+    );
+    Ok(())
+}
+
+/// Items a repetition can be made of, as text and as the pattern the text stands for.
+fn items() -> Vec<(&'static str, Pattern)> {
+    vec![
+        ("\"a\"", literal("a")),
+        ("DIGIT", builtin(Builtin::Digit)),
+        (
+            "(1..N LETTER)",
+            group(repeat(
+                1,
+                None,
+                builtin(Builtin::Letter),
+                QuantifierBias::Neutral,
+            )),
+        ),
+        (
+            "(0..2 ANYCHAR)",
+            group(repeat(
+                0,
+                Some(2),
+                builtin(Builtin::AnyChar),
+                QuantifierBias::Neutral,
+            )),
+        ),
+    ]
+}
+
+fn separators() -> Vec<(&'static str, Pattern)> {
+    vec![
+        ("\",\"", literal(",")),
+        ("SPACE", builtin(Builtin::Space)),
+        ("\"a\"", literal("a")),
+    ]
+}
+
+fn biases() -> [(&'static str, QuantifierBias); 3] {
+    [
+        ("", QuantifierBias::Neutral),
+        ("LAZY ", QuantifierBias::Lazy),
+        ("GREEDY ", QuantifierBias::Greedy),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn word_is_a_repetition_of_letters(input in "[ab1 <>]{0,8}", bias in 0..3usize) {
+        let (keyword, bias) = biases()[bias];
+        let sugar = format!("TEXT = \"<\" {}WORD \">\"", keyword);
+        let expanded = sequence(vec![
+            literal("<"),
+            repeat(1, None, builtin(Builtin::Letter), bias),
+            literal(">"),
+        ]);
+        assert_same_outcomes(&sugar, expanded, &input)?;
+    }
+
+    #[test]
+    fn alphanum_is_a_repetition_of_letters_or_digits(input in "[a1 <>]{0,8}", bias in 0..3usize) {
+        let (keyword, bias) = biases()[bias];
+        let sugar = format!("TEXT = \"<\" {}ALPHANUM \">\"", keyword);
+        let either = node(PatternKind::OrChain {
+            alternatives: vec![builtin(Builtin::Letter), builtin(Builtin::Digit)],
+            bias: AlternationBias::Neutral,
+        });
+        let expanded = sequence(vec![literal("<"), repeat(1, None, either, bias), literal(">")]);
+        assert_same_outcomes(&sugar, expanded, &input)?;
+    }
+
+    #[test]
+    fn any_is_a_repetition_of_anychar(input in "[ab1 ,]{0,8}", bias in 0..3usize) {
+        let (keyword, bias) = biases()[bias];
+        let sugar = format!("TEXT = {}ANY \",\" ANY", keyword);
+        let expanded = sequence(vec![
+            repeat(0, None, builtin(Builtin::AnyChar), bias),
+            literal(","),
+            repeat(0, None, builtin(Builtin::AnyChar), QuantifierBias::Neutral),
+        ]);
+        assert_same_outcomes(&sugar, expanded, &input)?;
+    }
+
+    #[test]
+    fn splitby_is_an_item_then_separated_items(
+        input in "[ab1 ,]{0,10}",
+        item in 0..4usize,
+        separator in 0..3usize,
+        bias in 0..3usize,
+    ) {
+        let (item_text, item) = items().swap_remove(item);
+        let (separator_text, separator) = separators().swap_remove(separator);
+        let (keyword, bias) = biases()[bias];
+        let sugar = format!("TEXT = {} {}SPLITBY {}", item_text, keyword, separator_text);
+        let expanded = sequence(vec![
+            item.clone(),
+            repeat(0, None, sequence(vec![separator, item]), bias),
+        ]);
+        assert_same_outcomes(&sugar, expanded, &input)?;
+    }
+}
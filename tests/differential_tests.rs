@@ -0,0 +1,54 @@
+//! Queries that lower to regular expressions against the `regex` crate, on random inputs.
+//! Run with `cargo test --features differential --test differential_tests`.
+
+#![cfg(feature = "differential")]
+
+use arbitrary::{Arbitrary, Unstructured};
+use proptest::prelude::*;
+use strql::differential::check_against_regex;
+use strql::parser::parse;
+use strql::Program;
+
+const QUERIES: &[&str] = &[
+    "TEXT = WORD",
+    "TEXT = 1..N DIGIT \".\" 0..2 DIGIT",
+    "TEXT = ANYCASE \"ab\" ANY",
+    "TEXT = item SPLITBY \",\"\nitem = 1..N LETTER OR DIGIT",
+    "TEXT = LAZY ANY SPACE GREEDY ANY",
+    "TEXT = 0..N (0..N \"a\") \"b\"",
+    "TEXT = 2..3 (\"a\" OR \"ab\") NEWLINE",
+    "TEXT = HEXNUM OR BINNUM OR OCTNUM",
+];
+
+fn check(program: &Program, inputs: &[String]) -> Result<(), TestCaseError> {
+    if let Err(disagreement) = check_against_regex(program, inputs.iter().map(String::as_str)) {
+        return Err(TestCaseError::fail(format!(
+            "{}\n{}",
+            disagreement, program.source
+        )));
+    }
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn queries_agree_with_their_regex(
+        query in 0..QUERIES.len(),
+        inputs in prop::collection::vec("[ab1 ,.xX\n0-]{0,10}", 8),
+    ) {
+        let program = parse(QUERIES[query]).unwrap();
+        prop_assert!(program.to_regex().is_some(), "`{}` doesn't lower", QUERIES[query]);
+        check(&program, &inputs)?;
+    }
+
+    #[test]
+    fn generated_programs_agree_with_their_regex(
+        data in prop::collection::vec(any::<u8>(), 0..256),
+        inputs in prop::collection::vec("[ab1 .\\-\"\\\\\né]{0,8}", 8),
+    ) {
+        let Ok(program) = Program::arbitrary(&mut Unstructured::new(&data)) else {
+            return Ok(());
+        };
+        check(&program, &inputs)?;
+    }
+}
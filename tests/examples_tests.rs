@@ -3,7 +3,8 @@ mod examples {
     use std::fs;
     use std::path::Path;
     use strql::error::StrqlError;
-    use strql::evaluate_partition;
+    use strql::lint::{lint, LintConfig};
+    use strql::{evaluate_partition, stdlib, CustomBuiltins};
 
     #[test]
     fn test_examples() {
@@ -24,6 +25,33 @@ mod examples {
         }
     }
 
+    #[test]
+    fn examples_and_grammars_lint_clean() {
+        let mut sources = Vec::new();
+        for entry in fs::read_dir("examples").unwrap() {
+            let path = entry.unwrap().path();
+            // shows the ambiguity the linter warns about
+            if path.ends_with("ambiguity") {
+                continue;
+            }
+            let query_path = path.join("query.strql");
+            if let Ok(source) = fs::read_to_string(&query_path) {
+                sources.push((query_path.display().to_string(), source));
+            }
+        }
+        for name in stdlib::names() {
+            sources.push((name.to_string(), stdlib::source(name).unwrap().to_string()));
+        }
+        assert!(sources.len() > 10);
+
+        for (name, source) in sources {
+            let program = strql::parser::parse(&source).unwrap();
+            let lints = lint(&program, &CustomBuiltins::new(), &LintConfig::default()).unwrap();
+            let messages: Vec<_> = lints.iter().map(|lint| &lint.message).collect();
+            assert!(lints.is_empty(), "{}: {:?}", name, messages);
+        }
+    }
+
     fn run_example_test(path: &Path) {
         let test_name = path.file_name().unwrap().to_str().unwrap();
 
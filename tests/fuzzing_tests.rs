@@ -122,8 +122,8 @@ mod fuzzing {
     #[test]
     fn fuzz_word_splitby_matching() {
         let query = r#"
-            TEXT = word GREEDY SPLITBY ", "
-            word = WORD -> ADD TO ROOT.words[]
+            TEXT = `word` GREEDY SPLITBY ", "
+            `word` = WORD -> ADD TO ROOT.words[]
         "#;
 
         println!("\n=== Fuzzing: word splitby (matching inputs) ===");
@@ -165,15 +165,18 @@ mod fuzzing {
                 word_count, avg_time, std_error, success_count, ITERATIONS
             );
 
-            assert_eq!(success_count, ITERATIONS, "Some matching inputs failed to parse");
+            assert_eq!(
+                success_count, ITERATIONS,
+                "Some matching inputs failed to parse"
+            );
         }
     }
 
     #[test]
     fn fuzz_lines_matching() {
         let query = r#"
-            TEXT = line GREEDY SPLITBY NEWLINE
-            line = LINE -> ADD TO ROOT.lines[]
+            TEXT = `line` GREEDY SPLITBY NEWLINE
+            `line` = LINE -> ADD TO ROOT.lines[]
         "#;
 
         println!("\n=== Fuzzing: lines (matching inputs) ===");
@@ -215,7 +218,10 @@ mod fuzzing {
                 line_count, avg_time, std_error, success_count, ITERATIONS
             );
 
-            assert_eq!(success_count, ITERATIONS, "Some matching inputs failed to parse");
+            assert_eq!(
+                success_count, ITERATIONS,
+                "Some matching inputs failed to parse"
+            );
         }
     }
 
@@ -262,7 +268,10 @@ mod fuzzing {
                 size, avg_time, std_error, nomatch_count, ITERATIONS
             );
 
-            assert_eq!(nomatch_count, ITERATIONS, "Some no-match inputs unexpectedly matched");
+            assert_eq!(
+                nomatch_count, ITERATIONS,
+                "Some no-match inputs unexpectedly matched"
+            );
         }
     }
 
@@ -363,7 +372,10 @@ mod fuzzing {
                 size, avg_time, std_error, success_count, ITERATIONS
             );
 
-            assert_eq!(success_count, ITERATIONS, "Some valid alternation inputs failed");
+            assert_eq!(
+                success_count, ITERATIONS,
+                "Some valid alternation inputs failed"
+            );
         }
     }
 
@@ -468,7 +480,10 @@ mod fuzzing {
                 line_count, avg_time, std_error, success_count, ITERATIONS
             );
 
-            assert_eq!(success_count, ITERATIONS, "Some valid complex capture inputs failed");
+            assert_eq!(
+                success_count, ITERATIONS,
+                "Some valid complex capture inputs failed"
+            );
         }
     }
 
@@ -147,8 +147,8 @@ mod performance {
     #[test]
     fn test_performance_splitby_words() {
         let query = r#"
-            TEXT = word GREEDY SPLITBY ", "
-            word = WORD
+            TEXT = `word` GREEDY SPLITBY ", "
+            `word` = WORD
         "#;
 
         println!("\nStress test: splitby words");
@@ -185,8 +185,8 @@ mod performance {
     #[test]
     fn test_performance_splitby_lines() {
         let query = r#"
-            TEXT = line GREEDY SPLITBY NEWLINE
-            line = LINE
+            TEXT = `line` GREEDY SPLITBY NEWLINE
+            `line` = LINE
         "#;
 
         println!("\nStress test: splitby lines");
@@ -221,6 +221,47 @@ mod performance {
         }
     }
 
+    #[test]
+    fn test_performance_splitby_lines_with_captures() {
+        // every line adds to the trace of the lines before it, which must not be copied
+        let query = r#"
+            TEXT = `line` GREEDY SPLITBY NEWLINE
+            `line` = LINE -> ADD TO ROOT.lines[]
+        "#;
+
+        println!("\nStress test: splitby lines with captures");
+        let sizes = [250, 500, 1000, 2000];
+        let mut prev_time = 0.0;
+
+        for &line_count in &sizes {
+            let input = generate_lines(line_count, 20, 42);
+            let (mean, std_error) = measure_time_stats(|| {
+                let _ = evaluate_partition(query, &input);
+            });
+
+            println!(
+                "Lines: {}, Input len: {}, Time: {:.0} ± {:.0} ns",
+                line_count,
+                input.len(),
+                mean,
+                std_error
+            );
+
+            // doubling the lines should about double the time, where copying the traces
+            // would quadruple it
+            if prev_time > 10000.0 {
+                let ratio = mean / prev_time;
+                println!("  Ratio from previous: {:.2}", ratio);
+                assert!(
+                    ratio < 3.0,
+                    "Performance degradation too high: {:.2}",
+                    ratio
+                );
+            }
+            prev_time = mean;
+        }
+    }
+
     #[test]
     fn test_performance_nested_quantifiers() {
         // Nested quantifiers can be expensive